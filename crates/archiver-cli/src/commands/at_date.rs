@@ -0,0 +1,43 @@
+//! At-date command implementation
+
+use anyhow::{Context, Result};
+use archiver_cli::helpers::format_timestamp;
+use archiver_core::Upstream;
+use archiver_db::ArchiverDb;
+use chrono::NaiveDate;
+use colored::Colorize;
+
+/// Shows which version of a package was current in nixpkgs on a date
+pub fn cmd_at_date(attr_name: String, date: String, upstream: &Upstream, db: ArchiverDb) -> Result<()> {
+    let day = NaiveDate::parse_from_str(&date, "%Y-%m-%d")
+        .with_context(|| format!("Invalid date format: {}. Expected YYYY-MM-DD", date))?;
+    // Commits from any time during that day count as "on" the date
+    let end_of_day = day.and_hms_opt(23, 59, 59).unwrap().and_utc().timestamp().max(0) as u64;
+
+    let Some(entry) = db.version_at_date(&attr_name, end_of_day)? else {
+        let versions = db.get_all_versions(&attr_name)?;
+        match versions.last() {
+            Some(oldest) => eprintln!(
+                "{} No version of {} is indexed on or before {}; the oldest indexed commit is from {} ({})",
+                "❌".red(),
+                attr_name.bold(),
+                date.bold(),
+                format_timestamp(oldest.timestamp),
+                oldest.version
+            ),
+            None => eprintln!("{} No versions found for package '{}'", "❌".red(), attr_name.bold()),
+        }
+        std::process::exit(1);
+    };
+
+    println!("\n{} {}", "📅 Package:".bright_cyan(), format!("{} on {}", entry.attr_name, date).bold());
+    println!("{}", "━".repeat(60).bright_black());
+    println!("  {}   {}", "Version:".bright_yellow(), entry.version.green().bold());
+    println!("  {}    {}", "Commit:".bright_yellow(), entry.commit_sha);
+    println!("  {}      {}", "Date:".bright_yellow(), format_timestamp(entry.timestamp));
+    println!("\n{}", "📝 Nix expression:".bright_cyan());
+    println!("{}", "━".repeat(60).bright_black());
+    println!("{}", entry.to_nix_import(upstream).bright_white());
+
+    Ok(())
+}
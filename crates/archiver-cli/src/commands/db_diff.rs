@@ -0,0 +1,73 @@
+//! Db-diff command implementation
+
+use anyhow::{Context, Result};
+use archiver_core::{short_sha, PackageEntry};
+use archiver_db::{ArchiverDb, EntryDiff};
+use colored::Colorize;
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+fn describe(entry: &PackageEntry) -> String {
+    match &entry.platform {
+        Some(platform) => format!("{} {} ({})", entry.attr_name, entry.version, platform),
+        None => format!("{} {}", entry.attr_name, entry.version),
+    }
+}
+
+/// Compares this database with the one at `other`: versions stored in only
+/// one of them and versions pinned to different commits
+pub fn cmd_db_diff(other: PathBuf, verbose: bool, db: ArchiverDb) -> Result<()> {
+    let other_db = ArchiverDb::open_for_reading(&other)
+        .with_context(|| format!("Failed to open database {:?}", other))?;
+
+    let mut only_here = 0;
+    let mut only_there = 0;
+    let mut changed = 0;
+    let unchanged = db.diff(&other_db, |diff| {
+        match &diff {
+            EntryDiff::OnlyHere(_) => only_here += 1,
+            EntryDiff::OnlyThere(_) => only_there += 1,
+            EntryDiff::CommitChanged { .. } => changed += 1,
+        }
+        if verbose {
+            match diff {
+                EntryDiff::OnlyHere(entry) => {
+                    println!("  {} {}", "-".red(), describe(&entry));
+                }
+                EntryDiff::OnlyThere(entry) => {
+                    println!("  {} {}", "+".green(), describe(&entry));
+                }
+                EntryDiff::CommitChanged { here, there } => println!(
+                    "  {} {}  {} → {}",
+                    "~".yellow(),
+                    describe(&here),
+                    short_sha(&here.commit_sha, 8),
+                    short_sha(&there.commit_sha, 8),
+                ),
+            }
+        }
+    })?;
+
+    let here_attrs: HashSet<String> = db.version_counts()?.into_iter().map(|(attr, _)| attr).collect();
+    let there_attrs: HashSet<String> = other_db.version_counts()?.into_iter().map(|(attr, _)| attr).collect();
+
+    if verbose && only_here + only_there + changed > 0 {
+        println!();
+    }
+    println!("{} {:?}", "Compared with".bright_cyan().bold(), other);
+    println!("  {}: {}", "Unchanged versions".bright_yellow(), unchanged.to_string().bold());
+    println!("  {}: {}  {} {}",
+        "Only in this database".bright_yellow(),
+        only_here.to_string().bold(),
+        "packages:".dimmed(),
+        here_attrs.difference(&there_attrs).count().to_string().bold(),
+    );
+    println!("  {}: {}  {} {}",
+        "Only in the other database".bright_yellow(),
+        only_there.to_string().bold(),
+        "packages:".dimmed(),
+        there_attrs.difference(&here_attrs).count().to_string().bold(),
+    );
+    println!("  {}: {}", "Different commits".bright_yellow(), changed.to_string().bold());
+    Ok(())
+}
@@ -0,0 +1,71 @@
+//! Debug command implementation
+
+use anyhow::{Context, Result};
+use archiver_db::ArchiverDb;
+use archiver_index::parsers::{AttrMap, ParseStrategy};
+use archiver_index::{Indexer, PackageInfo};
+use colored::Colorize;
+use std::path::PathBuf;
+
+fn format_packages(packages: &[PackageInfo]) -> String {
+    packages
+        .iter()
+        .map(|p| format!("{} {}", p.attr_name, p.version))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Shows how each parser strategy handles one file at a given commit
+pub fn cmd_debug(repo_path: PathBuf, path: String, commit: String, attr_map: Option<PathBuf>, db: ArchiverDb) -> Result<()> {
+    let mut indexer = Indexer::new(&repo_path, db)
+        .context("Failed to create indexer")?;
+    if let Some(map_path) = attr_map {
+        indexer = indexer.with_attr_map(AttrMap::load(map_path)?);
+    }
+    let diagnosis = indexer.diagnose_file(&commit, &path)?;
+
+    println!("{} {} @ {}", "🔍".bright_cyan(), path.bold(), commit.bold());
+
+    println!("\n{}", "AST parse:".bright_cyan().bold());
+    if diagnosis.parse_errors.is_empty() {
+        println!("  {} no parse errors", "✓".green());
+    } else {
+        println!("  {} {} parse error(s), AST strategies skipped:", "✗".red(), diagnosis.parse_errors.len());
+        for error in &diagnosis.parse_errors {
+            println!("    {}", error.dimmed());
+        }
+    }
+
+    for report in &diagnosis.ast_strategies {
+        if report.packages.is_empty() {
+            println!("  {} {}: {}", "·".dimmed(), report.name, "no match".dimmed());
+        } else {
+            println!("  {} {}: {}", "✓".green(), report.name, format_packages(&report.packages).bold());
+        }
+    }
+
+    println!("\n{}", "Regex fallback:".bright_cyan().bold());
+    match &diagnosis.regex {
+        Some(pkg) => println!("  {} {} {}", "✓".green(), pkg.attr_name.bold(), pkg.version.bold()),
+        None => println!("  {} {}", "·".dimmed(), "no package name or valid version found".dimmed()),
+    }
+
+    let strategy = match diagnosis.outcome.strategy {
+        ParseStrategy::Ast => "AST",
+        ParseStrategy::Regex => "regex fallback",
+        ParseStrategy::None => "none",
+    };
+    println!("\n{} {}", "Indexed as:".bright_cyan().bold(), strategy.bold());
+    if diagnosis.outcome.packages.is_empty() {
+        match diagnosis.miss {
+            Some(reason) => println!("  {} nothing would be indexed from this file ({})", "✗".red(), reason.to_string().bold()),
+            None => println!("  {} nothing would be indexed from this file", "✗".red()),
+        }
+    } else {
+        for pkg in &diagnosis.outcome.packages {
+            println!("  {} {}", pkg.attr_name.bright_white().bold(), pkg.version.bright_green());
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,99 @@
+//! Doctor command implementation
+
+use anyhow::Result;
+use archiver_db::{ArchiverDb, SCHEMA_VERSION};
+use colored::Colorize;
+use std::path::{Path, PathBuf};
+
+/// Outcome of a single environment check
+enum Check {
+    Pass(String),
+    /// Problem that only affects some commands
+    Warn(String),
+    /// Problem that breaks core functionality
+    Fail(String),
+}
+
+/// Checks the runtime environment and prints a pass/fail checklist.
+/// Exits with status 1 if any hard requirement is missing.
+pub fn cmd_doctor(repo: Option<PathBuf>, database: &Path) -> Result<()> {
+    let checks = [
+        ("git on PATH (index)", check_binary("git", true)),
+        ("nix-prefetch-url on PATH (prefetch-hashes)", check_binary("nix-prefetch-url", false)),
+        ("database", check_database(database)),
+        ("repository", check_repo(repo.as_deref())),
+    ];
+
+    println!("{}", "nix-archiver doctor".bright_cyan().bold());
+    let mut failed = 0;
+    for (name, check) in &checks {
+        match check {
+            Check::Pass(detail) => println!("  {} {}: {}", "✓".green(), name, detail.dimmed()),
+            Check::Warn(detail) => println!("  {} {}: {}", "!".yellow(), name, detail),
+            Check::Fail(detail) => {
+                failed += 1;
+                println!("  {} {}: {}", "✗".red(), name, detail.red());
+            }
+        }
+    }
+
+    if failed > 0 {
+        println!("\n{} {} required check(s) failed", "❌".red(), failed);
+        std::process::exit(1);
+    }
+    println!("\n{} Ready", "✓".green().bold());
+    Ok(())
+}
+
+/// Runs `<binary> --version` to confirm it is installed and executable
+fn check_binary(binary: &str, required: bool) -> Check {
+    match std::process::Command::new(binary).arg("--version").output() {
+        Ok(output) if output.status.success() => {
+            let version = String::from_utf8_lossy(&output.stdout);
+            Check::Pass(version.lines().next().unwrap_or("").trim().to_string())
+        }
+        Ok(output) => {
+            let message = format!("`{} --version` failed: {}", binary, String::from_utf8_lossy(&output.stderr).trim());
+            if required { Check::Fail(message) } else { Check::Warn(message) }
+        }
+        Err(_) if required => Check::Fail(format!("`{}` not found", binary)),
+        Err(_) => Check::Warn(format!("`{}` not found; install Nix to prefetch tarball hashes", binary)),
+    }
+}
+
+fn check_database(path: &Path) -> Check {
+    if !path.exists() {
+        return Check::Warn(format!("{} does not exist yet; it will be created by `index`", path.display()));
+    }
+    // Opening also rejects schema versions newer than this build supports
+    match ArchiverDb::open(path) {
+        Ok(db) => match db.schema_version() {
+            Ok(version) => Check::Pass(format!(
+                "{} (schema v{} of v{} supported, {} versions)",
+                path.display(),
+                version,
+                SCHEMA_VERSION,
+                db.version_count()
+            )),
+            Err(e) => Check::Fail(format!("{}: {:#}", path.display(), e)),
+        },
+        Err(e) => Check::Fail(format!("{:#}", e)),
+    }
+}
+
+fn check_repo(repo: Option<&Path>) -> Check {
+    let Some(repo) = repo else {
+        return Check::Warn("not checked (pass --repo to verify a nixpkgs checkout)".to_string());
+    };
+    match git2::Repository::open(repo) {
+        Ok(r) if r.is_shallow() => Check::Warn(format!(
+            "{} is a shallow clone; older history can't be indexed",
+            repo.display()
+        )),
+        Ok(r) if r.workdir().is_some_and(|w| !w.join("pkgs").is_dir()) => {
+            Check::Warn(format!("{} has no pkgs/ directory; is it nixpkgs?", repo.display()))
+        }
+        Ok(_) => Check::Pass(repo.display().to_string()),
+        Err(e) => Check::Fail(format!("{} is not a git repository: {}", repo.display(), e.message())),
+    }
+}
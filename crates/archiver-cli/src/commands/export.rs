@@ -0,0 +1,49 @@
+//! Export/import command implementations
+
+use anyhow::Result;
+use archiver_cli::export::{export_package_to_path, export_to_path, import_from_path, Compression, HistoryFormat};
+use archiver_db::ArchiverDb;
+use colored::Colorize;
+use std::path::PathBuf;
+
+/// Exports all package entries as JSON Lines
+pub fn cmd_export(output: PathBuf, compress: Option<Compression>, db: ArchiverDb) -> Result<()> {
+    let compression = compress.unwrap_or_else(|| Compression::from_path(&output));
+    let count = export_to_path(&db, &output, compression)?;
+
+    println!(
+        "{} Exported {} entries to {}",
+        "✓".green().bold(),
+        count.to_string().bold(),
+        output.display().to_string().bold()
+    );
+    Ok(())
+}
+
+/// Exports every version of one package as JSON or CSV
+pub fn cmd_export_package(attr_name: String, output: PathBuf, format: Option<HistoryFormat>, db: ArchiverDb) -> Result<()> {
+    let format = format.unwrap_or_else(|| HistoryFormat::from_path(&output));
+    let count = export_package_to_path(&db, &attr_name, &output, format)?;
+
+    println!(
+        "{} Exported {} entries of {} to {}",
+        "✓".green().bold(),
+        count.to_string().bold(),
+        attr_name.bold(),
+        output.display().to_string().bold()
+    );
+    Ok(())
+}
+
+/// Imports package entries from a JSON Lines export (plain or gzip)
+pub fn cmd_import(input: PathBuf, db: ArchiverDb) -> Result<()> {
+    let count = import_from_path(&db, &input)?;
+
+    println!(
+        "{} Imported {} entries from {}",
+        "✓".green().bold(),
+        count.to_string().bold(),
+        input.display().to_string().bold()
+    );
+    Ok(())
+}
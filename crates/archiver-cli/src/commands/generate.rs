@@ -1,273 +1,281 @@
 //! Generate command implementation
 
 use anyhow::{Context, Result};
+use archiver_cli::generate::{parse_packages_spec, render, report_json, resolve_spec, OutputFormat, PackageSpec};
+use archiver_cli::helpers::nix_hash_to_sri;
+use archiver_core::{short_sha, Upstream};
 use archiver_db::ArchiverDb;
 use colored::Colorize;
-use rnix::ast::{self, AttrpathValue, Expr, InterpolPart};
-use rowan::ast::AstNode;
-use std::path::PathBuf;
-
-use crate::helpers::sort_versions_semver;
-
-// ─── Parser ───────────────────────────────────────────────────────────────────
+use std::path::{Path, PathBuf};
+
+/// What `generate --output` writes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum GenerateFormat {
+    /// Attribute set of pinned packages, usable with `nix-shell`
+    Frozen,
+    /// List of pinned derivations for `mkShell`'s `buildInputs`
+    #[value(alias = "buildInputs")]
+    BuildInputs,
+    /// Like `frozen`, fetching each snapshot with `fetchFromGitHub`
+    /// (owner/repo/rev/sha256)
+    FetchFromGithub,
+}
 
-/// Parses a packages.nix attrset and returns (attr_name, version) pairs.
-///
-/// Uses rnix AST so comments, multi-line strings, and all valid Nix syntax are
-/// handled correctly — no manual comment stripping or regex needed.
-fn parse_packages_spec(path: &std::path::Path, content: &str) -> Result<Vec<(String, String)>> {
-    let parsed = rnix::Root::parse(content);
+/// Generates frozen.nix file from package specification, or with
+/// `pin_snapshot` (`attr=version`) the single nixpkgs snapshot that package
+/// resolves to
+#[allow(clippy::too_many_arguments)]
+pub fn cmd_generate(
+    input: Option<PathBuf>,
+    pin_snapshot: Option<String>,
+    output: Option<PathBuf>,
+    format: GenerateFormat,
+    output_dir: Option<PathBuf>,
+    nixpkgs: Option<PathBuf>,
+    report: Option<PathBuf>,
+    include_prerelease: bool,
+    force: bool,
+    upstream: &Upstream,
+    db: ArchiverDb,
+) -> Result<()> {
+    use std::fs;
 
-    if !parsed.errors().is_empty() {
-        let errs: Vec<String> = parsed.errors().iter().map(|e| e.to_string()).collect();
-        anyhow::bail!("Nix parse error in {}: {}", path.display(), errs.join("; "));
+    if format == GenerateFormat::FetchFromGithub && nixpkgs.is_some() {
+        anyhow::bail!("--nixpkgs fetches from a local repository; it can't be combined with --format fetch-from-github");
     }
 
-    let mut result = Vec::new();
-
-    for node in parsed.tree().syntax().descendants() {
-        let Some(kv) = AttrpathValue::cast(node) else { continue };
-
-        // Accept only simple (non-dotted) keys
-        let Some(attrpath) = kv.attrpath() else { continue };
-        let mut attrs = attrpath.attrs();
-        let Some(first) = attrs.next() else { continue };
-        if attrs.next().is_some() {
-            // dotted path like foo.bar — not a package spec entry
-            continue;
-        }
-
-        let attr_name = match first {
-            ast::Attr::Ident(ident) => match ident.ident_token() {
-                Some(t) => t.text().to_string(),
-                None => continue,
-            },
-            _ => continue,
-        };
-
-        // Value must be a plain string literal (no interpolation)
-        let Some(value) = kv.value() else { continue };
-        let Expr::Str(s) = value else { continue };
+    // Refuse before doing any work, so nothing is half-written
+    let mut targets: Vec<PathBuf> = output.iter().cloned().collect();
+    if let Some(dir) = &output_dir {
+        targets.push(dir.join("flake.nix"));
+        targets.push(dir.join("flake.lock"));
+    }
+    for target in &targets {
+        check_output_path(input.as_deref(), target, force)?;
+    }
+    if let Some(report_path) = &report {
+        // Regenerated on every run, so only the input is protected
+        check_output_path(input.as_deref(), report_path, true)?;
+    }
 
-        // normalized_parts() yields InterpolPart<String> — Literal is already a plain String,
-        // Interpolation means ${...} is present and we skip those entries.
-        let mut version = String::new();
-        let mut has_interpolation = false;
-        for part in s.normalized_parts() {
-            match part {
-                InterpolPart::Literal(text) => version.push_str(&text),
-                InterpolPart::Interpolation(_) => {
-                    has_interpolation = true;
-                    break;
-                }
+    let spec = match (&pin_snapshot, &input) {
+        (Some(pin), _) => {
+            let (attr_name, version) = pin
+                .split_once('=')
+                .filter(|(attr, version)| !attr.trim().is_empty() && !version.trim().is_empty())
+                .with_context(|| format!("Invalid --pin-snapshot '{}': expected ATTR=VERSION, e.g. nodejs=20.11.0", pin))?;
+            PackageSpec {
+                entries: vec![(attr_name.trim().to_string(), version.trim().to_string())],
+                ..PackageSpec::default()
             }
         }
-
-        if has_interpolation {
-            eprintln!(
-                "{} Skipping '{}': interpolated strings are not supported",
-                "⚠".yellow(),
-                attr_name
+        (None, Some(input)) => {
+            println!(
+                "{} Reading package specification from {}...",
+                "📖".bright_cyan(),
+                input.display()
             );
-            continue;
-        }
 
-        result.push((attr_name, version));
+            let content = fs::read_to_string(input)
+                .with_context(|| format!("Failed to read input file: {}", input.display()))?;
+
+            let spec = parse_packages_spec(input, &content)?;
+            for error in &spec.parse_errors {
+                eprintln!(
+                    "{} Syntax error in {}, {}; entries there were skipped",
+                    "⚠".yellow(),
+                    input.display(),
+                    error
+                );
+            }
+            spec
+        }
+        (None, None) => anyhow::bail!("Pass --input with a package spec or --pin-snapshot ATTR=VERSION"),
+    };
+    for attr_name in &spec.skipped {
+        eprintln!(
+            "{} Skipping '{}': interpolated strings are not supported",
+            "⚠".yellow(),
+            attr_name
+        );
     }
 
-    Ok(result)
-}
-
-// ─── Command ──────────────────────────────────────────────────────────────────
-
-/// Generates frozen.nix file from package specification
-pub fn cmd_generate(input: PathBuf, output: PathBuf, nixpkgs: Option<PathBuf>, db: ArchiverDb) -> Result<()> {
-    use std::fs;
-    use std::io::Write;
-
-    println!(
-        "{} Reading package specification from {}...",
-        "📖".bright_cyan(),
-        input.display()
-    );
+    let mut resolved = resolve_spec(&db, &spec.entries, include_prerelease)?;
+    resolved.upstream = upstream.clone();
 
-    let content = fs::read_to_string(&input)
-        .with_context(|| format!("Failed to read input file: {}", input.display()))?;
-
-    let spec = parse_packages_spec(&input, &content)?;
-
-    let mut packages = Vec::new();
-    let mut errors = Vec::new();
+    // Written before any error exit, so CI can see what failed to resolve
+    if let Some(ref report_path) = report {
+        let json = serde_json::to_string_pretty(&report_json(&resolved, &spec.skipped))
+            .context("Failed to serialize resolution report")?;
+        fs::write(report_path, json + "\n")
+            .with_context(|| format!("Failed to write report to {}", report_path.display()))?;
+        println!("{} Resolution report written to {}", "📝".bright_cyan(), report_path.display());
+    }
 
-    for (attr_name, version) in spec {
-        let entry = if version == "latest" {
-            let available = db.get_all_versions(&attr_name)?;
-            if available.is_empty() {
-                errors.push(format!("No versions found for package '{}'", attr_name));
-                continue;
-            }
-            let mut sorted = sort_versions_semver(available);
-            let newest = sorted.remove(0);
+    for p in &resolved.packages {
+        let entry = &p.entry;
+        if p.requested == "latest" {
             println!(
                 "  {} Resolved: {} latest → v{} @ commit {}",
                 "✓".green(),
-                attr_name.bold(),
-                newest.version.bright_yellow(),
-                &newest.commit_sha[..12].dimmed()
+                entry.attr_name.bold(),
+                entry.version.bright_yellow(),
+                short_sha(&entry.commit_sha, 12).dimmed()
             );
-            newest
         } else {
-            match db.get(&attr_name, &version)? {
-                Some(entry) => {
-                    println!(
-                        "  {} Found: {} v{} @ commit {}",
-                        "✓".green(),
-                        attr_name.bold(),
-                        version.bright_yellow(),
-                        &entry.commit_sha[..12].dimmed()
-                    );
-                    entry
-                }
-                None => {
-                    errors.push(format!(
-                        "Package {}:{} not found in database",
-                        attr_name, version
-                    ));
-                    let available = db.get_all_versions(&attr_name)?;
-                    if !available.is_empty() {
-                        let sorted = sort_versions_semver(available);
-                        let suggestions: Vec<String> = sorted
-                            .iter()
-                            .take(5)
-                            .map(|e| e.version.clone())
-                            .collect();
-                        errors.push(format!(
-                            "         Available versions: {}",
-                            suggestions.join(", ")
-                        ));
-                    } else {
-                        errors.push(format!(
-                            "         No versions available for package '{}'",
-                            attr_name
-                        ));
-                    }
-                    continue;
-                }
-            }
-        };
-        packages.push(entry);
+            println!(
+                "  {} Found: {} v{} @ commit {}",
+                "✓".green(),
+                entry.attr_name.bold(),
+                entry.version.bright_yellow(),
+                short_sha(&entry.commit_sha, 12).dimmed()
+            );
+        }
     }
 
     // Report errors if any
-    if !errors.is_empty() {
+    if !resolved.is_complete() {
         eprintln!("\n{} Errors found:\n", "❌".red().bold());
-        for error in &errors {
+        for error in &resolved.errors {
             eprintln!("  {}", error.red());
         }
-        eprintln!("\n{} Expected input format:", "💡".yellow());
-        eprintln!(
-            "  {{\n    nodejs = \"20.11.0\";  # specific version\n    python = \"latest\";   # newest version in database\n  }}"
-        );
+        if pin_snapshot.is_none() {
+            eprintln!("\n{} Expected input format:", "💡".yellow());
+            eprintln!(
+                "  {{\n    nodejs = \"20.11.0\";  # specific version\n    python = \"latest\";   # newest stable version in database\n  }}"
+            );
+        }
         anyhow::bail!("Failed to resolve all packages. Fix the errors above and try again.");
     }
 
-    if packages.is_empty() {
+    if resolved.packages.is_empty() {
         eprintln!("{} No packages found in input file.", "❌".red());
         eprintln!("\n{} Expected input format:", "💡".yellow());
         eprintln!(
-            "  {{\n    nodejs = \"20.11.0\";  # specific version\n    python = \"latest\";   # newest version in database\n  }}"
+            "  {{\n    nodejs = \"20.11.0\";  # specific version\n    python = \"latest\";   # newest stable version in database\n  }}"
         );
         anyhow::bail!("Input file is empty or invalid");
     }
 
     // Generate frozen.nix
-    println!(
-        "\n{} Generating frozen.nix with {} package{}...",
-        "🔨".bright_cyan(),
-        packages.len(),
-        if packages.len() == 1 { "" } else { "s" }
-    );
-
-    // Collect unique commits in order of first appearance so each nixpkgs
-    // snapshot is fetched only once even if multiple packages share a commit.
-    let mut seen = std::collections::HashSet::new();
-    let mut unique_commits: Vec<&str> = Vec::new();
-    for e in &packages {
-        if seen.insert(e.commit_sha.as_str()) {
-            unique_commits.push(&e.commit_sha);
-        }
+    let count = resolved.packages.len();
+    if pin_snapshot.is_some() {
+        println!("\n{} Pinning the whole nixpkgs snapshot...", "🔨".bright_cyan());
+    } else {
+        println!(
+            "\n{} Generating frozen.nix with {} package{}...",
+            "🔨".bright_cyan(),
+            count,
+            if count == 1 { "" } else { "s" }
+        );
     }
 
-    let nixpkgs_var = |commit: &str| format!("nixpkgs_{}", commit);
-
-    // Build the Nix source expression for a given commit:
-    //  1. --nixpkgs <path>  → builtins.fetchGit file:// (local bare repo, offline)
-    //  2. sha256 in DB      → fetchTarball { sha256 = "..." } (fully pinned tarball)
-    //  3. default           → builtins.fetchGit { url = github; rev = commit; }
-    //                         git is content-addressed by commit SHA — no hash needed
-    let source_expr = |commit: &str| -> String {
-        if let Some(ref local) = nixpkgs {
-            let canon = local.canonicalize().unwrap_or_else(|_| local.clone());
-            return format!(
-                "builtins.fetchGit {{ url = \"file://{}\"; rev = \"{}\"; }}",
-                canon.display(), commit
-            );
-        }
-        if let Ok(Some(hash)) = db.get_tarball_hash(commit) {
-            let url = format!("https://github.com/NixOS/nixpkgs/archive/{}.tar.gz", commit);
-            return format!("fetchTarball {{ url = \"{}\"; sha256 = \"{}\"; }}", url, hash);
-        }
-        // Default: builtins.fetchGit — git commit SHA is its own integrity guarantee
-        format!(
-            "builtins.fetchGit {{ url = \"https://github.com/NixOS/nixpkgs\"; rev = \"{}\"; }}",
-            commit
-        )
-    };
-
     if let Some(ref local) = nixpkgs {
         println!("  {} Using local nixpkgs: {}", "📦".bright_cyan(), local.display());
     }
 
-    let mut nix_content = String::from("# Generated by nix-archiver\n");
-    nix_content.push_str("# This file pins packages to specific historical versions from Nixpkgs\n\n");
+    if let Some(ref output) = output {
+        let nixpkgs = nixpkgs
+            .as_ref()
+            .map(|local| local.canonicalize().unwrap_or_else(|_| local.clone()));
+        let nix_content = match format {
+            _ if pin_snapshot.is_some() => render(&resolved, &OutputFormat::Snapshot { nixpkgs }),
+            GenerateFormat::Frozen => render(&resolved, &OutputFormat::FrozenNix { nixpkgs }),
+            GenerateFormat::BuildInputs => render(&resolved, &OutputFormat::BuildInputs { nixpkgs }),
+            GenerateFormat::FetchFromGithub => render(&resolved, &OutputFormat::FetchFromGitHub),
+        };
 
-    // let-bindings for each unique nixpkgs snapshot
-    nix_content.push_str("let\n");
-    for commit in &unique_commits {
-        nix_content.push_str(&format!(
-            "  {} = {};\n",
-            nixpkgs_var(commit),
-            source_expr(commit)
-        ));
-    }
-    nix_content.push_str("in\n{\n");
+        fs::write(output, nix_content)
+            .with_context(|| format!("Failed to write to output file: {}", output.display()))?;
 
-    for entry in &packages {
-        nix_content.push_str(&format!(
-            "  # {} v{} (commit: {})\n",
-            entry.attr_name, entry.version, &entry.commit_sha
-        ));
-        nix_content.push_str(&format!(
-            "  {} = import {} {{}};\n\n",
-            entry.attr_name,
-            nixpkgs_var(&entry.commit_sha)
-        ));
+        println!(
+            "{} Successfully generated: {}",
+            "✓".green().bold(),
+            output.display().to_string().bold()
+        );
+        match format {
+            _ if pin_snapshot.is_some() => {
+                let path = output.display().to_string();
+                let path = if path.contains('/') { path } else { format!("./{}", path) };
+                println!("\n{} Usage:\n  pkgs = import {};", "💡".yellow(), path);
+            }
+            GenerateFormat::Frozen | GenerateFormat::FetchFromGithub => {
+                println!("\n{} Usage:\n  nix-shell {}", "💡".yellow(), output.display());
+            }
+            GenerateFormat::BuildInputs => {
+                // Nix path literals need a slash: `deps.nix` → `./deps.nix`
+                let path = output.display().to_string();
+                let path = if path.contains('/') { path } else { format!("./{}", path) };
+                println!(
+                    "\n{} Usage:\n  pkgs.mkShell {{ buildInputs = import {}; }}",
+                    "💡".yellow(),
+                    path
+                );
+            }
+        }
     }
 
-    nix_content.push_str("}\n");
-
-    let mut file = fs::File::create(&output)
-        .with_context(|| format!("Failed to create output file: {}", output.display()))?;
+    if let Some(ref dir) = output_dir {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create output directory: {}", dir.display()))?;
+
+        let flake_path = dir.join("flake.nix");
+        fs::write(&flake_path, render(&resolved, &OutputFormat::FlakeNix))
+            .with_context(|| format!("Failed to write {}", flake_path.display()))?;
+
+        let lock_path = dir.join("flake.lock");
+        fs::write(&lock_path, render(&resolved, &OutputFormat::FlakeLock))
+            .with_context(|| format!("Failed to write {}", lock_path.display()))?;
+
+        for commit in resolved.unique_commits() {
+            let locked = resolved
+                .tarball_hashes
+                .get(commit)
+                .and_then(|h| nix_hash_to_sri(h))
+                .is_some();
+            if !locked {
+                eprintln!(
+                    "{} No cached tarball hash for commit {} — input left unlocked in flake.lock",
+                    "⚠".yellow(),
+                    short_sha(commit, 12)
+                );
+            }
+        }
 
-    file.write_all(nix_content.as_bytes())
-        .with_context(|| format!("Failed to write to output file: {}", output.display()))?;
+        println!(
+            "{} Successfully generated: {} and {}",
+            "✓".green().bold(),
+            flake_path.display().to_string().bold(),
+            lock_path.display().to_string().bold()
+        );
+        println!("\n{} Usage:\n  nix build {}#<package>", "💡".yellow(), dir.display());
+    }
 
-    println!(
-        "{} Successfully generated: {}",
-        "✓".green().bold(),
-        output.display().to_string().bold()
-    );
-    println!("\n{} Usage:\n  nix-shell {}", "💡".yellow(), output.display());
+    Ok(())
+}
 
+/// Fails if writing `output` would destroy data: when it is the input spec
+/// (if any) itself (always), or an existing non-empty file unless `force` is set.
+fn check_output_path(input: Option<&Path>, output: &Path, force: bool) -> Result<()> {
+    let Ok(metadata) = std::fs::metadata(output) else {
+        return Ok(());
+    };
+    if let Some(input) = input {
+        if let (Ok(input_path), Ok(output_path)) = (input.canonicalize(), output.canonicalize()) {
+            if input_path == output_path {
+                anyhow::bail!(
+                    "Output {} is the same file as the input {}; refusing to overwrite the package spec",
+                    output.display(),
+                    input.display()
+                );
+            }
+        }
+    }
+    if metadata.len() > 0 && !force {
+        anyhow::bail!(
+            "Output file {} already exists; pass --force to overwrite it",
+            output.display()
+        );
+    }
     Ok(())
 }
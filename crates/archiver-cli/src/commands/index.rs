@@ -2,21 +2,43 @@
 
 use anyhow::{Context, Result};
 use archiver_db::ArchiverDb;
-use archiver_index::Indexer;
-use std::path::PathBuf;
+use archiver_core::{short_sha, IndexRun};
+use archiver_index::archive::NarArchive;
+use archiver_index::parsers::AttrMap;
+use archiver_index::remote::clone_or_fetch;
+use archiver_index::{IndexOptions, IndexStats, Indexer, PATH_FILTER, PLACEHOLDER_VERSIONS};
+use super::stats::format_size;
+use crate::IndexArgs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 /// Indexes Nixpkgs repository
-pub fn cmd_index(
-    repo_path: PathBuf,
-    from_commit: String,
-    to_commit: Option<String>,
-    to_date: Option<String>,
-    max_commits: Option<usize>,
-    full_repo: bool,
-    threads: Option<usize>,
-    batch_size: usize,
-    db: ArchiverDb,
-) -> Result<()> {
+pub fn cmd_index(args: IndexArgs, mut db: ArchiverDb) -> Result<()> {
+    let IndexArgs {
+        repo: repo_path, clone, clone_depth, from: from_commit, to_commit, to_date, max_commits, full_repo,
+        threads, batch_size, flush_every, flush_entries, allow_shallow, stats_json, head_only, io_concurrency,
+        attr_map, normalize_versions, record_refs, only, commit_path, skip_placeholder_versions,
+        placeholder_versions, estimate, archive_dir, first_parent, compact_after, max_file_size, no_nar_hash,
+        store_messages, append_history,
+    } = args;
+    let repo_path = match clone {
+        Some(url) => {
+            if clone_depth.is_some_and(|depth| depth < 1) {
+                anyhow::bail!("--clone-depth must be at least 1");
+            }
+            let dest = match repo_path {
+                Some(path) => path,
+                None => default_clone_dir(&url)?,
+            };
+            clone_or_fetch(&url, &dest, clone_depth)?;
+            dest
+        }
+        None => repo_path.context("--repo is required without --clone")?,
+    };
+    // A depth-limited clone is shallow by request
+    let allow_shallow = allow_shallow || clone_depth.is_some();
+
     // Configure Rayon thread pool if specified
     let num_threads = if let Some(num_threads) = threads {
         rayon::ThreadPoolBuilder::new()
@@ -32,31 +54,107 @@ pub fn cmd_index(
     log::info!("Starting indexing of repository at {:?}", repo_path);
     log::info!("Using {} threads for parallel processing", num_threads);
     log::info!("Batch size: {} commits", batch_size);
+    log::info!("Flushing every {} batch(es)", flush_every);
+    if let Some(limit) = flush_entries {
+        log::info!("Also flushing after {} inserted entries", limit);
+    }
 
-    let indexer = Indexer::new(&repo_path, db)
-        .context("Failed to create indexer")?;
+    if normalize_versions && !db.uses_normalized_keys() {
+        log::info!("Switching the database to normalized version keys");
+        let merged = db.enable_normalized_keys()?;
+        log::info!("Merged {} version variant(s) into existing entries", merged);
+    }
 
-    // If from_commit is "HEAD", resolve to concrete SHA
-    let from_sha = if from_commit == "HEAD" {
-        resolve_head(&repo_path)?
-    } else {
-        from_commit
+    let mut indexer = Indexer::new(&repo_path, db)
+        .context("Failed to create indexer")?;
+    if let Some(limit) = io_concurrency {
+        if limit == 0 {
+            anyhow::bail!("--io-concurrency must be at least 1");
+        }
+        log::info!("Limiting concurrent object reads to {}", limit);
+        indexer = indexer.with_io_concurrency(limit);
+    }
+    if let Some(map_path) = &attr_map {
+        let map = AttrMap::load(map_path)?;
+        log::info!("Loaded {} attr map rule(s) from {:?}", map.rules().len(), map_path);
+        indexer = indexer.with_attr_map(map);
+    }
+    if !only.is_empty() {
+        log::info!("Only inserting {} package(s): {}", only.len(), only.join(", "));
+        indexer = indexer.with_only(only.iter().cloned());
+    }
+    let skipped_versions: Vec<String> = match (skip_placeholder_versions, placeholder_versions.is_empty()) {
+        (false, _) => Vec::new(),
+        (true, true) => PLACEHOLDER_VERSIONS.iter().map(|v| v.to_string()).collect(),
+        (true, false) => placeholder_versions,
     };
+    if !skipped_versions.is_empty() {
+        log::info!("Skipping placeholder versions: {}", skipped_versions.join(", "));
+        indexer = indexer.with_skipped_versions(skipped_versions.iter().cloned());
+    }
+    if let Some(dir) = &archive_dir {
+        log::info!("Archiving package directories as NAR files in {:?}", dir);
+        indexer = indexer.with_archive(NarArchive::open(dir)?);
+    }
+    if max_file_size == 0 {
+        log::info!("Parsing files of any size");
+    }
+    indexer = indexer.with_max_blob_size((max_file_size > 0).then_some(max_file_size));
+    if no_nar_hash {
+        log::info!("Skipping NAR hashes of package directories");
+        indexer = indexer.with_nar_hashes(false);
+    }
+    indexer = indexer.with_commit_subjects(store_messages);
+    if append_history {
+        log::info!("Recording every commit each version appears in");
+        indexer = indexer.with_version_history(true);
+    }
+
+    ensure_full_history(&indexer, &repo_path, allow_shallow)?;
+
+    // Totals after the last finished batch, written out if the run fails
+    let last_progress = Arc::new(Mutex::new(IndexStats::default()));
+    if stats_json.is_some() {
+        let last_progress = Arc::clone(&last_progress);
+        indexer = indexer.on_progress(move |stats| *last_progress.lock().unwrap() = stats.clone());
+    }
+
+    // Resolve HEAD, short SHAs, tags and refs to full commit SHAs up front
+    let from_sha = indexer.resolve_commit(&from_commit)?;
+
+    // A checkout left on a release branch silently misses everything newer
+    if from_commit.trim() == "HEAD" {
+        match indexer.head_branch()? {
+            Some(branch) if is_release_branch(&branch) => {
+                log::warn!("HEAD is on release branch '{}'", branch);
+                log::warn!("   Only that branch's history will be indexed; packages added to master since it branched off will be missing");
+                log::warn!("   Check out master (or pass --from master) for a complete archive");
+            }
+            Some(branch) => log::info!("Indexing from branch '{}'", branch),
+            None => log::info!("Indexing from detached HEAD at {}", short_sha(&from_sha, 12)),
+        }
+    }
+    let to_commit = to_commit
+        .map(|rev| indexer.resolve_commit(&rev))
+        .transpose()?;
 
     // Calculate max_commits based on to_commit, to_date, or full_repo
-    let computed_max_commits = if full_repo {
+    let computed_max_commits = if head_only {
+        log::info!("Head-only mode: indexing packages at {} without history", short_sha(&from_sha, 8));
+        None
+    } else if full_repo {
         log::info!("Indexing entire repository history (no limit)");
         None
-    } else if let Some(to_date_str) = to_date {
+    } else if let Some(to_date_str) = to_date.clone() {
         log::info!("Indexing until date: {}", to_date_str);
         let to_sha = resolve_commit_by_date(&repo_path, &to_date_str)?;
-        let count = count_commits_between(&repo_path, &from_sha, &to_sha)?;
-        log::info!("Found {} commits between {} and date {}", count, &from_sha[..8], to_date_str);
+        let count = count_commits_between(&repo_path, &from_sha, &to_sha, first_parent)?;
+        log::info!("Found {} commits between {} and date {}", count, short_sha(&from_sha, 8), to_date_str);
         Some(count)
-    } else if let Some(to_sha) = to_commit {
-        log::info!("Indexing until commit: {}", &to_sha[..12]);
-        let count = count_commits_between(&repo_path, &from_sha, &to_sha)?;
-        log::info!("Found {} commits between {} and {}", count, &from_sha[..8], &to_sha[..8]);
+    } else if let Some(to_sha) = to_commit.clone() {
+        log::info!("Indexing until commit: {}", short_sha(&to_sha, 12));
+        let count = count_commits_between(&repo_path, &from_sha, &to_sha, first_parent)?;
+        log::info!("Found {} commits between {} and {}", count, short_sha(&from_sha, 8), short_sha(&to_sha, 8));
         Some(count)
     } else {
         max_commits
@@ -65,21 +163,173 @@ pub fn cmd_index(
     if let Some(max) = computed_max_commits {
         log::info!("Max commits: {}", max);
     }
+    if first_parent {
+        log::info!("Following first parents only (merged side branches are skipped)");
+    }
+
+    // Counted once up front; commits indexed by earlier runs are skipped
+    // quickly, so they don't count towards the work left
+    let estimated_commits = if estimate && computed_max_commits.is_none() && !head_only {
+        let total = count_commits(&repo_path, &from_sha, first_parent)?;
+        let remaining = total.saturating_sub(indexer.db().processed_commit_count());
+        log::info!("Found {} commits reachable from {} ({} not yet indexed)",
+            total, short_sha(&from_sha, 8), remaining);
+        Some(remaining)
+    } else {
+        None
+    };
 
-    let _stats = indexer.index_from_commit(&from_sha, computed_max_commits, batch_size)
-        .context("Failed to index repository")?;
+    // Record how this database is being built before any entries are written
+    let run = IndexRun {
+        started_at: chrono::Utc::now().timestamp() as u64,
+        tool_version: env!("CARGO_PKG_VERSION").to_string(),
+        command_line: std::env::args().collect(),
+        repo_path: repo_path.display().to_string(),
+        repo_url: origin_url(&repo_path),
+        from_commit: from_sha.clone(),
+        to_commit,
+        to_date,
+        max_commits: computed_max_commits,
+        head_only,
+        version_regex: indexer.version_pattern().to_string(),
+        path_filter: PATH_FILTER.to_string(),
+        attr_map: attr_map.map(|p| p.display().to_string()),
+        only,
+        commit_path: commit_path.clone(),
+        skipped_versions,
+        first_parent,
+    };
+    indexer.db().record_index_run(&run)?;
+
+    let options = IndexOptions {
+        max_commits: computed_max_commits,
+        batch_size,
+        flush_every,
+        flush_entries,
+        head_only,
+        record_refs,
+        commit_path,
+        estimated_commits,
+        first_parent,
+        hide: None,
+    };
+    let start_time = Instant::now();
+    let stats = match indexer.index_from_commit(&from_sha, &options) {
+        Ok(stats) => stats,
+        Err(e) => {
+            if let Some(path) = &stats_json {
+                let partial = IndexStats { elapsed_time: start_time.elapsed(), ..last_progress.lock().unwrap().clone() };
+                match write_stats_json(path, &partial, false) {
+                    Ok(()) => log::info!("Statistics up to the failure written to {:?}", path),
+                    Err(write_err) => log::warn!("{:#}", write_err),
+                }
+            }
+            return Err(e).context("Failed to index repository");
+        }
+    };
 
     // Final stats are already logged by the indexer
+    if let Some(path) = stats_json {
+        write_stats_json(&path, &stats, true)?;
+        log::info!("Statistics written to {:?}", path);
+    }
+
+    if compact_after {
+        let db = indexer.into_db().context("Database still in use after indexing")?;
+        log::info!("Compacting database...");
+        let (before, after) = db.compact()?;
+        log::info!(
+            "Compacted database: {} -> {} ({} reclaimed)",
+            format_size(before),
+            format_size(after),
+            format_size(before.saturating_sub(after))
+        );
+    }
+
     Ok(())
 }
 
-/// Resolves HEAD to concrete commit SHA
-fn resolve_head(repo_path: &PathBuf) -> Result<String> {
-    use git2::Repository;
-    let repo = Repository::open(repo_path)?;
-    let head = repo.head()?;
-    let commit = head.peel_to_commit()?;
-    Ok(commit.id().to_string())
+/// Refuses a shallow clone unless `allow_shallow`: its truncated history
+/// would silently leave older versions out of the database
+pub(super) fn ensure_full_history(indexer: &Indexer, repo_path: &Path, allow_shallow: bool) -> Result<()> {
+    if indexer.is_shallow() && !allow_shallow {
+        anyhow::bail!(
+            "Repository at {:?} is a shallow clone, so older history cannot be indexed. \
+             Run `git fetch --unshallow` or pass --allow-shallow to index the truncated history anyway",
+            repo_path
+        );
+    }
+    Ok(())
+}
+
+/// Release and stable channel branches (`release-24.05`, `nixos-24.05`,
+/// `nixpkgs-24.05-darwin`), whose history stops following master
+fn is_release_branch(branch: &str) -> bool {
+    let name = branch.rsplit('/').next().unwrap_or(branch);
+    ["release-", "nixos-", "nixpkgs-"].iter().any(|prefix| {
+        name.strip_prefix(prefix)
+            .is_some_and(|rest| rest.starts_with(|c: char| c.is_ascii_digit()))
+    })
+}
+
+/// Writes statistics plus derived throughput as JSON; `completed` is false
+/// for the totals of a run that failed part-way
+fn write_stats_json(path: &PathBuf, stats: &IndexStats, completed: bool) -> Result<()> {
+    let payload = serde_json::json!({
+        "completed": completed,
+        "processed": stats.processed,
+        "skipped": stats.skipped,
+        "errors": stats.errors,
+        "packages_found": stats.packages_found,
+        "packages_inserted": stats.packages_inserted,
+        "packages_updated": stats.packages_updated,
+        "ast_files": stats.ast_files,
+        "regex_files": stats.regex_files,
+        "trees_archived": stats.trees_archived,
+        "head_scan": stats.head_scan.as_ref().map(|parse| serde_json::json!({
+            "files": parse.files,
+            "with_packages": parse.with_packages(),
+            "ast_multi": parse.ast_multi,
+            "ast_marketplace": parse.ast_marketplace,
+            "ast_single": parse.ast_single,
+            "regex": parse.regex,
+            "none": parse.none,
+        })),
+        "elapsed_secs": stats.elapsed_time.as_secs_f64(),
+        "commits_per_sec": stats.commits_per_sec(),
+        "packages_per_sec": stats.packages_per_sec(),
+    });
+    let json = serde_json::to_string_pretty(&payload).context("Failed to serialize statistics")?;
+    std::fs::write(path, json + "\n")
+        .with_context(|| format!("Failed to write statistics to {:?}", path))
+}
+
+/// Cache location for `--clone URL` without `--repo`:
+/// `$XDG_CACHE_HOME/nix-archiver/repos/<url>.git` (falls back to `~/.cache`)
+pub(super) fn default_clone_dir(url: &str) -> Result<PathBuf> {
+    let cache = std::env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .context("Cannot locate a cache directory (set XDG_CACHE_HOME or HOME), or pass --repo")?;
+
+    // github.com/NixOS/nixpkgs.git -> github.com_NixOS_nixpkgs.git
+    let name: String = url
+        .split_once("://")
+        .map_or(url, |(_, rest)| rest)
+        .trim_end_matches('/')
+        .trim_end_matches(".git")
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '.' | '-') { c } else { '_' })
+        .collect();
+    Ok(cache.join("nix-archiver").join("repos").join(format!("{}.git", name)))
+}
+
+/// Returns the URL of the `origin` remote, if the repository has one
+pub(super) fn origin_url(repo_path: &PathBuf) -> Option<String> {
+    let repo = git2::Repository::open(repo_path).ok()?;
+    let remote = repo.find_remote("origin").ok()?;
+    remote.url().map(str::to_string)
 }
 
 /// Resolves commit SHA by date using git log --until
@@ -88,7 +338,7 @@ fn resolve_commit_by_date(repo_path: &PathBuf, date: &str) -> Result<String> {
         .arg("-C")
         .arg(repo_path)
         .arg("log")
-        .arg(&format!("--until={}", date))
+        .arg(format!("--until={}", date))
         .arg("--format=%H")
         .arg("-1")
         .output()
@@ -106,14 +356,26 @@ fn resolve_commit_by_date(repo_path: &PathBuf, date: &str) -> Result<String> {
     Ok(commit_sha)
 }
 
-/// Counts commits between two commits (from..to)
-fn count_commits_between(repo_path: &PathBuf, from_sha: &str, to_sha: &str) -> Result<usize> {
-    let output = std::process::Command::new("git")
-        .arg("-C")
-        .arg(repo_path)
-        .arg("rev-list")
-        .arg("--count")
-        .arg(&format!("{}..{}", to_sha, from_sha))  // Reverse: to..from to count forward
+/// Counts commits between two commits (from..to); with `first_parent`,
+/// only those a `--first-parent` walk visits
+pub(super) fn count_commits_between(repo_path: &PathBuf, from_sha: &str, to_sha: &str, first_parent: bool) -> Result<usize> {
+    // Reverse: to..from to count forward
+    rev_list_count(repo_path, &format!("{}..{}", to_sha, from_sha), first_parent)
+}
+
+/// Counts all commits reachable from `from_sha` (the length of a full walk)
+fn count_commits(repo_path: &PathBuf, from_sha: &str, first_parent: bool) -> Result<usize> {
+    rev_list_count(repo_path, from_sha, first_parent)
+}
+
+fn rev_list_count(repo_path: &PathBuf, range: &str, first_parent: bool) -> Result<usize> {
+    let mut command = std::process::Command::new("git");
+    command.arg("-C").arg(repo_path).arg("rev-list").arg("--count");
+    if first_parent {
+        command.arg("--first-parent");
+    }
+    let output = command
+        .arg(range)
         .output()
         .context("Failed to run git rev-list")?;
 
@@ -0,0 +1,66 @@
+//! Missing-hashes command implementation
+
+use anyhow::Result;
+use archiver_cli::helpers::{format_relative_time, format_timestamp};
+use archiver_db::ArchiverDb;
+use colored::Colorize;
+
+use super::prefetch::{commits_missing_hash, hash_kind, FAILURE_COOLDOWN_SECS};
+use super::PrefetchOrder;
+
+/// Counts the indexed commits that `prefetch-hashes` would still fetch and
+/// lists up to `limit` of them with their dates, without fetching anything.
+/// Commits whose last prefetch failed are counted separately from pending
+/// ones and listed with their last error.
+pub fn cmd_missing_hashes(limit: usize, order: PrefetchOrder, git_narhash: bool, db: ArchiverDb) -> Result<()> {
+    let kind = hash_kind(git_narhash);
+    let total = db.all_unique_commits_with_timestamps()?.len();
+    let missing = commits_missing_hash(&db, git_narhash, order)?;
+
+    if missing.is_empty() {
+        println!("{} All {} indexed commit(s) have a {}", "✓".green(), total, kind);
+        return Ok(());
+    }
+
+    let now = chrono::Utc::now().timestamp() as u64;
+    let failed = missing.iter().filter(|m| m.failure.is_some()).count();
+    let skipped = missing.iter().filter(|m| m.failed_recently(now)).count();
+    println!(
+        "{} {} of {} indexed commit(s) have no {}: {} pending, {} failed",
+        "📭".bright_cyan(),
+        missing.len().to_string().bold(),
+        total,
+        kind,
+        (missing.len() - failed).to_string().bold(),
+        failed.to_string().bold()
+    );
+    if skipped > 0 {
+        println!(
+            "  {} {} failed in the last {} days and will be skipped without --retry-failed",
+            "·".dimmed(),
+            skipped,
+            FAILURE_COOLDOWN_SECS / (24 * 60 * 60)
+        );
+    }
+    for missing in missing.iter().take(limit) {
+        println!(
+            "  {}  {} {}",
+            missing.commit.bright_white(),
+            format_timestamp(missing.timestamp),
+            format!("({})", format_relative_time(missing.timestamp)).dimmed()
+        );
+        if let Some(failure) = &missing.failure {
+            println!(
+                "      {} failed {}× (last {}): {}",
+                "✗".red(),
+                failure.failures,
+                format_relative_time(failure.failed_at),
+                failure.error.dimmed()
+            );
+        }
+    }
+    if missing.len() > limit {
+        println!("  {} more (raise --limit to list them)", format!("… {}", missing.len() - limit).dimmed());
+    }
+    Ok(())
+}
@@ -4,8 +4,38 @@ mod index;
 mod search;
 mod generate;
 mod stats;
+mod prefetch;
+mod export;
+mod debug;
+mod provenance;
+mod doctor;
+mod reparse;
+mod provides;
+mod watch;
+mod at_date;
+mod top;
+mod db_diff;
+mod parse;
+mod missing_hashes;
+mod names;
+mod rekey;
 
 pub use index::cmd_index;
-pub use search::cmd_search;
-pub use generate::cmd_generate;
+pub use search::{cmd_search, cmd_search_description, SearchFormat, SearchSort};
+pub use generate::{cmd_generate, GenerateFormat};
 pub use stats::cmd_stats;
+pub use prefetch::{cmd_prefetch_hashes, PrefetchOrder};
+pub use export::{cmd_export, cmd_export_package, cmd_import};
+pub use debug::cmd_debug;
+pub use provenance::cmd_provenance;
+pub use doctor::cmd_doctor;
+pub use reparse::cmd_reparse;
+pub use provides::cmd_provides;
+pub use watch::cmd_watch;
+pub use at_date::cmd_at_date;
+pub use top::cmd_top;
+pub use db_diff::cmd_db_diff;
+pub use parse::cmd_parse;
+pub use missing_hashes::cmd_missing_hashes;
+pub use names::cmd_names;
+pub use rekey::cmd_rekey;
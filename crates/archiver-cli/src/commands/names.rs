@@ -0,0 +1,37 @@
+//! Names command implementation
+
+use anyhow::{Context, Result};
+use archiver_db::ArchiverDb;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+/// Writes every distinct attr_name, one per line or as a JSON array, for
+/// fuzzy finders and shell completion
+pub fn cmd_names(output: Option<PathBuf>, json: bool, db: ArchiverDb) -> Result<()> {
+    let names = db.package_names()?;
+
+    let out: Box<dyn Write> = match &output {
+        Some(path) => Box::new(
+            std::fs::File::create(path).with_context(|| format!("Failed to create {:?}", path))?,
+        ),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    let mut out = BufWriter::new(out);
+    let written = (|| -> std::io::Result<()> {
+        if json {
+            serde_json::to_writer(&mut out, &names)?;
+            writeln!(out)?;
+        } else {
+            for name in &names {
+                writeln!(out, "{}", name)?;
+            }
+        }
+        out.flush()
+    })();
+
+    match written {
+        // The reader (e.g. `head`) stopped early; that's not a failure
+        Err(e) if output.is_none() && e.kind() == std::io::ErrorKind::BrokenPipe => Ok(()),
+        result => result.context("Failed to write package names"),
+    }
+}
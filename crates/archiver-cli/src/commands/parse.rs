@@ -0,0 +1,97 @@
+//! Parse command implementation
+
+use anyhow::{Context, Result};
+use archiver_index::parsers::{extract_packages_from_file_with, AttrMap, ParseStrategy};
+use archiver_index::VERSION_PATTERN;
+use colored::Colorize;
+use regex::Regex;
+use std::path::{Path, PathBuf};
+use tabled::{Table, settings::{Style, Color, Modify, object::Rows}};
+use crate::output::ParsedRow;
+
+fn strategy_name(strategy: ParseStrategy) -> &'static str {
+    match strategy {
+        ParseStrategy::Ast => "ast",
+        ParseStrategy::Regex => "regex",
+        ParseStrategy::None => "none",
+    }
+}
+
+/// Shows what indexing would extract from a local `.nix` file, without a
+/// repository or database.
+///
+/// `path` is the repo-relative path the file is parsed as (it drives the
+/// attr name heuristic); it defaults to `file` as given. Sibling files such
+/// as `Cargo.toml` are read from the file's directory on disk.
+pub fn cmd_parse(file: PathBuf, path: Option<String>, json: bool, attr_map: Option<PathBuf>) -> Result<()> {
+    let content = std::fs::read_to_string(&file)
+        .with_context(|| format!("Failed to read {:?}", file))?;
+    let path = path.unwrap_or_else(|| file.display().to_string());
+    let path = path.trim_start_matches("./");
+    let attr_map = match attr_map {
+        Some(map_path) => AttrMap::load(map_path)?,
+        None => AttrMap::default(),
+    };
+    let version_regex = Regex::new(VERSION_PATTERN).context("Failed to compile version regex")?;
+
+    // The parser asks for siblings by repo-relative path; map them back
+    // next to the file on disk
+    let logical_dir = path.rsplit_once('/').map_or("", |(dir, _)| dir);
+    let disk_dir = file.parent().unwrap_or(Path::new("")).to_path_buf();
+    let read_sibling = |rel: &str| -> Option<String> {
+        let rest = if logical_dir.is_empty() {
+            rel
+        } else {
+            rel.strip_prefix(logical_dir)?.strip_prefix('/')?
+        };
+        std::fs::read_to_string(disk_dir.join(rest)).ok()
+    };
+
+    let outcome = extract_packages_from_file_with(path, &content, &version_regex, &attr_map, &read_sibling);
+
+    if json {
+        let packages: Vec<_> = outcome
+            .packages
+            .iter()
+            .map(|p| serde_json::json!({
+                "attr_name": p.attr_name,
+                "version": p.version,
+                "main_program": p.main_program,
+                "description": p.description,
+                "source_rev": p.source_rev,
+                "platform": p.platform,
+                "source_hash": p.source_hash,
+            }))
+            .collect();
+        let payload = serde_json::json!({
+            "path": path,
+            "strategy": strategy_name(outcome.strategy),
+            "packages": packages,
+        });
+        println!("{}", serde_json::to_string_pretty(&payload)?);
+        return Ok(());
+    }
+
+    if outcome.packages.is_empty() {
+        println!("{} Nothing would be indexed from {}", "❌".red(), path.bold());
+        return Ok(());
+    }
+
+    println!("\n{} {} ({} parser)", "📄".bright_cyan(), path.bold(), strategy_name(outcome.strategy).bold());
+    let rows: Vec<ParsedRow> = outcome
+        .packages
+        .into_iter()
+        .map(|p| ParsedRow {
+            attr_name: p.attr_name,
+            version: p.version,
+            main_program: p.main_program.unwrap_or_default(),
+            platform: p.platform.unwrap_or_default(),
+        })
+        .collect();
+    let mut table = Table::new(rows);
+    table.with(Style::rounded())
+        .with(Modify::new(Rows::first()).with(Color::FG_BRIGHT_CYAN));
+    println!("{}", table);
+
+    Ok(())
+}
@@ -0,0 +1,296 @@
+//! Prefetch-hashes command implementation
+
+use anyhow::{Context, Result};
+use archiver_cli::prefetch::prefetch_all;
+use archiver_core::hash::normalize_sha256;
+use archiver_core::{short_sha, Upstream};
+use archiver_db::{ArchiverDb, PrefetchFailure};
+use colored::Colorize;
+
+/// Which commits `prefetch-hashes` fetches first when limited
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum PrefetchOrder {
+    /// Most recently committed first (most likely to be pinned)
+    Newest,
+    /// Oldest commits first
+    Oldest,
+}
+
+/// What `git_narhash` selects, for messages
+pub(super) fn hash_kind(git_narhash: bool) -> &'static str {
+    if git_narhash { "fetchGit narHash" } else { "tarball hash" }
+}
+
+/// How long a commit whose prefetch failed is skipped by later runs
+/// (unless `--retry-failed`): a week, so commits GitHub no longer serves
+/// aren't retried on every run but transient outages heal by themselves
+pub(super) const FAILURE_COOLDOWN_SECS: u64 = 7 * 24 * 60 * 60;
+
+/// An indexed commit without a cached hash
+pub(super) struct MissingCommit {
+    pub commit: String,
+    pub timestamp: u64,
+    /// Set if the last prefetch of this commit failed
+    pub failure: Option<PrefetchFailure>,
+}
+
+impl MissingCommit {
+    /// Whether the last prefetch failed less than [`FAILURE_COOLDOWN_SECS`]
+    /// before `now`
+    pub fn failed_recently(&self, now: u64) -> bool {
+        self.failure
+            .as_ref()
+            .is_some_and(|f| now.saturating_sub(f.failed_at) < FAILURE_COOLDOWN_SECS)
+    }
+}
+
+/// Indexed commits that have no cached tarball hash, or no fetchGit
+/// narHash with `git_narhash`, in `order`, with any recorded failure
+pub(super) fn commits_missing_hash(db: &ArchiverDb, git_narhash: bool, order: PrefetchOrder) -> Result<Vec<MissingCommit>> {
+    let mut missing = Vec::new();
+    for (commit, timestamp) in db.all_unique_commits_with_timestamps()? {
+        let cached = if git_narhash {
+            db.get_git_narhash(&commit)?
+        } else {
+            db.get_tarball_hash(&commit)?
+        };
+        if cached.is_none() {
+            let failure = db.prefetch_failure(&commit, git_narhash)?;
+            missing.push(MissingCommit { commit, timestamp, failure });
+        }
+    }
+
+    // all_unique_commits_with_timestamps() is sorted oldest first
+    if order == PrefetchOrder::Newest {
+        missing.reverse();
+    }
+    Ok(missing)
+}
+
+/// Fetches and stores nixpkgs tarball hashes for indexed commits that lack one.
+///
+/// Each hash is obtained with `nix-prefetch-url --unpack` and stored in the
+/// database, so `generate` can emit fully pinned `fetchTarball` expressions.
+///
+/// With `git_narhash`, the `builtins.fetchGit` narHash is prefetched instead
+/// (`nix flake prefetch` of the git checkout), which pins the `fetchGit`
+/// fallback of `generate`.
+///
+/// Up to `jobs` commits are fetched at once; fewer while the server reports
+/// rate limiting (see [`archiver_cli::prefetch`]).
+///
+/// Failed commits are recorded in the database and skipped for
+/// [`FAILURE_COOLDOWN_SECS`] unless `retry_failed`. Every fetched hash is
+/// flushed right away, so an interrupted run loses no finished work.
+pub fn cmd_prefetch_hashes(
+    limit: Option<usize>,
+    order: PrefetchOrder,
+    git_narhash: bool,
+    jobs: usize,
+    retry_failed: bool,
+    upstream: &Upstream,
+    db: ArchiverDb,
+) -> Result<()> {
+    if jobs == 0 {
+        anyhow::bail!("--jobs must be at least 1");
+    }
+    let kind = hash_kind(git_narhash);
+    let now = chrono::Utc::now().timestamp() as u64;
+    let (mut to_fetch, skipped): (Vec<_>, Vec<_>) = commits_missing_hash(&db, git_narhash, order)?
+        .into_iter()
+        .partition(|missing| retry_failed || !missing.failed_recently(now));
+
+    let missing = to_fetch.len();
+    if let Some(limit) = limit {
+        to_fetch.truncate(limit);
+    }
+
+    if !skipped.is_empty() {
+        println!(
+            "{} Skipping {} commit(s) that failed in the last {} days (--retry-failed to try them again)",
+            "⏭".yellow(),
+            skipped.len(),
+            FAILURE_COOLDOWN_SECS / (24 * 60 * 60)
+        );
+    }
+    if to_fetch.is_empty() {
+        if skipped.is_empty() {
+            println!("{} All indexed commits already have a {}", "✓".green(), kind);
+        } else {
+            println!("{} No other indexed commit is missing a {}", "✓".green(), kind);
+        }
+        return Ok(());
+    }
+
+    // Checked once up front: a missing binary would otherwise fail every commit
+    require_tool(if git_narhash { "nix" } else { "nix-prefetch-url" })?;
+
+    println!(
+        "{} Prefetching {} of {} commit(s) missing a {} ({} at a time)...",
+        "📥".bright_cyan(),
+        to_fetch.len(),
+        missing,
+        kind,
+        jobs.min(to_fetch.len())
+    );
+
+    let total = to_fetch.len();
+    let mut done = 0;
+    let mut fetched = 0;
+    let mut failed = 0;
+    let mut retried = 0;
+    let mut store_error = None;
+
+    let commits = to_fetch.into_iter().map(|missing| missing.commit).collect();
+    let fetch = |commit: &str| {
+        if git_narhash {
+            prefetch_git_narhash(upstream, commit)
+        } else {
+            prefetch_tarball_hash(upstream, commit)
+        }
+    };
+    prefetch_all(commits, jobs, fetch, |outcome| {
+        done += 1;
+        if outcome.attempts > 1 {
+            retried += 1;
+        }
+        let commit = &outcome.commit;
+        match outcome.result {
+            Ok(hash) => {
+                // Flushed per hash: fetches take seconds, and an interrupted
+                // run must not fetch these again
+                let stored = if git_narhash {
+                    db.store_git_narhash(commit, &hash)
+                } else {
+                    db.store_tarball_hash(commit, &hash)
+                }
+                .and_then(|_| db.clear_prefetch_failure(commit, git_narhash))
+                .and_then(|_| db.flush());
+                if let Err(e) = stored {
+                    store_error.get_or_insert(e);
+                    return;
+                }
+                fetched += 1;
+                println!(
+                    "  {} [{}/{}] {} → {}",
+                    "✓".green(),
+                    done,
+                    total,
+                    short_sha(commit, 12),
+                    hash.dimmed()
+                );
+            }
+            Err(e) => {
+                failed += 1;
+                let failed_at = chrono::Utc::now().timestamp() as u64;
+                let recorded = db
+                    .record_prefetch_failure(commit, git_narhash, failed_at, &format!("{:#}", e))
+                    .and_then(|_| db.flush());
+                if let Err(e) = recorded {
+                    store_error.get_or_insert(e);
+                }
+                eprintln!(
+                    "  {} [{}/{}] {}: {:#}{}",
+                    "✗".red(),
+                    done,
+                    total,
+                    short_sha(commit, 12),
+                    e,
+                    if outcome.attempts > 1 { format!(" (after {} attempts)", outcome.attempts) } else { String::new() }
+                );
+            }
+        }
+    });
+
+    db.flush()?;
+    if let Some(e) = store_error {
+        return Err(e).context("Failed to store a prefetch result");
+    }
+
+    println!(
+        "\n{} Prefetched {} hash(es), {} failed, {} needed retries after rate limiting",
+        "📊".bright_cyan(),
+        fetched.to_string().bold(),
+        failed.to_string().bold(),
+        retried.to_string().bold()
+    );
+    let pending = missing - fetched - failed;
+    if pending > 0 {
+        println!("{} {} commit(s) still pending; rerun to continue", "·".dimmed(), pending);
+    }
+
+    Ok(())
+}
+
+/// Fails with installation guidance if `program` can't be run at all.
+///
+/// Only a missing (or non-executable) binary is an error here; a tool that
+/// starts but exits non-zero is left to the per-commit error reporting.
+fn require_tool(program: &str) -> Result<()> {
+    match std::process::Command::new(program)
+        .arg("--version")
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status()
+    {
+        Ok(_) => Ok(()),
+        Err(e) => anyhow::bail!(
+            "`{}` is not available ({}). prefetch-hashes needs Nix: install it from \
+             https://nixos.org/download and make sure `{}` is on PATH. \
+             All other commands work without Nix.",
+            program,
+            e,
+            program
+        ),
+    }
+}
+
+/// Runs `nix-prefetch-url --unpack` for the nixpkgs tarball of a commit and
+/// returns the printed sha256.
+///
+/// The URL comes from the same [`Upstream`] `generate` writes into
+/// `fetchTarball`, so the cached hash always matches the generated URL.
+fn prefetch_tarball_hash(upstream: &Upstream, commit: &str) -> Result<String> {
+    let url = upstream.tarball_url(commit);
+    let output = std::process::Command::new("nix-prefetch-url")
+        .arg("--unpack")
+        .arg(&url)
+        .output()
+        .context("Failed to run nix-prefetch-url")?;
+
+    if !output.status.success() {
+        anyhow::bail!("nix-prefetch-url failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let hash = stdout.lines().last().unwrap_or("").trim().to_string();
+    if hash.is_empty() {
+        anyhow::bail!("nix-prefetch-url returned no hash for {}", url);
+    }
+
+    Ok(hash)
+}
+
+/// Runs `nix flake prefetch` on the upstream git repository at a commit and
+/// returns the narHash `builtins.fetchGit { url; rev; }` will verify.
+///
+/// This hashes the git checkout, not the GitHub tarball, so it generally
+/// differs from [`prefetch_tarball_hash`] for the same commit.
+fn prefetch_git_narhash(upstream: &Upstream, commit: &str) -> Result<String> {
+    let flake_ref = format!("git+{}?rev={}&shallow=1", upstream.git_url(), commit);
+    let output = std::process::Command::new("nix")
+        .args(["--extra-experimental-features", "nix-command flakes", "flake", "prefetch", "--json"])
+        .arg(&flake_ref)
+        .output()
+        .context("Failed to run nix flake prefetch")?;
+
+    if !output.status.success() {
+        anyhow::bail!("nix flake prefetch failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout)
+        .context("nix flake prefetch printed invalid JSON")?;
+    let hash = json["hash"].as_str().unwrap_or("");
+    normalize_sha256(hash)
+        .with_context(|| format!("nix flake prefetch returned no usable hash for {}", flake_ref))
+}
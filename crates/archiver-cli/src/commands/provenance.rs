@@ -0,0 +1,62 @@
+//! Provenance command implementation
+
+use anyhow::Result;
+use archiver_cli::helpers::format_timestamp;
+use archiver_db::ArchiverDb;
+use colored::Colorize;
+
+/// Shows how the database was built: one record per `index` run
+pub fn cmd_provenance(db: ArchiverDb) -> Result<()> {
+    let runs = db.index_runs()?;
+    if runs.is_empty() {
+        println!("{} No index runs recorded in this database", "❌".red());
+        return Ok(());
+    }
+
+    println!("{} {} index run(s), oldest first", "📜".bright_cyan(), runs.len().to_string().bold());
+
+    for (i, run) in runs.iter().enumerate() {
+        println!("\n{} {}", format!("#{}", i + 1).bright_cyan().bold(), format_timestamp(run.started_at).bold());
+        println!("{}", "━".repeat(60).bright_black());
+        println!("  {}       {}", "Tool:".bright_yellow(), run.tool_version);
+        println!("  {}    {}", "Command:".bright_yellow(), run.command_line.join(" "));
+        println!("  {} {}", "Repository:".bright_yellow(), run.repo_path);
+        if let Some(url) = &run.repo_url {
+            println!("  {}     {}", "Origin:".bright_yellow(), url);
+        }
+        println!("  {}       {}", "From:".bright_yellow(), run.from_commit);
+
+        let range = if run.head_only {
+            "head only (no history)".to_string()
+        } else if let Some(to) = &run.to_commit {
+            format!("until commit {}", to)
+        } else if let Some(date) = &run.to_date {
+            format!("until {}", date)
+        } else if let Some(max) = run.max_commits {
+            format!("at most {} commits", max)
+        } else {
+            "entire history".to_string()
+        };
+        if run.first_parent {
+            println!("  {}      {} (first parents only)", "Range:".bright_yellow(), range);
+        } else {
+            println!("  {}      {}", "Range:".bright_yellow(), range);
+        }
+        println!("  {}      {}", "Files:".bright_yellow(), run.path_filter);
+        println!("  {}      {}", "Regex:".bright_yellow(), run.version_regex);
+        if let Some(map) = &run.attr_map {
+            println!("  {}   {}", "Attr map:".bright_yellow(), map);
+        }
+        if !run.only.is_empty() {
+            println!("  {}       {}", "Only:".bright_yellow(), run.only.join(", "));
+        }
+        if let Some(path) = &run.commit_path {
+            println!("  {}    touching {}", "Commits:".bright_yellow(), path);
+        }
+        if !run.skipped_versions.is_empty() {
+            println!("  {}    {}", "Skipped:".bright_yellow(), run.skipped_versions.join(", "));
+        }
+    }
+
+    Ok(())
+}
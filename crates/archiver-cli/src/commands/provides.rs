@@ -0,0 +1,45 @@
+//! Provides command implementation
+
+use anyhow::Result;
+use archiver_cli::helpers::{format_relative_time, sort_versions_semver};
+use archiver_db::ArchiverDb;
+use colored::Colorize;
+use std::collections::BTreeMap;
+use tabled::{Table, settings::{Style, Color, Modify, object::Rows}};
+use crate::output::PackageSummaryRow;
+
+/// Lists packages whose `meta.mainProgram` is `program`
+pub fn cmd_provides(program: String, db: ArchiverDb) -> Result<()> {
+    let entries = db.find_by_main_program(&program)?;
+    if entries.is_empty() {
+        println!("{} No indexed package provides '{}'", "❌".red(), program.bold());
+        return Ok(());
+    }
+
+    let mut by_package: BTreeMap<String, Vec<_>> = BTreeMap::new();
+    for entry in entries {
+        by_package.entry(entry.attr_name.clone()).or_default().push(entry);
+    }
+
+    let rows: Vec<PackageSummaryRow> = by_package
+        .into_values()
+        .map(|versions| {
+            let count = versions.len();
+            let latest = sort_versions_semver(versions).remove(0);
+            PackageSummaryRow {
+                attr_name: latest.attr_name,
+                version_count: count.to_string(),
+                latest_version: latest.version,
+                latest_date: format_relative_time(latest.timestamp),
+            }
+        })
+        .collect();
+
+    println!("\n{} {}", "🔧 Provides:".bright_cyan(), program.bold());
+    let mut table = Table::new(rows);
+    table.with(Style::rounded())
+        .with(Modify::new(Rows::first()).with(Color::FG_BRIGHT_CYAN));
+    println!("{}", table);
+
+    Ok(())
+}
@@ -0,0 +1,76 @@
+//! Rekey command implementation
+
+use anyhow::Result;
+use archiver_core::PackageEntry;
+use archiver_db::ArchiverDb;
+use archiver_index::parsers::AttrMap;
+use colored::Colorize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// Re-derives path-based attr_names with the current heuristic (and attr
+/// map), renaming stored entries whose name changed. Entries named by
+/// their file's contents (`pname`, bindings) are never touched.
+pub fn cmd_rekey(attr_map: Option<PathBuf>, dry_run: bool, yes: bool, db: ArchiverDb) -> Result<()> {
+    let attr_map = match attr_map {
+        Some(map_path) => AttrMap::load(map_path)?,
+        None => AttrMap::default(),
+    };
+    let rename = |entry: &PackageEntry| -> Option<String> {
+        if !entry.attr_from_path {
+            return None;
+        }
+        attr_map.attr_name_for(entry.source_path.as_deref()?)
+    };
+
+    // Old name → new name, with the number of entries that move
+    let mut changes: BTreeMap<(String, String), usize> = BTreeMap::new();
+    let mut without_path = 0;
+    for entry in db.iter_entries() {
+        let entry = entry?;
+        if entry.source_path.is_none() {
+            without_path += 1;
+            continue;
+        }
+        if let Some(name) = rename(&entry).filter(|name| *name != entry.attr_name) {
+            *changes.entry((entry.attr_name, name)).or_default() += 1;
+        }
+    }
+
+    if without_path > 0 {
+        println!(
+            "{} {} entries were indexed before source paths were recorded and can't be rekeyed; reindex to update them",
+            "💡".yellow(),
+            without_path
+        );
+    }
+    if changes.is_empty() {
+        println!("{} Every path-derived attr name is current", "✓".green().bold());
+        return Ok(());
+    }
+
+    println!("\n{} Attr names to change:", "🔑".bright_cyan());
+    for ((old, new), count) in &changes {
+        println!("  {} → {} ({} entries)", old, new.bold(), count);
+    }
+    if dry_run {
+        println!("\n{} Dry run: nothing was changed", "·".dimmed());
+        return Ok(());
+    }
+    if !yes {
+        anyhow::bail!(
+            "rekey rewrites keys in place and merges entries that now share a key; this can't be undone. \
+             Back up the database first (e.g. `nix-archiver export -o backup.jsonl.gz`), then rerun with --yes"
+        );
+    }
+
+    let (renamed, merged) = db.rename_entries(rename)?;
+    db.flush()?;
+    println!(
+        "\n{} Renamed {} entries ({} merged into existing versions)",
+        "✓".green().bold(),
+        renamed.to_string().bold(),
+        merged
+    );
+    Ok(())
+}
@@ -0,0 +1,43 @@
+//! Reparse command implementation
+
+use anyhow::{Context, Result};
+use archiver_db::ArchiverDb;
+use archiver_index::parsers::AttrMap;
+use archiver_index::Indexer;
+use colored::Colorize;
+use std::path::PathBuf;
+
+/// Re-runs the parsers over one commit and inserts newly recognized packages
+pub fn cmd_reparse(
+    repo_path: PathBuf,
+    commit: String,
+    only_unrecognized: bool,
+    attr_map: Option<PathBuf>,
+    db: ArchiverDb,
+) -> Result<()> {
+    let mut indexer = Indexer::new(&repo_path, db)
+        .context("Failed to create indexer")?;
+    if let Some(map_path) = attr_map {
+        indexer = indexer.with_attr_map(AttrMap::load(map_path)?);
+    }
+
+    println!("{} Re-parsing every .nix file at {}...", "🔁".bright_cyan(), commit.bold());
+    let stats = indexer.reparse(&commit, only_unrecognized)?;
+
+    println!(
+        "{} {} package(s) found, {} new or updated in {:.1}s",
+        "✓".green().bold(),
+        stats.packages_found,
+        stats.packages_inserted.to_string().bold(),
+        stats.elapsed_time.as_secs_f64()
+    );
+    if only_unrecognized {
+        println!(
+            "  {} {} already recognized file(s) skipped",
+            "·".dimmed(),
+            stats.recognized_files
+        );
+    }
+
+    Ok(())
+}
@@ -1,14 +1,145 @@
 //! Search command implementation
 
 use std::collections::HashMap;
-use anyhow::Result;
+use anyhow::{Context, Result};
+use archiver_core::{short_sha, PackageEntry, Upstream};
 use archiver_db::ArchiverDb;
 use colored::Colorize;
-use tabled::{Table, settings::{Style, Color, Modify, object::Rows}};
-use crate::helpers::{sort_versions_semver, filter_versions, format_relative_time, format_timestamp};
-use crate::output::{PackageSummaryRow, PackageSetRow, VersionRow};
+use tabled::{Table, Tabled, settings::{Style, Color, Modify, object::Rows}};
+use archiver_cli::helpers::{sort_versions_semver, sort_versions_by_date, filter_versions, first_versions, version_count, format_relative_time, format_timestamp, attr_namespace, filter_by_set, rank_matches};
+use crate::output::{fit_to_terminal, PackageSummaryRow, PackageSetRow, VersionRow};
+
+/// How search results are printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum SearchFormat {
+    /// Colored tables for reading in a terminal
+    Table,
+    /// Comma-separated values with a header row (see --delimiter)
+    Csv,
+    /// One tab-separated line per result (`version\tcommit\tdate`), with
+    /// no header, borders or colors, for grep and awk
+    Plain,
+}
+
+/// Commits listed under `Appeared:` in the version view
+const HISTORY_SHOWN: usize = 10;
+
+/// Order of a package's version list, and of the package list when a
+/// search matches several packages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum SearchSort {
+    /// Natural version order, highest first; packages by relevance
+    Version,
+    /// Commit date, most recent first (for calendar versions and snapshots);
+    /// packages by relevance
+    Date,
+    /// Packages alphabetically; versions in natural version order
+    Name,
+}
+
+/// Resolved output mode passed to the display functions
+#[derive(Clone, Copy)]
+enum Output {
+    /// `truncate`: fit tables to the terminal width (off with --no-truncate)
+    Table { truncate: bool },
+    Csv(u8),
+    Plain,
+}
+
+impl Output {
+    fn new(format: SearchFormat, delimiter: char, truncate: bool) -> Result<Self> {
+        Ok(match format {
+            SearchFormat::Table => Output::Table { truncate },
+            SearchFormat::Csv => {
+                if !delimiter.is_ascii() {
+                    anyhow::bail!("--delimiter must be a single ASCII character, got '{}'", delimiter);
+                }
+                Output::Csv(delimiter as u8)
+            }
+            SearchFormat::Plain => Output::Plain,
+        })
+    }
+
+    /// Applies the table width policy before printing
+    fn fit(self, table: &mut Table) {
+        if let Output::Table { truncate: true } = self {
+            fit_to_terminal(table);
+        }
+    }
+
+    /// Tables show relative dates; machine formats use absolute ones
+    fn format_date(self, timestamp: u64) -> String {
+        match self {
+            Output::Table { .. } => format_relative_time(timestamp),
+            Output::Csv(_) | Output::Plain => format_timestamp(timestamp),
+        }
+    }
+}
+
+/// How the packages of a grouped summary were found
+#[derive(Clone, Copy)]
+enum MatchMode {
+    Prefix,
+    Substring,
+    Description,
+}
+
+/// Writes rows as CSV to stdout, using the table headers as the header row.
+/// The header is written even when there are no rows.
+fn write_csv<T: Tabled>(rows: &[T], delimiter: u8) -> Result<()> {
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(delimiter)
+        .from_writer(std::io::stdout().lock());
+    writer.write_record(T::headers().iter().map(|h| h.as_ref()))?;
+    for row in rows {
+        writer.write_record(row.fields().iter().map(|f| f.as_ref()))?;
+    }
+    writer.flush().context("Failed to write CSV output")?;
+    Ok(())
+}
+
+/// Prints version rows as `version\tcommit\tdate` lines
+fn write_plain_versions(rows: &[VersionRow]) {
+    for row in rows {
+        println!("{}\t{}\t{}", row.version, row.commit, row.date);
+    }
+}
+
+/// Prints package summaries as `attr_name\tversions\tlatest\tdate` lines
+fn write_plain_packages(rows: &[PackageSummaryRow]) {
+    for row in rows {
+        println!("{}\t{}\t{}\t{}", row.attr_name, row.version_count, row.latest_version, row.latest_date);
+    }
+}
+
+/// Commit SHA followed by the branches/tags recorded for it, if any
+/// (e.g. "abc123… — release-24.05")
+fn commit_label(db: &ArchiverDb, commit_sha: &str) -> Result<String> {
+    let refs = db.commit_refs(commit_sha)?;
+    if refs.is_empty() {
+        Ok(commit_sha.to_string())
+    } else {
+        Ok(format!("{} — {}", commit_sha, refs.join(", ")))
+    }
+}
+
+/// Version for list rows; per-platform entries name their system
+/// (e.g. "2026.36.0 (x86_64-linux)"). Tables also show the normalized form
+/// where it differs (e.g. "v1.2.3 (1.2.3)"); CSV and plain keep the raw string.
+fn version_label(entry: &PackageEntry, output: Output) -> String {
+    let normalized = entry.normalized_version();
+    let version = match output {
+        Output::Table { .. } if normalized != entry.version => format!("{} ({})", entry.version, normalized),
+        _ => entry.version.clone(),
+    };
+    match &entry.platform {
+        Some(platform) => format!("{} ({})", version, platform),
+        None => version,
+    }
+}
 
 /// Searches for package in database
+#[allow(clippy::too_many_arguments)]
 pub fn cmd_search(
     attr_name: String,
     version: Option<String>,
@@ -16,22 +147,96 @@ pub fn cmd_search(
     major: Option<u64>,
     pattern: Option<String>,
     since: Option<String>,
+    since_version: Option<String>,
+    sort: SearchSort,
     show_all: bool,
+    set: Option<String>,
+    format: SearchFormat,
+    delimiter: char,
+    truncate: bool,
+    upstream: &Upstream,
     db: ArchiverDb,
 ) -> Result<()> {
+    let output = Output::new(format, delimiter, truncate)?;
+
     if let Some(ver) = version {
-        // Search for specific version
-        match db.get(&attr_name, &ver)? {
+        // Search for specific version (case-insensitive fallback on a miss)
+        match db.get_ci(&attr_name, &ver)? {
             Some(entry) => {
-                println!("\n{} {}", "📦 Package:".bright_cyan(), format!("{} v{}", attr_name, ver).bold());
+                let row = || VersionRow {
+                    version: version_label(&entry, output),
+                    commit: entry.commit_sha.clone(),
+                    date: output.format_date(entry.timestamp),
+                };
+                match output {
+                    Output::Csv(delimiter) => return write_csv(&[row()], delimiter),
+                    Output::Plain => {
+                        write_plain_versions(&[row()]);
+                        return Ok(());
+                    }
+                    Output::Table { .. } => {}
+                }
+
+                println!("\n{} {}", "📦 Package:".bright_cyan(), format!("{} v{}", entry.attr_name, ver).bold());
                 println!("{}", "━".repeat(60).bright_black());
-                println!("  {}    {}", "Commit:".bright_yellow(), entry.commit_sha);
+                if entry.normalized_version() != entry.version {
+                    println!("  {}   {} (normalized: {})", "Version:".bright_yellow(), entry.version, entry.normalized_version());
+                }
+                println!("  {}    {}", "Commit:".bright_yellow(), commit_label(&db, &entry.commit_sha)?);
                 println!("  {}      {}", "Date:".bright_yellow(), format_timestamp(entry.timestamp));
+                if let Some(subject) = db.commit_subject(&entry.commit_sha)? {
+                    println!("  {}   {}", "Message:".bright_yellow(), subject);
+                }
+                let history = db.version_history(&entry.attr_name, &entry.version)?;
+                if history.len() > 1 {
+                    println!("  {}  {} commits, oldest first", "Appeared:".bright_yellow(), history.len());
+                    for (commit, timestamp) in history.iter().take(HISTORY_SHOWN) {
+                        println!("    {}  {}", short_sha(commit, 12), format_timestamp(*timestamp));
+                    }
+                    if history.len() > HISTORY_SHOWN {
+                        println!("    {}", format!("… {} more", history.len() - HISTORY_SHOWN).dimmed());
+                    }
+                }
+                if let Some(nar_hash) = &entry.nar_hash {
+                    println!("  {}  {}", "NAR hash:".bright_yellow(), nar_hash);
+                }
+                if let Some(content_hash) = &entry.content_hash {
+                    println!("  {}   {}", "Content:".bright_yellow(), content_hash);
+                }
+                if let Some(program) = &entry.main_program {
+                    println!("  {}   {}", "Program:".bright_yellow(), program);
+                }
+                if let Some(description) = &entry.description {
+                    println!("  {}     {}", "About:".bright_yellow(), description);
+                }
+                if let Some(rev) = &entry.source_rev {
+                    println!("  {}  rev {}", "Upstream:".bright_yellow(), rev);
+                }
+                if entry.platform.is_some() {
+                    println!("  {}", "Platforms:".bright_yellow());
+                    for platform_entry in db.get_platforms(&entry.attr_name, &entry.version)? {
+                        println!(
+                            "    {:<16} {}",
+                            platform_entry.platform.unwrap_or_default(),
+                            platform_entry.source_hash.as_deref().unwrap_or("(no hash)")
+                        );
+                    }
+                }
                 println!("\n{}", "📝 Nix expression:".bright_cyan());
                 println!("{}", "━".repeat(60).bright_black());
-                println!("{}", entry.to_nix_import().bright_white());
+                println!("{}", entry.to_nix_import(upstream).bright_white());
             }
             None => {
+                // `search nodejs 20` means "the 20.x versions"
+                let by_prefix = db.get_by_version_prefix(&attr_name, &ver)?;
+                if !by_prefix.is_empty() {
+                    if let Output::Table { .. } = output {
+                        println!("{} No version {} of {}; showing versions starting with it",
+                            "💡".yellow(), ver.bold(), attr_name.bold());
+                    }
+                    return display_single_package(&db, attr_name, by_prefix, None, major, pattern.as_deref(), since.as_deref(), since_version.as_deref(), sort, limit, show_all, output);
+                }
+
                 eprintln!("{} Package {}:{} not found in database", "❌".red(), attr_name.bold(), ver.bold());
                 
                 // Suggest available versions
@@ -42,7 +247,7 @@ pub fn cmd_search(
                     let rows: Vec<VersionRow> = sorted.iter()
                         .take(10)
                         .map(|entry| VersionRow {
-                            version: entry.version.clone(),
+                            version: version_label(entry, output),
                             commit: entry.commit_sha.clone(),
                             date: format_relative_time(entry.timestamp),
                         })
@@ -51,6 +256,7 @@ pub fn cmd_search(
                     let mut table = Table::new(rows);
                     table.with(Style::rounded())
                         .with(Modify::new(Rows::first()).with(Color::FG_BRIGHT_CYAN));
+                    output.fit(&mut table);
                     eprintln!("{}", table);
                     
                     if sorted.len() > 10 {
@@ -64,18 +270,49 @@ pub fn cmd_search(
             }
         }
     } else {
+        // Restrict to one package set (--set python313Packages) if requested
+        let in_set = |matches| match &set {
+            Some(set) => filter_by_set(matches, set),
+            None => matches,
+        };
+
+        // A single matching package with a plain --limit only needs its newest
+        // versions: count via keys, then keep just the top `limit` while
+        // scanning instead of loading the whole history. The newest by date
+        // may be anywhere in version order, so date sorting loads it all.
+        if !show_all && sort != SearchSort::Date && !filter_is_specific(major, &pattern, &since, &since_version) {
+            let mut counts = db.count_versions_by_prefix(&attr_name)?;
+            if let Some(set) = &set {
+                counts = filter_by_set(counts, set);
+            }
+            if counts.len() == 1 {
+                let (name, total) = counts.into_iter().next().unwrap();
+                let newest = db.top_versions(&name, limit)?;
+                return display_single_package(&db, name, newest, Some(total), None, None, None, None, sort, limit, show_all, output);
+            }
+        }
+
         // Phase 1: fast prefix scan ("python" → python311, python314, …)
-        let mut matches = db.search_packages(&attr_name)?;
-        let mut used_substring = false;
+        let mut matches = in_set(db.search_packages(&attr_name)?);
+        let mut mode = MatchMode::Prefix;
 
         // Phase 2: substring fallback ("biomejs" → vscode-extensions.biomejs.biome, etc.)
         if matches.is_empty() {
-            matches = db.search_packages_contains(&attr_name)?;
-            used_substring = true;
+            matches = in_set(db.search_packages_contains(&attr_name)?);
+            mode = MatchMode::Substring;
         }
 
         if matches.is_empty() {
-            println!("{} No packages found matching '{}'", "❌".red(), attr_name.bold());
+            match output {
+                // Header only, so consumers still see the expected columns
+                Output::Csv(delimiter) => return write_csv::<PackageSummaryRow>(&[], delimiter),
+                Output::Plain => return Ok(()),
+                Output::Table { .. } => {}
+            }
+            match &set {
+                Some(set) => println!("{} No packages found matching '{}' in set '{}'", "❌".red(), attr_name.bold(), set.bold()),
+                None => println!("{} No packages found matching '{}'", "❌".red(), attr_name.bold()),
+            }
             println!("  {} Try a different spelling or a broader term", "💡".yellow());
             return Ok(());
         }
@@ -83,17 +320,17 @@ pub fn cmd_search(
         if matches.len() == 1 {
             // Only one package matched - show detailed version list
             let (name, entries) = matches.into_iter().next().unwrap();
-            return display_single_package(name, entries, major, pattern.as_deref(), since.as_deref(), limit, show_all);
+            return display_single_package(&db, name, entries, None, major, pattern.as_deref(), since.as_deref(), since_version.as_deref(), sort, limit, show_all, output);
         }
 
         // Multiple packages matched:
         // - exact name match → show detail with hint about others
         // - no exact match → show grouped summary table
-        if matches.contains_key(&attr_name) && filter_is_specific(major, &pattern, &since) {
+        if matches.contains_key(&attr_name) && filter_is_specific(major, &pattern, &since, &since_version) {
             // User is filtering, so they probably want the exact package
             let entries = matches[&attr_name].clone();
             let other_count = matches.len() - 1;
-            if other_count > 0 {
+            if other_count > 0 && matches!(output, Output::Table { .. }) {
                 let mut other_names: Vec<&str> = matches.keys()
                     .map(|k| k.as_str())
                     .filter(|k| *k != attr_name.as_str())
@@ -106,60 +343,135 @@ pub fn cmd_search(
                 );
                 println!();
             }
-            return display_single_package(attr_name, entries, major, pattern.as_deref(), since.as_deref(), limit, show_all);
+            return display_single_package(&db, attr_name, entries, None, major, pattern.as_deref(), since.as_deref(), since_version.as_deref(), sort, limit, show_all, output);
         }
 
         // Show grouped summary for all matching packages
-        return display_multiple_packages(&attr_name, matches, limit, used_substring);
+        return display_multiple_packages(&attr_name, matches, limit, mode, sort, output);
     }
 
     Ok(())
 }
 
-fn filter_is_specific(major: Option<u64>, pattern: &Option<String>, since: &Option<String>) -> bool {
-    major.is_some() || pattern.is_some() || since.is_some()
+/// Lists packages whose `meta.description` contains `query`, grouped like
+/// a name search matching several packages
+#[allow(clippy::too_many_arguments)]
+pub fn cmd_search_description(
+    query: String,
+    limit: usize,
+    show_all: bool,
+    set: Option<String>,
+    format: SearchFormat,
+    delimiter: char,
+    truncate: bool,
+    db: ArchiverDb,
+) -> Result<()> {
+    let output = Output::new(format, delimiter, truncate)?;
+
+    let mut matches: HashMap<String, Vec<PackageEntry>> = HashMap::new();
+    for entry in db.search_by_description(&query)? {
+        matches.entry(entry.attr_name.clone()).or_default().push(entry);
+    }
+    if let Some(set) = &set {
+        matches = filter_by_set(matches, set);
+    }
+
+    if matches.is_empty() {
+        match output {
+            Output::Csv(delimiter) => return write_csv::<PackageSummaryRow>(&[], delimiter),
+            Output::Plain => return Ok(()),
+            Output::Table { .. } => {}
+        }
+        println!("{} No package descriptions contain '{}'", "❌".red(), query.bold());
+        println!("  {} Only packages indexed with descriptions can match", "💡".yellow());
+        return Ok(());
+    }
+
+    let limit = if show_all { matches.len() } else { limit };
+    display_multiple_packages(&query, matches, limit, MatchMode::Description, SearchSort::Name, output)
+}
+
+fn filter_is_specific(major: Option<u64>, pattern: &Option<String>, since: &Option<String>, since_version: &Option<String>) -> bool {
+    major.is_some() || pattern.is_some() || since.is_some() || since_version.is_some()
 }
 
 /// Displays detailed version list for a single package
+///
+/// `total` is set when `all_versions` holds only the newest part of a
+/// longer history (see `ArchiverDb::top_versions`); it is the full count.
+#[allow(clippy::too_many_arguments)]
 fn display_single_package(
+    db: &ArchiverDb,
     attr_name: String,
     all_versions: Vec<archiver_core::PackageEntry>,
+    total: Option<usize>,
     major: Option<u64>,
     pattern: Option<&str>,
     since: Option<&str>,
+    since_version: Option<&str>,
+    sort: SearchSort,
     limit: usize,
     show_all: bool,
+    output: Output,
 ) -> Result<()> {
-    let all_versions = filter_versions(all_versions, major, pattern, since)?;
+    let all_versions = filter_versions(all_versions, major, pattern, since, since_version)?;
 
     if all_versions.is_empty() {
+        match output {
+            Output::Csv(delimiter) => return write_csv::<VersionRow>(&[], delimiter),
+            Output::Plain => return Ok(()),
+            Output::Table { .. } => {}
+        }
         println!("{} No versions match the specified filters", "❌".red());
         return Ok(());
     }
 
     let sorted = sort_versions_semver(all_versions);
-    let total_count = sorted.len();
+    // Counts are of versions: a per-platform version lists all its platforms
+    let loaded_count = version_count(&sorted);
+    let total_count = total.unwrap_or(loaded_count);
+    let display_limit = if show_all { total_count } else { limit.min(total_count) };
+    // Newest/oldest in the header stay by version; only the list is reordered
+    let by_date = (sort == SearchSort::Date).then(|| sort_versions_by_date(sorted.clone()));
+    let listed = by_date.as_ref().unwrap_or(&sorted);
+    let rows: Vec<VersionRow> = first_versions(listed, display_limit).into_iter().map(|entry| Ok(VersionRow {
+        version: version_label(entry, output),
+        // CSV and plain keep a bare SHA in the commit column
+        commit: match output {
+            Output::Table { .. } => commit_label(db, &entry.commit_sha)?,
+            Output::Csv(_) | Output::Plain => entry.commit_sha.clone(),
+        },
+        date: output.format_date(entry.timestamp),
+    })).collect::<Result<_>>()?;
+
+    match output {
+        Output::Csv(delimiter) => return write_csv(&rows, delimiter),
+        Output::Plain => {
+            write_plain_versions(&rows);
+            return Ok(());
+        }
+        Output::Table { .. } => {}
+    }
+
     let newest = &sorted[0];
-    let oldest = &sorted[sorted.len() - 1];
 
     println!("\n{} {}", "📦".bright_cyan(), attr_name.bold().bright_white());
     println!("{}", "━".repeat(60).bright_black());
-    println!("  {} {}  {} {}  {} {}",
+    print!("  {} {}  {} {}",
         "Total:".bright_yellow(), total_count.to_string().bold(),
         "Newest:".bright_green(), newest.version.clone().green().bold(),
-        "Oldest:".bright_blue(), oldest.version.clone().blue()
     );
+    // The oldest version is only known when the whole history was loaded
+    if loaded_count == total_count {
+        let oldest = &sorted[sorted.len() - 1];
+        print!("  {} {}", "Oldest:".bright_blue(), oldest.version.clone().blue());
+    }
+    println!();
     println!();
-
-    let display_limit = if show_all { total_count } else { limit.min(total_count) };
-    let rows: Vec<VersionRow> = sorted.iter().take(display_limit).map(|entry| VersionRow {
-        version: entry.version.clone(),
-        commit: entry.commit_sha.clone(),
-        date: format_relative_time(entry.timestamp),
-    }).collect();
 
     let mut table = Table::new(rows);
     table.with(Style::rounded()).with(Modify::new(Rows::first()).with(Color::FG_BRIGHT_CYAN));
+    output.fit(&mut table);
     println!("{}", table);
 
     if display_limit < total_count {
@@ -170,19 +482,6 @@ fn display_single_package(
     Ok(())
 }
 
-/// Extracts the top-level namespace (package set) from an attr_name.
-///
-/// Examples:
-///   "vscode-extensions.biomejs.biome" → "vscode-extensions"
-///   "python313Packages.numpy"          → "python313Packages"
-///   "python314"                        → "(top-level)"
-fn attr_namespace(attr_name: &str) -> &str {
-    match attr_name.find('.') {
-        Some(pos) => &attr_name[..pos],
-        None => "(top-level)",
-    }
-}
-
 /// Displays a grouped summary table when multiple packages match.
 /// Shows a package-set breakdown (like NixOS search sidebar) followed by
 /// a paginated alphabetical package list.
@@ -190,18 +489,47 @@ fn display_multiple_packages(
     query: &str,
     matches: HashMap<String, Vec<archiver_core::PackageEntry>>,
     limit: usize,
-    used_substring: bool,
+    mode: MatchMode,
+    sort: SearchSort,
+    output: Output,
 ) -> Result<()> {
-    let mut names: Vec<String> = matches.keys().cloned().collect();
-    names.sort();
+    let names = match sort {
+        SearchSort::Name => {
+            let mut names: Vec<String> = matches.keys().cloned().collect();
+            names.sort();
+            names
+        }
+        SearchSort::Version | SearchSort::Date => rank_matches(query, &matches),
+    };
 
     let total = names.len();
     let display_limit = limit.min(total);
 
-    let mode_tag = if used_substring {
-        "substring".bright_yellow()
-    } else {
-        "prefix".bright_cyan()
+    let rows: Vec<PackageSummaryRow> = names.iter().take(display_limit).map(|name| {
+        let entries = &matches[name];
+        let sorted = sort_versions_semver(entries.clone());
+        let newest = sorted.first().unwrap();
+        PackageSummaryRow {
+            attr_name: name.clone(),
+            version_count: version_count(&sorted).to_string(),
+            latest_version: newest.version.clone(),
+            latest_date: output.format_date(newest.timestamp),
+        }
+    }).collect();
+
+    match output {
+        Output::Csv(delimiter) => return write_csv(&rows, delimiter),
+        Output::Plain => {
+            write_plain_packages(&rows);
+            return Ok(());
+        }
+        Output::Table { .. } => {}
+    }
+
+    let mode_tag = match mode {
+        MatchMode::Prefix => "prefix".bright_cyan(),
+        MatchMode::Substring => "substring".bright_yellow(),
+        MatchMode::Description => "description".bright_magenta(),
     };
 
     println!("\n{} {}  {} {}",
@@ -241,20 +569,9 @@ fn display_multiple_packages(
     }
 
     // ── Package list ────────────────────────────────────────────────────────
-    let rows: Vec<PackageSummaryRow> = names.iter().take(display_limit).map(|name| {
-        let entries = &matches[name];
-        let sorted = sort_versions_semver(entries.clone());
-        let newest = sorted.first().unwrap();
-        PackageSummaryRow {
-            attr_name: name.clone(),
-            version_count: sorted.len().to_string(),
-            latest_version: newest.version.clone(),
-            latest_date: format_relative_time(newest.timestamp),
-        }
-    }).collect();
-
     let mut table = Table::new(rows);
     table.with(Style::rounded()).with(Modify::new(Rows::first()).with(Color::FG_BRIGHT_CYAN));
+    output.fit(&mut table);
     println!("{}", table);
 
     if display_limit < total {
@@ -4,7 +4,7 @@ use anyhow::Result;
 use archiver_db::ArchiverDb;
 use colored::Colorize;
 
-fn format_size(bytes: u64) -> String {
+pub(super) fn format_size(bytes: u64) -> String {
     const KIB: u64 = 1024;
     const MIB: u64 = 1024 * KIB;
     const GIB: u64 = 1024 * MIB;
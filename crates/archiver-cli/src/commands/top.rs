@@ -0,0 +1,37 @@
+//! Top command implementation
+
+use anyhow::Result;
+use archiver_db::ArchiverDb;
+use colored::Colorize;
+use tabled::{Table, settings::{Style, Color, Modify, object::Rows}};
+use crate::output::TopRow;
+
+/// Lists the `n` packages with the most indexed versions
+pub fn cmd_top(n: usize, db: ArchiverDb) -> Result<()> {
+    let counts = db.version_counts()?;
+    if counts.is_empty() {
+        println!("{} The database has no indexed packages", "❌".red());
+        return Ok(());
+    }
+
+    let total: usize = counts.iter().map(|(_, count)| count).sum();
+    let rows: Vec<TopRow> = counts
+        .into_iter()
+        .take(n)
+        .enumerate()
+        .map(|(i, (attr_name, count))| TopRow {
+            rank: (i + 1).to_string(),
+            attr_name,
+            version_count: count.to_string(),
+            share: format!("{:.1}%", count as f64 * 100.0 / total as f64),
+        })
+        .collect();
+
+    println!("\n{} {} of {} versions", "📈 Most versions:".bright_cyan(), rows.len().to_string().bold(), total.to_string().bold());
+    let mut table = Table::new(rows);
+    table.with(Style::rounded())
+        .with(Modify::new(Rows::first()).with(Color::FG_BRIGHT_CYAN));
+    println!("{}", table);
+
+    Ok(())
+}
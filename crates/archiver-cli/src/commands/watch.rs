@@ -0,0 +1,207 @@
+//! Watch command implementation
+
+use anyhow::{Context, Result};
+use archiver_core::{short_sha, IndexRun};
+use archiver_db::ArchiverDb;
+use archiver_index::parsers::AttrMap;
+use archiver_index::remote::{clone_or_fetch, fetch_remote};
+use archiver_index::{IndexOptions, Indexer, PATH_FILTER};
+use colored::Colorize;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use super::index::{count_commits_between, default_clone_dir, ensure_full_history, origin_url};
+
+/// Keeps the database current: fetches, indexes new commits on `rev`, sleeps
+#[allow(clippy::too_many_arguments)]
+pub fn cmd_watch(
+    repo_path: Option<PathBuf>,
+    clone: Option<String>,
+    rev: String,
+    interval: u64,
+    cycles: Option<usize>,
+    batch_size: usize,
+    flush_every: usize,
+    flush_entries: Option<usize>,
+    allow_shallow: bool,
+    attr_map: Option<PathBuf>,
+    db: ArchiverDb,
+) -> Result<()> {
+    if interval == 0 {
+        anyhow::bail!("--interval must be at least 1 second");
+    }
+    let repo_path = match &clone {
+        Some(url) => {
+            let dest = match repo_path {
+                Some(path) => path,
+                None => default_clone_dir(url)?,
+            };
+            clone_or_fetch(url, &dest, None)?;
+            dest
+        }
+        None => repo_path.context("--repo is required without --clone")?,
+    };
+
+    let mut indexer = Indexer::new(&repo_path, db)
+        .context("Failed to create indexer")?;
+    if let Some(map_path) = &attr_map {
+        let map = AttrMap::load(map_path)?;
+        log::info!("Loaded {} attr map rule(s) from {:?}", map.rules().len(), map_path);
+        indexer = indexer.with_attr_map(map);
+    }
+    ensure_full_history(&indexer, &repo_path, allow_shallow)?;
+
+    let options = IndexOptions {
+        max_commits: None,
+        batch_size,
+        flush_every,
+        flush_entries,
+        head_only: false,
+        record_refs: false,
+        commit_path: None,
+        estimated_commits: None,
+        first_parent: false,
+        hide: None,
+    };
+    let attr_map = attr_map.map(|p| p.display().to_string());
+
+    println!(
+        "{} Watching {} in {:?} every {}s",
+        "👀".bright_cyan(),
+        rev.bold(),
+        repo_path,
+        interval
+    );
+
+    let mut last_head = resume_point(&indexer, &repo_path, &indexer.resolve_commit(&rev)?)?;
+    match &last_head {
+        Some(sha) => log::info!("Resuming after {}, the last indexed head", short_sha(sha, 12)),
+        None => log::info!("No earlier run to resume from: the first cycle walks the whole history"),
+    }
+    let mut cycle = 0;
+    loop {
+        cycle += 1;
+        // The first cycle runs right away (clone_or_fetch already fetched)
+        if cycle > 1 || clone.is_none() {
+            if let Err(e) = fetch(&repo_path, clone.as_deref()) {
+                // Network hiccups shouldn't stop the daemon; try again next cycle
+                log::warn!("Fetch failed: {:#}", e);
+            }
+        }
+
+        match run_cycle(&indexer, &repo_path, &rev, last_head.as_deref(), &options, &attr_map) {
+            Ok((head, new_commits, inserted)) => {
+                let now = chrono::Local::now().format("%Y-%m-%d %H:%M:%S");
+                if new_commits == 0 {
+                    println!("{} {} {} — no new commits", "·".dimmed(), now, short_sha(&head, 12));
+                } else {
+                    println!(
+                        "{} {} {} — {} new commit(s), {} package(s) new or updated",
+                        "✓".green().bold(),
+                        now,
+                        short_sha(&head, 12),
+                        new_commits,
+                        inserted.to_string().bold()
+                    );
+                }
+                last_head = Some(head);
+            }
+            Err(e) => log::error!("Indexing cycle failed: {:#}", e),
+        }
+
+        if cycles.is_some_and(|max| cycle >= max) {
+            return Ok(());
+        }
+        std::thread::sleep(Duration::from_secs(interval));
+    }
+}
+
+/// Updates the clone: the `--clone` URL if given, else the `origin` remote
+fn fetch(repo_path: &Path, clone: Option<&str>) -> Result<()> {
+    match clone {
+        Some(url) => clone_or_fetch(url, repo_path, None),
+        None => fetch_remote(repo_path, "origin"),
+    }
+}
+
+/// Where a previous run left off: the head of the latest recorded run that
+/// was indexed and lies on `head`'s history. Runs restricted to some
+/// packages or paths don't count, since they leave the rest unindexed.
+fn resume_point(indexer: &Indexer, repo_path: &Path, head: &str) -> Result<Option<String>> {
+    let repo = git2::Repository::open(repo_path).context("Failed to open repository")?;
+    let head_oid = git2::Oid::from_str(head)?;
+    for run in indexer.db().index_runs()?.into_iter().rev() {
+        if !run.only.is_empty() || run.commit_path.is_some() {
+            continue;
+        }
+        // Runs are recorded before indexing: one that failed early left its head unprocessed
+        if !indexer.db().is_commit_processed(&run.from_commit)? {
+            continue;
+        }
+        let Ok(from) = git2::Oid::from_str(&run.from_commit) else {
+            continue;
+        };
+        if from == head_oid || repo.graph_descendant_of(head_oid, from).unwrap_or(false) {
+            return Ok(Some(run.from_commit));
+        }
+    }
+    Ok(None)
+}
+
+/// One watch cycle: indexes the commits reachable from `rev` but not from
+/// `last_head`, or every unprocessed commit without one. Returns the new
+/// head, the number of new commits and the number of inserted packages.
+fn run_cycle(
+    indexer: &Indexer,
+    repo_path: &PathBuf,
+    rev: &str,
+    last_head: Option<&str>,
+    options: &IndexOptions,
+    attr_map: &Option<String>,
+) -> Result<(String, usize, usize)> {
+    let head = indexer.resolve_commit(rev)?;
+
+    // Hiding the last head keeps each cycle proportional to what changed.
+    // A count limit wouldn't: the walk is ordered by date, so commits merged
+    // from older side branches come after already indexed ones
+    let new_commits = match last_head {
+        Some(last_head) => Some(count_commits_between(repo_path, &head, last_head, false)?),
+        None => None,
+    };
+    if new_commits == Some(0) {
+        return Ok((head, 0, 0));
+    }
+
+    let run = IndexRun {
+        started_at: chrono::Utc::now().timestamp() as u64,
+        tool_version: env!("CARGO_PKG_VERSION").to_string(),
+        command_line: std::env::args().collect(),
+        repo_path: repo_path.display().to_string(),
+        repo_url: origin_url(repo_path),
+        from_commit: head.clone(),
+        to_commit: last_head.map(str::to_string),
+        to_date: None,
+        max_commits: None,
+        head_only: false,
+        version_regex: indexer.version_pattern().to_string(),
+        path_filter: PATH_FILTER.to_string(),
+        attr_map: attr_map.clone(),
+        only: Vec::new(),
+        commit_path: None,
+        skipped_versions: Vec::new(),
+        first_parent: false,
+    };
+    indexer.db().record_index_run(&run)?;
+
+    let options = IndexOptions {
+        hide: last_head.map(str::to_string),
+        estimated_commits: new_commits,
+        ..options.clone()
+    };
+    let stats = indexer.index_from_commit(&head, &options)
+        .context("Failed to index new commits")?;
+    // Without a last head, count what the walk found unprocessed (plus a
+    // head scanned in full on an empty database)
+    let new_commits = new_commits.unwrap_or(stats.processed + usize::from(stats.head_scan.is_some()));
+    Ok((head, new_commits, stats.packages_inserted))
+}
@@ -0,0 +1,188 @@
+//! Database export/import as JSON Lines, optionally gzip-compressed
+//!
+//! One `PackageEntry` per line. Both directions stream entry by entry, so
+//! memory use stays flat regardless of database size.
+//!
+//! A single package's history can also be written on its own, as a JSON
+//! array or CSV ([`export_package_to_path`]).
+
+use anyhow::{Context, Result};
+use archiver_core::PackageEntry;
+use archiver_db::ArchiverDb;
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use crate::helpers::sort_versions_semver;
+
+/// Compression applied to an export file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Compression {
+    /// Plain JSON Lines
+    None,
+    /// gzip-compressed JSON Lines
+    Gzip,
+}
+
+impl Compression {
+    /// Picks gzip for paths ending in `.gz`, plain JSON Lines otherwise.
+    pub fn from_path(path: &Path) -> Self {
+        if path.extension().is_some_and(|ext| ext == "gz") {
+            Compression::Gzip
+        } else {
+            Compression::None
+        }
+    }
+}
+
+/// Format of a single package's history export
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum HistoryFormat {
+    /// Pretty-printed JSON array of entries
+    Json,
+    /// CSV with a header row, one entry per row
+    Csv,
+}
+
+impl HistoryFormat {
+    /// Picks CSV for paths ending in `.csv`, JSON otherwise.
+    pub fn from_path(path: &Path) -> Self {
+        if path.extension().is_some_and(|ext| ext == "csv") {
+            HistoryFormat::Csv
+        } else {
+            HistoryFormat::Json
+        }
+    }
+}
+
+/// CSV columns of a history export: every `PackageEntry` field, in order
+const HISTORY_CSV_HEADER: [&str; 14] = [
+    "attr_name", "version", "commit_sha", "timestamp", "is_primary", "nar_hash",
+    "main_program", "description", "content_hash", "platform", "source_hash", "source_rev",
+    "source_path", "attr_from_path",
+];
+
+/// Every indexed entry of `attr_name` (all versions and platforms), newest
+/// version first. Fails if the package isn't in the database.
+pub fn package_history(db: &ArchiverDb, attr_name: &str) -> Result<Vec<PackageEntry>> {
+    let entries = db.get_all_versions(attr_name)?;
+    if entries.is_empty() {
+        anyhow::bail!("Package '{}' not found in database", attr_name);
+    }
+    Ok(sort_versions_semver(entries))
+}
+
+/// Writes `entries` to `writer` as a JSON array or CSV with all fields.
+pub fn write_package_history<W: Write>(entries: &[PackageEntry], format: HistoryFormat, mut writer: W) -> Result<()> {
+    match format {
+        HistoryFormat::Json => {
+            serde_json::to_writer_pretty(&mut writer, entries).context("Failed to serialize entries")?;
+            writer.write_all(b"\n").context("Failed to write export")?;
+            writer.flush().context("Failed to write export")?;
+        }
+        HistoryFormat::Csv => {
+            let mut csv = csv::Writer::from_writer(writer);
+            csv.write_record(HISTORY_CSV_HEADER)?;
+            let opt = |value: &Option<String>| value.clone().unwrap_or_default();
+            for entry in entries {
+                csv.write_record([
+                    entry.attr_name.clone(),
+                    entry.version.clone(),
+                    entry.commit_sha.clone(),
+                    entry.timestamp.to_string(),
+                    entry.is_primary.to_string(),
+                    opt(&entry.nar_hash),
+                    opt(&entry.main_program),
+                    opt(&entry.description),
+                    opt(&entry.content_hash),
+                    opt(&entry.platform),
+                    opt(&entry.source_hash),
+                    opt(&entry.source_rev),
+                    opt(&entry.source_path),
+                    entry.attr_from_path.to_string(),
+                ])?;
+            }
+            csv.flush().context("Failed to write export")?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes the history of `attr_name` to `path`. Returns the number of
+/// entries written.
+pub fn export_package_to_path(db: &ArchiverDb, attr_name: &str, path: &Path, format: HistoryFormat) -> Result<usize> {
+    let entries = package_history(db, attr_name)?;
+    let file = File::create(path)
+        .with_context(|| format!("Failed to create export file: {}", path.display()))?;
+    write_package_history(&entries, format, BufWriter::new(file))?;
+    Ok(entries.len())
+}
+
+/// Writes every entry in the database to `writer` as JSON Lines.
+/// Returns the number of entries written.
+pub fn export_jsonl<W: Write>(db: &ArchiverDb, mut writer: W) -> Result<usize> {
+    let mut count = 0;
+    for entry in db.iter_entries() {
+        serde_json::to_writer(&mut writer, &entry?).context("Failed to serialize entry")?;
+        writer.write_all(b"\n").context("Failed to write export")?;
+        count += 1;
+    }
+    writer.flush().context("Failed to write export")?;
+    Ok(count)
+}
+
+/// Reads JSON Lines entries from `reader` and inserts them with the usual
+/// deduplication rules. Returns the number of entries read.
+pub fn import_jsonl<R: BufRead>(db: &ArchiverDb, reader: R) -> Result<usize> {
+    let mut count = 0;
+    for (i, line) in reader.lines().enumerate() {
+        let line = line.context("Failed to read import")?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: PackageEntry = serde_json::from_str(&line)
+            .with_context(|| format!("Invalid entry on line {}", i + 1))?;
+        db.insert_if_better(&entry)?;
+        count += 1;
+    }
+    db.flush()?;
+    Ok(count)
+}
+
+/// Exports the database to `path`, compressing with gzip if requested.
+pub fn export_to_path(db: &ArchiverDb, path: &Path, compression: Compression) -> Result<usize> {
+    let file = File::create(path)
+        .with_context(|| format!("Failed to create export file: {}", path.display()))?;
+
+    match compression {
+        Compression::None => export_jsonl(db, BufWriter::new(file)),
+        Compression::Gzip => {
+            let mut encoder = GzEncoder::new(BufWriter::new(file), flate2::Compression::default());
+            let count = export_jsonl(db, &mut encoder)?;
+            encoder
+                .finish()
+                .and_then(|mut w| w.flush())
+                .context("Failed to finish gzip stream")?;
+            Ok(count)
+        }
+    }
+}
+
+/// Imports entries from `path`, transparently decompressing gzip input
+/// (detected by the `.gz` extension or the gzip magic bytes).
+pub fn import_from_path(db: &ArchiverDb, path: &Path) -> Result<usize> {
+    let file = File::open(path)
+        .with_context(|| format!("Failed to open import file: {}", path.display()))?;
+    let mut reader = BufReader::new(file);
+
+    let is_gzip = Compression::from_path(path) == Compression::Gzip
+        || reader.fill_buf().map(|b| b.starts_with(&[0x1f, 0x8b])).unwrap_or(false);
+
+    if is_gzip {
+        import_jsonl(db, BufReader::new(MultiGzDecoder::new(reader)))
+    } else {
+        import_jsonl(db, reader)
+    }
+}
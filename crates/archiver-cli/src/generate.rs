@@ -0,0 +1,804 @@
+//! Spec resolution and Nix rendering for the `generate` command
+//!
+//! Split into three pure steps so each can be used on its own:
+//!   1. [`parse_packages_spec`] — packages.nix / .toml → (attr_name, version) pairs
+//!   2. [`resolve_spec`]        — pairs → database entries + errors as data
+//!   3. [`render`]              — resolved entries → Nix source text
+//!
+//! [`report_json`] summarises step 2 for CI, whether or not it succeeded.
+//! [`generate_frozen`] runs steps 2 and 3 in one call for library users.
+
+use anyhow::{Context, Result};
+use archiver_core::version::is_prerelease;
+use archiver_core::{PackageEntry, Upstream};
+use archiver_db::ArchiverDb;
+use rnix::ast::{self, AttrpathValue, Expr, InterpolPart};
+use rowan::ast::AstNode;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use crate::helpers::{nix_hash_to_sri, sort_versions_semver};
+
+// ─── Parser ───────────────────────────────────────────────────────────────────
+
+/// Parsed contents of a packages.nix (or .toml) file
+#[derive(Debug, Default)]
+pub struct PackageSpec {
+    /// Requested (attr_name, version) pairs in file order
+    pub entries: Vec<(String, String)>,
+
+    /// Attr names skipped because their value uses string interpolation
+    pub skipped: Vec<String>,
+
+    /// Bindings skipped because they don't parse (`line N: …`)
+    pub parse_errors: Vec<String>,
+}
+
+/// Parses a packages.nix attrset and returns (attr_name, version) pairs.
+///
+/// Uses rnix AST so comments, multi-line strings, and all valid Nix syntax are
+/// handled correctly — no manual comment stripping or regex needed.
+///
+/// Fails if the same attr_name is listed more than once, since the later
+/// binding would silently shadow the earlier one in the generated attrset.
+///
+/// A syntax error doesn't discard the rest of the file: each top-level
+/// binding is then parsed on its own, the ones that parse are returned and
+/// the broken ones are listed in [`PackageSpec::parse_errors`]. Only a file
+/// with errors and no readable entry at all fails.
+///
+/// Files with a `.toml` extension are read as a TOML spec instead (see
+/// [`parse_toml_spec`]).
+pub fn parse_packages_spec(path: &std::path::Path, content: &str) -> Result<PackageSpec> {
+    if path.extension().is_some_and(|ext| ext == "toml") {
+        return parse_toml_spec(path, content);
+    }
+
+    let mut spec = PackageSpec::default();
+    let mut seen = std::collections::HashSet::new();
+
+    let parsed = rnix::Root::parse(content);
+    if parsed.errors().is_empty() {
+        collect_spec_entries(&parsed.syntax(), path, &mut spec, &mut seen)?;
+        return Ok(spec);
+    }
+
+    // rnix's own recovery tends to swallow the binding after a broken one,
+    // so re-parse the bindings one at a time instead
+    for (line, binding) in top_level_bindings(content) {
+        let parsed = rnix::Root::parse(&format!("{{ {} }}", binding));
+        if parsed.errors().is_empty() {
+            collect_spec_entries(&parsed.syntax(), path, &mut spec, &mut seen)?;
+        } else {
+            let text = binding.split_whitespace().collect::<Vec<_>>().join(" ");
+            spec.parse_errors.push(format!("line {}: `{}` is not a valid binding", line, text));
+        }
+    }
+
+    if spec.entries.is_empty() {
+        let errs: Vec<String> = parsed.errors().iter().map(|e| e.to_string()).collect();
+        anyhow::bail!("Nix parse error in {}: {}", path.display(), errs.join("; "));
+    }
+
+    Ok(spec)
+}
+
+/// Adds the `name = "version";` bindings under `root` to `spec`
+fn collect_spec_entries(
+    root: &rnix::SyntaxNode,
+    path: &std::path::Path,
+    spec: &mut PackageSpec,
+    seen: &mut std::collections::HashSet<String>,
+) -> Result<()> {
+    for node in root.descendants() {
+        let Some(kv) = AttrpathValue::cast(node) else { continue };
+
+        // Accept only simple (non-dotted) keys
+        let Some(attrpath) = kv.attrpath() else { continue };
+        let mut attrs = attrpath.attrs();
+        let Some(first) = attrs.next() else { continue };
+        if attrs.next().is_some() {
+            // dotted path like foo.bar — not a package spec entry
+            continue;
+        }
+
+        let attr_name = match first {
+            ast::Attr::Ident(ident) => match ident.ident_token() {
+                Some(t) => t.text().to_string(),
+                None => continue,
+            },
+            _ => continue,
+        };
+
+        if !seen.insert(attr_name.clone()) {
+            anyhow::bail!(
+                "Duplicate package '{}' in {}: each package may only be listed once",
+                attr_name,
+                path.display()
+            );
+        }
+
+        // Value must be a plain string literal (no interpolation)
+        let Some(value) = kv.value() else { continue };
+        let Expr::Str(s) = value else { continue };
+
+        // normalized_parts() yields InterpolPart<String> — Literal is already a plain String,
+        // Interpolation means ${...} is present and we skip those entries.
+        let mut version = String::new();
+        let mut has_interpolation = false;
+        for part in s.normalized_parts() {
+            match part {
+                InterpolPart::Literal(text) => version.push_str(&text),
+                InterpolPart::Interpolation(_) => {
+                    has_interpolation = true;
+                    break;
+                }
+            }
+        }
+
+        if has_interpolation {
+            spec.skipped.push(attr_name);
+            continue;
+        }
+
+        spec.entries.push((attr_name, version));
+    }
+    Ok(())
+}
+
+/// Splits the body of the outermost attrset into its bindings (each with
+/// its `;`, if any) and the line each starts on. Works on tokens, so it
+/// doesn't need the file to parse.
+fn top_level_bindings(content: &str) -> Vec<(usize, &str)> {
+    use rnix::SyntaxKind::*;
+
+    let mut bindings = Vec::new();
+    let mut depth = 0usize;
+    let mut offset = 0;
+    let mut start = None;
+    for (kind, text) in rnix::tokenize(content) {
+        let token_start = offset;
+        offset += text.len();
+        match kind {
+            TOKEN_L_BRACE | TOKEN_L_BRACK | TOKEN_L_PAREN | TOKEN_INTERPOL_START => {
+                depth += 1;
+                if depth == 1 {
+                    start = Some(offset);
+                    continue;
+                }
+            }
+            TOKEN_R_BRACE | TOKEN_R_BRACK | TOKEN_R_PAREN | TOKEN_INTERPOL_END => {
+                if depth == 1 {
+                    if let Some(from) = start.take() {
+                        bindings.push((from, token_start));
+                    }
+                }
+                depth = depth.saturating_sub(1);
+            }
+            TOKEN_SEMICOLON if depth == 1 => {
+                if let Some(from) = start.replace(offset) {
+                    bindings.push((from, offset));
+                }
+            }
+            _ => {}
+        }
+    }
+    // An unclosed attrset still yields its last binding
+    if let Some(from) = start {
+        bindings.push((from, content.len()));
+    }
+
+    bindings
+        .into_iter()
+        .filter_map(|(from, to)| {
+            let text = &content[from..to];
+            // Comments are trivia: a binding starts at its first real token
+            let lead = text.len() - trim_trivia_start(text).len();
+            let text = text[lead..].trim_end();
+            (!text.is_empty()).then(|| (content[..from + lead].matches('\n').count() + 1, text))
+        })
+        .collect()
+}
+
+/// `text` without leading whitespace and `#` / `/* */` comments
+fn trim_trivia_start(mut text: &str) -> &str {
+    loop {
+        text = text.trim_start();
+        if let Some(rest) = text.strip_prefix('#') {
+            text = rest.split_once('\n').map_or("", |(_, rest)| rest);
+        } else if let Some(rest) = text.strip_prefix("/*") {
+            text = rest.split_once("*/").map_or("", |(_, rest)| rest);
+        } else {
+            return text;
+        }
+    }
+}
+
+/// Parses a TOML spec into the same pairs as a packages.nix:
+///
+/// ```toml
+/// [packages]
+/// nodejs = "20.11.0"
+/// python = "latest"
+/// ```
+///
+/// Entries keep their file order; TOML itself rejects duplicate keys.
+pub fn parse_toml_spec(path: &std::path::Path, content: &str) -> Result<PackageSpec> {
+    let mut table: toml::Table = toml::from_str(content)
+        .with_context(|| format!("TOML parse error in {}", path.display()))?;
+
+    if let Some(key) = table.keys().find(|key| *key != "packages") {
+        anyhow::bail!("Unknown key '{}' in {}: only a [packages] table is allowed", key, path.display());
+    }
+    let Some(packages) = table.remove("packages") else {
+        anyhow::bail!("No [packages] table in {}", path.display());
+    };
+    let toml::Value::Table(packages) = packages else {
+        anyhow::bail!("'packages' in {} must be a table", path.display());
+    };
+
+    let mut spec = PackageSpec::default();
+    for (attr_name, value) in packages {
+        let toml::Value::String(version) = value else {
+            anyhow::bail!(
+                "Package '{}' in {}: version must be a string like \"20.11.0\" or \"latest\"",
+                attr_name,
+                path.display()
+            );
+        };
+        spec.entries.push((attr_name, version));
+    }
+    Ok(spec)
+}
+
+// ─── Resolution ───────────────────────────────────────────────────────────────
+
+/// A spec entry matched to a database entry
+#[derive(Debug, Clone)]
+pub struct ResolvedPackage {
+    /// Version as written in the spec (e.g. "20.11.0" or "latest")
+    pub requested: String,
+
+    /// Matching database entry
+    pub entry: PackageEntry,
+}
+
+/// A spec entry with no matching database entry
+#[derive(Debug, Clone)]
+pub struct UnresolvedPackage {
+    pub attr_name: String,
+
+    /// Version as written in the spec
+    pub requested: String,
+
+    /// Why it didn't resolve (first line of the matching [`ResolvedSpec::errors`])
+    pub reason: String,
+
+    /// Up to 5 indexed versions of the package, newest first
+    pub available: Vec<String>,
+}
+
+/// Result of resolving a package spec against the database
+#[derive(Debug, Default)]
+pub struct ResolvedSpec {
+    /// Successfully resolved packages, in spec order
+    pub packages: Vec<ResolvedPackage>,
+
+    /// Human-readable resolution errors (missing packages/versions)
+    pub errors: Vec<String>,
+
+    /// The same failures as data, in spec order
+    pub unresolved: Vec<UnresolvedPackage>,
+
+    /// Cached nixpkgs tarball hashes for the resolved commits (commit → hash)
+    pub tarball_hashes: HashMap<String, String>,
+
+    /// Cached `fetchGit` narHashes (SRI) for the resolved commits (commit → hash)
+    pub git_narhashes: HashMap<String, String>,
+
+    /// Where the rendered expressions fetch nixpkgs from
+    pub upstream: Upstream,
+}
+
+impl ResolvedSpec {
+    /// Returns true if every spec entry resolved
+    pub fn is_complete(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    /// Unique commits in order of first appearance, so each nixpkgs snapshot
+    /// is fetched only once even if multiple packages share a commit.
+    pub fn unique_commits(&self) -> Vec<&str> {
+        let mut seen = std::collections::HashSet::new();
+        let mut commits = Vec::new();
+        for p in &self.packages {
+            if seen.insert(p.entry.commit_sha.as_str()) {
+                commits.push(p.entry.commit_sha.as_str());
+            }
+        }
+        commits
+    }
+}
+
+/// Resolves (attr_name, version) pairs against the database.
+///
+/// `"latest"` resolves to the newest version by semver-aware ordering,
+/// skipping release candidates, betas and alphas unless `include_prerelease`
+/// is set. Missing packages or versions are collected into
+/// [`ResolvedSpec::errors`] (with up to 5 suggested versions) instead of
+/// failing early.
+pub fn resolve_spec(db: &ArchiverDb, spec: &[(String, String)], include_prerelease: bool) -> Result<ResolvedSpec> {
+    let mut resolved = ResolvedSpec::default();
+
+    for (attr_name, version) in spec {
+        let entry = if version == "latest" {
+            let available = db.get_all_versions(attr_name)?;
+            if available.is_empty() {
+                let reason = format!("No versions found for package '{}'", attr_name);
+                resolved.errors.push(reason.clone());
+                resolved.unresolved.push(UnresolvedPackage {
+                    attr_name: attr_name.clone(),
+                    requested: version.clone(),
+                    reason,
+                    available: Vec::new(),
+                });
+                continue;
+            }
+            let sorted = sort_versions_semver(available);
+            match sorted.iter().position(|e| include_prerelease || !is_prerelease(&e.version)) {
+                Some(i) => sorted[i].clone(),
+                None => {
+                    let reason = format!(
+                        "No stable versions found for package '{}' (pass --include-prerelease to allow pre-releases)",
+                        attr_name
+                    );
+                    let suggestions: Vec<String> = sorted.iter().take(5).map(|e| e.version.clone()).collect();
+                    resolved.errors.push(reason.clone());
+                    resolved.errors.push(format!("         Available versions: {}", suggestions.join(", ")));
+                    resolved.unresolved.push(UnresolvedPackage {
+                        attr_name: attr_name.clone(),
+                        requested: version.clone(),
+                        reason,
+                        available: suggestions,
+                    });
+                    continue;
+                }
+            }
+        } else {
+            match db.get(attr_name, version)? {
+                Some(entry) => entry,
+                None => {
+                    let reason = format!("Package {}:{} not found in database", attr_name, version);
+                    resolved.errors.push(reason.clone());
+                    let available = db.get_all_versions(attr_name)?;
+                    let suggestions: Vec<String> = sort_versions_semver(available)
+                        .iter()
+                        .take(5)
+                        .map(|e| e.version.clone())
+                        .collect();
+                    if !suggestions.is_empty() {
+                        resolved.errors.push(format!(
+                            "         Available versions: {}",
+                            suggestions.join(", ")
+                        ));
+                    } else {
+                        resolved.errors.push(format!(
+                            "         No versions available for package '{}'",
+                            attr_name
+                        ));
+                    }
+                    resolved.unresolved.push(UnresolvedPackage {
+                        attr_name: attr_name.clone(),
+                        requested: version.clone(),
+                        reason,
+                        available: suggestions,
+                    });
+                    continue;
+                }
+            }
+        };
+
+        if !resolved.tarball_hashes.contains_key(&entry.commit_sha) {
+            if let Some(hash) = db.get_tarball_hash(&entry.commit_sha)? {
+                resolved.tarball_hashes.insert(entry.commit_sha.clone(), hash);
+            }
+        }
+        if !resolved.git_narhashes.contains_key(&entry.commit_sha) {
+            if let Some(hash) = db.get_git_narhash(&entry.commit_sha)? {
+                resolved.git_narhashes.insert(entry.commit_sha.clone(), hash);
+            }
+        }
+
+        resolved.packages.push(ResolvedPackage {
+            requested: version.clone(),
+            entry,
+        });
+    }
+
+    Ok(resolved)
+}
+
+// ─── Report ───────────────────────────────────────────────────────────────────
+
+/// Machine-readable summary of a resolution for CI (`generate --report-json`).
+///
+/// Lists every resolved package with its commit and commit date, every
+/// unresolved one with the reason and suggested versions, and the spec
+/// entries that were skipped as unsupported.
+pub fn report_json(resolved: &ResolvedSpec, skipped: &[String]) -> serde_json::Value {
+    let packages: Vec<_> = resolved.packages.iter().map(|p| {
+        let entry = &p.entry;
+        serde_json::json!({
+            "attr_name": entry.attr_name,
+            "requested": p.requested,
+            "version": entry.version,
+            "commit": entry.commit_sha,
+            "commit_timestamp": entry.timestamp,
+            "commit_date": commit_date(entry.timestamp),
+            "tarball_hash": resolved.tarball_hashes.get(&entry.commit_sha),
+            "git_narhash": resolved.git_narhashes.get(&entry.commit_sha),
+            "nar_hash": entry.nar_hash,
+        })
+    }).collect();
+
+    let errors: Vec<_> = resolved.unresolved.iter().map(|u| {
+        serde_json::json!({
+            "attr_name": u.attr_name,
+            "requested": u.requested,
+            "error": u.reason,
+            "available_versions": u.available,
+        })
+    }).collect();
+
+    serde_json::json!({
+        "complete": resolved.is_complete(),
+        "packages": packages,
+        "errors": errors,
+        "skipped": skipped,
+    })
+}
+
+/// UTC commit date as RFC 3339 (`2024-01-15T10:30:00Z`)
+fn commit_date(timestamp: u64) -> String {
+    chrono::DateTime::<chrono::Utc>::from_timestamp(timestamp as i64, 0)
+        .unwrap_or(chrono::DateTime::<chrono::Utc>::MIN_UTC)
+        .format("%Y-%m-%dT%H:%M:%SZ")
+        .to_string()
+}
+
+// ─── Rendering ────────────────────────────────────────────────────────────────
+
+/// Output format for [`render`]
+#[derive(Debug, Clone)]
+pub enum OutputFormat {
+    /// frozen.nix attrset; `nixpkgs` points at a local nixpkgs git repo to
+    /// fetch from instead of GitHub
+    FrozenNix { nixpkgs: Option<PathBuf> },
+
+    /// flake.nix with one `nixpkgs_<sha>` input per commit
+    FlakeNix,
+
+    /// flake.lock pinning every input that has a cached tarball hash
+    FlakeLock,
+
+    /// A list of derivations for `mkShell`'s `buildInputs`; `nixpkgs` as
+    /// for [`OutputFormat::FrozenNix`]
+    BuildInputs { nixpkgs: Option<PathBuf> },
+
+    /// frozen.nix attrset whose snapshots are `fetchFromGitHub` calls with
+    /// explicit owner/repo/rev/sha256
+    FetchFromGitHub,
+
+    /// The whole nixpkgs snapshot of the first resolved package, bound as
+    /// `pkgs`; `nixpkgs` as for [`OutputFormat::FrozenNix`]
+    Snapshot { nixpkgs: Option<PathBuf> },
+}
+
+/// Renders a resolved spec into Nix (or flake.lock JSON) source text.
+pub fn render(resolved: &ResolvedSpec, format: &OutputFormat) -> String {
+    match format {
+        OutputFormat::FrozenNix { nixpkgs } => render_frozen_nix(resolved, nixpkgs.as_deref()),
+        OutputFormat::FlakeNix => render_flake_nix(resolved),
+        OutputFormat::FlakeLock => render_flake_lock(resolved),
+        OutputFormat::BuildInputs { nixpkgs } => render_build_inputs(resolved, nixpkgs.as_deref()),
+        OutputFormat::FetchFromGitHub => render_fetch_from_github(resolved),
+        OutputFormat::Snapshot { nixpkgs } => render_snapshot(resolved, nixpkgs.as_deref()),
+    }
+}
+
+/// Resolves `spec` against `db` and renders it in `format`, in one call.
+///
+/// Unlike [`resolve_spec`], unresolvable packages are an error (listing every
+/// failure), so the returned text always pins the whole spec. Use the
+/// separate steps when partial results or a [`report_json`] are needed.
+pub fn generate_frozen(
+    db: &ArchiverDb,
+    spec: &[(String, String)],
+    format: &OutputFormat,
+    upstream: &Upstream,
+    include_prerelease: bool,
+) -> Result<String> {
+    let mut resolved = resolve_spec(db, spec, include_prerelease)?;
+    resolved.upstream = upstream.clone();
+    if !resolved.is_complete() {
+        anyhow::bail!("Failed to resolve {} package(s):\n{}", resolved.unresolved.len(), resolved.errors.join("\n"));
+    }
+    Ok(render(&resolved, format))
+}
+
+/// Upstream of the repository the database was indexed from: the `origin`
+/// URL of the newest index run that recorded a usable one. `None` for
+/// local-only repositories (and databases without provenance), in which
+/// case `NixOS/nixpkgs` on github.com is the natural default.
+pub fn recorded_upstream(db: &ArchiverDb) -> Result<Option<Upstream>> {
+    Ok(db
+        .index_runs()?
+        .iter()
+        .rev()
+        .find_map(|run| run.repo_url.as_deref().and_then(Upstream::from_remote_url)))
+}
+
+fn nixpkgs_var(commit: &str) -> String {
+    format!("nixpkgs_{}", commit)
+}
+
+/// Nix source expression for the nixpkgs snapshot at `commit`
+fn source_expr(resolved: &ResolvedSpec, nixpkgs: Option<&std::path::Path>, commit: &str) -> String {
+    //  1. --nixpkgs <path>  → builtins.fetchGit file:// (local bare repo, offline)
+    //  2. sha256 in DB      → fetchTarball { sha256 = "..." } (fully pinned tarball)
+    //  3. default           → builtins.fetchGit { url = upstream; rev = commit; }
+    //                         plus narHash when `prefetch-hashes --git-narhash` cached
+    //                         one, so the checkout is verified like a tarball
+    if let Some(local) = nixpkgs {
+        return format!(
+            "builtins.fetchGit {{ url = \"file://{}\"; rev = \"{}\"; }}",
+            local.display(), commit
+        );
+    }
+    if let Some(hash) = resolved.tarball_hashes.get(commit) {
+        let url = resolved.upstream.tarball_url(commit);
+        return format!("fetchTarball {{ url = \"{}\"; sha256 = \"{}\"; }}", url, hash);
+    }
+    // Default: builtins.fetchGit — the commit SHA identifies the tree,
+    // narHash additionally pins the checked-out content
+    let url = resolved.upstream.git_url();
+    match resolved.git_narhashes.get(commit) {
+        Some(nar_hash) => format!(
+            "builtins.fetchGit {{ url = \"{}\"; rev = \"{}\"; narHash = \"{}\"; }}",
+            url, commit, nar_hash
+        ),
+        None => format!(
+            "builtins.fetchGit {{ url = \"{}\"; rev = \"{}\"; }}",
+            url, commit
+        ),
+    }
+}
+
+/// `let` block binding each unique nixpkgs snapshot once
+fn snapshot_bindings(resolved: &ResolvedSpec, nixpkgs: Option<&std::path::Path>) -> String {
+    let mut bindings = String::from("let\n");
+    for commit in resolved.unique_commits() {
+        bindings.push_str(&format!(
+            "  {} = {};\n",
+            nixpkgs_var(commit),
+            source_expr(resolved, nixpkgs, commit)
+        ));
+    }
+    bindings.push_str("in\n");
+    bindings
+}
+
+/// Attribute path in selectable form: components that aren't plain Nix
+/// identifiers are quoted (`vscode-extensions.foo."1password"`)
+fn nix_attr_path(attr_name: &str) -> String {
+    attr_name
+        .split('.')
+        .map(|part| {
+            let is_ident = part.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                && part.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '\''));
+            if is_ident {
+                part.to_string()
+            } else {
+                format!("\"{}\"", part.replace('\\', "\\\\").replace('"', "\\\"").replace("${", "\\${"))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+fn render_build_inputs(resolved: &ResolvedSpec, nixpkgs: Option<&std::path::Path>) -> String {
+    let mut nix_content = String::from("# Generated by nix-archiver\n");
+    nix_content.push_str("# Pinned packages as a list, e.g. mkShell { buildInputs = import ./this-file.nix; }\n\n");
+    nix_content.push_str(&snapshot_bindings(resolved, nixpkgs));
+    nix_content.push_str("[\n");
+
+    for p in &resolved.packages {
+        let entry = &p.entry;
+        nix_content.push_str(&format!(
+            "  # {} v{} (commit: {})\n",
+            entry.attr_name, entry.version, &entry.commit_sha
+        ));
+        nix_content.push_str(&format!(
+            "  (import {} {{}}).{}\n",
+            nixpkgs_var(&entry.commit_sha),
+            nix_attr_path(&entry.attr_name)
+        ));
+    }
+
+    nix_content.push_str("]\n");
+    nix_content
+}
+
+fn render_frozen_nix(resolved: &ResolvedSpec, nixpkgs: Option<&std::path::Path>) -> String {
+    let mut nix_content = String::from("# Generated by nix-archiver\n");
+    nix_content.push_str("# This file pins packages to specific historical versions from Nixpkgs\n\n");
+
+    // let-bindings for each unique nixpkgs snapshot
+    nix_content.push_str(&snapshot_bindings(resolved, nixpkgs));
+    nix_content.push_str(&frozen_attrset(resolved));
+    nix_content
+}
+
+/// One consistent nixpkgs instead of per-package imports: the snapshot in
+/// which the first resolved package had its version, imported once
+fn render_snapshot(resolved: &ResolvedSpec, nixpkgs: Option<&std::path::Path>) -> String {
+    let mut nix_content = String::from("# Generated by nix-archiver\n");
+    let Some(p) = resolved.packages.first() else {
+        nix_content.push_str("throw \"no package resolved to pin a nixpkgs snapshot\"\n");
+        return nix_content;
+    };
+    let entry = &p.entry;
+    nix_content.push_str(&format!(
+        "# The nixpkgs snapshot with {} v{} (commit: {})\n\n",
+        entry.attr_name, entry.version, &entry.commit_sha
+    ));
+    nix_content.push_str("let\n");
+    nix_content.push_str(&format!(
+        "  pkgs = import ({}) {{}};\n",
+        source_expr(resolved, nixpkgs, &entry.commit_sha)
+    ));
+    nix_content.push_str("in\npkgs\n");
+    nix_content
+}
+
+/// `{ attr = import nixpkgs_<sha> {}; … }` for the bindings made above it
+fn frozen_attrset(resolved: &ResolvedSpec) -> String {
+    let mut nix_content = String::from("{\n");
+
+    for p in &resolved.packages {
+        let entry = &p.entry;
+        nix_content.push_str(&format!(
+            "  # {} v{} (commit: {})\n",
+            entry.attr_name, entry.version, &entry.commit_sha
+        ));
+        nix_content.push_str(&format!(
+            "  {} = import {} {{}};\n\n",
+            entry.attr_name,
+            nixpkgs_var(&entry.commit_sha)
+        ));
+    }
+
+    nix_content.push_str("}\n");
+    nix_content
+}
+
+/// Like frozen.nix, but each snapshot is a `pkgs.fetchFromGitHub` call.
+/// fetchFromGitHub hashes the unpacked tarball, so the cached tarball hash
+/// pins it as is; commits without one get `lib.fakeSha256`, whose mismatch
+/// error prints the real hash.
+fn render_fetch_from_github(resolved: &ResolvedSpec) -> String {
+    let upstream = &resolved.upstream;
+    let mut nix_content = String::from("# Generated by nix-archiver\n");
+    nix_content.push_str("# This file pins packages to specific historical versions from Nixpkgs\n\n");
+    nix_content.push_str("{ pkgs ? import <nixpkgs> { } }:\n\n");
+
+    nix_content.push_str("let\n");
+    for commit in resolved.unique_commits() {
+        nix_content.push_str(&format!("  {} = pkgs.fetchFromGitHub {{\n", nixpkgs_var(commit)));
+        if !upstream.is_github_com() {
+            nix_content.push_str(&format!("    githubBase = \"{}\";\n", upstream.host));
+        }
+        nix_content.push_str(&format!("    owner = \"{}\";\n", upstream.owner));
+        nix_content.push_str(&format!("    repo = \"{}\";\n", upstream.repo));
+        nix_content.push_str(&format!("    rev = \"{}\";\n", commit));
+        match resolved.tarball_hashes.get(commit) {
+            Some(hash) => nix_content.push_str(&format!("    sha256 = \"{}\";\n", hash)),
+            None => nix_content.push_str("    sha256 = pkgs.lib.fakeSha256; # no cached hash; see `nix-archiver prefetch-hashes`\n"),
+        }
+        nix_content.push_str("  };\n");
+    }
+    nix_content.push_str("in\n");
+    nix_content.push_str(&frozen_attrset(resolved));
+    nix_content
+}
+
+fn render_flake_nix(resolved: &ResolvedSpec) -> String {
+    let mut flake = String::from("# Generated by nix-archiver\n");
+    flake.push_str("# This flake pins packages to specific historical versions from Nixpkgs\n");
+    flake.push_str("{\n");
+    flake.push_str("  description = \"Packages pinned by nix-archiver\";\n\n");
+    flake.push_str("  inputs = {\n");
+    for commit in resolved.unique_commits() {
+        flake.push_str(&format!(
+            "    {}.url = \"{}\";\n",
+            nixpkgs_var(commit), resolved.upstream.flake_ref(commit)
+        ));
+    }
+    flake.push_str("  };\n\n");
+    flake.push_str("  outputs = inputs:\n");
+    flake.push_str("    let\n");
+    flake.push_str("      systems = [ \"x86_64-linux\" \"aarch64-linux\" \"x86_64-darwin\" \"aarch64-darwin\" ];\n");
+    flake.push_str("      forAllSystems = f: builtins.listToAttrs (map (system: { name = system; value = f system; }) systems);\n");
+    flake.push_str("    in\n");
+    flake.push_str("    {\n");
+    flake.push_str("      packages = forAllSystems (system: {\n");
+    for p in &resolved.packages {
+        let entry = &p.entry;
+        flake.push_str(&format!(
+            "        # {} v{} (commit: {})\n",
+            entry.attr_name, entry.version, entry.commit_sha
+        ));
+        flake.push_str(&format!(
+            "        {} = inputs.{}.legacyPackages.${{system}}.{};\n",
+            entry.attr_name, nixpkgs_var(&entry.commit_sha), entry.attr_name
+        ));
+    }
+    flake.push_str("      });\n");
+    flake.push_str("    };\n");
+    flake.push_str("}\n");
+    flake
+}
+
+/// Inputs whose tarball hash is cached are locked by rev + narHash so
+/// `nix build` needs no network access to lock; the rest are left out and
+/// Nix will lock them on first use.
+fn render_flake_lock(resolved: &ResolvedSpec) -> String {
+    let mut nodes = serde_json::Map::new();
+    let mut root_inputs = serde_json::Map::new();
+
+    for commit in resolved.unique_commits() {
+        let Some(nar_hash) = resolved.tarball_hashes.get(commit).and_then(|h| nix_hash_to_sri(h)) else {
+            continue;
+        };
+        let input = nixpkgs_var(commit);
+        let last_modified = resolved
+            .packages
+            .iter()
+            .find(|p| p.entry.commit_sha == commit)
+            .map(|p| p.entry.timestamp)
+            .unwrap_or(0);
+
+        let upstream = &resolved.upstream;
+        let mut locked = serde_json::json!({
+            "lastModified": last_modified,
+            "narHash": nar_hash,
+            "owner": upstream.owner,
+            "repo": upstream.repo,
+            "rev": commit,
+            "type": "github",
+        });
+        let mut original = serde_json::json!({
+            "owner": upstream.owner,
+            "repo": upstream.repo,
+            "rev": commit,
+            "type": "github",
+        });
+        // Nix omits `host` for github.com and writes it for Enterprise hosts
+        if !upstream.is_github_com() {
+            locked["host"] = upstream.host.clone().into();
+            original["host"] = upstream.host.clone().into();
+        }
+        nodes.insert(input.clone(), serde_json::json!({ "locked": locked, "original": original }));
+        root_inputs.insert(input.clone(), serde_json::Value::String(input));
+    }
+    nodes.insert("root".to_string(), serde_json::json!({ "inputs": root_inputs }));
+
+    let lock = serde_json::json!({
+        "nodes": nodes,
+        "root": "root",
+        "version": 7,
+    });
+
+    // Serializing a Value built from json! never fails
+    serde_json::to_string_pretty(&lock).unwrap_or_default() + "\n"
+}
@@ -1,104 +1,53 @@
 //! Helper functions for CLI operations
 
 use anyhow::{Context, Result};
+use archiver_core::version::{compare_versions, parse_version_key};
 use archiver_core::PackageEntry;
 use chrono::{DateTime, Utc};
-
-/// Parsed version key for comparison.
-/// Represents versions like: 1.20.2, 1.26rc3, 1.18beta1, 1.18.0-alpha.1
-struct VersionKey {
-    /// Numeric components, e.g. [1, 20, 2] for "1.20.2"
-    nums: Vec<u64>,
-    /// Pre-release tier: 3=stable, 2=rc, 1=beta, 0=alpha (higher = newer)
-    pre_tier: u8,
-    /// Pre-release index, e.g. 3 for "rc3"
-    pre_num: u64,
-}
-
-fn parse_version_key(v: &str) -> VersionKey {
-    // Match: numeric parts, optional pre-release tag, optional trailing number
-    // Handles: "1.20.2", "1.26rc3", "1.18beta1", "1.18rc1", "1.18.0-beta.1"
-    let v_lower = v.to_ascii_lowercase();
-    // Normalise semver pre-release separator: "1.18.0-rc.2" → "1.18.0rc2"
-    let v_norm = v_lower.replace("-rc.", "rc").replace("-beta.", "beta").replace("-alpha.", "alpha");
-
-    // Split at the first non-numeric, non-dot character
-    let tag_start = v_norm.find(|c: char| !c.is_ascii_digit() && c != '.');
-    let (num_part, rest) = match tag_start {
-        Some(i) => (&v_norm[..i], &v_norm[i..]),
-        None    => (v_norm.as_str(), ""),
-    };
-
-    let nums: Vec<u64> = num_part
-        .split('.')
-        .filter(|s| !s.is_empty())
-        .map(|s| s.parse().unwrap_or(0))
-        .collect();
-
-    let (pre_tier, pre_num) = if rest.is_empty() {
-        (3u8, 0u64)
-    } else if rest.starts_with("rc") {
-        let n = rest[2..].parse().unwrap_or(0);
-        (2, n)
-    } else if rest.starts_with("beta") {
-        let n = rest[4..].parse().unwrap_or(0);
-        (1, n)
-    } else if rest.starts_with("alpha") {
-        let n = rest[5..].parse().unwrap_or(0);
-        (0, n)
-    } else {
-        // Unknown suffix — treat as stable but preserve trailing digits for ordering
-        let n: u64 = rest.chars().filter(|c| c.is_ascii_digit()).collect::<String>().parse().unwrap_or(0);
-        (3, n)
-    };
-
-    VersionKey { nums, pre_tier, pre_num }
-}
-
-fn cmp_num_vecs(a: &[u64], b: &[u64]) -> std::cmp::Ordering {
-    let len = a.len().max(b.len());
-    for i in 0..len {
-        let av = a.get(i).copied().unwrap_or(0);
-        let bv = b.get(i).copied().unwrap_or(0);
-        match av.cmp(&bv) {
-            std::cmp::Ordering::Equal => continue,
-            other => return other,
-        }
-    }
-    std::cmp::Ordering::Equal
-}
+use std::collections::{HashMap, HashSet};
 
 /// Sorts versions newest-first using a natural version comparator.
 ///
 /// Correctly handles: stable releases, rc, beta, alpha suffixes.
 /// Examples (newest first): 1.21 > 1.21rc3 > 1.21rc2 > 1.21beta1 > 1.20.2 > 1.20.1
 pub fn sort_versions_semver(mut versions: Vec<PackageEntry>) -> Vec<PackageEntry> {
-    versions.sort_by(|a, b| {
-        let ka = parse_version_key(&a.version);
-        let kb = parse_version_key(&b.version);
-
-        // 1. Compare numeric parts (newest first → reverse)
-        match cmp_num_vecs(&ka.nums, &kb.nums).reverse() {
-            std::cmp::Ordering::Equal => {}
-            other => return other,
-        }
-        // 2. Same numeric version → stable > rc > beta > alpha (reverse for newest first)
-        match ka.pre_tier.cmp(&kb.pre_tier).reverse() {
-            std::cmp::Ordering::Equal => {}
-            other => return other,
-        }
-        // 3. Same tag → higher index is newer (rc3 > rc2, reverse for newest first)
-        ka.pre_num.cmp(&kb.pre_num).reverse()
-    });
+    versions.sort_by(|a, b| compare_versions(&b.version, &a.version));
     versions
 }
 
+/// Sorts versions by commit timestamp, most recent first; versions pinned
+/// to the same moment keep the natural version order.
+///
+/// For calendar versions and git snapshots, whose strings don't compare
+/// meaningfully, this is the order in which they actually appeared.
+pub fn sort_versions_by_date(versions: Vec<PackageEntry>) -> Vec<PackageEntry> {
+    let mut versions = sort_versions_semver(versions);
+    versions.sort_by_key(|entry| std::cmp::Reverse(entry.timestamp));
+    versions
+}
+
+/// Number of distinct versions; per-platform entries of one version count once
+pub fn version_count(entries: &[PackageEntry]) -> usize {
+    entries.iter().map(|entry| entry.version.as_str()).collect::<HashSet<_>>().len()
+}
+
+/// The entries of the first `limit` distinct versions in `entries`, in
+/// order, with every platform entry of those versions
+pub fn first_versions(entries: &[PackageEntry], limit: usize) -> Vec<&PackageEntry> {
+    let mut kept = HashSet::new();
+    entries
+        .iter()
+        .filter(|entry| kept.contains(entry.version.as_str()) || (kept.len() < limit && kept.insert(entry.version.as_str())))
+        .collect()
+}
+
 /// Filters versions based on criteria
 pub fn filter_versions(
     versions: Vec<PackageEntry>,
     major: Option<u64>,
     pattern: Option<&str>,
     since: Option<&str>,
+    since_version: Option<&str>,
 ) -> Result<Vec<PackageEntry>> {
     use regex::Regex;
 
@@ -106,21 +55,17 @@ pub fn filter_versions(
 
     // Filter by major version — use our own parser instead of semver crate
     if let Some(major_ver) = major {
-        filtered = filtered.into_iter()
-            .filter(|entry| {
-                let key = parse_version_key(&entry.version);
-                key.nums.first().copied().unwrap_or(u64::MAX) == major_ver
-            })
-            .collect();
+        filtered.retain(|entry| {
+            let key = parse_version_key(&entry.version);
+            key.nums.first().copied().unwrap_or(u64::MAX) == major_ver
+        });
     }
     
     // Filter by regex pattern
     if let Some(pat) = pattern {
         let re = Regex::new(pat)
             .with_context(|| format!("Invalid regex pattern: {}", pat))?;
-        filtered = filtered.into_iter()
-            .filter(|entry| re.is_match(&entry.version))
-            .collect();
+        filtered.retain(|entry| re.is_match(&entry.version));
     }
     
     // Filter by date
@@ -133,18 +78,84 @@ pub fn filter_versions(
             .and_utc()
             .timestamp() as u64;
         
-        filtered = filtered.into_iter()
-            .filter(|entry| entry.timestamp >= since_timestamp)
-            .collect();
+        filtered.retain(|entry| entry.timestamp >= since_timestamp);
     }
-    
+
+    // Filter by version: strictly newer than the given one, which doesn't
+    // have to be indexed itself
+    if let Some(floor) = since_version {
+        filtered.retain(|entry| compare_versions(&entry.version, floor).is_gt());
+    }
+
     Ok(filtered)
 }
 
+/// Converts a sha256 hash as printed by `nix-prefetch-url` (52-char Nix base32)
+/// into SRI form (`sha256-<base64>`), as required by `narHash` in flake.lock.
+///
+/// Hashes already in SRI form are returned normalized. Returns `None` if the
+/// input is neither.
+pub fn nix_hash_to_sri(hash: &str) -> Option<String> {
+    archiver_core::hash::normalize_sha256(hash)
+}
+
+/// Extracts the top-level namespace (package set) from an attr_name.
+///
+/// Examples:
+///   "vscode-extensions.biomejs.biome" → "vscode-extensions"
+///   "python313Packages.numpy"          → "python313Packages"
+///   "python314"                        → "(top-level)"
+pub fn attr_namespace(attr_name: &str) -> &str {
+    match attr_name.find('.') {
+        Some(pos) => &attr_name[..pos],
+        None => "(top-level)",
+    }
+}
+
+/// Keeps only the packages whose top-level namespace equals `set`
+/// (e.g. `python313Packages`; `(top-level)` selects un-namespaced names).
+pub fn filter_by_set<V>(matches: HashMap<String, V>, set: &str) -> HashMap<String, V> {
+    matches
+        .into_iter()
+        .filter(|(name, _)| attr_namespace(name) == set)
+        .collect()
+}
+
+/// Orders the names of a multi-package search result by relevance to
+/// `query` (case-insensitive): the exact name first, then names starting
+/// with the query, then names whose last component does
+/// (`python3Packages.biome`), then the rest. Within each group shorter
+/// names come first, then the one with the most recently indexed version,
+/// then alphabetical order.
+///
+/// `search biome` thus lists `biome` ahead of `vscode-extensions.biomejs.biome`.
+pub fn rank_matches(query: &str, matches: &HashMap<String, Vec<PackageEntry>>) -> Vec<String> {
+    let query = query.to_lowercase();
+    let tier = |name: &str| {
+        let name = name.to_lowercase();
+        let leaf = name.rsplit('.').next().unwrap_or(&name);
+        if name == query {
+            0
+        } else if name.starts_with(&query) {
+            1
+        } else if leaf.starts_with(&query) {
+            2
+        } else {
+            3
+        }
+    };
+    let mut names: Vec<String> = matches.keys().cloned().collect();
+    names.sort_by_cached_key(|name| {
+        let newest = matches[name].iter().map(|entry| entry.timestamp).max().unwrap_or(0);
+        (tier(name), name.len(), std::cmp::Reverse(newest), name.clone())
+    });
+    names
+}
+
 /// Formats timestamp as relative time (e.g., "2 days ago")
 pub fn format_relative_time(timestamp: u64) -> String {
     let dt = DateTime::<Utc>::from_timestamp(timestamp as i64, 0)
-        .unwrap_or_else(|| DateTime::<Utc>::MIN_UTC);
+        .unwrap_or(DateTime::<Utc>::MIN_UTC);
     let now = Utc::now();
     let duration = now.signed_duration_since(dt);
     
@@ -171,6 +182,6 @@ pub fn format_relative_time(timestamp: u64) -> String {
 /// Formats Unix timestamp to readable date
 pub fn format_timestamp(timestamp: u64) -> String {
     let dt = DateTime::<Utc>::from_timestamp(timestamp as i64, 0)
-        .unwrap_or_else(|| DateTime::<Utc>::MIN_UTC);
+        .unwrap_or(DateTime::<Utc>::MIN_UTC);
     dt.format("%Y-%m-%d %H:%M").to_string()
 }
@@ -0,0 +1,10 @@
+//! Nix-Archiver CLI library - reusable pieces of the command-line tool
+//!
+//! Exposes the spec resolution and Nix rendering used by `generate` so they
+//! can be tested and embedded without touching the filesystem or printing,
+//! plus the JSON Lines export/import format.
+
+pub mod export;
+pub mod generate;
+pub mod helpers;
+pub mod prefetch;
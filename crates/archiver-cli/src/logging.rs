@@ -0,0 +1,60 @@
+//! Logger setup: decorated text for terminals, JSON lines for log pipelines
+
+use log::kv::{self, VisitSource};
+use serde_json::{Map, Value};
+use std::io::Write;
+
+/// How log records are written to stderr
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogFormat {
+    /// Human-readable lines (env_logger's default format)
+    Text,
+    /// One JSON object per record: timestamp, level, target, message and
+    /// any structured fields (batch, commits_done, packages_inserted, ...)
+    Json,
+}
+
+/// Initializes the global logger with `filter` (RUST_LOG still wins)
+pub fn init(filter: &str, format: LogFormat, no_color: bool) {
+    let mut logger = env_logger::Builder::from_env(
+        env_logger::Env::default().default_filter_or(filter)
+    );
+    if no_color || format == LogFormat::Json {
+        logger.write_style(env_logger::WriteStyle::Never);
+    }
+    if format == LogFormat::Json {
+        logger.format(|buf, record| {
+            let mut fields = Map::new();
+            fields.insert("timestamp".into(), chrono::Utc::now().to_rfc3339().into());
+            fields.insert("level".into(), record.level().as_str().into());
+            fields.insert("target".into(), record.target().into());
+            fields.insert("message".into(), record.args().to_string().into());
+            // Structured fields only add keys; they never replace the ones above
+            let _ = record.key_values().visit(&mut JsonFields(&mut fields));
+            writeln!(buf, "{}", Value::Object(fields))
+        });
+    }
+    logger.init();
+}
+
+/// Copies a record's key-value pairs into a JSON object, keeping numbers
+/// and booleans typed
+struct JsonFields<'a>(&'a mut Map<String, Value>);
+
+impl<'kvs> VisitSource<'kvs> for JsonFields<'_> {
+    fn visit_pair(&mut self, key: kv::Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        let json = if let Some(n) = value.to_u64() {
+            n.into()
+        } else if let Some(n) = value.to_i64() {
+            n.into()
+        } else if let Some(b) = value.to_bool() {
+            b.into()
+        } else if let Some(f) = value.to_f64() {
+            serde_json::Number::from_f64(f).map_or(Value::Null, Value::Number)
+        } else {
+            value.to_string().into()
+        };
+        self.0.entry(key.as_str()).or_insert(json);
+        Ok(())
+    }
+}
@@ -6,15 +6,21 @@
 //! - Generating frozen.nix files with pinned versions
 
 mod commands;
-mod helpers;
+mod logging;
 mod output;
 
 use anyhow::{Context, Result};
+use archiver_core::Upstream;
 use archiver_db::ArchiverDb;
 use clap::{Parser, Subcommand};
+use archiver_index::DEFAULT_MAX_BLOB_SIZE;
+use std::io::IsTerminal;
 use std::path::PathBuf;
 
-use commands::{cmd_index, cmd_search, cmd_generate, cmd_stats};
+use archiver_cli::export::{Compression, HistoryFormat};
+use archiver_cli::generate::recorded_upstream;
+use logging::LogFormat;
+use commands::{cmd_index, cmd_search, cmd_search_description, SearchFormat, SearchSort, cmd_generate, GenerateFormat, cmd_stats, cmd_prefetch_hashes, PrefetchOrder, cmd_export, cmd_export_package, cmd_import, cmd_debug, cmd_provenance, cmd_doctor, cmd_reparse, cmd_provides, cmd_watch, cmd_at_date, cmd_top, cmd_db_diff, cmd_parse, cmd_missing_hashes, cmd_names, cmd_rekey};
 
 #[derive(Parser)]
 #[command(name = "nix-archiver")]
@@ -26,54 +32,155 @@ struct Cli {
     database: PathBuf,
 
     /// Log level
-    #[arg(long, default_value = "info")]
+    #[arg(long, default_value = "info", conflicts_with_all = ["quiet", "verbose"])]
     log_level: String,
 
+    /// Only log errors (hides progress output)
+    #[arg(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Log more: -v for debug, -vv for trace
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Log record format: decorated text, or one JSON object per line for
+    /// log aggregation (with structured progress fields)
+    #[arg(long, value_enum, default_value = "text", global = true)]
+    log_format: LogFormat,
+
+    /// Disable colored output (also set by NO_COLOR, or when stdout isn't a terminal)
+    #[arg(long, global = true)]
+    no_color: bool,
+
+    /// Where generated expressions and prefetch-hashes fetch nixpkgs from,
+    /// as HOST/OWNER/REPO (e.g. a GitHub Enterprise mirror). Defaults to the
+    /// `origin` recorded when indexing, else github.com/NixOS/nixpkgs
+    #[arg(long, global = true, env = "NIX_ARCHIVER_UPSTREAM")]
+    upstream: Option<Upstream>,
+
     #[command(subcommand)]
     command: Commands,
 }
 
+impl Cli {
+    /// Effective log filter: -q / -v / -vv shortcuts, else --log-level
+    fn log_filter(&self) -> &str {
+        if self.quiet {
+            return "error";
+        }
+        match self.verbose {
+            0 => &self.log_level,
+            1 => "debug",
+            _ => "trace",
+        }
+    }
+
+    /// Whether colors must be turned off: --no-color or NO_COLOR always win;
+    /// otherwise colors are dropped for non-terminal stdout unless
+    /// CLICOLOR_FORCE asks for them.
+    fn colors_disabled(&self) -> bool {
+        let env_set = |name: &str| std::env::var_os(name).is_some_and(|v| !v.is_empty() && v != "0");
+        if self.no_color || env_set("NO_COLOR") {
+            return true;
+        }
+        !env_set("CLICOLOR_FORCE") && !std::io::stdout().is_terminal()
+    }
+}
+
+// Parsed once per run, so the size of the `Index` variant doesn't matter
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand)]
 enum Commands {
     /// Indexes Nixpkgs repository
-    Index {
+    Index(IndexArgs),
+
+    /// Keeps the database current: periodically fetches the repository and
+    /// indexes commits that appeared on the watched branch since the last cycle
+    Watch {
+        /// Path to local Nixpkgs repository (with --clone: where to keep the clone).
+        /// Without --clone, its `origin` remote is fetched each cycle.
+        #[arg(short, long, required_unless_present = "clone")]
+        repo: Option<PathBuf>,
+
+        /// Repository URL to clone (bare) on start and fetch from each cycle
+        #[arg(long)]
+        clone: Option<String>,
+
+        /// Branch or ref to follow (e.g. origin/master for a regular clone)
+        #[arg(long, default_value = "HEAD")]
+        rev: String,
+
+        /// Seconds to sleep between cycles
+        #[arg(long, default_value = "300")]
+        interval: u64,
+
+        /// Stop after this many cycles (default: run until interrupted)
+        #[arg(long)]
+        cycles: Option<usize>,
+
+        /// Batch size for parallel processing (default: 500)
+        #[arg(short = 'b', long, default_value = "500")]
+        batch_size: usize,
+
+        /// Flush the database to disk every N batches (default: 5)
+        #[arg(long, default_value = "5", value_parser = parse_at_least_one)]
+        flush_every: usize,
+
+        /// Also flush once N packages were inserted since the last flush
+        #[arg(long, value_name = "N", value_parser = parse_at_least_one)]
+        flush_entries: Option<usize>,
+
+        /// Allow watching a shallow clone
+        #[arg(long)]
+        allow_shallow: bool,
+
+        /// Attr map file to apply, as with `index --attr-map`
+        #[arg(long)]
+        attr_map: Option<PathBuf>,
+    },
+
+    /// Re-runs the parsers over one commit and adds packages they now
+    /// recognize, without a full reindex (e.g. after upgrading nix-archiver)
+    Reparse {
         /// Path to local Nixpkgs repository
         #[arg(short, long)]
         repo: PathBuf,
 
-        /// Commit to start indexing from (default: HEAD)
+        /// Commit whose tree is re-parsed (SHA, short SHA, tag or ref)
         #[arg(short, long, default_value = "HEAD")]
-        from: String,
+        commit: String,
 
-        /// Stop indexing at this commit SHA (optional)
-        #[arg(long, conflicts_with = "to_date", conflicts_with = "max_commits", conflicts_with = "full_repo")]
-        to_commit: Option<String>,
-
-        /// Stop indexing at this date (YYYY-MM-DD) (optional)
-        #[arg(long, conflicts_with = "to_commit", conflicts_with = "max_commits", conflicts_with = "full_repo")]
-        to_date: Option<String>,
+        /// Skip files whose packages are all already in the database
+        #[arg(long)]
+        only_unrecognized: bool,
 
-        /// Maximum number of commits to process
-        #[arg(short, long, conflicts_with = "full_repo")]
-        max_commits: Option<usize>,
+        /// Attr map file to apply, as with `index --attr-map`
+        #[arg(long)]
+        attr_map: Option<PathBuf>,
+    },
 
-        /// Index entire repository history (no commit limit)
-        #[arg(long, conflicts_with = "max_commits", conflicts_with = "to_commit", conflicts_with = "to_date")]
-        full_repo: bool,
+    /// Re-derives path-based attr names with the current heuristic and
+    /// renames stored entries whose name changed, merging duplicates.
+    /// Rewrites the database in place: back it up first
+    Rekey {
+        /// Attr map file to apply, as with `index --attr-map`
+        #[arg(long)]
+        attr_map: Option<PathBuf>,
 
-        /// Number of threads for parallel processing (default: number of CPU cores)
-        #[arg(short = 'j', long)]
-        threads: Option<usize>,
+        /// Only list the names that would change
+        #[arg(long)]
+        dry_run: bool,
 
-        /// Batch size for parallel processing (default: 500)
-        #[arg(short = 'b', long, default_value = "500")]
-        batch_size: usize,
+        /// Confirm the in-place rewrite
+        #[arg(long, conflicts_with = "dry_run")]
+        yes: bool,
     },
 
     /// Searches for a specific package version
     Search {
         /// Package attribute name (e.g., "nodejs")
-        attr_name: String,
+        #[arg(required_unless_present = "description")]
+        attr_name: Option<String>,
 
         /// Version to search for (optional - displays all versions)
         version: Option<String>,
@@ -94,58 +201,552 @@ enum Commands {
         #[arg(long)]
         since: Option<String>,
 
+        /// Only show versions strictly newer than this one (e.g. the version
+        /// you currently pin); it doesn't have to be indexed
+        #[arg(long, value_name = "VERSION")]
+        since_version: Option<String>,
+
+        /// Order of the version list: natural version order, or commit date
+        /// (most recent first) for calendar versions and git snapshots.
+        /// When several packages match they are ranked by relevance; `name`
+        /// lists them alphabetically instead
+        #[arg(long, value_enum, default_value = "version")]
+        sort: SearchSort,
+
         /// Show all versions (no limit)
         #[arg(short, long)]
         all: bool,
+
+        /// Only show packages from this package set (e.g. "python313Packages")
+        #[arg(long, conflicts_with = "version")]
+        set: Option<String>,
+
+        /// Output format
+        #[arg(long, value_enum, default_value = "table")]
+        format: SearchFormat,
+
+        /// Field delimiter for --format csv (e.g. '\t' for TSV)
+        #[arg(long, default_value = ",", value_parser = parse_delimiter)]
+        delimiter: char,
+
+        /// Print full cell values even if tables get wider than the terminal
+        #[arg(long)]
+        no_truncate: bool,
+
+        /// Find packages whose meta.description contains this text (ignoring
+        /// case) instead of searching by name
+        #[arg(long, value_name = "TEXT", conflicts_with_all = ["attr_name", "version", "major", "pattern", "since", "since_version", "sort"])]
+        description: Option<String>,
+    },
+
+    /// Shows which version of a package was current in nixpkgs on a date
+    AtDate {
+        /// Package attribute name (e.g., "nodejs")
+        attr_name: String,
+
+        /// Date (YYYY-MM-DD)
+        date: String,
+    },
+
+    /// Finds packages that provide an executable (their meta.mainProgram)
+    Provides {
+        /// Executable name (e.g. "rg")
+        program: String,
+    },
+
+    /// Lists the packages with the most indexed versions
+    Top {
+        /// Number of packages to show
+        #[arg(default_value = "20")]
+        n: usize,
+    },
+
+    /// Prints every indexed package name, one per line (for fzf or shell
+    /// completion)
+    Names {
+        /// Write to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Print a JSON array instead of one name per line
+        #[arg(long)]
+        json: bool,
     },
 
     /// Generates frozen.nix from requirements file
     Generate {
         /// Input requirements file
-        #[arg(short, long)]
-        input: PathBuf,
+        #[arg(short, long, required_unless_present = "pin_snapshot")]
+        input: Option<PathBuf>,
+
+        /// Instead of an input file, pin the whole nixpkgs snapshot in which
+        /// this package had this version (e.g. nodejs=20.11.0), bound as `pkgs`
+        #[arg(long, value_name = "ATTR=VERSION", conflicts_with_all = ["input", "output_dir", "format"])]
+        pin_snapshot: Option<String>,
 
         /// Output frozen.nix file
-        #[arg(short, long)]
-        output: PathBuf,
+        #[arg(short, long, required_unless_present = "output_dir")]
+        output: Option<PathBuf>,
+
+        /// What to write to --output: an attribute set for nix-shell, a
+        /// list for `mkShell { buildInputs = import ./deps.nix; }`, or the
+        /// attribute set with fetchFromGitHub-pinned snapshots
+        #[arg(long, value_enum, default_value = "frozen")]
+        format: GenerateFormat,
+
+        /// Also write a flake.nix and a matching flake.lock into this directory.
+        /// Inputs with a cached tarball hash are locked by rev + narHash.
+        #[arg(long)]
+        output_dir: Option<PathBuf>,
 
         /// Path to a local nixpkgs bare git repo (e.g. /data/nixpkgs.git).
         /// When provided, frozen.nix will use builtins.fetchGit with a local
         /// file:// URL instead of fetching from GitHub — fully offline.
         #[arg(long)]
         nixpkgs: Option<PathBuf>,
+
+        /// Also write a JSON report of how each package resolved (written
+        /// even when resolution fails, so CI can see what went wrong)
+        #[arg(long)]
+        report_json: Option<PathBuf>,
+
+        /// Resolve "latest" to the newest stable release, skipping rc/beta/alpha (default)
+        #[arg(long, conflicts_with = "include_prerelease")]
+        stable_only: bool,
+
+        /// Let "latest" resolve to a release candidate, beta or alpha
+        #[arg(long)]
+        include_prerelease: bool,
+
+        /// Overwrite existing non-empty output files (frozen.nix, flake.nix, flake.lock)
+        #[arg(long)]
+        force: bool,
     },
 
     /// Show database statistics
     Stats,
+
+    /// Shows how this database was built (every recorded index run)
+    Provenance,
+
+    /// Compares this database with another one (e.g. before and after a parser change)
+    DbDiff {
+        /// Path to the other database
+        other: PathBuf,
+
+        /// List every differing version, not just the counts
+        #[arg(long)]
+        verbose: bool,
+    },
+
+    /// Prefetches nixpkgs tarball hashes for indexed commits (needs nix-prefetch-url)
+    PrefetchHashes {
+        /// Maximum number of commits to prefetch
+        #[arg(short = 'n', long)]
+        limit: Option<usize>,
+
+        /// Which commits to prefetch first when limited
+        #[arg(long, value_enum, default_value = "newest")]
+        order: PrefetchOrder,
+
+        /// Prefetch the narHash used by the `builtins.fetchGit` fallback
+        /// (via `nix flake prefetch`) instead of the tarball hash
+        #[arg(long)]
+        git_narhash: bool,
+
+        /// Maximum number of concurrent downloads; lowered automatically
+        /// while the server rate-limits, and never exceeded
+        #[arg(short = 'j', long, default_value = "4")]
+        jobs: usize,
+
+        /// Also fetch commits that failed in the last 7 days (skipped by
+        /// default)
+        #[arg(long)]
+        retry_failed: bool,
+    },
+
+    /// Lists indexed commits that have no cached hash yet (what
+    /// `prefetch-hashes` would still fetch), with their dates
+    MissingHashes {
+        /// Number of commits to list (the count covers all of them)
+        #[arg(short = 'n', long, default_value = "20")]
+        limit: usize,
+
+        /// Which commits to list first
+        #[arg(long, value_enum, default_value = "newest")]
+        order: PrefetchOrder,
+
+        /// Check the `builtins.fetchGit` narHash instead of the tarball hash
+        #[arg(long)]
+        git_narhash: bool,
+    },
+
+    /// Exports all package entries as JSON Lines
+    Export {
+        /// Output file (a `.gz` extension enables gzip compression)
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// Compression to apply (default: detected from the output extension)
+        #[arg(long, value_enum)]
+        compress: Option<Compression>,
+    },
+
+    /// Exports every indexed version of one package, newest first
+    ExportPackage {
+        /// Package attribute name (e.g., "nodejs")
+        attr_name: String,
+
+        /// Output file (a `.csv` extension selects CSV)
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// Output format (default: detected from the output extension)
+        #[arg(long, value_enum)]
+        format: Option<HistoryFormat>,
+    },
+
+    /// Imports package entries from a JSON Lines export (plain or gzip)
+    Import {
+        /// Input file produced by `export`
+        #[arg(short, long)]
+        input: PathBuf,
+    },
+
+    /// Checks the environment: git, nix-prefetch-url, database and repository
+    Doctor {
+        /// Path to the Nixpkgs repository to verify
+        #[arg(short, long)]
+        repo: Option<PathBuf>,
+    },
+
+    /// Shows how the parsers handle one file (parse errors, per-strategy results)
+    Debug {
+        /// Path to local Nixpkgs repository
+        #[arg(short, long)]
+        repo: PathBuf,
+
+        /// Repo-relative path of the .nix file (e.g. pkgs/tools/misc/hello/default.nix)
+        path: String,
+
+        /// Commit to read the file from (SHA, short SHA, tag or ref)
+        #[arg(short, long, default_value = "HEAD")]
+        commit: String,
+
+        /// Attr map file to apply, as with `index --attr-map`
+        #[arg(long)]
+        attr_map: Option<PathBuf>,
+    },
+
+    /// Shows the packages indexing would extract from a local .nix file
+    /// (no repository or database needed)
+    Parse {
+        /// The .nix file to parse
+        file: PathBuf,
+
+        /// Repo-relative path to parse the file as, for the attr name
+        /// heuristic (e.g. pkgs/by-name/he/hello/package.nix; default: FILE)
+        #[arg(long)]
+        path: Option<String>,
+
+        /// Print the result as JSON instead of a table
+        #[arg(long)]
+        json: bool,
+
+        /// Attr map file to apply, as with `index --attr-map`
+        #[arg(long)]
+        attr_map: Option<PathBuf>,
+    },
+}
+
+/// Options of the `index` command
+#[derive(clap::Args)]
+struct IndexArgs {
+    /// Path to local Nixpkgs repository (with --clone: where to keep the clone)
+    #[arg(short, long, required_unless_present = "clone")]
+    repo: Option<PathBuf>,
+
+    /// Clone this repository URL (bare) before indexing, or fetch new
+    /// commits if it was cloned before. Without --repo the clone is kept
+    /// in the user cache directory.
+    #[arg(long)]
+    clone: Option<String>,
+
+    /// Only clone the most recent N commits (shallow; implies --allow-shallow)
+    #[arg(long, requires = "clone")]
+    clone_depth: Option<i32>,
+
+    /// Commit to start indexing from: any git revision, e.g. a full or
+    /// short SHA, a tag (23.11), a branch (release-24.05) or HEAD~10
+    #[arg(short, long, default_value = "HEAD")]
+    from: String,
+
+    /// Stop indexing at this commit (any git revision, like --from; optional)
+    #[arg(long, conflicts_with = "to_date", conflicts_with = "max_commits", conflicts_with = "full_repo")]
+    to_commit: Option<String>,
+
+    /// Stop indexing at this date (YYYY-MM-DD) (optional)
+    #[arg(long, conflicts_with = "to_commit", conflicts_with = "max_commits", conflicts_with = "full_repo")]
+    to_date: Option<String>,
+
+    /// Maximum number of commits to process
+    #[arg(short, long, conflicts_with = "full_repo")]
+    max_commits: Option<usize>,
+
+    /// Index entire repository history (no commit limit)
+    #[arg(long, conflicts_with = "max_commits", conflicts_with = "to_commit", conflicts_with = "to_date")]
+    full_repo: bool,
+
+    /// Number of threads for parallel processing (default: number of CPU cores)
+    #[arg(short = 'j', long)]
+    threads: Option<usize>,
+
+    /// Batch size for parallel processing (default: 500)
+    #[arg(short = 'b', long, default_value = "500")]
+    batch_size: usize,
+
+    /// Flush the database to disk every N batches (default: 5).
+    /// Lower values lose less work on a crash; higher values reduce I/O
+    /// and speed up indexing on machines with plenty of RAM.
+    #[arg(long, default_value = "5", value_parser = parse_at_least_one)]
+    flush_every: usize,
+
+    /// Also flush once N packages were inserted since the last flush,
+    /// checked after every batch. Bounds memory and lost work when a few
+    /// batches insert far more packages than the rest
+    #[arg(long, value_name = "N", value_parser = parse_at_least_one)]
+    flush_entries: Option<usize>,
+
+    /// Allow indexing a shallow clone (history older than the clone depth is skipped)
+    #[arg(long)]
+    allow_shallow: bool,
+
+    /// Write final statistics and throughput as JSON to this file (for benchmarking)
+    #[arg(long)]
+    stats_json: Option<PathBuf>,
+
+    /// Only index the packages at the starting commit, without walking history.
+    /// Recommended for a quick start or a "latest"-only database.
+    #[arg(long, conflicts_with_all = ["max_commits", "to_commit", "to_date", "full_repo"])]
+    head_only: bool,
+
+    /// Maximum number of threads reading git objects at once (default: unbounded).
+    /// Helps on network filesystems; parsing still uses all threads.
+    #[arg(long)]
+    io_concurrency: Option<usize>,
+
+    /// TOML file of path-regex -> attr-name rules, tried before the
+    /// built-in path heuristic for files without a pname
+    #[arg(long)]
+    attr_map: Option<PathBuf>,
+
+    /// Key versions by their normalized form, merging variants like
+    /// v1.2.3 and 1.2.3 into one entry. Permanent for this database;
+    /// existing entries are re-keyed.
+    #[arg(long)]
+    normalize_versions: bool,
+
+    /// Record the branches and tags pointing at commits (e.g.
+    /// release-24.05), shown next to pinned commits by `search`
+    #[arg(long)]
+    record_refs: bool,
+
+    /// Only insert this package (repeatable, e.g. --only nodejs --only rustc).
+    /// Changed files are still parsed; commits are not marked as
+    /// processed, so a later full run indexes the rest
+    #[arg(long = "only", value_name = "ATTR")]
+    only: Vec<String>,
+
+    /// Only process commits that change this path, like `git log -- PATH`
+    /// (e.g. pkgs/development/web/nodejs). Much faster for one package's
+    /// history; skipped commits stay unprocessed for later runs
+    #[arg(long = "path-filter-commits", value_name = "PATH", conflicts_with = "head_only")]
+    commit_path: Option<String>,
+
+    /// Don't insert packages with a placeholder version (0.0.0, git,
+    /// unstable, dev, HEAD; compared case-insensitively)
+    #[arg(long)]
+    skip_placeholder_versions: bool,
+
+    /// Placeholder version to skip instead of the default set
+    /// (repeatable, e.g. --placeholder-version 0.0.0 --placeholder-version main)
+    #[arg(long = "placeholder-version", value_name = "VERSION", requires = "skip_placeholder_versions")]
+    placeholder_versions: Vec<String>,
+
+    /// With --full-repo, count the commits to index up front (git
+    /// rev-list --count) so progress shows a percentage and ETA
+    #[arg(long, requires = "full_repo")]
+    estimate: bool,
+
+    /// Also write each indexed package directory as a NAR file to DIR,
+    /// keyed by its NAR hash (DIR/ab/ab….nar). Opt-in: needs disk space
+    /// on the order of every version of every package ever indexed
+    #[arg(long, value_name = "DIR")]
+    archive_dir: Option<PathBuf>,
+
+    /// Follow only the first parent of merges, like `git log
+    /// --first-parent`: the mainline snapshots, skipping commits that
+    /// only exist on merged side branches
+    #[arg(long, conflicts_with = "head_only")]
+    first_parent: bool,
+
+    /// Compact the database once indexing finishes, reclaiming the
+    /// space of overwritten entries. Needs free disk for a second copy
+    /// of the database while it runs
+    #[arg(long)]
+    compact_after: bool,
+
+    /// Skip .nix files larger than this many bytes without reading them
+    /// further (0 = no limit)
+    #[arg(long, value_name = "BYTES", default_value_t = DEFAULT_MAX_BLOB_SIZE)]
+    max_file_size: usize,
+
+    /// Don't NAR-hash package directories: much faster when only the
+    /// version history matters (`generate` pins tarball hashes instead)
+    #[arg(long, conflicts_with = "archive_dir")]
+    no_nar_hash: bool,
+
+    /// Store the subject line of each commit that pinned a version
+    /// (e.g. "nodejs: 18.0.0 -> 20.0.0"), shown by `search`. Grows
+    /// the database by a line per such commit
+    #[arg(long)]
+    store_messages: bool,
+
+    /// Also record every commit each version is seen in, not just the
+    /// newest, so `search` can show when a version appeared, vanished
+    /// and came back. Makes the database many times larger
+    #[arg(long)]
+    append_history: bool,
+}
+
+/// Parses a single-character delimiter, accepting `\t` for tab
+fn parse_delimiter(s: &str) -> std::result::Result<char, String> {
+    match s {
+        "\\t" | "tab" => Ok('\t'),
+        _ => {
+            let mut chars = s.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Ok(c),
+                _ => Err(format!("expected a single character, got '{}'", s)),
+            }
+        }
+    }
+}
+
+/// Parses a count that must be at least 1, rejecting 0 before any work starts
+fn parse_at_least_one(s: &str) -> std::result::Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(0) => Err("must be at least 1".to_string()),
+        Ok(n) => Ok(n),
+        Err(e) => Err(e.to_string()),
+    }
 }
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
 
-    // Configure logger
-    env_logger::Builder::from_env(
-        env_logger::Env::default().default_filter_or(&cli.log_level)
-    ).init();
+    let no_color = cli.colors_disabled();
+    if no_color {
+        colored::control::set_override(false);
+    }
+
+    logging::init(cli.log_filter(), cli.log_format, cli.no_color);
 
-    // Open database
-    let db = ArchiverDb::open(&cli.database)
-        .with_context(|| format!("Failed to open database at {:?}", cli.database))?;
+    // Opened per command, so `doctor` can report a database that won't open
+    let database = cli.database.clone();
+    let open_db = || ArchiverDb::open(&database)
+        .with_context(|| format!("Failed to open database at {:?}", database));
+    // Commands that only read never upgrade the schema, and still work
+    // (on a snapshot) while an `index` holds the lock
+    let open_db_for_reading = || ArchiverDb::open_for_reading(&database)
+        .with_context(|| format!("Failed to open database at {:?}", database));
+    // --upstream (or NIX_ARCHIVER_UPSTREAM), else what the index recorded
+    let explicit_upstream = cli.upstream.clone();
+    let upstream_for = |db: &ArchiverDb| -> Result<Upstream> {
+        if let Some(upstream) = &explicit_upstream {
+            return Ok(upstream.clone());
+        }
+        let recorded = recorded_upstream(db)?;
+        if let Some(upstream) = &recorded {
+            log::debug!("Using upstream {} recorded at index time", upstream);
+        }
+        Ok(recorded.unwrap_or_default())
+    };
 
     match cli.command {
-        Commands::Index { repo, from, to_commit, to_date, max_commits, full_repo, threads, batch_size } => {
-            cmd_index(repo, from, to_commit, to_date, max_commits, full_repo, threads, batch_size, db)?;
+        Commands::Index(args) => {
+            cmd_index(args, open_db()?)?;
+        }
+        Commands::Watch { repo, clone, rev, interval, cycles, batch_size, flush_every, flush_entries, allow_shallow, attr_map } => {
+            cmd_watch(repo, clone, rev, interval, cycles, batch_size, flush_every, flush_entries, allow_shallow, attr_map, open_db()?)?;
+        }
+        Commands::Reparse { repo, commit, only_unrecognized, attr_map } => {
+            cmd_reparse(repo, commit, only_unrecognized, attr_map, open_db()?)?;
+        }
+        Commands::Rekey { attr_map, dry_run, yes } => {
+            cmd_rekey(attr_map, dry_run, yes, open_db()?)?;
+        }
+        Commands::Search { attr_name, version, limit, major, pattern, since, since_version, sort, all, set, format, delimiter, no_truncate, description } => {
+            let db = open_db_for_reading()?;
+            match (description, attr_name) {
+                (Some(query), _) => cmd_search_description(query, limit, all, set, format, delimiter, !no_truncate, db)?,
+                (None, Some(attr_name)) => {
+                    cmd_search(attr_name, version, limit, major, pattern, since, since_version, sort, all, set, format, delimiter, !no_truncate, &upstream_for(&db)?, db)?
+                }
+                (None, None) => unreachable!("clap requires a package name without --description"),
+            }
+        }
+        Commands::AtDate { attr_name, date } => {
+            let db = open_db_for_reading()?;
+            cmd_at_date(attr_name, date, &upstream_for(&db)?, db)?;
+        }
+        Commands::Provides { program } => {
+            cmd_provides(program, open_db_for_reading()?)?;
         }
-        Commands::Search { attr_name, version, limit, major, pattern, since, all } => {
-            cmd_search(attr_name, version, limit, major, pattern, since, all, db)?;
+        Commands::Top { n } => {
+            cmd_top(n, open_db_for_reading()?)?;
         }
-        Commands::Generate { input, output, nixpkgs } => {
-            cmd_generate(input, output, nixpkgs, db)?;
+        Commands::Names { output, json } => {
+            cmd_names(output, json, open_db_for_reading()?)?;
+        }
+        Commands::Generate { input, pin_snapshot, output, format, output_dir, nixpkgs, report_json, stable_only: _, include_prerelease, force } => {
+            let db = open_db_for_reading()?;
+            cmd_generate(input, pin_snapshot, output, format, output_dir, nixpkgs, report_json, include_prerelease, force, &upstream_for(&db)?, db)?;
         }
         Commands::Stats => {
-            cmd_stats(db)?;
+            cmd_stats(open_db_for_reading()?)?;
+        }
+        Commands::Provenance => {
+            cmd_provenance(open_db_for_reading()?)?;
+        }
+        Commands::DbDiff { other, verbose } => {
+            cmd_db_diff(other, verbose, open_db_for_reading()?)?;
+        }
+        Commands::PrefetchHashes { limit, order, git_narhash, jobs, retry_failed } => {
+            let db = open_db()?;
+            cmd_prefetch_hashes(limit, order, git_narhash, jobs, retry_failed, &upstream_for(&db)?, db)?;
+        }
+        Commands::MissingHashes { limit, order, git_narhash } => {
+            cmd_missing_hashes(limit, order, git_narhash, open_db_for_reading()?)?;
+        }
+        Commands::Export { output, compress } => {
+            cmd_export(output, compress, open_db_for_reading()?)?;
+        }
+        Commands::ExportPackage { attr_name, output, format } => {
+            cmd_export_package(attr_name, output, format, open_db_for_reading()?)?;
+        }
+        Commands::Import { input } => {
+            cmd_import(input, open_db()?)?;
+        }
+        Commands::Debug { repo, path, commit, attr_map } => {
+            cmd_debug(repo, path, commit, attr_map, open_db()?)?;
+        }
+        Commands::Doctor { repo } => {
+            cmd_doctor(repo, &database)?;
+        }
+        Commands::Parse { file, path, json, attr_map } => {
+            cmd_parse(file, path, json, attr_map)?;
         }
-
     }
 
     Ok(())
@@ -1,6 +1,7 @@
 //! Output formatting structures for CLI display
 
-use tabled::Tabled;
+use tabled::settings::{peaker::PriorityMax, Width};
+use tabled::{Table, Tabled};
 
 /// Table row for displaying package versions
 #[derive(Tabled)]
@@ -34,3 +35,40 @@ pub struct PackageSetRow {
     #[tabled(rename = "Packages")]
     pub packages: String,
 }
+
+/// Table row for `top`: packages ranked by number of indexed versions
+#[derive(Tabled)]
+pub struct TopRow {
+    #[tabled(rename = "#")]
+    pub rank: String,
+    #[tabled(rename = "Package")]
+    pub attr_name: String,
+    #[tabled(rename = "Versions")]
+    pub version_count: String,
+    #[tabled(rename = "Share")]
+    pub share: String,
+}
+
+/// Table row for `parse`: one package extracted from a file
+#[derive(Tabled)]
+pub struct ParsedRow {
+    #[tabled(rename = "Package")]
+    pub attr_name: String,
+    #[tabled(rename = "Version")]
+    pub version: String,
+    #[tabled(rename = "Program")]
+    pub main_program: String,
+    #[tabled(rename = "Platform")]
+    pub platform: String,
+}
+
+/// Shrinks `table` to the terminal width, eliding the widest cells with `…`.
+///
+/// Widths are measured in terminal columns, so wide and multi-byte
+/// characters are never split. Does nothing when stdout isn't a terminal,
+/// so piped output keeps full values.
+pub fn fit_to_terminal(table: &mut Table) {
+    if let Some((terminal_size::Width(columns), _)) = terminal_size::terminal_size() {
+        table.with(Width::truncate(columns as usize).suffix("…").priority(PriorityMax::right()));
+    }
+}
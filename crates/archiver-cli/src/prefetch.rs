@@ -0,0 +1,203 @@
+//! Parallel prefetching with adaptive concurrency
+//!
+//! `prefetch-hashes` downloads one nixpkgs snapshot per commit. Too many
+//! parallel downloads make GitHub answer with HTTP 429, so the number of
+//! downloads in flight adapts to how the server responds:
+//!
+//! - start at `--jobs`, which is also the ceiling;
+//! - on a rate-limit or network error, halve the limit (at least 1), pause
+//!   new launches for a backoff that doubles with each such error (2 s up to
+//!   60 s) and put the commit back in the queue, up to [`MAX_ATTEMPTS`] tries;
+//! - after [`RECOVER_AFTER`] successes in a row, raise the limit by one
+//!   again and clear the backoff.
+//!
+//! Hard failures (404, a missing tool, unparsable output) are reported once
+//! and never retried or throttled.
+
+use anyhow::Result;
+use regex::Regex;
+use std::collections::VecDeque;
+use std::sync::{mpsc, Condvar, LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+/// Tries per commit before a transient failure counts as an error
+pub const MAX_ATTEMPTS: u32 = 4;
+
+/// Successes in a row that raise the concurrency limit by one
+pub const RECOVER_AFTER: u32 = 4;
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(2);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Whether a failed fetch is worth retrying later
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureKind {
+    /// Rate limiting, server overload or a network problem
+    Transient,
+    /// Anything else; retrying would fail the same way
+    Hard,
+}
+
+static TRANSIENT_ERROR: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?i)HTTP error (429|50[234])|too many requests|rate limit|timed out|timeout was reached|could not resolve host|connection (reset|refused)|temporary failure",
+    )
+    .unwrap()
+});
+
+/// Classifies the error output of `nix-prefetch-url` / `nix flake prefetch`.
+///
+/// Matches status codes only as `HTTP error NNN`, so digits inside the
+/// commit SHA of the printed URL are never mistaken for one.
+pub fn classify_failure(message: &str) -> FailureKind {
+    if TRANSIENT_ERROR.is_match(message) {
+        FailureKind::Transient
+    } else {
+        FailureKind::Hard
+    }
+}
+
+/// Concurrency limit that backs off on transient failures and recovers
+/// on success (see the module docs for the heuristic)
+#[derive(Debug)]
+pub struct AdaptiveLimit {
+    ceiling: usize,
+    limit: usize,
+    streak: u32,
+    backoff: Duration,
+}
+
+impl AdaptiveLimit {
+    /// Starts at `ceiling` (at least 1)
+    pub fn new(ceiling: usize) -> Self {
+        let ceiling = ceiling.max(1);
+        AdaptiveLimit { ceiling, limit: ceiling, streak: 0, backoff: Duration::ZERO }
+    }
+
+    /// How many fetches may currently run at once
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Pause before the next launch after the latest transient failure
+    pub fn backoff(&self) -> Duration {
+        self.backoff
+    }
+
+    pub fn on_success(&mut self) {
+        self.streak += 1;
+        if self.streak >= RECOVER_AFTER {
+            self.streak = 0;
+            self.limit = (self.limit + 1).min(self.ceiling);
+            self.backoff = Duration::ZERO;
+        }
+    }
+
+    pub fn on_transient_failure(&mut self) {
+        self.streak = 0;
+        self.limit = (self.limit / 2).max(1);
+        self.backoff = if self.backoff.is_zero() {
+            INITIAL_BACKOFF
+        } else {
+            (self.backoff * 2).min(MAX_BACKOFF)
+        };
+    }
+}
+
+/// Outcome of one commit, as reported by [`prefetch_all`]
+#[derive(Debug)]
+pub struct Fetched {
+    pub commit: String,
+    pub result: Result<String>,
+    /// Tries it took, including the final one
+    pub attempts: u32,
+    /// Concurrency limit when the fetch finished
+    pub limit: usize,
+}
+
+struct Scheduler {
+    queue: VecDeque<(String, u32)>,
+    in_flight: usize,
+    limit: AdaptiveLimit,
+    resume_at: Instant,
+}
+
+/// Fetches every commit with `fetch` on up to `jobs` threads, adapting the
+/// number of concurrent fetches to transient failures. `on_done` runs on the
+/// calling thread once per commit, in completion order.
+pub fn prefetch_all<F>(commits: Vec<String>, jobs: usize, fetch: F, mut on_done: impl FnMut(Fetched))
+where
+    F: Fn(&str) -> Result<String> + Sync,
+{
+    let jobs = jobs.max(1).min(commits.len().max(1));
+    let state = Mutex::new(Scheduler {
+        queue: commits.into_iter().map(|commit| (commit, 0)).collect(),
+        in_flight: 0,
+        limit: AdaptiveLimit::new(jobs),
+        resume_at: Instant::now(),
+    });
+    let wakeup = Condvar::new();
+    let (tx, rx) = mpsc::channel();
+
+    std::thread::scope(|scope| {
+        for _ in 0..jobs {
+            let tx = tx.clone();
+            let (state, wakeup, fetch) = (&state, &wakeup, &fetch);
+            scope.spawn(move || {
+                while let Some((commit, attempts)) = next_job(state, wakeup) {
+                    let result = fetch(&commit);
+                    let attempts = attempts + 1;
+
+                    let mut s = state.lock().unwrap();
+                    s.in_flight -= 1;
+                    let retry = match &result {
+                        Ok(_) => {
+                            s.limit.on_success();
+                            false
+                        }
+                        Err(e) if classify_failure(&format!("{:#}", e)) == FailureKind::Transient => {
+                            s.limit.on_transient_failure();
+                            s.resume_at = Instant::now() + s.limit.backoff();
+                            attempts < MAX_ATTEMPTS
+                        }
+                        Err(_) => false,
+                    };
+                    let limit = s.limit.limit();
+                    if retry {
+                        log::debug!("Retrying {} later (attempt {}, limit now {})", commit, attempts, limit);
+                        s.queue.push_back((commit, attempts));
+                        drop(s);
+                        wakeup.notify_all();
+                    } else {
+                        drop(s);
+                        wakeup.notify_all();
+                        // Can't fail: the receiver is drained until every worker exits
+                        let _ = tx.send(Fetched { commit, result, attempts, limit });
+                    }
+                }
+            });
+        }
+        drop(tx);
+        for fetched in rx {
+            on_done(fetched);
+        }
+    });
+}
+
+/// Blocks until a commit may be launched; `None` once all work is done
+fn next_job(state: &Mutex<Scheduler>, wakeup: &Condvar) -> Option<(String, u32)> {
+    let mut s = state.lock().unwrap();
+    loop {
+        // An in-flight fetch may still put its commit back
+        if s.queue.is_empty() && s.in_flight == 0 {
+            return None;
+        }
+        let now = Instant::now();
+        if !s.queue.is_empty() && s.in_flight < s.limit.limit() && now >= s.resume_at {
+            s.in_flight += 1;
+            return s.queue.pop_front();
+        }
+        let wait = if now < s.resume_at { s.resume_at - now } else { Duration::from_secs(1) };
+        s = wakeup.wait_timeout(s, wait).unwrap().0;
+    }
+}
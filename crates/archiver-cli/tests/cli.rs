@@ -3,6 +3,9 @@
 //! These tests run the compiled `nix-archiver` binary directly, so they work
 //! even though the helper functions live in private modules of the bin crate.
 
+use archiver_core::PackageEntry;
+use archiver_db::ArchiverDb;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 use tempfile::TempDir;
 
@@ -10,6 +13,122 @@ fn bin() -> Command {
     Command::new(env!("CARGO_BIN_EXE_nix-archiver"))
 }
 
+/// sled may hold the database lock briefly after an in-process handle is
+/// dropped; wait until the binary can open it before running the real command.
+/// Uses an empty `import`, since reading commands fall back to a snapshot
+/// and succeed even while the lock is held.
+fn wait_until_unlocked(db_path: &std::path::Path) {
+    for _ in 0..50 {
+        let status = bin()
+            .arg("--database").arg(db_path)
+            .args(["import", "--input", "/dev/null"])
+            .output()
+            .unwrap()
+            .status;
+        if status.success() {
+            return;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    panic!("database at {:?} stayed locked", db_path);
+}
+
+/// Commit every entry from `seed_db` points at
+const SHA: &str = "abc1234567890abcdef01234567890abcdef0123";
+
+/// Creates a database holding `entries` and waits until the binary can open
+/// it. Keep the returned `TempDir` alive while the database is used.
+fn seed_entries(entries: impl IntoIterator<Item = PackageEntry>) -> (TempDir, PathBuf) {
+    seed_entries_with(entries, |_| {})
+}
+
+/// `seed_entries`, then `extra` writes anything else the test needs
+/// (hashes, failures, …) before the database is flushed
+fn seed_entries_with(entries: impl IntoIterator<Item = PackageEntry>, extra: impl FnOnce(&ArchiverDb)) -> (TempDir, PathBuf) {
+    let tmp = TempDir::new().unwrap();
+    let db_path = tmp.path().join("test.db");
+    write_db(&db_path, |db| {
+        for entry in entries {
+            db.insert_if_better(&entry).unwrap();
+        }
+        extra(db);
+    });
+    (tmp, db_path)
+}
+
+/// Opens the database at `db_path` for `write`, then flushes and closes it
+/// and waits until the binary can open it again
+fn write_db(db_path: &Path, write: impl FnOnce(&ArchiverDb)) {
+    {
+        let db = ArchiverDb::open(db_path).unwrap();
+        write(&db);
+        db.flush().unwrap();
+    }
+    wait_until_unlocked(db_path);
+}
+
+/// `seed_entries` for `(attr, version, timestamp)` triples, all at `SHA`
+fn seed_db(entries: &[(&str, &str, u64)]) -> (TempDir, PathBuf) {
+    seed_entries(entries.iter().map(|&(attr, version, ts)| {
+        PackageEntry::new(attr.to_string(), version.to_string(), SHA.to_string(), ts)
+    }))
+}
+
+/// Runs the binary on `db_path`, asserts it succeeded and returns its stdout
+fn run_ok(db_path: &Path, args: &[&str]) -> String {
+    let output = bin()
+        .arg("--database").arg(db_path)
+        .args(args)
+        .output()
+        .expect("failed to run binary");
+    assert!(output.status.success(), "stderr: {}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+/// Runs git in `dir` as a fixed identity, with author and committer dated
+/// `date` (the indexer walks history by date)
+fn git_at(dir: &Path, date: &str, args: &[&str]) {
+    let status = Command::new("git").arg("-C").arg(dir)
+        .args(["-c", "user.name=t", "-c", "user.email=t@t"])
+        .args(args)
+        .env("GIT_AUTHOR_DATE", date)
+        .env("GIT_COMMITTER_DATE", date)
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+/// Commits `pkgs/<name>/default.nix` at `version` in `repo`, dated `date`
+fn commit_pkg(repo: &Path, name: &str, version: &str, date: &str) {
+    std::fs::create_dir_all(repo.join("pkgs").join(name)).unwrap();
+    std::fs::write(
+        repo.join("pkgs").join(name).join("default.nix"),
+        format!(r#"{{ pname = "{}"; version = "{}"; }}"#, name, version),
+    ).unwrap();
+    git_at(repo, date, &["add", "."]);
+    git_at(repo, date, &["commit", "-qm", version]);
+}
+
+/// A git repository at `<tmp>/repo` whose one commit adds foo 1.0
+fn one_package_repo(tmp: &TempDir) -> PathBuf {
+    let repo = tmp.path().join("repo");
+    std::fs::create_dir_all(&repo).unwrap();
+    git_at(&repo, "2024-01-01T00:00:00Z", &["init", "-q"]);
+    commit_pkg(&repo, "foo", "1.0", "2024-01-01T00:00:00Z");
+    repo
+}
+
+/// True if `search NAME VERSION` finds the version in the database
+fn is_indexed(db_path: &Path, name: &str, version: &str) -> bool {
+    bin()
+        .arg("--database").arg(db_path)
+        .args(["search", name, version])
+        .output()
+        .expect("failed to run binary")
+        .status
+        .success()
+}
+
 // ── help / version ────────────────────────────────────────────────────────────
 
 #[test]
@@ -70,3 +189,798 @@ fn test_search_on_empty_db_prints_not_found() {
         combined
     );
 }
+
+// ── generate output safety ───────────────────────────────────────────────────
+
+#[test]
+fn test_generate_refuses_to_overwrite_input() {
+    let tmp = TempDir::new().unwrap();
+    let input = tmp.path().join("packages.nix");
+    let spec = "{ nodejs = \"20.11.0\"; }";
+    std::fs::write(&input, spec).unwrap();
+
+    // Same file through a different spelling of the path, even with --force
+    let output = bin()
+        .current_dir(tmp.path())
+        .arg("--database").arg(tmp.path().join("test.db"))
+        .args(["generate", "--input", "packages.nix", "--force", "--output"])
+        .arg(tmp.path().join(".").join("packages.nix"))
+        .output()
+        .expect("failed to run binary");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("same file as the input"));
+    assert_eq!(std::fs::read_to_string(&input).unwrap(), spec);
+}
+
+#[test]
+fn test_generate_requires_force_to_overwrite_existing_output() {
+    let (tmp, db_path) = seed_db(&[("nodejs", "20.11.0", 1700000000)]);
+
+    let input = tmp.path().join("packages.nix");
+    std::fs::write(&input, "{ nodejs = \"20.11.0\"; }").unwrap();
+    let frozen = tmp.path().join("frozen.nix");
+    std::fs::write(&frozen, "# hand-edited\n").unwrap();
+
+    let generate = |extra: &[&str]| {
+        bin()
+            .arg("--database").arg(&db_path)
+            .arg("generate")
+            .arg("--input").arg(&input)
+            .arg("--output").arg(&frozen)
+            .args(extra)
+            .output()
+            .expect("failed to run binary")
+    };
+
+    let output = generate(&[]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("pass --force"));
+    assert_eq!(std::fs::read_to_string(&frozen).unwrap(), "# hand-edited\n");
+
+    let output = generate(&["--force"]);
+    assert!(output.status.success(), "generate failed: {}", String::from_utf8_lossy(&output.stderr));
+    assert!(std::fs::read_to_string(&frozen).unwrap().contains("nodejs"));
+
+    // An empty file is not hand-edited work, so no --force is needed
+    std::fs::write(&frozen, "").unwrap();
+    assert!(generate(&[]).status.success());
+}
+
+// ── generate --output-dir (flake) ─────────────────────────────────────────────
+
+#[test]
+fn test_generate_output_dir_writes_locked_flake() {
+    const SHA_LOCKED: &str = "abc1234567890abcdef01234567890abcdef0123";
+    const SHA_UNLOCKED: &str = "def1234567890abcdef01234567890abcdef0456";
+
+    let entries = [
+        PackageEntry::new("nodejs".to_string(), "20.11.0".to_string(), SHA_LOCKED.to_string(), 1700000000),
+        PackageEntry::new("go".to_string(), "1.21.5".to_string(), SHA_UNLOCKED.to_string(), 1700000100),
+    ];
+    let (tmp, db_path) = seed_entries_with(entries, |db| {
+        // sha256 of the empty string, as printed by nix-prefetch-url
+        db.store_tarball_hash(SHA_LOCKED, "0mdqa9w1p6cmli6976v4wi0sw9r4p5prkj7lzfd1877wk11c9c73").unwrap();
+    });
+
+    let input = tmp.path().join("packages.nix");
+    std::fs::write(&input, "{ nodejs = \"20.11.0\"; go = \"1.21.5\"; }").unwrap();
+    let out_dir = tmp.path().join("flake");
+
+    let output = bin()
+        .arg("--database").arg(&db_path)
+        .arg("generate")
+        .arg("--input").arg(&input)
+        .arg("--output-dir").arg(&out_dir)
+        .output()
+        .expect("failed to run binary");
+    assert!(output.status.success(), "generate failed: {}", String::from_utf8_lossy(&output.stderr));
+
+    let flake = std::fs::read_to_string(out_dir.join("flake.nix")).unwrap();
+    assert!(flake.contains(&format!("nixpkgs_{}.url = \"github:NixOS/nixpkgs/{}\"", SHA_LOCKED, SHA_LOCKED)));
+    assert!(flake.contains(&format!("nixpkgs_{}.url", SHA_UNLOCKED)));
+
+    let lock = std::fs::read_to_string(out_dir.join("flake.lock")).unwrap();
+    assert!(lock.contains("sha256-47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU="));
+    assert!(lock.contains(&format!("nixpkgs_{}", SHA_LOCKED)));
+    // Inputs without a cached hash are left out of the lock
+    assert!(!lock.contains(SHA_UNLOCKED));
+}
+
+// ── search --set ──────────────────────────────────────────────────────────────
+
+#[test]
+fn test_search_set_restricts_to_one_package_set() {
+    let (_tmp, db_path) = seed_db(&[
+        ("python312Packages.numpy", "1.26.4", 1700000000),
+        ("python313Packages.numpy", "1.26.4", 1700000000),
+    ]);
+    let search = |extra: &[&str]| run_ok(&db_path, &[&["search", "numpy"], extra].concat());
+
+    let all = search(&[]);
+    assert!(all.contains("python312Packages.numpy") && all.contains("python313Packages.numpy"), "got: {}", all);
+
+    let filtered = search(&["--set", "python313Packages"]);
+    assert!(filtered.contains("python313Packages.numpy"), "got: {}", filtered);
+    assert!(!filtered.contains("python312Packages"), "got: {}", filtered);
+
+    let none = search(&["--set", "haskellPackages"]);
+    assert!(none.contains("in set 'haskellPackages'"), "got: {}", none);
+}
+
+// ── search by version prefix ──────────────────────────────────────────────────
+
+#[test]
+fn test_search_partial_version_lists_matching_versions() {
+    let (_tmp, db_path) = seed_db(&[
+        ("nodejs", "20.11.0", 1700000000),
+        ("nodejs", "20.11.1", 1710000000),
+        ("nodejs", "200.1.0", 1720000000),
+    ]);
+    let search = |version: &str| run_ok(&db_path, &["search", "nodejs", version, "--format", "csv"]);
+
+    let prefix = search("20");
+    assert!(prefix.contains("20.11.0") && prefix.contains("20.11.1"), "got: {}", prefix);
+    assert!(!prefix.contains("200.1.0"), "got: {}", prefix);
+
+    let exact = search("20.11.0");
+    assert!(exact.contains("20.11.0") && !exact.contains("20.11.1"), "got: {}", exact);
+}
+
+// ── search --since-version ─────────────────────────────────────────────────────
+
+#[test]
+fn test_search_since_version_keeps_only_newer_versions() {
+    let (_tmp, db_path) = seed_db(&[
+        ("go", "1.19.5", 1690000000),
+        ("go", "1.20", 1700000000),
+        ("go", "1.20rc1", 1695000000),
+        ("go", "1.21.0", 1710000000),
+    ]);
+    let search = |since: &str| run_ok(&db_path, &["search", "go", "--since-version", since, "--format", "csv"]);
+
+    let newer = search("1.20");
+    assert!(newer.contains("1.21.0"), "got: {}", newer);
+    assert!(!newer.contains("1.19.5") && !newer.contains("1.20,") && !newer.contains("1.20rc1"), "got: {}", newer);
+
+    // Not indexed itself: still compared
+    let newer = search("1.19.7");
+    assert!(newer.contains("1.20,") && newer.contains("1.21.0") && !newer.contains("1.19.5"), "got: {}", newer);
+}
+
+#[test]
+fn test_search_sort_date_orders_by_commit_time_not_version() {
+    // 1.8.0 lives on in a maintenance branch committed after 1.10.0
+    let (_tmp, db_path) = seed_db(&[
+        ("foo", "1.9.0", 1690000000),
+        ("foo", "1.10.0", 1700000000),
+        ("foo", "1.8.0", 1710000000),
+    ]);
+    let versions = |sort: &str| -> Vec<String> {
+        run_ok(&db_path, &["search", "foo", "-n", "2", "--format", "csv", "--sort", sort])
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').next().unwrap().to_string())
+            .collect()
+    };
+
+    assert_eq!(versions("version"), ["1.10.0", "1.9.0"]);
+    // The limit applies after sorting: the two most recent commits
+    assert_eq!(versions("date"), ["1.8.0", "1.10.0"]);
+}
+
+#[test]
+fn test_search_plain_prints_tab_separated_lines_within_limit() {
+    let (_tmp, db_path) = seed_db(&[
+        ("foo", "1.0", 1690000000),
+        ("foo", "2.0", 1700000000),
+        ("foo", "3.0", 1710000000),
+        ("fop", "0.1", 1700000000),
+    ]);
+    let search = |args: &[&str]| run_ok(&db_path, &[&["search"], args, &["--format", "plain"]].concat());
+
+    // Filter and limit apply; no header, borders or colors
+    let lines = search(&["foo", "--major", "2", "-n", "1"]);
+    assert_eq!(lines, format!("2.0\t{}\t2023-11-14 22:13\n", SHA));
+    let lines = search(&["foo", "-n", "2"]);
+    assert_eq!(lines.lines().count(), 2, "got: {}", lines);
+    assert!(lines.starts_with("3.0\t"), "got: {}", lines);
+
+    // Several matching packages: one summary line each
+    let lines = search(&["fo"]);
+    assert_eq!(lines.lines().collect::<Vec<_>>(), [
+        "foo\t3\t3.0\t2024-03-09 16:00",
+        "fop\t1\t0.1\t2023-11-14 22:13",
+    ]);
+}
+
+#[test]
+fn test_search_ranks_matching_packages_unless_sorted_by_name() {
+    let (_tmp, db_path) = seed_db(&[
+        ("nodejs-slim", "20.11.0", 1710000000),
+        ("nodejs_20", "20.11.0", 1700000000),
+        ("nodejs", "20.11.0", 1690000000),
+    ]);
+    let names = |extra: &[&str]| {
+        run_ok(&db_path, &[&["search", "nodejs", "--format", "plain"], extra].concat())
+            .lines()
+            .map(|line| line.split('\t').next().unwrap().to_string())
+            .collect::<Vec<_>>()
+    };
+
+    // Exact name first, then shorter names, regardless of recency
+    assert_eq!(names(&[]), ["nodejs", "nodejs_20", "nodejs-slim"]);
+    assert_eq!(names(&["--sort", "name"]), ["nodejs", "nodejs-slim", "nodejs_20"]);
+}
+
+#[test]
+fn test_search_limit_counts_versions_of_per_platform_packages() {
+    let entries = [("1.0", 1690000000), ("2.0", 1700000000), ("3.0", 1710000000)].into_iter().flat_map(|(version, ts)| {
+        ["aarch64-darwin", "x86_64-linux"].map(|system| {
+            PackageEntry::new("ruff".to_string(), version.to_string(), SHA.to_string(), ts)
+                .with_platform(system.to_string(), None)
+        })
+    });
+    let (_tmp, db_path) = seed_entries(entries);
+
+    let versions: Vec<String> = run_ok(&db_path, &["search", "ruff", "-n", "2", "--format", "plain"])
+        .lines()
+        .map(|line| line.split('\t').next().unwrap().to_string())
+        .collect();
+    assert_eq!(versions, [
+        "3.0 (aarch64-darwin)", "3.0 (x86_64-linux)",
+        "2.0 (aarch64-darwin)", "2.0 (x86_64-linux)",
+    ]);
+}
+
+// ── rekey ─────────────────────────────────────────────────────────────────────
+
+#[test]
+fn test_rekey_renames_path_derived_names_only_with_yes() {
+    const SHA_OLD: &str = "abc1234567890abcdef01234567890abcdef0123";
+    const SHA_NEW: &str = "def1234567890abcdef01234567890abcdef0456";
+
+    let entry = |name: &str, version: &str, sha: &str, ts: u64, from_path: bool| {
+        PackageEntry::new(name.to_string(), version.to_string(), sha.to_string(), ts)
+            .with_source_path("pkgs/tools/misc/hello/default.nix".to_string(), from_path)
+    };
+    let (_tmp, db_path) = seed_entries([
+        // Misparsed by an older heuristic: named after the wrong directory
+        entry("misc", "2.12", SHA_NEW, 2000, true),
+        entry("misc", "2.10", SHA_OLD, 1000, true),
+        entry("hello", "2.10", SHA_NEW, 2000, false),
+        // Named by its pname: left alone even though the path says otherwise
+        entry("gnuhello", "2.12", SHA_NEW, 2000, false),
+    ]);
+
+    let rekey = |extra: &[&str]| {
+        bin()
+            .arg("--database").arg(&db_path)
+            .arg("rekey").args(extra)
+            .output()
+            .expect("failed to run binary")
+    };
+
+    let dry = rekey(&["--dry-run"]);
+    assert!(dry.status.success());
+    let stdout = String::from_utf8_lossy(&dry.stdout);
+    assert!(stdout.contains("misc → hello (2 entries)"), "got: {}", stdout);
+
+    // Destructive: refused without --yes
+    let refused = rekey(&[]);
+    assert!(!refused.status.success());
+    assert!(String::from_utf8_lossy(&refused.stderr).contains("--yes"));
+
+    let done = rekey(&["--yes"]);
+    assert!(done.status.success(), "stderr: {}", String::from_utf8_lossy(&done.stderr));
+    assert!(String::from_utf8_lossy(&done.stdout).contains("1 merged"));
+    wait_until_unlocked(&db_path);
+
+    let db = ArchiverDb::open(&db_path).unwrap();
+    assert!(db.get_all_versions("misc").unwrap().is_empty());
+    assert_eq!(db.get("hello", "2.12").unwrap().unwrap().commit_sha, SHA_NEW);
+    // hello 2.10 already existed from a newer commit, which is kept
+    assert_eq!(db.get("hello", "2.10").unwrap().unwrap().commit_sha, SHA_NEW);
+    assert!(db.get("gnuhello", "2.12").unwrap().is_some());
+}
+
+// ── search --description ──────────────────────────────────────────────────────
+
+#[test]
+fn test_search_description_lists_packages_whose_description_matches() {
+    let (_tmp, db_path) = seed_entries(
+        [("cmark", "CommonMark Markdown parser"), ("ripgrep", "Fast line-oriented search tool")].map(|(name, description)| {
+            PackageEntry::new(name.to_string(), "1.0".to_string(), SHA.to_string(), 1700000000)
+                .with_description(description.to_string())
+        }),
+    );
+
+    let stdout = run_ok(&db_path, &["search", "--description", "markdown parser", "--format", "csv"]);
+    assert!(stdout.contains("cmark") && !stdout.contains("ripgrep"), "got: {}", stdout);
+}
+
+// ── names ─────────────────────────────────────────────────────────────────────
+
+#[test]
+fn test_names_prints_each_package_once() {
+    let (_tmp, db_path) = seed_db(&[
+        ("nodejs", "18.0.0", 1700000000),
+        ("nodejs", "20.0.0", 1700000000),
+        ("ripgrep", "14.1.0", 1700000000),
+        ("python312", "3.12.1", 1700000000),
+    ]);
+
+    assert_eq!(run_ok(&db_path, &["names"]), "nodejs\npython312\nripgrep\n");
+    let json: Vec<String> = serde_json::from_str(&run_ok(&db_path, &["names", "--json"])).unwrap();
+    assert_eq!(json, ["nodejs", "python312", "ripgrep"]);
+}
+
+// ── search --format csv ───────────────────────────────────────────────────────
+
+#[test]
+fn test_search_csv_quotes_fields_and_honours_delimiter() {
+    let (_tmp, db_path) = seed_db(&[("foo", "1.0", 1700000000), ("foo", "2.0,beta", 1700000000)]);
+    let search = |extra: &[&str]| run_ok(&db_path, &[&["search", "foo", "--format", "csv"], extra].concat());
+
+    let csv = search(&[]);
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines[0], "Version,Commit,Date");
+    // A comma inside a version is quoted; no ANSI colour codes leak in
+    assert!(lines.iter().any(|l| l.starts_with(&format!("\"2.0,beta\",{},", SHA))), "got: {}", csv);
+    assert!(!csv.contains('\u{1b}'));
+
+    let tsv = search(&["--delimiter", "\\t"]);
+    assert!(tsv.starts_with("Version\tCommit\tDate\n"), "got: {}", tsv);
+    assert!(tsv.contains(&format!("2.0,beta\t{}\t", SHA)), "got: {}", tsv);
+}
+
+#[test]
+fn test_search_table_shows_differing_normalized_versions_only() {
+    let (_tmp, db_path) = seed_db(&[("foo", "v1.2.3", 1700000000), ("foo", "2.0", 1710000000)]);
+
+    let table = run_ok(&db_path, &["search", "foo"]);
+    assert!(table.contains("v1.2.3 (1.2.3)"), "got: {}", table);
+    assert!(!table.contains("2.0 (2.0)") && !table.contains("Normalized"), "got: {}", table);
+
+    let csv = run_ok(&db_path, &["search", "foo", "--format", "csv"]);
+    assert!(csv.lines().any(|l| l.starts_with("v1.2.3,")), "got: {}", csv);
+}
+
+// ── log level shortcuts ───────────────────────────────────────────────────────
+
+#[test]
+fn test_quiet_and_log_level_conflict() {
+    let output = bin()
+        .args(["-q", "--log-level", "debug", "stats"])
+        .output()
+        .expect("failed to run binary");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("cannot be used with"));
+}
+
+#[test]
+fn test_quiet_silences_info_logs() {
+    let tmp = TempDir::new().unwrap();
+    let repo = one_package_repo(&tmp);
+
+    let output = bin()
+        .env_remove("RUST_LOG")
+        .arg("--database").arg(tmp.path().join("test.db"))
+        .arg("-q")
+        .args(["index", "--head-only", "--repo"]).arg(&repo)
+        .output()
+        .expect("failed to run binary");
+    assert!(output.status.success(), "index failed: {}", String::from_utf8_lossy(&output.stderr));
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("INFO"));
+}
+
+#[test]
+fn test_index_compact_after_leaves_a_usable_database() {
+    let tmp = TempDir::new().unwrap();
+    let repo = one_package_repo(&tmp);
+    let db_path = tmp.path().join("test.db");
+
+    let output = bin()
+        .arg("--database").arg(&db_path)
+        .args(["index", "--head-only", "--compact-after", "--repo"]).arg(&repo)
+        .output()
+        .expect("failed to run binary");
+    assert!(output.status.success(), "index failed: {}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Compacted database"));
+
+    let output = bin()
+        .arg("--database").arg(&db_path)
+        .args(["search", "foo", "1.0"])
+        .output()
+        .expect("failed to run binary");
+    assert!(output.status.success(), "search failed: {}", String::from_utf8_lossy(&output.stderr));
+}
+
+#[test]
+fn test_index_stats_json_reports_parser_counts_and_throughput() {
+    let tmp = TempDir::new().unwrap();
+    let repo = one_package_repo(&tmp);
+    commit_pkg(&repo, "foo", "2.0", "2024-01-02T00:00:00Z");
+    let stats_path = tmp.path().join("stats.json");
+
+    let output = bin()
+        .arg("--database").arg(tmp.path().join("test.db"))
+        .args(["index", "--full-repo", "--repo"]).arg(&repo)
+        .arg("--stats-json").arg(&stats_path)
+        .output()
+        .expect("failed to run binary");
+    assert!(output.status.success(), "index failed: {}", String::from_utf8_lossy(&output.stderr));
+
+    let stats: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&stats_path).unwrap()).unwrap();
+    assert_eq!(stats["completed"], true);
+    // HEAD is full-scanned, then the older commit is walked
+    assert_eq!(stats["processed"], 1);
+    assert_eq!(stats["head_scan"]["files"], 1);
+    for key in ["elapsed_secs", "ast_files", "regex_files", "commits_per_sec"] {
+        assert!(stats[key].is_number(), "{} missing from {}", key, stats);
+    }
+    assert_eq!(stats["ast_files"].as_u64().unwrap() + stats["regex_files"].as_u64().unwrap(), 1);
+}
+
+#[test]
+fn test_zero_flush_intervals_are_rejected_before_opening_anything() {
+    let tmp = TempDir::new().unwrap();
+    let db_path = tmp.path().join("test.db");
+
+    for args in [
+        ["index", "--flush-every", "0"],
+        ["index", "--flush-entries", "0"],
+        ["watch", "--flush-every", "0"],
+        ["watch", "--flush-entries", "0"],
+    ] {
+        let output = bin()
+            .arg("--database").arg(&db_path)
+            .args(args)
+            .arg("--repo").arg(tmp.path().join("repo"))
+            .output()
+            .expect("failed to run binary");
+        assert!(!output.status.success(), "{:?} should fail", args);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("must be at least 1"), "got: {}", stderr);
+    }
+    // No database, hence no provenance record of a run that never started
+    assert!(!db_path.exists());
+}
+
+#[test]
+fn test_index_refuses_shallow_clone_without_allow_shallow() {
+    let tmp = TempDir::new().unwrap();
+    let repo = tmp.path().join("repo");
+    let shallow = tmp.path().join("shallow");
+    let db_path = tmp.path().join("test.db");
+    let git = |args: &[&str]| {
+        let status = Command::new("git").arg("-C").arg(tmp.path())
+            .args(["-c", "user.name=t", "-c", "user.email=t@t"])
+            .args(args)
+            .status()
+            .unwrap();
+        assert!(status.success());
+    };
+    std::fs::create_dir_all(repo.join("pkgs/foo")).unwrap();
+    git(&["init", "-q", "repo"]);
+    for version in ["1.0", "2.0"] {
+        std::fs::write(repo.join("pkgs/foo/default.nix"), format!(r#"{{ pname = "foo"; version = "{}"; }}"#, version)).unwrap();
+        git(&["-C", "repo", "add", "."]);
+        git(&["-C", "repo", "commit", "-qm", version]);
+    }
+    git(&["clone", "-q", "--depth", "1", &format!("file://{}", repo.display()), "shallow"]);
+
+    let index = |extra: &[&str]| {
+        bin()
+            .arg("--database").arg(&db_path)
+            .args(["index", "--repo"]).arg(&shallow)
+            .args(extra)
+            .output()
+            .expect("failed to run binary")
+    };
+
+    let output = index(&[]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("is a shallow clone") && stderr.contains("--allow-shallow"), "got: {}", stderr);
+
+    let output = index(&["--allow-shallow"]);
+    assert!(output.status.success(), "index failed: {}", String::from_utf8_lossy(&output.stderr));
+}
+
+// ── reading commands ──────────────────────────────────────────────────────────
+
+#[test]
+fn test_reading_commands_work_while_database_is_locked() {
+    let tmp = TempDir::new().unwrap();
+    let db_path = tmp.path().join("test.db");
+
+    // Held open for the whole test, like a running `index`
+    let db = ArchiverDb::open(&db_path).unwrap();
+    db.insert_if_better(&PackageEntry::new(
+        "foo".to_string(), "1.0".to_string(), "abc1234567890abcdef01234567890abcdef0123".to_string(), 1700000000,
+    )).unwrap();
+    db.flush().unwrap();
+
+    let output = bin()
+        .arg("--database").arg(&db_path)
+        .args(["search", "foo", "1.0"])
+        .output()
+        .expect("failed to run binary");
+    assert!(output.status.success(), "search failed: {}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("abc1234567890abcdef01234567890abcdef0123"));
+
+    // Commands that write still need the lock
+    let output = bin()
+        .arg("--database").arg(&db_path)
+        .args(["import", "--input", "/dev/null"])
+        .output()
+        .expect("failed to run binary");
+    assert!(!output.status.success());
+    drop(db);
+}
+
+// ── JSON logs ─────────────────────────────────────────────────────────────────
+
+#[test]
+fn test_log_format_json_emits_structured_records() {
+    let tmp = TempDir::new().unwrap();
+    let repo = one_package_repo(&tmp);
+
+    let output = bin()
+        .env_remove("RUST_LOG")
+        .arg("--database").arg(tmp.path().join("test.db"))
+        .args(["--log-format", "json", "index", "--head-only", "--repo"]).arg(&repo)
+        .output()
+        .expect("failed to run binary");
+    assert!(output.status.success(), "index failed: {}", String::from_utf8_lossy(&output.stderr));
+
+    let records: Vec<serde_json::Value> = String::from_utf8_lossy(&output.stderr)
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap_or_else(|e| panic!("not JSON ({}): {}", e, line)))
+        .collect();
+    assert!(records.iter().all(|r| r["level"].is_string() && r["timestamp"].is_string()));
+    let done = records.iter()
+        .find(|r| r["message"].as_str().is_some_and(|m| m.contains("Head-only scan complete")))
+        .expect("completion record");
+    assert_eq!(done["packages_found"], 1);
+    assert_eq!(done["packages_inserted"], 1);
+}
+
+// ── doctor ────────────────────────────────────────────────────────────────────
+
+#[test]
+fn test_doctor_fails_on_invalid_repo_without_creating_db() {
+    let tmp = TempDir::new().unwrap();
+    let db_path = tmp.path().join("test.db");
+
+    let output = bin()
+        .arg("--database").arg(&db_path)
+        .arg("doctor")
+        .arg("--repo").arg(tmp.path())
+        .output()
+        .expect("failed to run binary");
+
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("is not a git repository"), "got: {}", stdout);
+    assert!(!db_path.exists(), "doctor must not create the database");
+}
+
+// ── missing-hashes ────────────────────────────────────────────────────────────
+
+#[test]
+fn test_missing_hashes_lists_commits_without_tarball_hash() {
+    const OLD: &str = "abc1234567890abcdef01234567890abcdef0123";
+    const NEW: &str = "def1234567890abcdef01234567890abcdef0456";
+    const HASHED: &str = "0001234567890abcdef01234567890abcdef0789";
+
+    let entries = [("1.0", OLD, 1600000000), ("2.0", NEW, 1700000000), ("3.0", HASHED, 1710000000)]
+        .map(|(version, sha, ts)| PackageEntry::new("foo".to_string(), version.to_string(), sha.to_string(), ts));
+    let (_tmp, db_path) = seed_entries_with(entries, |db| {
+        db.store_tarball_hash(HASHED, "sha256-47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=").unwrap();
+    });
+
+    let stdout = run_ok(&db_path, &["missing-hashes", "-n", "1"]);
+    assert!(stdout.contains("2 of 3 indexed commit(s) have no tarball hash"), "got: {}", stdout);
+    // Newest first by default; the other one is only counted
+    assert!(stdout.contains(NEW) && stdout.contains("2023-11-14"), "got: {}", stdout);
+    assert!(!stdout.contains(OLD) && !stdout.contains(HASHED), "got: {}", stdout);
+    assert!(stdout.contains("… 1 more"), "got: {}", stdout);
+}
+
+#[test]
+fn test_recent_prefetch_failures_are_reported_and_skipped() {
+    const PENDING: &str = "abc1234567890abcdef01234567890abcdef0123";
+    const FAILED: &str = "def1234567890abcdef01234567890abcdef0456";
+
+    let entries = [("1.0", PENDING, 1600000000), ("2.0", FAILED, 1700000000)]
+        .map(|(version, sha, ts)| PackageEntry::new("foo".to_string(), version.to_string(), sha.to_string(), ts));
+    let (_tmp, db_path) = seed_entries_with(entries, |db| {
+        let now = chrono::Utc::now().timestamp() as u64;
+        db.record_prefetch_failure(FAILED, false, now, "HTTP error 404").unwrap();
+    });
+
+    let stdout = run_ok(&db_path, &["missing-hashes"]);
+    assert!(stdout.contains("2 of 2 indexed commit(s) have no tarball hash: 1 pending, 1 failed"), "got: {}", stdout);
+    assert!(stdout.contains("failed 1×") && stdout.contains("HTTP error 404"), "got: {}", stdout);
+    wait_until_unlocked(&db_path);
+
+    // With the pending one hashed, nothing is left to fetch: the failed
+    // commit is skipped before Nix is even needed
+    write_db(&db_path, |db| {
+        db.store_tarball_hash(PENDING, "sha256-47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=").unwrap();
+    });
+    let stdout = run_ok(&db_path, &["prefetch-hashes"]);
+    assert!(stdout.contains("Skipping 1 commit(s) that failed in the last 7 days"), "got: {}", stdout);
+    assert!(stdout.contains("No other indexed commit is missing a tarball hash"), "got: {}", stdout);
+}
+
+// ── parse ─────────────────────────────────────────────────────────────────────
+
+#[test]
+fn test_parse_reads_local_file_and_its_siblings_without_database() {
+    let tmp = TempDir::new().unwrap();
+    let db_path = tmp.path().join("test.db");
+    let dir = tmp.path().join("checkout");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("default.nix"),
+        "{ lib, rustPlatform }:\nrustPlatform.buildRustPackage {\n  pname = \"foo\";\n  version = (lib.importTOML ./Cargo.toml).package.version;\n}\n",
+    )
+    .unwrap();
+    std::fs::write(dir.join("Cargo.toml"), "[package]\nname = \"foo\"\nversion = \"0.4.2\"\n").unwrap();
+
+    let output = bin()
+        .arg("--database").arg(&db_path)
+        .arg("parse")
+        .arg(dir.join("default.nix"))
+        .args(["--path", "pkgs/tools/foo/default.nix", "--json"])
+        .output()
+        .expect("failed to run binary");
+
+    assert!(output.status.success(), "stderr: {}", String::from_utf8_lossy(&output.stderr));
+    let parsed: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(parsed["strategy"], "ast");
+    assert_eq!(parsed["packages"][0]["attr_name"], "foo");
+    assert_eq!(parsed["packages"][0]["version"], "0.4.2");
+    assert!(!db_path.exists(), "parse must not create the database");
+}
+
+// ── colors ────────────────────────────────────────────────────────────────────
+
+#[test]
+fn test_no_color_flag_and_env_strip_escape_codes() {
+    let tmp = TempDir::new().unwrap();
+    let db_path = tmp.path().join("test.db");
+
+    let stats = |args: &[&str], env: &[(&str, &str)]| {
+        let output = bin()
+            .arg("--database").arg(&db_path)
+            .args(args)
+            .arg("stats")
+            .env_remove("NO_COLOR")
+            .envs(env.iter().copied())
+            .output()
+            .expect("failed to run binary");
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+
+    // Piped stdout: colors are off by default
+    assert!(!stats(&[], &[]).contains('\x1b'));
+
+    // CLICOLOR_FORCE turns them back on, --no-color and NO_COLOR win over it
+    let forced = [("CLICOLOR_FORCE", "1")];
+    assert!(stats(&[], &forced).contains('\x1b'));
+    assert!(!stats(&["--no-color"], &forced).contains('\x1b'));
+    assert!(!stats(&[], &[("CLICOLOR_FORCE", "1"), ("NO_COLOR", "1")]).contains('\x1b'));
+}
+
+// ── watch ─────────────────────────────────────────────────────────────────────
+
+#[test]
+fn test_watch_indexes_only_new_upstream_commits() {
+    let tmp = TempDir::new().unwrap();
+    let upstream = tmp.path().join("upstream");
+    let clone = tmp.path().join("clone");
+    let db_path = tmp.path().join("test.db");
+    let git = |dir: &Path, args: &[&str]| git_at(dir, "2024-01-01T00:00:00Z", args);
+
+    std::fs::create_dir_all(&upstream).unwrap();
+    git(&upstream, &["init", "-q"]);
+    commit_pkg(&upstream, "foo", "1.0", "2024-01-01T00:00:00Z");
+    git(tmp.path(), &["clone", "-q", upstream.to_str().unwrap(), clone.to_str().unwrap()]);
+
+    let watch_once = || run_ok(&db_path, &["watch", "--cycles", "1", "--rev", "origin/HEAD", "--repo", clone.to_str().unwrap()]);
+
+    assert!(watch_once().contains("1 new commit(s)"));
+    assert!(watch_once().contains("no new commits"));
+
+    commit_pkg(&upstream, "foo", "2.0", "2024-01-03T00:00:00Z");
+    assert!(watch_once().contains("1 new commit(s)"));
+    assert!(is_indexed(&db_path, "foo", "2.0"), "foo 2.0 was not indexed");
+
+    // A side branch dated before foo 2.0, merged after it: the walk
+    // reaches it only after passing the already indexed foo 2.0 commit
+    git(&upstream, &["checkout", "-q", "-b", "side", "HEAD~1"]);
+    commit_pkg(&upstream, "bar", "1.0", "2024-01-02T00:00:00Z");
+    git(&upstream, &["checkout", "-q", "-"]);
+    commit_pkg(&upstream, "foo", "3.0", "2024-01-04T00:00:00Z");
+    git_at(&upstream, "2024-01-05T00:00:00Z", &["merge", "-q", "--no-edit", "side"]);
+
+    assert!(watch_once().contains("3 new commit(s)"));
+    assert!(is_indexed(&db_path, "foo", "3.0"), "foo 3.0 was not indexed");
+    assert!(is_indexed(&db_path, "bar", "1.0"), "bar 1.0 from the merged branch was not indexed");
+}
+
+#[test]
+fn test_watch_resumes_after_the_last_indexed_commit() {
+    let tmp = TempDir::new().unwrap();
+    let upstream = tmp.path().join("upstream");
+    let clone = tmp.path().join("clone");
+    let db_path = tmp.path().join("test.db");
+    let git = |dir: &Path, args: &[&str]| git_at(dir, "2024-01-01T00:00:00Z", args);
+
+    std::fs::create_dir_all(&upstream).unwrap();
+    git(&upstream, &["init", "-q"]);
+    commit_pkg(&upstream, "foo", "1.0", "2024-01-01T00:00:00Z");
+    git(tmp.path(), &["clone", "-q", upstream.to_str().unwrap(), clone.to_str().unwrap()]);
+    run_ok(&db_path, &["index", "--repo", clone.to_str().unwrap()]);
+
+    // Upstream moves on while nothing watches it
+    commit_pkg(&upstream, "foo", "2.0", "2024-01-02T00:00:00Z");
+    commit_pkg(&upstream, "bar", "1.0", "2024-01-03T00:00:00Z");
+    commit_pkg(&upstream, "foo", "3.0", "2024-01-04T00:00:00Z");
+    // Already fetched when watch starts, so its head is the newest commit
+    git(&clone, &["fetch", "-q"]);
+
+    let watch_once = || run_ok(&db_path, &["watch", "--cycles", "1", "--rev", "origin/HEAD", "--repo", clone.to_str().unwrap()]);
+    let stdout = watch_once();
+    assert!(stdout.contains("3 new commit(s)"), "got: {}", stdout);
+    for (name, version) in [("foo", "2.0"), ("bar", "1.0"), ("foo", "3.0")] {
+        assert!(is_indexed(&db_path, name, version), "{} {} was not indexed", name, version);
+    }
+
+    // A restarted watch resumes from its own last cycle
+    assert!(watch_once().contains("no new commits"));
+}
+
+// ── recorded upstream ─────────────────────────────────────────────────────────
+
+#[test]
+fn test_generate_defaults_to_recorded_origin_of_a_fork() {
+    let tmp = TempDir::new().unwrap();
+    let repo = one_package_repo(&tmp);
+    let db_path = tmp.path().join("test.db");
+    git_at(&repo, "2024-01-01T00:00:00Z", &["remote", "add", "origin", "git@github.com:someone/nixpkgs.git"]);
+
+    let output = bin()
+        .arg("--database").arg(&db_path)
+        .args(["index", "--head-only", "--repo"]).arg(&repo)
+        .output()
+        .expect("failed to run binary");
+    assert!(output.status.success(), "index failed: {}", String::from_utf8_lossy(&output.stderr));
+
+    let input = tmp.path().join("packages.nix");
+    std::fs::write(&input, "{ foo = \"1.0\"; }").unwrap();
+    let generate = |extra: &[&str]| {
+        let frozen = tmp.path().join("frozen.nix");
+        let output = bin()
+            .arg("--database").arg(&db_path)
+            .args(extra)
+            .arg("generate").arg("--force")
+            .arg("--input").arg(&input)
+            .arg("--output").arg(&frozen)
+            .env_remove("NIX_ARCHIVER_UPSTREAM")
+            .output()
+            .expect("failed to run binary");
+        assert!(output.status.success(), "generate failed: {}", String::from_utf8_lossy(&output.stderr));
+        std::fs::read_to_string(frozen).unwrap()
+    };
+
+    let frozen = generate(&[]);
+    assert!(frozen.contains("url = \"https://github.com/someone/nixpkgs\""), "got: {}", frozen);
+
+    let frozen = generate(&["--upstream", "github.com/NixOS/nixpkgs"]);
+    assert!(frozen.contains("url = \"https://github.com/NixOS/nixpkgs\""), "got: {}", frozen);
+}
@@ -0,0 +1,107 @@
+//! Tests for JSON Lines export/import
+
+use anyhow::Result;
+use archiver_cli::export::{export_to_path, import_from_path, Compression};
+use archiver_core::PackageEntry;
+use archiver_db::ArchiverDb;
+use tempfile::TempDir;
+
+const SHA1: &str = "abc1234567890abcdef01234567890abcdef0123";
+const SHA2: &str = "def1234567890abcdef01234567890abcdef0456";
+
+#[test]
+fn test_gzip_export_import_round_trip() -> Result<()> {
+    let tmp = TempDir::new()?;
+    let src = ArchiverDb::open(tmp.path().join("src.db"))?;
+    let sample = PackageEntry::new("nodejs".to_string(), "20.11.0".to_string(), SHA2.to_string(), 2000);
+    src.insert_if_better(&PackageEntry::new("nodejs".to_string(), "18.0.0".to_string(), SHA1.to_string(), 1000))?;
+    src.insert_if_better(&sample)?;
+    src.insert_if_better(&PackageEntry::new("go".to_string(), "1.21.5".to_string(), SHA1.to_string(), 1000))?;
+
+    let dump = tmp.path().join("dump.jsonl.gz");
+    assert_eq!(Compression::from_path(&dump), Compression::Gzip);
+    assert_eq!(export_to_path(&src, &dump, Compression::Gzip)?, 3);
+
+    // gzip magic bytes
+    let raw = std::fs::read(&dump)?;
+    assert_eq!(&raw[..2], &[0x1f, 0x8b]);
+
+    let dst = ArchiverDb::open(tmp.path().join("dst.db"))?;
+    assert_eq!(import_from_path(&dst, &dump)?, 3);
+    assert_eq!(dst.version_count(), 3);
+    assert_eq!(dst.get("nodejs", "20.11.0")?, Some(sample));
+    Ok(())
+}
+
+#[test]
+fn test_plain_export_is_json_lines() -> Result<()> {
+    let tmp = TempDir::new()?;
+    let db = ArchiverDb::open(tmp.path().join("src.db"))?;
+    db.insert_if_better(&PackageEntry::new("go".to_string(), "1.21.5".to_string(), SHA1.to_string(), 1000))?;
+
+    let dump = tmp.path().join("dump.jsonl");
+    export_to_path(&db, &dump, Compression::from_path(&dump))?;
+
+    let text = std::fs::read_to_string(&dump)?;
+    assert_eq!(text.lines().count(), 1);
+    assert!(text.contains("\"attr_name\":\"go\""));
+    Ok(())
+}
+
+#[test]
+fn test_unknown_hash_placeholder_round_trips_as_no_hash() -> Result<()> {
+    use archiver_cli::export::{export_jsonl, import_jsonl};
+
+    let tmp = TempDir::new()?;
+    let db = ArchiverDb::open(tmp.path().join("db"))?;
+    // Dumps from older indexers spell a failed hash as "unknown"
+    let line = format!(
+        r#"{{"attr_name":"go","version":"1.21.5","commit_sha":"{}","timestamp":1000,"is_primary":true,"nar_hash":"unknown"}}"#,
+        SHA1
+    );
+    assert_eq!(import_jsonl(&db, line.as_bytes())?, 1);
+    db.insert_if_better(&PackageEntry::new("nodejs".to_string(), "20.11.0".to_string(), SHA2.to_string(), 2000))?;
+
+    assert_eq!(db.get("go", "1.21.5")?.unwrap().nar_hash, None);
+    assert_eq!(db.get("nodejs", "20.11.0")?.unwrap().nar_hash, None);
+
+    let mut out = Vec::new();
+    export_jsonl(&db, &mut out)?;
+    let text = String::from_utf8(out)?;
+    assert_eq!(text.matches("\"nar_hash\":null").count(), 2, "got: {}", text);
+    assert!(!text.contains("unknown"));
+    Ok(())
+}
+
+#[test]
+fn test_package_history_export_lists_every_version_newest_first() -> Result<()> {
+    use archiver_cli::export::{export_package_to_path, HistoryFormat};
+
+    let tmp = TempDir::new()?;
+    let db = ArchiverDb::open(tmp.path().join("db"))?;
+    // Timestamps out of version order: the export sorts by version
+    for (version, sha, ts) in [("1.20", SHA1, 3000), ("1.9", SHA1, 1000), ("1.21", SHA2, 2000)] {
+        db.insert_if_better(&PackageEntry::new("go".to_string(), version.to_string(), sha.to_string(), ts))?;
+    }
+    db.insert_if_better(&PackageEntry::new("nodejs".to_string(), "20.11.0".to_string(), SHA2.to_string(), 2000))?;
+
+    let csv_path = tmp.path().join("go.csv");
+    assert_eq!(HistoryFormat::from_path(&csv_path), HistoryFormat::Csv);
+    assert_eq!(export_package_to_path(&db, "go", &csv_path, HistoryFormat::Csv)?, 3);
+    let text = std::fs::read_to_string(&csv_path)?;
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines[0], "attr_name,version,commit_sha,timestamp,is_primary,nar_hash,main_program,description,content_hash,platform,source_hash,source_rev,source_path,attr_from_path");
+    assert_eq!(lines.len(), 4);
+    assert!(lines[1].starts_with(&format!("go,1.21,{},2000,", SHA2)), "got: {}", lines[1]);
+    assert!(lines[2].starts_with("go,1.20,"), "got: {}", lines[2]);
+    assert!(lines[3].starts_with("go,1.9,"), "got: {}", lines[3]);
+
+    let json_path = tmp.path().join("go.json");
+    export_package_to_path(&db, "go", &json_path, HistoryFormat::from_path(&json_path))?;
+    let entries: Vec<PackageEntry> = serde_json::from_str(&std::fs::read_to_string(&json_path)?)?;
+    let versions: Vec<&str> = entries.iter().map(|e| e.version.as_str()).collect();
+    assert_eq!(versions, ["1.21", "1.20", "1.9"]);
+
+    assert!(export_package_to_path(&db, "python", &json_path, HistoryFormat::Json).is_err());
+    Ok(())
+}
@@ -0,0 +1,360 @@
+//! Tests for spec parsing, resolution and rendering used by `generate`
+
+use anyhow::Result;
+use archiver_cli::generate::{generate_frozen, parse_packages_spec, render, report_json, resolve_spec, OutputFormat};
+use archiver_core::{PackageEntry, Upstream};
+use archiver_db::ArchiverDb;
+use std::path::Path;
+use tempfile::TempDir;
+
+// ── fixtures ─────────────────────────────────────────────────────────────────
+
+const SHA1: &str = "abc1234567890abcdef01234567890abcdef0123";
+const SHA2: &str = "def1234567890abcdef01234567890abcdef0456";
+
+fn spec(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+    pairs.iter().map(|(a, v)| (a.to_string(), v.to_string())).collect()
+}
+
+fn seeded_db(tmp: &TempDir) -> Result<ArchiverDb> {
+    let db = ArchiverDb::open(tmp.path())?;
+    db.insert_if_better(&PackageEntry::new("nodejs".to_string(), "18.0.0".to_string(), SHA1.to_string(), 1000))?;
+    db.insert_if_better(&PackageEntry::new("nodejs".to_string(), "20.11.0".to_string(), SHA2.to_string(), 2000))?;
+    Ok(db)
+}
+
+// ── parse ────────────────────────────────────────────────────────────────────
+
+#[test]
+fn test_parse_spec_collects_pairs_and_skips_interpolation() -> Result<()> {
+    let content = r#"{ nodejs = "20.11.0"; go = "${v}"; python = "latest"; }"#;
+    let parsed = parse_packages_spec(Path::new("packages.nix"), content)?;
+    assert_eq!(parsed.entries, spec(&[("nodejs", "20.11.0"), ("python", "latest")]));
+    assert_eq!(parsed.skipped, vec!["go".to_string()]);
+    Ok(())
+}
+
+#[test]
+fn test_parse_spec_rejects_duplicate_attr_name() {
+    let content = r#"{ nodejs = "18.0.0"; go = "1.21.5"; nodejs = "20.11.0"; }"#;
+    let err = parse_packages_spec(Path::new("packages.nix"), content).unwrap_err();
+    assert!(err.to_string().contains("Duplicate package 'nodejs'"), "got: {}", err);
+}
+
+#[test]
+fn test_parse_spec_keeps_valid_entries_around_a_syntax_error() -> Result<()> {
+    let tmp = TempDir::new()?;
+    let db = seeded_db(&tmp)?;
+    db.insert_if_better(&PackageEntry::new("go".to_string(), "1.21.5".to_string(), SHA1.to_string(), 1000))?;
+
+    let content = r#"{
+  nodejs = "20.11.0";
+  ripgrep = ;
+  go = "1.21.5";
+  nodejs_18 = "18.0.0";
+}
+"#;
+    let parsed = parse_packages_spec(Path::new("packages.nix"), content)?;
+    assert_eq!(parsed.entries, spec(&[("nodejs", "20.11.0"), ("go", "1.21.5"), ("nodejs_18", "18.0.0")]));
+    assert_eq!(parsed.parse_errors, vec!["line 3: `ripgrep = ;` is not a valid binding".to_string()]);
+
+    let resolved = resolve_spec(&db, &parsed.entries[..2], false)?;
+    assert_eq!(resolved.packages.len(), 2);
+    assert!(resolved.errors.is_empty(), "got: {:?}", resolved.errors);
+
+    // Nothing readable at all is still an error
+    let err = parse_packages_spec(Path::new("packages.nix"), "{ nodejs = ; }").unwrap_err();
+    assert!(err.to_string().contains("Nix parse error"), "got: {}", err);
+    Ok(())
+}
+
+#[test]
+fn test_toml_spec_renders_same_frozen_nix_as_nix_spec() -> Result<()> {
+    let tmp = TempDir::new()?;
+    let db = seeded_db(&tmp)?;
+    db.insert_if_better(&PackageEntry::new("go".to_string(), "1.21.5".to_string(), SHA1.to_string(), 1000))?;
+
+    let nix_spec = parse_packages_spec(Path::new("packages.nix"), r#"{ nodejs = "latest"; go = "1.21.5"; }"#)?;
+    let toml_spec = parse_packages_spec(
+        Path::new("packages.toml"),
+        "# pins\n[packages]\nnodejs = \"latest\"\ngo = \"1.21.5\"\n",
+    )?;
+    assert_eq!(toml_spec.entries, nix_spec.entries, "file order is kept");
+
+    let render_spec = |entries| -> Result<String> {
+        let resolved = resolve_spec(&db, entries, false)?;
+        Ok(render(&resolved, &OutputFormat::FrozenNix { nixpkgs: None }))
+    };
+    assert_eq!(render_spec(&toml_spec.entries)?, render_spec(&nix_spec.entries)?);
+    Ok(())
+}
+
+#[test]
+fn test_toml_spec_rejects_non_string_versions_and_missing_table() {
+    let err = parse_packages_spec(Path::new("packages.toml"), "[packages]\nnodejs = 20\n").unwrap_err();
+    assert!(err.to_string().contains("Package 'nodejs'"), "got: {}", err);
+
+    let err = parse_packages_spec(Path::new("packages.toml"), "nodejs = \"20.11.0\"\n").unwrap_err();
+    assert!(err.to_string().contains("Unknown key 'nodejs'"), "got: {}", err);
+
+    let err = parse_packages_spec(Path::new("packages.toml"), "").unwrap_err();
+    assert!(err.to_string().contains("No [packages] table"), "got: {}", err);
+}
+
+// ── resolve ──────────────────────────────────────────────────────────────────
+
+#[test]
+fn test_resolve_latest_picks_newest_version() -> Result<()> {
+    let tmp = TempDir::new()?;
+    let db = seeded_db(&tmp)?;
+
+    let resolved = resolve_spec(&db, &spec(&[("nodejs", "latest")]), false)?;
+    assert!(resolved.is_complete());
+    assert_eq!(resolved.packages.len(), 1);
+    assert_eq!(resolved.packages[0].requested, "latest");
+    assert_eq!(resolved.packages[0].entry.version, "20.11.0");
+    Ok(())
+}
+
+#[test]
+fn test_resolve_latest_skips_prereleases_unless_included() -> Result<()> {
+    let tmp = TempDir::new()?;
+    let db = ArchiverDb::open(tmp.path())?;
+    for (version, sha) in [("1.21", SHA1), ("1.22rc1", SHA2)] {
+        db.insert_if_better(&PackageEntry::new("go".to_string(), version.to_string(), sha.to_string(), 1000))?;
+    }
+    db.insert_if_better(&PackageEntry::new("zig".to_string(), "0.14.0-beta.1".to_string(), SHA2.to_string(), 1000))?;
+
+    let resolved = resolve_spec(&db, &spec(&[("go", "latest")]), false)?;
+    assert_eq!(resolved.packages[0].entry.version, "1.21");
+
+    let resolved = resolve_spec(&db, &spec(&[("go", "latest")]), true)?;
+    assert_eq!(resolved.packages[0].entry.version, "1.22rc1");
+
+    // Only pre-releases: an error pointing at the opt-in, not a silent pick
+    let resolved = resolve_spec(&db, &spec(&[("zig", "latest")]), false)?;
+    assert!(!resolved.is_complete());
+    assert!(resolved.errors[0].contains("No stable versions found for package 'zig'"));
+    assert_eq!(resolved.unresolved[0].available, vec!["0.14.0-beta.1".to_string()]);
+    Ok(())
+}
+
+#[test]
+fn test_resolve_collects_errors_with_suggestions() -> Result<()> {
+    let tmp = TempDir::new()?;
+    let db = seeded_db(&tmp)?;
+
+    let resolved = resolve_spec(&db, &spec(&[
+        ("nodejs", "18.0.0"),
+        ("nodejs", "99.0.0"),
+        ("missing", "latest"),
+    ]), false)?;
+    assert!(!resolved.is_complete());
+    assert_eq!(resolved.packages.len(), 1);
+    assert!(resolved.errors.iter().any(|e| e.contains("nodejs:99.0.0 not found")));
+    assert!(resolved.errors.iter().any(|e| e.contains("Available versions: 20.11.0, 18.0.0")));
+    assert!(resolved.errors.iter().any(|e| e.contains("No versions found for package 'missing'")));
+    Ok(())
+}
+
+#[test]
+fn test_report_json_lists_resolved_and_failed_packages() -> Result<()> {
+    let tmp = TempDir::new()?;
+    let db = seeded_db(&tmp)?;
+
+    let resolved = resolve_spec(&db, &spec(&[("nodejs", "latest"), ("nodejs", "99.0.0")]), false)?;
+    let report = report_json(&resolved, &["go".to_string()]);
+
+    assert_eq!(report["complete"], false);
+    assert_eq!(report["skipped"], serde_json::json!(["go"]));
+
+    let pkg = &report["packages"][0];
+    assert_eq!(pkg["requested"], "latest");
+    assert_eq!(pkg["version"], "20.11.0");
+    assert_eq!(pkg["commit"], SHA2);
+    assert_eq!(pkg["commit_date"], "1970-01-01T00:33:20Z");
+    assert!(pkg["tarball_hash"].is_null());
+
+    let err = &report["errors"][0];
+    assert_eq!(err["requested"], "99.0.0");
+    assert_eq!(err["error"], "Package nodejs:99.0.0 not found in database");
+    assert_eq!(err["available_versions"], serde_json::json!(["20.11.0", "18.0.0"]));
+    Ok(())
+}
+
+// ── render ───────────────────────────────────────────────────────────────────
+
+#[test]
+fn test_render_frozen_nix_shares_commit_bindings() -> Result<()> {
+    let tmp = TempDir::new()?;
+    let db = seeded_db(&tmp)?;
+    db.insert_if_better(&PackageEntry::new("go".to_string(), "1.21.5".to_string(), SHA2.to_string(), 2000))?;
+    db.store_tarball_hash(SHA2, "0mdqa9w1p6cmli6976v4wi0sw9r4p5prkj7lzfd1877wk11c9c73")?;
+
+    let resolved = resolve_spec(&db, &spec(&[("nodejs", "latest"), ("go", "1.21.5")]), false)?;
+    let nix = render(&resolved, &OutputFormat::FrozenNix { nixpkgs: None });
+
+    // Both packages share one commit → one let-binding
+    assert_eq!(nix.matches(&format!("nixpkgs_{} = ", SHA2)).count(), 1);
+    assert!(nix.contains("sha256 = \"0mdqa9w1p6cmli6976v4wi0sw9r4p5prkj7lzfd1877wk11c9c73\""));
+    assert!(nix.contains(&format!("nodejs = import nixpkgs_{} {{}};", SHA2)));
+    assert!(nix.contains(&format!("go = import nixpkgs_{} {{}};", SHA2)));
+    Ok(())
+}
+
+#[test]
+fn test_render_build_inputs_lists_nested_attrpaths() -> Result<()> {
+    let tmp = TempDir::new()?;
+    let db = seeded_db(&tmp)?;
+    db.insert_if_better(&PackageEntry::new("python3Packages.requests".to_string(), "2.31.0".to_string(), SHA2.to_string(), 2000))?;
+    db.insert_if_better(&PackageEntry::new("vscode-extensions.1password.op-vscode".to_string(), "1.0.4".to_string(), SHA1.to_string(), 1000))?;
+
+    let resolved = resolve_spec(
+        &db,
+        &spec(&[("nodejs", "20.11.0"), ("python3Packages.requests", "latest"), ("vscode-extensions.1password.op-vscode", "1.0.4")]),
+        false,
+    )?;
+    let nix = render(&resolved, &OutputFormat::BuildInputs { nixpkgs: None });
+
+    // One binding per commit, then a list instead of an attribute set
+    assert_eq!(nix.matches(&format!("nixpkgs_{} = ", SHA2)).count(), 1);
+    assert_eq!(nix.matches(&format!("nixpkgs_{} = ", SHA1)).count(), 1);
+    assert!(nix.contains("in\n[\n"));
+    assert!(nix.trim_end().ends_with(']'));
+    assert!(nix.contains(&format!("  (import nixpkgs_{} {{}}).nodejs\n", SHA2)));
+    assert!(nix.contains(&format!("  (import nixpkgs_{} {{}}).python3Packages.requests\n", SHA2)));
+    assert!(nix.contains(&format!("  (import nixpkgs_{} {{}}).vscode-extensions.\"1password\".op-vscode\n", SHA1)));
+    Ok(())
+}
+
+#[test]
+fn test_render_frozen_nix_pins_fetchgit_fallback_with_git_narhash() -> Result<()> {
+    let tmp = TempDir::new()?;
+    let db = seeded_db(&tmp)?;
+    let nar = "sha256-1t4a7U6pIWDlsXQlsAfAa+u9uS999tngBaykwy22Vx8=";
+    db.store_git_narhash(SHA2, nar)?;
+
+    let resolved = resolve_spec(&db, &spec(&[("nodejs", "20.11.0")]), false)?;
+    let nix = render(&resolved, &OutputFormat::FrozenNix { nixpkgs: None });
+    assert!(nix.contains(&format!(
+        "builtins.fetchGit {{ url = \"https://github.com/NixOS/nixpkgs\"; rev = \"{}\"; narHash = \"{}\"; }}",
+        SHA2, nar
+    )));
+
+    // A cached tarball hash still takes priority
+    db.store_tarball_hash(SHA2, "0mdqa9w1p6cmli6976v4wi0sw9r4p5prkj7lzfd1877wk11c9c73")?;
+    let resolved = resolve_spec(&db, &spec(&[("nodejs", "20.11.0")]), false)?;
+    let nix = render(&resolved, &OutputFormat::FrozenNix { nixpkgs: None });
+    assert!(nix.contains("fetchTarball"));
+    assert!(!nix.contains("narHash"));
+    Ok(())
+}
+
+#[test]
+fn test_render_uses_custom_upstream_host_everywhere() -> Result<()> {
+    let tmp = TempDir::new()?;
+    let db = seeded_db(&tmp)?;
+    db.store_tarball_hash(SHA2, "0mdqa9w1p6cmli6976v4wi0sw9r4p5prkj7lzfd1877wk11c9c73")?;
+    let upstream: Upstream = "github.example.com/infra/nixpkgs".parse()?;
+
+    let mut resolved = resolve_spec(&db, &spec(&[("nodejs", "18.0.0"), ("nodejs", "20.11.0")]), false)?;
+    resolved.upstream = upstream.clone();
+
+    let nix = render(&resolved, &OutputFormat::FrozenNix { nixpkgs: None });
+    assert!(nix.contains(&format!("url = \"https://github.example.com/infra/nixpkgs/archive/{}.tar.gz\";", SHA2)));
+    assert!(nix.contains(&format!("builtins.fetchGit {{ url = \"https://github.example.com/infra/nixpkgs\"; rev = \"{}\"; }}", SHA1)));
+    assert!(!nix.contains("github.com/NixOS"));
+
+    let flake = render(&resolved, &OutputFormat::FlakeNix);
+    assert!(flake.contains(&format!("\"github:infra/nixpkgs/{}?host=github.example.com\"", SHA1)));
+
+    let lock: serde_json::Value = serde_json::from_str(&render(&resolved, &OutputFormat::FlakeLock))?;
+    let locked = &lock["nodes"][format!("nixpkgs_{}", SHA2)]["locked"];
+    assert_eq!(locked["host"], "github.example.com");
+    assert_eq!(locked["owner"], "infra");
+    Ok(())
+}
+
+#[test]
+fn test_render_snapshot_binds_single_pkgs_from_resolved_commit() -> Result<()> {
+    let tmp = TempDir::new()?;
+    let db = seeded_db(&tmp)?;
+    db.store_tarball_hash(SHA2, "0mdqa9w1p6cmli6976v4wi0sw9r4p5prkj7lzfd1877wk11c9c73")?;
+
+    let resolved = resolve_spec(&db, &spec(&[("nodejs", "20.11.0")]), false)?;
+    let nix = render(&resolved, &OutputFormat::Snapshot { nixpkgs: None });
+
+    // One `pkgs` binding for the whole snapshot, no per-package imports
+    assert_eq!(nix.matches(" = import ").count(), 1, "got:\n{}", nix);
+    assert!(nix.contains(&format!(
+        "  pkgs = import (fetchTarball {{ url = \"https://github.com/NixOS/nixpkgs/archive/{}.tar.gz\"; sha256 = \"0mdqa9w1p6cmli6976v4wi0sw9r4p5prkj7lzfd1877wk11c9c73\"; }}) {{}};\n",
+        SHA2
+    )), "got:\n{}", nix);
+    assert!(nix.ends_with("in\npkgs\n"));
+    assert!(!nix.contains(SHA1));
+
+    // Without a cached hash the same commit is fetched with fetchGit
+    let resolved = resolve_spec(&db, &spec(&[("nodejs", "18.0.0")]), false)?;
+    let nix = render(&resolved, &OutputFormat::Snapshot { nixpkgs: None });
+    assert!(nix.contains(&format!(
+        "  pkgs = import (builtins.fetchGit {{ url = \"https://github.com/NixOS/nixpkgs\"; rev = \"{}\"; }}) {{}};\n",
+        SHA1
+    )), "got:\n{}", nix);
+    Ok(())
+}
+
+#[test]
+fn test_render_fetch_from_github_binds_each_commit_once() -> Result<()> {
+    let tmp = TempDir::new()?;
+    let db = seeded_db(&tmp)?;
+    db.insert_if_better(&PackageEntry::new("go".to_string(), "1.21.5".to_string(), SHA2.to_string(), 2000))?;
+    db.store_tarball_hash(SHA2, "0mdqa9w1p6cmli6976v4wi0sw9r4p5prkj7lzfd1877wk11c9c73")?;
+
+    let resolved = resolve_spec(&db, &spec(&[("nodejs", "latest"), ("go", "1.21.5"), ("nodejs", "18.0.0")]), false)?;
+    let nix = render(&resolved, &OutputFormat::FetchFromGitHub);
+
+    assert!(nix.contains("{ pkgs ? import <nixpkgs> { } }:"));
+    // nodejs latest and go share SHA2 → one binding
+    assert_eq!(nix.matches(&format!("nixpkgs_{} = pkgs.fetchFromGitHub {{", SHA2)).count(), 1);
+    assert_eq!(nix.matches("pkgs.fetchFromGitHub {").count(), 2);
+    assert!(nix.contains("    owner = \"NixOS\";\n    repo = \"nixpkgs\";\n"));
+    assert!(nix.contains(&format!("    rev = \"{}\";\n    sha256 = \"0mdqa9w1p6cmli6976v4wi0sw9r4p5prkj7lzfd1877wk11c9c73\";", SHA2)));
+    // No cached hash for SHA1: a fake one whose mismatch reports the real hash
+    assert!(nix.contains(&format!("    rev = \"{}\";\n    sha256 = pkgs.lib.fakeSha256;", SHA1)));
+    assert!(nix.contains(&format!("go = import nixpkgs_{} {{}};", SHA2)));
+    assert!(!nix.contains("githubBase"));
+
+    let mut resolved = resolved;
+    resolved.upstream = "github.example.com/infra/nixpkgs".parse()?;
+    let nix = render(&resolved, &OutputFormat::FetchFromGitHub);
+    assert!(nix.contains("    githubBase = \"github.example.com\";\n    owner = \"infra\";"));
+    Ok(())
+}
+
+// ── generate_frozen ──────────────────────────────────────────────────────────
+
+#[test]
+fn test_generate_frozen_matches_resolve_then_render() -> Result<()> {
+    let tmp = TempDir::new()?;
+    let db = seeded_db(&tmp)?;
+    let pins = spec(&[("nodejs", "18.0.0")]);
+    let format = OutputFormat::FrozenNix { nixpkgs: None };
+
+    let nix = generate_frozen(&db, &pins, &format, &Upstream::default(), false)?;
+    assert_eq!(nix, render(&resolve_spec(&db, &pins, false)?, &format));
+    assert!(nix.contains(&format!("nodejs = import nixpkgs_{} {{}};", SHA1)));
+    Ok(())
+}
+
+#[test]
+fn test_generate_frozen_fails_on_unresolved_packages() -> Result<()> {
+    let tmp = TempDir::new()?;
+    let db = seeded_db(&tmp)?;
+
+    let err = generate_frozen(&db, &spec(&[("nodejs", "99.0.0"), ("nosuchpkg", "latest")]), &OutputFormat::FrozenNix { nixpkgs: None }, &Upstream::default(), false)
+        .unwrap_err();
+    let message = err.to_string();
+    assert!(message.contains("2 package(s)"), "got: {}", message);
+    assert!(message.contains("nosuchpkg"), "got: {}", message);
+    Ok(())
+}
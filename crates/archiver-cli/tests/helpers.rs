@@ -0,0 +1,50 @@
+//! Tests for search result ranking
+
+use archiver_cli::helpers::rank_matches;
+use archiver_core::PackageEntry;
+use std::collections::HashMap;
+
+const SHA: &str = "abc1234567890abcdef01234567890abcdef0123";
+
+fn matches(names: &[(&str, u64)]) -> HashMap<String, Vec<PackageEntry>> {
+    names
+        .iter()
+        .map(|(name, ts)| {
+            let entry = PackageEntry::new(name.to_string(), "1.0.0".to_string(), SHA.to_string(), *ts);
+            (name.to_string(), vec![entry])
+        })
+        .collect()
+}
+
+#[test]
+fn test_rank_matches_puts_exact_and_prefix_names_first() {
+    let found = matches(&[
+        ("vscode-extensions.biomejs.biome", 3000),
+        ("biome-language-server", 5000),
+        ("biome", 1000),
+        ("nodePackages.biome", 1000),
+        ("python3Packages.pybiome", 5000),
+        ("Biome-bin", 2000),
+    ]);
+    assert_eq!(rank_matches("biome", &found), [
+        "biome",
+        // Prefix matches, case-insensitive; the shorter name wins over the newer one
+        "Biome-bin",
+        "biome-language-server",
+        // Last component starts with the query
+        "nodePackages.biome",
+        "vscode-extensions.biomejs.biome",
+        // Anywhere else
+        "python3Packages.pybiome",
+    ]);
+}
+
+#[test]
+fn test_rank_matches_breaks_ties_by_recency_then_name() {
+    let mut found = matches(&[("nodejs_18", 1000), ("nodejs_22", 3000), ("nodejs_20", 1000)]);
+    // The newest version decides, not the first one stored
+    found.get_mut("nodejs_18").unwrap().push(PackageEntry::new(
+        "nodejs_18".to_string(), "18.20.0".to_string(), SHA.to_string(), 2000,
+    ));
+    assert_eq!(rank_matches("node", &found), ["nodejs_22", "nodejs_18", "nodejs_20"]);
+}
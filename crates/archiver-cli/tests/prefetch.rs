@@ -0,0 +1,99 @@
+//! Tests for the adaptive concurrency used by `prefetch-hashes`
+
+use archiver_cli::prefetch::{classify_failure, prefetch_all, AdaptiveLimit, FailureKind, RECOVER_AFTER};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+#[test]
+fn test_classify_failure_separates_rate_limits_from_hard_errors() {
+    let rate_limited = "nix-prefetch-url failed: error: unable to download \
+        'https://github.com/NixOS/nixpkgs/archive/abc.tar.gz': HTTP error 429 (curl error: Too Many Requests)";
+    assert_eq!(classify_failure(rate_limited), FailureKind::Transient);
+    assert_eq!(classify_failure("error: unable to download: Timeout was reached"), FailureKind::Transient);
+    assert_eq!(classify_failure("curl error: Could not resolve host: github.com"), FailureKind::Transient);
+
+    let not_found = "nix-prefetch-url failed: error: unable to download \
+        'https://github.com/NixOS/nixpkgs/archive/4293503abc.tar.gz': HTTP error 404";
+    assert_eq!(classify_failure(not_found), FailureKind::Hard, "digits in the SHA are not a status code");
+    assert_eq!(classify_failure("Failed to run nix-prefetch-url"), FailureKind::Hard);
+}
+
+#[test]
+fn test_adaptive_limit_halves_on_failure_and_recovers_up_to_ceiling() {
+    let mut limit = AdaptiveLimit::new(8);
+    assert_eq!(limit.limit(), 8);
+    assert_eq!(limit.backoff(), Duration::ZERO);
+
+    limit.on_transient_failure();
+    assert_eq!(limit.limit(), 4);
+    assert_eq!(limit.backoff(), Duration::from_secs(2));
+    for _ in 0..5 {
+        limit.on_transient_failure();
+    }
+    assert_eq!(limit.limit(), 1, "never below one");
+    assert_eq!(limit.backoff(), Duration::from_secs(60), "backoff is capped");
+
+    for _ in 0..RECOVER_AFTER {
+        limit.on_success();
+    }
+    assert_eq!(limit.limit(), 2);
+    assert_eq!(limit.backoff(), Duration::ZERO);
+
+    for _ in 0..RECOVER_AFTER * 20 {
+        limit.on_success();
+    }
+    assert_eq!(limit.limit(), 8, "the initial jobs is the ceiling");
+}
+
+#[test]
+fn test_prefetch_all_respects_jobs_and_reports_every_commit() {
+    let running = AtomicUsize::new(0);
+    let peak = AtomicUsize::new(0);
+    let commits: Vec<String> = (0..12).map(|i| format!("commit{}", i)).collect();
+
+    let done = Mutex::new(Vec::new());
+    prefetch_all(
+        commits,
+        3,
+        |commit| {
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            peak.fetch_max(now, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(10));
+            running.fetch_sub(1, Ordering::SeqCst);
+            if commit == "commit5" {
+                anyhow::bail!("HTTP error 404");
+            }
+            Ok(format!("hash-{}", commit))
+        },
+        |fetched| done.lock().unwrap().push(fetched),
+    );
+
+    let done = done.into_inner().unwrap();
+    assert_eq!(done.len(), 12);
+    assert!(peak.load(Ordering::SeqCst) <= 3);
+    let failed: Vec<_> = done.iter().filter(|f| f.result.is_err()).collect();
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0].commit, "commit5");
+    assert_eq!(failed[0].attempts, 1, "hard failures are not retried");
+}
+
+#[test]
+fn test_prefetch_all_retries_rate_limited_commit() {
+    let calls = AtomicUsize::new(0);
+    let mut done = Vec::new();
+    prefetch_all(
+        vec!["abc".to_string()],
+        2,
+        |_| match calls.fetch_add(1, Ordering::SeqCst) {
+            0 => anyhow::bail!("HTTP error 429"),
+            _ => Ok("hash".to_string()),
+        },
+        |fetched| done.push(fetched),
+    );
+
+    assert_eq!(done.len(), 1);
+    assert_eq!(done[0].result.as_deref().unwrap(), "hash");
+    assert_eq!(done[0].attempts, 2);
+    assert_eq!(done[0].limit, 1);
+}
@@ -8,4 +8,7 @@ pub enum CoreError {
     
     #[error("Version parsing error: {0}")]
     VersionParsing(String),
+
+    #[error("Invalid upstream: {0}")]
+    InvalidUpstream(String),
 }
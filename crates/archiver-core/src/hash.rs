@@ -0,0 +1,63 @@
+//! sha256 hash encodings used by Nix
+//!
+//! The same 32-byte digest shows up as SRI (`sha256-<base64>`, flake.lock
+//! `narHash`), as Nix base32 (`nix-prefetch-url` output), optionally
+//! prefixed with `sha256:`, or as plain hex. These helpers accept any of
+//! them and produce the canonical SRI form.
+
+use data_encoding::{BASE64, HEXLOWER_PERMISSIVE};
+
+/// Alphabet used by Nix's base32 encoding (omits e, o, u, t).
+const NIX_BASE32_CHARS: &[u8] = b"0123456789abcdfghijklmnpqrsvwxyz";
+
+const HASH_SIZE: usize = 32;
+
+/// Placeholder older indexers wrote as `nar_hash` when hashing failed.
+/// Means the same as no hash (`None`), which is how it is stored and read.
+pub const UNKNOWN_HASH: &str = "unknown";
+
+/// Decodes a sha256 hash in SRI, Nix base32 or hex form into its raw bytes.
+///
+/// Returns `None` for anything else, including [`UNKNOWN_HASH`].
+pub fn parse_sha256(hash: &str) -> Option<[u8; HASH_SIZE]> {
+    let hash = hash.trim();
+    if let Some(b64) = hash.strip_prefix("sha256-") {
+        return BASE64.decode(b64.as_bytes()).ok()?.try_into().ok();
+    }
+
+    let digest = hash.strip_prefix("sha256:").unwrap_or(hash);
+    match digest.len() {
+        52 => decode_nix_base32(digest),
+        64 => HEXLOWER_PERMISSIVE.decode(digest.as_bytes()).ok()?.try_into().ok(),
+        _ => None,
+    }
+}
+
+/// Formats raw sha256 bytes as SRI (`sha256-<base64>`).
+pub fn sha256_to_sri(bytes: &[u8; HASH_SIZE]) -> String {
+    format!("sha256-{}", BASE64.encode(bytes))
+}
+
+/// Normalizes any accepted sha256 encoding to SRI; `None` if it isn't one.
+pub fn normalize_sha256(hash: &str) -> Option<String> {
+    parse_sha256(hash).map(|bytes| sha256_to_sri(&bytes))
+}
+
+fn decode_nix_base32(digest: &str) -> Option<[u8; HASH_SIZE]> {
+    // Nix base32 is little-endian: the last character holds the lowest bits.
+    let mut bytes = [0u8; HASH_SIZE];
+    for (n, &c) in digest.as_bytes().iter().rev().enumerate() {
+        let digit = NIX_BASE32_CHARS.iter().position(|&a| a == c)? as u16;
+        let b = n * 5;
+        let i = b / 8;
+        let j = b % 8;
+        bytes[i] |= (digit << j) as u8;
+        let carry = digit >> (8 - j);
+        if i + 1 < HASH_SIZE {
+            bytes[i + 1] |= carry as u8;
+        } else if carry != 0 {
+            return None;
+        }
+    }
+    Some(bytes)
+}
@@ -5,7 +5,11 @@
 
 mod models;
 mod error;
+mod upstream;
+pub mod hash;
+pub mod version;
 
-pub use models::PackageEntry;
+pub use models::{normalize_version, short_sha, IndexRun, PackageEntry};
 pub use error::CoreError;
+pub use upstream::Upstream;
 
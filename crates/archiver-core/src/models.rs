@@ -1,5 +1,6 @@
 //! Core data models for the archiver
 
+use crate::Upstream;
 use serde::{Deserialize, Serialize};
 use std::fmt;
 
@@ -23,6 +24,48 @@ pub struct PackageEntry {
     
     /// Whether this is the primary/active version
     pub is_primary: bool,
+
+    /// NAR hash (`sha256-<base64>`) of the directory containing the
+    /// package's `.nix` file, i.e. the package definition and its patches
+    #[serde(default)]
+    pub nar_hash: Option<String>,
+
+    /// `meta.mainProgram`: the executable the package provides (e.g. `rg`)
+    #[serde(default)]
+    pub main_program: Option<String>,
+
+    /// `meta.description`: one line on what the package does
+    #[serde(default)]
+    pub description: Option<String>,
+
+    /// sha256 (`sha256-<base64>`) of the raw `.nix` file bytes. Cheap
+    /// identity check for the definition itself; not usable for pinning
+    #[serde(default)]
+    pub content_hash: Option<String>,
+
+    /// System (e.g. `x86_64-linux`) for packages with per-platform sources;
+    /// `None` for the usual platform-independent definition
+    #[serde(default)]
+    pub platform: Option<String>,
+
+    /// sha256 (`sha256-<base64>`) of this platform's source
+    #[serde(default)]
+    pub source_hash: Option<String>,
+
+    /// Upstream revision the source is fetched from (the `rev` of e.g.
+    /// `fetchFromGitHub`), when it says more than the version does
+    #[serde(default)]
+    pub source_rev: Option<String>,
+
+    /// Repository path of the `.nix` file the entry was parsed from
+    #[serde(default)]
+    pub source_path: Option<String>,
+
+    /// Whether `attr_name` was derived from `source_path` (attr map rules
+    /// or the directory heuristic) rather than read from the file, so a
+    /// corrected heuristic can re-derive it (see `rekey`)
+    #[serde(default)]
+    pub attr_from_path: bool,
 }
 
 impl PackageEntry {
@@ -39,21 +82,95 @@ impl PackageEntry {
             commit_sha,
             timestamp,
             is_primary: true,
+            nar_hash: None,
+            main_program: None,
+            description: None,
+            content_hash: None,
+            platform: None,
+            source_hash: None,
+            source_rev: None,
+            source_path: None,
+            attr_from_path: false,
         }
     }
 
+    /// Sets the NAR hash of the package directory
+    pub fn with_nar_hash(mut self, nar_hash: String) -> Self {
+        self.nar_hash = Some(nar_hash);
+        self
+    }
+
+    /// Sets the executable the package provides (`meta.mainProgram`)
+    pub fn with_main_program(mut self, main_program: String) -> Self {
+        self.main_program = Some(main_program);
+        self
+    }
+
+    /// Sets the package description (`meta.description`)
+    pub fn with_description(mut self, description: String) -> Self {
+        self.description = Some(description);
+        self
+    }
+
+    /// Sets the sha256 of the package's `.nix` file
+    pub fn with_content_hash(mut self, content_hash: String) -> Self {
+        self.content_hash = Some(content_hash);
+        self
+    }
+
+    /// Marks the entry as the source for one system, with that source's hash
+    pub fn with_platform(mut self, platform: String, source_hash: Option<String>) -> Self {
+        self.platform = Some(platform);
+        self.source_hash = source_hash;
+        self
+    }
+
+    /// Sets the upstream revision the source is fetched from
+    pub fn with_source_rev(mut self, source_rev: String) -> Self {
+        self.source_rev = Some(source_rev);
+        self
+    }
+
+    /// Sets the file the entry was parsed from, and whether its attr_name
+    /// was derived from that path
+    pub fn with_source_path(mut self, source_path: String, attr_from_path: bool) -> Self {
+        self.source_path = Some(source_path);
+        self.attr_from_path = attr_from_path;
+        self
+    }
+
     /// Generates a key for database storage
-    /// Format: "attr_name:version"
+    /// Format: "attr_name:version", or "attr_name:version:platform" for
+    /// per-platform entries. Versions may themselves contain ':' (epochs
+    /// like `1:2.3.4`); attr names never do, so the attr name is always the
+    /// part before the first ':'
     pub fn key(&self) -> String {
-        format!("{}:{}", self.attr_name, self.version)
+        self.with_platform_suffix(format!("{}:{}", self.attr_name, self.version))
     }
 
-    /// Generates a `fetchTarball` expression in Nix format.
-    pub fn to_nix_fetchtarball(&self) -> String {
-        format!(
-            r#"fetchTarball "https://github.com/NixOS/nixpkgs/archive/{}.tar.gz""#,
-            self.commit_sha
-        )
+    /// The version in canonical form (see [`normalize_version`]);
+    /// `version` itself keeps the raw string for display
+    pub fn normalized_version(&self) -> String {
+        normalize_version(&self.version)
+    }
+
+    /// Database key using the normalized version, so that variants such as
+    /// `v1.2.3` and `1.2.3` share one key
+    pub fn normalized_key(&self) -> String {
+        self.with_platform_suffix(format!("{}:{}", self.attr_name, self.normalized_version()))
+    }
+
+    fn with_platform_suffix(&self, key: String) -> String {
+        match &self.platform {
+            Some(platform) => format!("{}:{}", key, platform),
+            None => key,
+        }
+    }
+
+    /// Generates a `fetchTarball` expression in Nix format for the
+    /// snapshot of `upstream` at this entry's commit.
+    pub fn to_nix_fetchtarball(&self, upstream: &Upstream) -> String {
+        format!(r#"fetchTarball "{}""#, upstream.tarball_url(&self.commit_sha))
     }
 
     /// Generates a complete Nix expression for package import
@@ -65,18 +182,54 @@ impl PackageEntry {
     /// in
     ///   pkgs.nodejs
     /// ```
-    pub fn to_nix_import(&self) -> String {
+    pub fn to_nix_import(&self, upstream: &Upstream) -> String {
         format!(
             r#"let
   pkgs = import ({}) {{}};
 in
   pkgs.{}"#,
-            self.to_nix_fetchtarball(),
+            self.to_nix_fetchtarball(upstream),
             self.attr_name
         )
     }
 }
 
+/// Canonical form of a version string, used to recognize variants of the
+/// same release: `v1.2.3`, `1.2.3`, `1_2_3` and `1.2.3-RELEASE` all become
+/// `1.2.3`.
+///
+/// Lowercases, drops a leading `v` before a digit, turns `_` separators
+/// into `.` and strips a trailing `-release` / `-final` marker. Other
+/// suffixes (`-rc1`, `-unstable-2024-01-01`) are kept, since they do name
+/// different releases.
+pub fn normalize_version(version: &str) -> String {
+    let lower = version.trim().to_lowercase();
+    let unprefixed = match lower.strip_prefix('v') {
+        Some(rest) if rest.starts_with(|c: char| c.is_ascii_digit()) => rest,
+        _ => &lower,
+    };
+    let dotted = unprefixed.replace('_', ".");
+    for marker in ["-release", ".release", "-final", ".final"] {
+        if let Some(base) = dotted.strip_suffix(marker) {
+            if !base.is_empty() {
+                return base.to_string();
+            }
+        }
+    }
+    dotted
+}
+
+/// First `n` characters of a commit SHA for display.
+///
+/// Returns the whole string when it is shorter, so a truncated or malformed
+/// SHA (e.g. from an import) degrades gracefully instead of panicking.
+pub fn short_sha(sha: &str, n: usize) -> &str {
+    match sha.char_indices().nth(n) {
+        Some((end, _)) => &sha[..end],
+        None => sha,
+    }
+}
+
 impl fmt::Display for PackageEntry {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
@@ -84,7 +237,73 @@ impl fmt::Display for PackageEntry {
             "{} {} @ {}",
             self.attr_name,
             self.version,
-            &self.commit_sha[..8]
+            short_sha(&self.commit_sha, 8)
         )
     }
 }
+
+/// Record of one `index` run, stored so a database documents how it was built
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct IndexRun {
+    /// When the run started (Unix epoch)
+    pub started_at: u64,
+
+    /// nix-archiver version that performed the run
+    pub tool_version: String,
+
+    /// Full command line, including the program name
+    pub command_line: Vec<String>,
+
+    /// Local path of the indexed repository
+    pub repo_path: String,
+
+    /// URL of the `origin` remote, if any
+    #[serde(default)]
+    pub repo_url: Option<String>,
+
+    /// Resolved SHA the run started from
+    pub from_commit: String,
+
+    /// Resolved SHA the run stopped at (`--to-commit`)
+    #[serde(default)]
+    pub to_commit: Option<String>,
+
+    /// Date the run stopped at (`--to-date`)
+    #[serde(default)]
+    pub to_date: Option<String>,
+
+    /// Commit limit in effect (None = whole history)
+    #[serde(default)]
+    pub max_commits: Option<usize>,
+
+    /// Whether only the starting commit was scanned
+    #[serde(default)]
+    pub head_only: bool,
+
+    /// Regex used by the fallback parser
+    pub version_regex: String,
+
+    /// Which files were considered (e.g. `pkgs/**/*.nix`)
+    pub path_filter: String,
+
+    /// Attr map file (`--attr-map`) applied during the run
+    #[serde(default)]
+    pub attr_map: Option<String>,
+
+    /// Attr names the run was limited to (`--only`); empty = all packages
+    #[serde(default)]
+    pub only: Vec<String>,
+
+    /// Path whose commits were the only ones processed (`--path-filter-commits`)
+    #[serde(default)]
+    pub commit_path: Option<String>,
+
+    /// Placeholder versions that were not inserted
+    /// (`--skip-placeholder-versions`); empty = every version kept
+    #[serde(default)]
+    pub skipped_versions: Vec<String>,
+
+    /// Whether only first parents were followed (`--first-parent`)
+    #[serde(default)]
+    pub first_parent: bool,
+}
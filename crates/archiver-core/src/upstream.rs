@@ -0,0 +1,107 @@
+//! Where pinned nixpkgs snapshots are fetched from
+//!
+//! Generated expressions and `prefetch-hashes` both build their URLs from
+//! one [`Upstream`], so the hashes cached by prefetching always belong to
+//! the URLs `generate` writes. The default is `github.com/NixOS/nixpkgs`;
+//! GitHub Enterprise and self-hosted mirrors that serve GitHub-style
+//! `/archive/<rev>.tar.gz` tarballs can be used instead.
+
+use crate::CoreError;
+use std::fmt;
+use std::str::FromStr;
+
+/// Host and repository of the nixpkgs snapshots, e.g.
+/// `github.example.com/infra/nixpkgs`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Upstream {
+    pub host: String,
+    pub owner: String,
+    pub repo: String,
+}
+
+impl Default for Upstream {
+    fn default() -> Self {
+        Upstream {
+            host: "github.com".to_string(),
+            owner: "NixOS".to_string(),
+            repo: "nixpkgs".to_string(),
+        }
+    }
+}
+
+impl Upstream {
+    /// Whether this is the public github.com (flake refs need no `host`)
+    pub fn is_github_com(&self) -> bool {
+        self.host.eq_ignore_ascii_case("github.com")
+    }
+
+    /// HTTPS URL of the git repository, for `builtins.fetchGit`
+    pub fn git_url(&self) -> String {
+        format!("https://{}/{}/{}", self.host, self.owner, self.repo)
+    }
+
+    /// Source tarball of `commit`, for `fetchTarball` and `nix-prefetch-url`
+    pub fn tarball_url(&self, commit: &str) -> String {
+        format!("{}/archive/{}.tar.gz", self.git_url(), commit)
+    }
+
+    /// Upstream of a git remote URL: `https://host/owner/repo.git`,
+    /// `git@host:owner/repo.git`, `ssh://git@host:22/owner/repo` or plain
+    /// `host/owner/repo`. `None` for local paths and other layouts.
+    pub fn from_remote_url(url: &str) -> Option<Self> {
+        let url = url.trim();
+        let location = match url.split_once("://") {
+            Some(("https" | "http" | "ssh" | "git", rest)) => rest.to_string(),
+            Some(_) => return None,
+            // scp-like syntax: [user@]host:owner/repo
+            None => match url.split_once(':') {
+                Some((host, path)) if !host.contains('/') => format!("{}/{}", host, path),
+                Some(_) => return None,
+                None => url.to_string(),
+            },
+        };
+        let location = location.trim_end_matches('/');
+        let location = location.strip_suffix(".git").unwrap_or(location);
+
+        let [authority, owner, repo] = location.split('/').collect::<Vec<_>>()[..] else {
+            return None;
+        };
+        // Drop user info and port: git@host:22 → host
+        let host = authority.rsplit('@').next()?.split(':').next()?;
+        if host.is_empty() || owner.is_empty() || repo.is_empty() {
+            return None;
+        }
+        Some(Upstream { host: host.to_string(), owner: owner.to_string(), repo: repo.to_string() })
+    }
+
+    /// `github:` flake reference pinned to `commit`
+    pub fn flake_ref(&self, commit: &str) -> String {
+        let flake_ref = format!("github:{}/{}/{}", self.owner, self.repo, commit);
+        if self.is_github_com() {
+            flake_ref
+        } else {
+            format!("{}?host={}", flake_ref, self.host)
+        }
+    }
+}
+
+/// Parses `host/owner/repo`; clone URLs are accepted as well (see
+/// [`Upstream::from_remote_url`]), so one can be pasted as-is
+impl FromStr for Upstream {
+    type Err = CoreError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Upstream::from_remote_url(s).ok_or_else(|| {
+            CoreError::InvalidUpstream(format!(
+                "'{}' (expected HOST/OWNER/REPO, e.g. github.com/NixOS/nixpkgs)",
+                s
+            ))
+        })
+    }
+}
+
+impl fmt::Display for Upstream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}/{}", self.host, self.owner, self.repo)
+    }
+}
@@ -0,0 +1,149 @@
+//! Natural ordering of version strings
+//!
+//! Shared by the CLI (sorting search results, resolving `latest`) and the
+//! database (bounded top-K queries), so both agree on what "newest" means.
+
+use std::cmp::Ordering;
+
+/// Parsed version key for comparison.
+/// Represents versions like: 1.20.2, 1.26rc3, 1.18beta1, 1.18.0-alpha.1,
+/// Debian-style epochs such as 1:2.3.4 and git snapshots such as
+/// 0-unstable-2024-01-15
+#[derive(Debug, Clone)]
+pub struct VersionKey {
+    /// Epoch before a `N:` prefix (0 without one); dominates everything else
+    pub epoch: u64,
+    /// Numeric components, e.g. [1, 20, 2] for "1.20.2"
+    pub nums: Vec<u64>,
+    /// Pre-release tier: 3=stable, 2=rc, 1=beta, 0=alpha (higher = newer)
+    pub pre_tier: u8,
+    /// Date of an `unstable-YYYY-MM-DD` snapshot as YYYYMMDD, 0 for releases.
+    /// A snapshot sorts after the release it is based on (`1.2` <
+    /// `1.2-unstable-2024-01-15` < `1.3`) and snapshots of the same base by
+    /// date; anything after the date (e.g. a revision) is ignored
+    pub snapshot_date: u32,
+    /// Pre-release index, e.g. 3 for "rc3"
+    pub pre_num: u64,
+}
+
+/// [`VersionKey::pre_tier`] of a release without an rc/beta/alpha tag
+pub const STABLE_TIER: u8 = 3;
+
+impl VersionKey {
+    /// Whether this is a final release rather than an rc, beta or alpha
+    pub fn is_stable(&self) -> bool {
+        self.pre_tier == STABLE_TIER
+    }
+}
+
+pub fn parse_version_key(v: &str) -> VersionKey {
+    // Match: numeric parts, optional pre-release tag, optional trailing number
+    // Handles: "1.20.2", "1.26rc3", "1.18beta1", "1.18rc1", "1.18.0-beta.1"
+    let v_lower = v.to_ascii_lowercase();
+
+    // "1:2.3.4" → epoch 1, version 2.3.4; no prefix means epoch 0
+    let (epoch, v_lower) = match v_lower.split_once(':') {
+        Some((e, rest)) if !e.is_empty() && e.chars().all(|c| c.is_ascii_digit()) => {
+            (e.parse().unwrap_or(u64::MAX), rest.to_string())
+        }
+        _ => (0, v_lower),
+    };
+    // Normalise semver pre-release separator: "1.18.0-rc.2" → "1.18.0rc2"
+    let v_norm = v_lower.replace("-rc.", "rc").replace("-beta.", "beta").replace("-alpha.", "alpha");
+
+    // Split at the first non-numeric, non-dot character
+    let tag_start = v_norm.find(|c: char| !c.is_ascii_digit() && c != '.');
+    let (num_part, rest) = match tag_start {
+        Some(i) => (&v_norm[..i], &v_norm[i..]),
+        None    => (v_norm.as_str(), ""),
+    };
+
+    let nums: Vec<u64> = num_part
+        .split('.')
+        .filter(|s| !s.is_empty())
+        .map(|s| s.parse().unwrap_or(0))
+        .collect();
+
+    let snapshot_date = parse_snapshot_date(rest);
+    let (pre_tier, pre_num) = if rest.is_empty() || snapshot_date.is_some() {
+        (STABLE_TIER, 0u64)
+    } else if let Some(n) = rest.strip_prefix("rc") {
+        (2, n.parse().unwrap_or(0))
+    } else if let Some(n) = rest.strip_prefix("beta") {
+        (1, n.parse().unwrap_or(0))
+    } else if let Some(n) = rest.strip_prefix("alpha") {
+        (0, n.parse().unwrap_or(0))
+    } else {
+        // Unknown suffix — treat as stable but preserve trailing digits for ordering
+        let n: u64 = rest.chars().filter(|c| c.is_ascii_digit()).collect::<String>().parse().unwrap_or(0);
+        (STABLE_TIER, n)
+    };
+
+    VersionKey { epoch, nums, pre_tier, pre_num, snapshot_date: snapshot_date.unwrap_or(0) }
+}
+
+/// YYYYMMDD from the `unstable-YYYY-MM-DD` part of a version suffix
+fn parse_snapshot_date(rest: &str) -> Option<u32> {
+    let (_, date) = rest.split_once("unstable-")?;
+    let mut fields = date.splitn(3, '-');
+    let year = fields.next().filter(|y| y.len() == 4)?.parse::<u32>().ok()?;
+    let month = fields.next().filter(|m| (1..=2).contains(&m.len()))?.parse::<u32>().ok()?;
+    let day_field = fields.next()?;
+    let day_len = day_field.find(|c: char| !c.is_ascii_digit()).unwrap_or(day_field.len());
+    let day = day_field[..day_len].parse::<u32>().ok().filter(|_| day_len <= 2)?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    Some(year * 10_000 + month * 100 + day)
+}
+
+fn cmp_num_vecs(a: &[u64], b: &[u64]) -> Ordering {
+    let len = a.len().max(b.len());
+    for i in 0..len {
+        let av = a.get(i).copied().unwrap_or(0);
+        let bv = b.get(i).copied().unwrap_or(0);
+        match av.cmp(&bv) {
+            Ordering::Equal => continue,
+            other => return other,
+        }
+    }
+    Ordering::Equal
+}
+
+impl Ord for VersionKey {
+    /// Older < newer: epoch first, then numeric parts, then
+    /// stable > rc > beta > alpha, then the snapshot date, then the
+    /// pre-release index (rc3 > rc2)
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.epoch.cmp(&other.epoch)
+            .then_with(|| cmp_num_vecs(&self.nums, &other.nums))
+            .then(self.pre_tier.cmp(&other.pre_tier))
+            .then(self.snapshot_date.cmp(&other.snapshot_date))
+            .then(self.pre_num.cmp(&other.pre_num))
+    }
+}
+
+impl PartialOrd for VersionKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for VersionKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for VersionKey {}
+
+/// Whether a version string is an rc, beta or alpha (e.g. `1.22rc1`)
+pub fn is_prerelease(v: &str) -> bool {
+    !parse_version_key(v).is_stable()
+}
+
+/// Compares two version strings, older first.
+/// e.g. 1.20.1 < 1.20.2 < 1.21beta1 < 1.21rc2 < 1.21rc3 < 1.21
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    parse_version_key(a).cmp(&parse_version_key(b))
+}
@@ -1,6 +1,7 @@
 //! Tests for core data models
 
-use archiver_core::PackageEntry;
+use archiver_core::version::compare_versions;
+use archiver_core::{normalize_version, PackageEntry, Upstream};
 
 // ── fixtures ─────────────────────────────────────────────────────────────────
 
@@ -31,17 +32,119 @@ fn test_key_uses_attr_name_and_version() {
     assert_eq!(e.key(), "charliermarsh.ruff:2026.36.0");
 }
 
+// ── version normalization ────────────────────────────────────────────────────
+
+#[test]
+fn test_normalize_version_merges_release_variants() {
+    for raw in ["v1.2.3", "1.2.3", "V1.2.3", "1_2_3", "1.2.3-RELEASE", " 1.2.3 "] {
+        assert_eq!(normalize_version(raw), "1.2.3", "raw: {:?}", raw);
+    }
+    // Different releases stay different
+    assert_eq!(normalize_version("1.2.3-rc1"), "1.2.3-rc1");
+    assert_eq!(normalize_version("0-unstable-2024-01-01"), "0-unstable-2024-01-01");
+    // A leading v that isn't a version prefix is kept
+    assert_eq!(normalize_version("vim-9.0"), "vim-9.0");
+}
+
+#[test]
+fn test_normalized_key_keeps_raw_version() {
+    let e = PackageEntry::new(
+        "ripgrep".to_string(),
+        "v14.1.0".to_string(),
+        "abc1234567890abcdef01234567890abcdef0123".to_string(),
+        0,
+    );
+    assert_eq!(e.key(), "ripgrep:v14.1.0");
+    assert_eq!(e.normalized_key(), "ripgrep:14.1.0");
+    assert_eq!(e.version, "v14.1.0");
+}
+
+// ── version ordering ─────────────────────────────────────────────────────────
+
+#[test]
+fn test_compare_versions_orders_prereleases_before_stable() {
+    let mut versions = vec!["1.21", "1.20.1", "1.21rc2", "1.21beta1", "1.21rc3", "1.20.2", "1.18.0-rc.2"];
+    versions.sort_by(|a, b| compare_versions(a, b));
+    assert_eq!(versions, vec!["1.18.0-rc.2", "1.20.1", "1.20.2", "1.21beta1", "1.21rc2", "1.21rc3", "1.21"]);
+    assert_eq!(compare_versions("1.0", "1.0.0"), std::cmp::Ordering::Equal);
+}
+
+#[test]
+fn test_compare_versions_epoch_dominates() {
+    use std::cmp::Ordering;
+
+    assert_eq!(compare_versions("1:1.0", "0:9.9"), Ordering::Greater);
+    assert_eq!(compare_versions("1:1.0", "9.9"), Ordering::Greater);
+    // A missing epoch is epoch 0
+    assert_eq!(compare_versions("0:2.3.4", "2.3.4"), Ordering::Equal);
+    assert_eq!(compare_versions("2:1.0rc1", "2:1.0"), Ordering::Less);
+    assert!(archiver_core::version::is_prerelease("1:2.0beta1"));
+}
+
+#[test]
+fn test_compare_versions_orders_unstable_snapshots_by_date() {
+    use std::cmp::Ordering;
+
+    assert_eq!(compare_versions("unstable-2023-12-01", "unstable-2024-01-15"), Ordering::Less);
+    // A trailing revision doesn't outweigh the date
+    assert_eq!(compare_versions("unstable-2024-01-15-g1a2b3c4d5", "unstable-2024-01-16"), Ordering::Less);
+    assert_eq!(compare_versions("0-unstable-2024-1-5", "0-unstable-2024-01-05"), Ordering::Equal);
+
+    let mut versions = vec!["1.3", "1.2-unstable-2024-03-01", "1.2", "1.2-unstable-2024-01-15"];
+    versions.sort_by(|a, b| compare_versions(a, b));
+    assert_eq!(versions, vec!["1.2", "1.2-unstable-2024-01-15", "1.2-unstable-2024-03-01", "1.3"]);
+
+    let key = archiver_core::version::parse_version_key("0-unstable-2024-01-15");
+    assert_eq!(key.snapshot_date, 20240115);
+    assert!(key.is_stable(), "snapshots can still be picked as latest");
+}
+
 // ── nix generation ───────────────────────────────────────────────────────────
 
 #[test]
 fn test_nix_fetchtarball_contains_sha() {
-    let nix = make_entry().to_nix_fetchtarball();
-    assert!(nix.contains("abc1234567890abcdef01234567890abcdef0123.tar.gz"));
+    let nix = make_entry().to_nix_fetchtarball(&Upstream::default());
+    assert_eq!(
+        nix,
+        r#"fetchTarball "https://github.com/NixOS/nixpkgs/archive/abc1234567890abcdef01234567890abcdef0123.tar.gz""#
+    );
+}
+
+#[test]
+fn test_nix_fetchtarball_uses_custom_upstream() {
+    let upstream: Upstream = "github.example.com/infra/nixpkgs".parse().unwrap();
+    let nix = make_entry().to_nix_fetchtarball(&upstream);
+    assert_eq!(
+        nix,
+        r#"fetchTarball "https://github.example.com/infra/nixpkgs/archive/abc1234567890abcdef01234567890abcdef0123.tar.gz""#
+    );
+}
+
+#[test]
+fn test_upstream_parses_clone_urls_and_builds_refs() {
+    let upstream: Upstream = "https://github.example.com/infra/nixpkgs.git".parse().unwrap();
+    assert_eq!(upstream.to_string(), "github.example.com/infra/nixpkgs");
+    assert_eq!(upstream.git_url(), "https://github.example.com/infra/nixpkgs");
+    assert_eq!(upstream.flake_ref("abc"), "github:infra/nixpkgs/abc?host=github.example.com");
+    assert_eq!(Upstream::default().flake_ref("abc"), "github:NixOS/nixpkgs/abc");
+
+    for remote in [
+        "git@github.com:someone/nixpkgs.git",
+        "ssh://git@github.com:22/someone/nixpkgs",
+        "https://github.com/someone/nixpkgs/",
+    ] {
+        assert_eq!(Upstream::from_remote_url(remote).unwrap().to_string(), "github.com/someone/nixpkgs", "{}", remote);
+    }
+    assert_eq!(Upstream::from_remote_url("/srv/git/nixpkgs"), None);
+    assert_eq!(Upstream::from_remote_url("file:///srv/git/nixpkgs.git"), None);
+
+    assert!("github.com/NixOS".parse::<Upstream>().is_err());
+    assert!("github.com//nixpkgs".parse::<Upstream>().is_err());
 }
 
 #[test]
 fn test_nix_import_contains_pkgs_and_attr() {
-    let nix = make_entry().to_nix_import();
+    let nix = make_entry().to_nix_import(&Upstream::default());
     assert!(nix.contains("import"));
     assert!(nix.contains("pkgs.nodejs"));
 }
@@ -56,3 +159,46 @@ fn test_display_format_contains_name_version_and_short_sha() {
     // Display uses first 8 chars of commit SHA
     assert!(s.contains("abc12345"));
 }
+
+// ── hash encodings ───────────────────────────────────────────────────────────
+
+#[test]
+fn test_normalize_sha256_accepts_sri_nix32_and_hex() {
+    use archiver_core::hash::normalize_sha256;
+
+    // sha256 of the empty string in each encoding
+    let sri = "sha256-47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=";
+    assert_eq!(normalize_sha256(sri).as_deref(), Some(sri));
+    assert_eq!(normalize_sha256("0mdqa9w1p6cmli6976v4wi0sw9r4p5prkj7lzfd1877wk11c9c73").as_deref(), Some(sri));
+    assert_eq!(normalize_sha256("sha256:0mdqa9w1p6cmli6976v4wi0sw9r4p5prkj7lzfd1877wk11c9c73").as_deref(), Some(sri));
+    assert_eq!(
+        normalize_sha256("E3B0C44298FC1C149AFBF4C8996FB92427AE41E4649B934CA495991B7852B855").as_deref(),
+        Some(sri)
+    );
+
+    assert_eq!(normalize_sha256("unknown"), None);
+    assert_eq!(normalize_sha256("sha256-dG9vIHNob3J0"), None); // not 32 bytes
+    assert_eq!(normalize_sha256(""), None);
+}
+
+// ── short_sha ────────────────────────────────────────────────────────────────
+
+#[test]
+fn test_short_sha_clamps_to_available_length() {
+    use archiver_core::short_sha;
+
+    let sha = "abc1234567890abcdef01234567890abcdef0123";
+    assert_eq!(short_sha(sha, 8), "abc12345");
+    assert_eq!(short_sha(sha, 12), "abc123456789");
+    assert_eq!(short_sha("abc12", 8), "abc12");
+    assert_eq!(short_sha("", 12), "");
+    // Never splits a multi-byte character
+    assert_eq!(short_sha("ąbc", 2), "ąb");
+}
+
+#[test]
+fn test_display_with_short_sha_does_not_panic() {
+    let mut entry = make_entry();
+    entry.commit_sha = "abc".to_string();
+    assert_eq!(entry.to_string(), "nodejs 14.17.0 @ abc");
+}
@@ -1,11 +1,15 @@
 //! Database operations and management
 
-use archiver_core::PackageEntry;
+use crate::retry::{retry_on_io_error, FLUSH_RETRY_DELAYS};
+use archiver_core::hash::{parse_sha256, sha256_to_sri, UNKNOWN_HASH};
+use archiver_core::version::{parse_version_key, VersionKey};
+use archiver_core::{normalize_version, short_sha, IndexRun, PackageEntry};
 use anyhow::{Context, Result};
 use data_encoding::HEXLOWER;
 use serde::{Deserialize, Serialize};
 use sled::Db;
-use std::collections::HashMap;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet};
 use std::path::Path;
 
 // ---------------------------------------------------------------------------
@@ -19,6 +23,7 @@ use std::path::Path;
 ///   commit_sha: 40-char hex string → [u8; 20]  (-20 bytes)
 ///   JSON overhead (field names, punctuation) → 0 with bincode (-~50 bytes)
 ///   Total saving: ~70 bytes per entry
+///   nar_hash, content_hash: "sha256-<base64>" → Option<[u8; 32]>
 #[derive(Serialize, Deserialize)]
 struct StoredEntry {
     attr_name: String,
@@ -26,6 +31,115 @@ struct StoredEntry {
     commit_sha: [u8; 20],
     timestamp: u64,
     is_primary: bool,
+    nar_hash: Option<[u8; 32]>,
+    main_program: Option<String>,
+    content_hash: Option<[u8; 32]>,
+    platform: Option<String>,
+    source_hash: Option<[u8; 32]>,
+    source_rev: Option<String>,
+    description: Option<String>,
+    source_path: Option<String>,
+    attr_from_path: bool,
+}
+
+/// Layout written before `source_path` existed (schema 9).
+#[derive(Deserialize)]
+struct StoredEntryV9 {
+    attr_name: String,
+    version: String,
+    commit_sha: [u8; 20],
+    timestamp: u64,
+    is_primary: bool,
+    nar_hash: Option<[u8; 32]>,
+    main_program: Option<String>,
+    content_hash: Option<[u8; 32]>,
+    platform: Option<String>,
+    source_hash: Option<[u8; 32]>,
+    source_rev: Option<String>,
+    description: Option<String>,
+}
+
+/// Layout written before `description` existed (schema 8).
+#[derive(Deserialize)]
+struct StoredEntryV8 {
+    attr_name: String,
+    version: String,
+    commit_sha: [u8; 20],
+    timestamp: u64,
+    is_primary: bool,
+    nar_hash: Option<[u8; 32]>,
+    main_program: Option<String>,
+    content_hash: Option<[u8; 32]>,
+    platform: Option<String>,
+    source_hash: Option<[u8; 32]>,
+    source_rev: Option<String>,
+}
+
+/// Layout written before `source_rev` existed (schema 7).
+#[derive(Deserialize)]
+struct StoredEntryV7 {
+    attr_name: String,
+    version: String,
+    commit_sha: [u8; 20],
+    timestamp: u64,
+    is_primary: bool,
+    nar_hash: Option<[u8; 32]>,
+    main_program: Option<String>,
+    content_hash: Option<[u8; 32]>,
+    platform: Option<String>,
+    source_hash: Option<[u8; 32]>,
+}
+
+/// Layout written before `platform` existed (schema 6).
+#[derive(Deserialize)]
+struct StoredEntryV6 {
+    attr_name: String,
+    version: String,
+    commit_sha: [u8; 20],
+    timestamp: u64,
+    is_primary: bool,
+    nar_hash: Option<[u8; 32]>,
+    main_program: Option<String>,
+    content_hash: Option<[u8; 32]>,
+}
+
+/// Layout written before `content_hash` existed (schema 4–5).
+#[derive(Deserialize)]
+struct StoredEntryV4 {
+    attr_name: String,
+    version: String,
+    commit_sha: [u8; 20],
+    timestamp: u64,
+    is_primary: bool,
+    nar_hash: Option<[u8; 32]>,
+    main_program: Option<String>,
+}
+
+/// Layout written before `main_program` existed (schema 2–3).
+#[derive(Deserialize)]
+struct StoredEntryV2 {
+    attr_name: String,
+    version: String,
+    commit_sha: [u8; 20],
+    timestamp: u64,
+    is_primary: bool,
+    nar_hash: Option<[u8; 32]>,
+}
+
+/// Layout written before `nar_hash` existed. Still decoded so older
+/// databases keep working; such entries simply have no NAR hash.
+#[derive(Deserialize)]
+struct LegacyStoredEntry {
+    attr_name: String,
+    version: String,
+    commit_sha: [u8; 20],
+    timestamp: u64,
+    is_primary: bool,
+}
+
+/// Whether sled failed because another process holds the database lock
+fn is_lock_error(error: &sled::Error) -> bool {
+    matches!(error, sled::Error::Io(e) if e.to_string().contains("could not acquire lock"))
 }
 
 /// Serialize a `PackageEntry` into compact binary bytes.
@@ -36,32 +150,293 @@ fn pack(entry: &PackageEntry) -> Result<Vec<u8>> {
     let mut commit_bytes = [0u8; 20];
     commit_bytes.copy_from_slice(&sha_vec);
 
+    let nar_hash = pack_hash(entry, "NAR", entry.nar_hash.as_deref());
+    let content_hash = pack_hash(entry, "content", entry.content_hash.as_deref());
+    let source_hash = pack_hash(entry, "source", entry.source_hash.as_deref());
+
     let stored = StoredEntry {
         attr_name: entry.attr_name.clone(),
         version: entry.version.clone(),
         commit_sha: commit_bytes,
         timestamp: entry.timestamp,
         is_primary: entry.is_primary,
+        nar_hash,
+        main_program: entry.main_program.clone(),
+        content_hash,
+        platform: entry.platform.clone(),
+        source_hash,
+        source_rev: entry.source_rev.clone(),
+        description: entry.description.clone(),
+        source_path: entry.source_path.clone(),
+        attr_from_path: entry.attr_from_path,
     };
     bincode::serialize(&stored).context("Failed to serialize PackageEntry")
 }
 
+/// Raw bytes of an entry's hash; `None` is the only "no hash" stored.
+///
+/// The [`UNKNOWN_HASH`] placeholder maps to `None` silently. Any other hash
+/// that can't be decoded is stored as absent too, with a warning, rather
+/// than losing the whole entry.
+fn pack_hash(entry: &PackageEntry, kind: &str, hash: Option<&str>) -> Option<[u8; 32]> {
+    let hash = hash?;
+    if hash.trim() == UNKNOWN_HASH {
+        return None;
+    }
+    let bytes = parse_sha256(hash);
+    if bytes.is_none() {
+        log::warn!("Ignoring invalid {} hash '{}' for {}", kind, hash, entry.key());
+    }
+    bytes
+}
+
 /// Deserialize a `PackageEntry` from compact binary bytes.
 fn unpack(bytes: &[u8]) -> Result<PackageEntry> {
-    let stored: StoredEntry =
-        bincode::deserialize(bytes).context("Failed to deserialize PackageEntry")?;
+    // Newest layout first: older layouts are prefixes of newer ones, so
+    // decoding them as a newer layout runs out of bytes and fails
+    let stored: StoredEntry = if let Ok(stored) = bincode::deserialize(bytes) {
+        stored
+    } else if let Ok(v9) = bincode::deserialize::<StoredEntryV9>(bytes) {
+        StoredEntry {
+            attr_name: v9.attr_name,
+            version: v9.version,
+            commit_sha: v9.commit_sha,
+            timestamp: v9.timestamp,
+            is_primary: v9.is_primary,
+            nar_hash: v9.nar_hash,
+            main_program: v9.main_program,
+            content_hash: v9.content_hash,
+            platform: v9.platform,
+            source_hash: v9.source_hash,
+            source_rev: v9.source_rev,
+            description: v9.description,
+            source_path: None,
+            attr_from_path: false,
+        }
+    } else if let Ok(v8) = bincode::deserialize::<StoredEntryV8>(bytes) {
+        StoredEntry {
+            attr_name: v8.attr_name,
+            version: v8.version,
+            commit_sha: v8.commit_sha,
+            timestamp: v8.timestamp,
+            is_primary: v8.is_primary,
+            nar_hash: v8.nar_hash,
+            main_program: v8.main_program,
+            content_hash: v8.content_hash,
+            platform: v8.platform,
+            source_hash: v8.source_hash,
+            source_rev: v8.source_rev,
+            description: None,
+            source_path: None,
+            attr_from_path: false,
+        }
+    } else if let Ok(v7) = bincode::deserialize::<StoredEntryV7>(bytes) {
+        StoredEntry {
+            attr_name: v7.attr_name,
+            version: v7.version,
+            commit_sha: v7.commit_sha,
+            timestamp: v7.timestamp,
+            is_primary: v7.is_primary,
+            nar_hash: v7.nar_hash,
+            main_program: v7.main_program,
+            content_hash: v7.content_hash,
+            platform: v7.platform,
+            source_hash: v7.source_hash,
+            source_rev: None,
+            description: None,
+            source_path: None,
+            attr_from_path: false,
+        }
+    } else if let Ok(v6) = bincode::deserialize::<StoredEntryV6>(bytes) {
+        StoredEntry {
+            attr_name: v6.attr_name,
+            version: v6.version,
+            commit_sha: v6.commit_sha,
+            timestamp: v6.timestamp,
+            is_primary: v6.is_primary,
+            nar_hash: v6.nar_hash,
+            main_program: v6.main_program,
+            content_hash: v6.content_hash,
+            platform: None,
+            source_hash: None,
+            source_rev: None,
+            description: None,
+            source_path: None,
+            attr_from_path: false,
+        }
+    } else if let Ok(v4) = bincode::deserialize::<StoredEntryV4>(bytes) {
+        StoredEntry {
+            attr_name: v4.attr_name,
+            version: v4.version,
+            commit_sha: v4.commit_sha,
+            timestamp: v4.timestamp,
+            is_primary: v4.is_primary,
+            nar_hash: v4.nar_hash,
+            main_program: v4.main_program,
+            content_hash: None,
+            platform: None,
+            source_hash: None,
+            source_rev: None,
+            description: None,
+            source_path: None,
+            attr_from_path: false,
+        }
+    } else if let Ok(v2) = bincode::deserialize::<StoredEntryV2>(bytes) {
+        StoredEntry {
+            attr_name: v2.attr_name,
+            version: v2.version,
+            commit_sha: v2.commit_sha,
+            timestamp: v2.timestamp,
+            is_primary: v2.is_primary,
+            nar_hash: v2.nar_hash,
+            main_program: None,
+            content_hash: None,
+            platform: None,
+            source_hash: None,
+            source_rev: None,
+            description: None,
+            source_path: None,
+            attr_from_path: false,
+        }
+    } else {
+        let legacy: LegacyStoredEntry =
+            bincode::deserialize(bytes).context("Failed to deserialize PackageEntry")?;
+        StoredEntry {
+            attr_name: legacy.attr_name,
+            version: legacy.version,
+            commit_sha: legacy.commit_sha,
+            timestamp: legacy.timestamp,
+            is_primary: legacy.is_primary,
+            nar_hash: None,
+            main_program: None,
+            content_hash: None,
+            platform: None,
+            source_hash: None,
+            source_rev: None,
+            description: None,
+            source_path: None,
+            attr_from_path: false,
+        }
+    };
     Ok(PackageEntry {
         attr_name: stored.attr_name,
         version: stored.version,
         commit_sha: HEXLOWER.encode(&stored.commit_sha),
         timestamp: stored.timestamp,
         is_primary: stored.is_primary,
+        nar_hash: stored.nar_hash.as_ref().map(sha256_to_sri),
+        main_program: stored.main_program,
+        description: stored.description,
+        content_hash: stored.content_hash.as_ref().map(sha256_to_sri),
+        platform: stored.platform,
+        source_hash: stored.source_hash.as_ref().map(sha256_to_sri),
+        source_rev: stored.source_rev,
+        source_path: stored.source_path,
+        attr_from_path: stored.attr_from_path,
     })
 }
 
+/// Heap item for [`ArchiverDb::top_versions`]: ordered by version, then by
+/// commit timestamp, with the parsed key cached so each comparison is cheap
+struct RankedEntry {
+    key: VersionKey,
+    entry: PackageEntry,
+}
+
+impl Ord for RankedEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key.cmp(&other.key).then(self.entry.timestamp.cmp(&other.entry.timestamp))
+    }
+}
+
+impl PartialOrd for RankedEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for RankedEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for RankedEntry {}
+
+/// Total size of the files under `path`, recursively
+fn dir_size(path: &Path) -> u64 {
+    let Ok(entries) = std::fs::read_dir(path) else { return 0; };
+    entries.flatten().map(|e| {
+        let p = e.path();
+        if p.is_dir() {
+            dir_size(&p)
+        } else {
+            e.metadata().map(|m| m.len()).unwrap_or(0)
+        }
+    }).sum()
+}
+
+/// Attr names of sorted package keys, each once. All keys of one name
+/// share the prefix "attr_name:" and are therefore adjacent, so it is
+/// enough to skip repeats of the previous name.
+fn distinct_attr_names(keys: impl Iterator<Item = sled::Result<sled::IVec>>) -> impl Iterator<Item = Result<Vec<u8>>> {
+    let mut previous: Option<Vec<u8>> = None;
+    keys.filter_map(move |key| {
+        let key = match key {
+            Ok(key) => key,
+            Err(e) => return Some(Err(e).context("Failed to read from database")),
+        };
+        // key format: "attr_name:version" — take bytes before first ':'
+        let pos = key.iter().position(|&b| b == b':').unwrap_or(key.len());
+        if previous.as_deref() == Some(&key[..pos]) {
+            return None;
+        }
+        previous = Some(key[..pos].to_vec());
+        previous.clone().map(Ok)
+    })
+}
+
+/// Recursively copies a database directory (used for reader snapshots)
+fn copy_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            std::fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Storage schema version written by this build.
+///
+/// 1: original layout (no `nar_hash`)
+/// 2: `StoredEntry` gains `nar_hash`; version 1 entries are still readable
+/// 3: `commits` index of distinct commit SHAs (rebuilt from packages on upgrade)
+/// 4: `StoredEntry` gains `main_program`; older entries are still readable
+/// 5: optional normalized version keys (`version_keys` record in `meta`)
+/// 6: `StoredEntry` gains `content_hash`; older entries are still readable
+/// 7: `StoredEntry` gains `platform` and `source_hash`; per-platform entries
+///    are keyed `attr_name:version:platform`
+/// 8: `StoredEntry` gains `source_rev`; older entries are still readable
+/// 9: `StoredEntry` gains `description`; older entries are still readable
+/// 10: `StoredEntry` gains `source_path` and `attr_from_path`; older
+///     entries are still readable
+pub const SCHEMA_VERSION: u32 = 10;
+
+const SCHEMA_VERSION_KEY: &[u8] = b"schema_version";
+
+/// Present (value `normalized`) once package keys use normalized versions
+const VERSION_KEYS_KEY: &[u8] = b"version_keys";
+const VERSION_KEYS_NORMALIZED: &[u8] = b"normalized";
+
 /// Main structure managing the database
 pub struct ArchiverDb {
-    /// Tree storing package entries (key: "attr_name:version")
+    /// Tree storing package entries (key: "attr_name:version", with the
+    /// normalized version when `normalized_keys` is set)
     packages: sled::Tree,
 
     /// Tree tracking processed commits
@@ -71,19 +446,193 @@ pub struct ArchiverDb {
     /// key: commit_sha hex string, value: hash string as returned by nix-prefetch-url
     tarball_hashes: sled::Tree,
 
+    /// Tree storing the `builtins.fetchGit` narHash of nixpkgs per commit
+    /// key: commit_sha hex string, value: SRI hash string
+    git_narhashes: sled::Tree,
+
+    /// Commits whose last `prefetch-hashes` attempt failed, per hash kind
+    /// key: `tarball:<sha>` or `git:<sha>`, value: JSON-encoded `PrefetchFailure`
+    prefetch_failures: sled::Tree,
+
+    /// Distinct commits referenced by package entries
+    /// key: commit_sha hex string, value: big-endian (timestamp, entry count)
+    commits: sled::Tree,
+
+    /// Database-wide metadata (schema version)
+    meta: sled::Tree,
+
+    /// Branches and tags pointing at commits, recorded by `index --record-refs`
+    /// key: commit_sha hex string, value: JSON array of short ref names
+    commit_refs: sled::Tree,
+
+    /// Every commit a version was seen in, not deduplicated, recorded by
+    /// `index --append-history`
+    /// key: `attr_name:version\0` + big-endian timestamp + commit_sha hex, value: empty
+    version_history: sled::Tree,
+
+    /// Subject lines of commits that entries were taken from, recorded by
+    /// `index --store-messages`
+    /// key: commit_sha hex string, value: UTF-8 subject
+    commit_subjects: sled::Tree,
+
+    /// Append-only log of `index` runs (provenance)
+    /// key: big-endian sequence number, value: JSON-encoded `IndexRun`
+    index_runs: sled::Tree,
+
     /// Sled database instance
     db: Db,
 
-    /// Path to the database directory (for size calculation)
+    /// Path to the database directory (for size calculation); empty for a
+    /// temporary database
     path: std::path::PathBuf,
+
+    /// Whether keys use [`normalize_version`], merging variants like
+    /// `v1.2.3` and `1.2.3` into one entry
+    normalized_keys: bool,
+
+    /// Temporary copy backing a reading handle whose database was locked.
+    /// Declared last so sled is closed before the directory is removed.
+    snapshot: Option<tempfile::TempDir>,
+}
+
+/// What [`ArchiverDb::insert_with_outcome`] did with an entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InsertOutcome {
+    /// The version was new to the database
+    Inserted,
+    /// Replaced the entry of an older commit
+    Updated,
+    /// The stored entry already comes from this commit
+    AlreadyStored,
+    /// A newer commit's entry was kept
+    KeptNewer,
+}
+
+impl InsertOutcome {
+    /// True when the database now points at this entry's commit (what
+    /// [`ArchiverDb::insert_if_better`] returns)
+    pub fn is_stored(self) -> bool {
+        self != InsertOutcome::KeptNewer
+    }
+}
+
+/// One difference found by [`ArchiverDb::diff`]
+// Values are handed straight to a callback, never stored in bulk
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EntryDiff {
+    /// Stored only in the database `diff` was called on
+    OnlyHere(PackageEntry),
+    /// Stored only in the other database
+    OnlyThere(PackageEntry),
+    /// Same package version in both, pinned to different commits
+    CommitChanged { here: PackageEntry, there: PackageEntry },
+}
+
+/// A commit whose hash couldn't be prefetched, kept so later
+/// `prefetch-hashes` runs can skip it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrefetchFailure {
+    /// When the last attempt failed (Unix epoch)
+    pub failed_at: u64,
+    /// Number of runs in which the commit failed
+    pub failures: u32,
+    /// Error of the last attempt
+    pub error: String,
+}
+
+/// Start of the `version_history` keys of one version; the NUL keeps
+/// `1.2` from matching `1.20`
+fn version_history_prefix(attr_name: &str, version: &str) -> Vec<u8> {
+    format!("{}:{}\0", attr_name, version).into_bytes()
+}
+
+/// Key of a commit in the `prefetch_failures` tree
+fn prefetch_failure_key(commit_sha: &str, git_narhash: bool) -> String {
+    format!("{}:{}", if git_narhash { "git" } else { "tarball" }, commit_sha)
 }
 
 impl ArchiverDb {
-    /// Opens or creates a new database at the specified location
+    /// Opens or creates a new database at the specified location.
+    ///
+    /// Only one process can have a database open for writing; while another
+    /// one does, this fails with an error saying so (reading works anyway,
+    /// see [`open_for_reading`](Self::open_for_reading)).
     pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let db = sled::open(path.as_ref())
-            .with_context(|| format!("Failed to open database at {:?}", path.as_ref()))?;
-        
+        let path = path.as_ref();
+        match sled::open(path) {
+            Ok(db) => Self::from_sled(db, path, None, true),
+            Err(e) if is_lock_error(&e) => anyhow::bail!(
+                "Database at {:?} is already open by another nix-archiver process \
+                 (e.g. a running `index`, `watch` or `prefetch-hashes`). Wait for it to finish, \
+                 or write to a separate --database and merge it later with `export`/`import`",
+                path
+            ),
+            Err(e) => Err(e).with_context(|| format!("Failed to open database at {:?}", path)),
+        }
+    }
+
+    /// Opens a throwaway database that is never persisted and is discarded
+    /// when dropped. Useful for tests and ephemeral use; sizes report 0.
+    /// Requires the `temporary` feature.
+    #[cfg(feature = "temporary")]
+    pub fn open_temporary() -> Result<Self> {
+        let db = sled::Config::new()
+            .temporary(true)
+            .open()
+            .context("Failed to open temporary database")?;
+        Self::from_sled(db, Path::new(""), None, true)
+    }
+
+    /// Opens a database for a command that only reads it, without ever
+    /// upgrading its schema.
+    ///
+    /// sled has no shared read-only mode: when the database is free this
+    /// takes the same exclusive lock as [`open`](Self::open), so a writer
+    /// (e.g. `index`) started while the handle is alive fails with the
+    /// "already open" error. Keep such handles short-lived.
+    ///
+    /// When the database is already locked, its directory is copied to a
+    /// temporary snapshot and the snapshot is opened instead. The copy is
+    /// taken while the writer keeps running, so it is best effort: usually
+    /// the state of the writer's last flush, but a file written during the
+    /// copy can leave it torn. Anything written through the returned handle
+    /// is discarded when it is dropped.
+    ///
+    /// A database with an older schema is read as it is; entries of every
+    /// older layout decode, and the next [`open`](Self::open) upgrades it.
+    pub fn open_for_reading<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        match sled::open(path) {
+            Ok(db) => Self::from_sled(db, path, None, false),
+            Err(e) if is_lock_error(&e) => {
+                log::warn!(
+                    "Database at {:?} is in use by another process; reading a best-effort snapshot of it",
+                    path
+                );
+                let snapshot = tempfile::Builder::new()
+                    .prefix("nix-archiver-snapshot")
+                    .tempdir()
+                    .context("Failed to create snapshot directory")?;
+                copy_dir(path, snapshot.path())
+                    .with_context(|| format!("Failed to snapshot database at {:?}", path))?;
+                let db = sled::open(snapshot.path())
+                    .with_context(|| format!("Failed to open snapshot of database at {:?}", path))?;
+                Self::from_sled(db, path, Some(snapshot), false)
+            }
+            Err(e) => Err(e).with_context(|| format!("Failed to open database at {:?}", path)),
+        }
+    }
+
+    /// Whether this handle reads a temporary snapshot (see
+    /// [`open_for_reading`](Self::open_for_reading)) rather than the database itself
+    pub fn is_snapshot(&self) -> bool {
+        self.snapshot.is_some()
+    }
+
+    /// Opens the trees of an already opened sled database and, with
+    /// `upgrade`, upgrades its schema
+    fn from_sled(db: Db, path: &Path, snapshot: Option<tempfile::TempDir>, upgrade: bool) -> Result<Self> {
         let packages = db
             .open_tree("packages")
             .context("Failed to open packages tree")?;
@@ -95,14 +644,212 @@ impl ArchiverDb {
         let tarball_hashes = db
             .open_tree("tarball_hashes")
             .context("Failed to open tarball_hashes tree")?;
-        
-        Ok(Self {
+
+        let git_narhashes = db
+            .open_tree("git_narhashes")
+            .context("Failed to open git_narhashes tree")?;
+
+        let prefetch_failures = db
+            .open_tree("prefetch_failures")
+            .context("Failed to open prefetch_failures tree")?;
+
+        let index_runs = db
+            .open_tree("index_runs")
+            .context("Failed to open index_runs tree")?;
+
+        let commits = db
+            .open_tree("commits")
+            .context("Failed to open commits tree")?;
+
+        let commit_refs = db
+            .open_tree("commit_refs")
+            .context("Failed to open commit_refs tree")?;
+
+        let version_history = db
+            .open_tree("version_history")
+            .context("Failed to open version_history tree")?;
+
+        let commit_subjects = db
+            .open_tree("commit_subjects")
+            .context("Failed to open commit_subjects tree")?;
+
+        let meta = db
+            .open_tree("meta")
+            .context("Failed to open meta tree")?;
+
+        // Databases from before versioning have no record and are version 1.
+        // Older layouts stay readable, so they're upgraded in place; newer
+        // ones may contain entries this build can't decode.
+        let stored_version = match meta.get(SCHEMA_VERSION_KEY)? {
+            Some(bytes) => u32::from_be_bytes(
+                bytes.as_ref().try_into().context("Corrupt schema version record")?,
+            ),
+            None => 1,
+        };
+        if stored_version > SCHEMA_VERSION {
+            anyhow::bail!(
+                "Database at {:?} uses schema version {}, but this build supports up to {}. \
+                 Upgrade nix-archiver to use it",
+                path,
+                stored_version,
+                SCHEMA_VERSION
+            );
+        }
+
+        let normalized_keys = meta.get(VERSION_KEYS_KEY)?.as_deref() == Some(VERSION_KEYS_NORMALIZED);
+
+        let archiver_db = Self {
             packages,
             processed_commits,
             tarball_hashes,
+            git_narhashes,
+            prefetch_failures,
+            commits,
+            commit_refs,
+            version_history,
+            commit_subjects,
+            meta,
+            index_runs,
             db,
-            path: path.as_ref().to_path_buf(),
-        })
+            path: path.to_path_buf(),
+            normalized_keys,
+            snapshot,
+        };
+
+        if !upgrade {
+            if stored_version < SCHEMA_VERSION && !archiver_db.packages.is_empty() {
+                log::warn!(
+                    "Database at {:?} uses schema version {}; it is upgraded to {} the next time it is written",
+                    path,
+                    stored_version,
+                    SCHEMA_VERSION
+                );
+            }
+            return Ok(archiver_db);
+        }
+        if stored_version < 3 {
+            archiver_db.rebuild_commit_index()?;
+        }
+        if stored_version < SCHEMA_VERSION {
+            archiver_db.meta.insert(SCHEMA_VERSION_KEY, &SCHEMA_VERSION.to_be_bytes())
+                .context("Failed to record schema version")?;
+        }
+
+        Ok(archiver_db)
+    }
+
+    /// Recomputes the `commits` index from the packages tree
+    fn rebuild_commit_index(&self) -> Result<()> {
+        self.commits.clear().context("Failed to clear commits tree")?;
+        for entry in self.iter_entries() {
+            let entry = entry?;
+            self.adjust_commit_refs(&entry.commit_sha, entry.timestamp, 1)?;
+        }
+        Ok(())
+    }
+
+    /// Whether package keys use normalized versions
+    pub fn uses_normalized_keys(&self) -> bool {
+        self.normalized_keys
+    }
+
+    /// Switches the database to normalized version keys, re-keying existing
+    /// entries. Variants that now share a key are merged, keeping the entry
+    /// from the newest commit (and its raw version string).
+    ///
+    /// The switch is permanent and recorded in the database. Returns the
+    /// number of entries merged away.
+    pub fn enable_normalized_keys(&mut self) -> Result<usize> {
+        if self.normalized_keys {
+            return Ok(0);
+        }
+        self.meta.insert(VERSION_KEYS_KEY, VERSION_KEYS_NORMALIZED)
+            .context("Failed to record version key mode")?;
+        self.normalized_keys = true;
+
+        let before = self.packages.len();
+        let mut rekeyed = Vec::new();
+        for item in self.packages.iter() {
+            let (key, value) = item.context("Failed to read from database")?;
+            let entry = unpack(&value).context("Failed to deserialize PackageEntry")?;
+            if key.as_ref() != entry.normalized_key().as_bytes() {
+                rekeyed.push((key, entry));
+            }
+        }
+        for (key, entry) in rekeyed {
+            self.packages.remove(key).context("Failed to remove package entry")?;
+            self.adjust_commit_refs(&entry.commit_sha, entry.timestamp, -1)?;
+            self.insert_if_better(&entry)?;
+        }
+        Ok(before - self.packages.len())
+    }
+
+    /// Renames every entry for which `rename` returns an attr_name other
+    /// than its own, moving it to the new key. An entry that lands on an
+    /// existing key is merged with the usual rule: the newest commit wins.
+    ///
+    /// Rewrites keys in place and can't be undone. Returns the number of
+    /// entries renamed and, of those, how many were merged away.
+    pub fn rename_entries(&self, rename: impl Fn(&PackageEntry) -> Option<String>) -> Result<(usize, usize)> {
+        let before = self.packages.len();
+        let mut renamed = Vec::new();
+        for item in self.packages.iter() {
+            let (key, value) = item.context("Failed to read from database")?;
+            let mut entry = unpack(&value).context("Failed to deserialize PackageEntry")?;
+            if let Some(name) = rename(&entry).filter(|name| *name != entry.attr_name) {
+                entry.attr_name = name;
+                renamed.push((key, entry));
+            }
+        }
+        // Remove every old key before inserting, so a renamed entry never
+        // lands on a key that is itself about to move
+        for (key, entry) in &renamed {
+            self.packages.remove(key).context("Failed to remove package entry")?;
+            self.adjust_commit_refs(&entry.commit_sha, entry.timestamp, -1)?;
+        }
+        for (_, entry) in &renamed {
+            self.insert_if_better(entry)?;
+        }
+        let count = renamed.len();
+        Ok((count, before - self.packages.len()))
+    }
+
+    /// Storage key for a package version under the current key mode
+    fn key_for(&self, attr_name: &str, version: &str) -> String {
+        if self.normalized_keys {
+            format!("{}:{}", attr_name, normalize_version(version))
+        } else {
+            format!("{}:{}", attr_name, version)
+        }
+    }
+
+    /// Storage key for an entry: [`key_for`](Self::key_for), plus
+    /// `:platform` for per-platform entries
+    fn entry_key(&self, entry: &PackageEntry) -> String {
+        let key = self.key_for(&entry.attr_name, &entry.version);
+        match &entry.platform {
+            Some(platform) => format!("{}:{}", key, platform),
+            None => key,
+        }
+    }
+
+    /// Adds `delta` to the number of entries pointing at a commit,
+    /// dropping the commit from the index when nothing references it.
+    fn adjust_commit_refs(&self, commit_sha: &str, timestamp: u64, delta: i64) -> Result<()> {
+        self.commits
+            .update_and_fetch(commit_sha.as_bytes(), |old| {
+                let (ts, refs) = match old {
+                    Some(bytes) if bytes.len() == 16 => (
+                        u64::from_be_bytes(bytes[..8].try_into().unwrap()),
+                        u64::from_be_bytes(bytes[8..].try_into().unwrap()),
+                    ),
+                    _ => (timestamp, 0),
+                };
+                let refs = refs.saturating_add_signed(delta);
+                (refs > 0).then(|| [ts.to_be_bytes(), refs.to_be_bytes()].concat())
+            })
+            .context("Failed to update commits index")?;
+        Ok(())
     }
 
     /// Inserts package entry only if it's newer than existing one
@@ -110,11 +857,18 @@ impl ArchiverDb {
     /// Deduplication logic: if an entry for the given version already exists,
     /// it is replaced only when the new entry has a newer timestamp.
     pub fn insert_if_better(&self, entry: &PackageEntry) -> Result<bool> {
-        let key = entry.key();
+        Ok(self.insert_with_outcome(entry)?.is_stored())
+    }
+
+    /// Same as [`insert_if_better`](Self::insert_if_better), but tells
+    /// new versions apart from ones moved to a newer commit, so callers
+    /// can count overwrites instead of logging each one.
+    pub fn insert_with_outcome(&self, entry: &PackageEntry) -> Result<InsertOutcome> {
+        let key = self.entry_key(entry);
         let new_value = pack(entry)
             .context("Failed to serialize PackageEntry")?;
 
-        let was_inserted = self.packages.update_and_fetch(key.as_bytes(), |old_value| {
+        let previous = self.packages.fetch_and_update(key.as_bytes(), |old_value| {
             match old_value {
                 None => {
                     // No existing value - insert
@@ -126,11 +880,11 @@ impl ArchiverDb {
                         Ok(old_entry) => {
                             if entry.timestamp > old_entry.timestamp {
                                 // New entry is newer - overwrite
-                                log::info!(
+                                log::debug!(
                                     "Updating {} from commit {} -> {} (newer timestamp)",
                                     key,
-                                    &old_entry.commit_sha[..8],
-                                    &entry.commit_sha[..8]
+                                    short_sha(&old_entry.commit_sha, 8),
+                                    short_sha(&entry.commit_sha, 8)
                                 );
                                 Some(new_value.clone())
                             } else {
@@ -149,19 +903,38 @@ impl ArchiverDb {
         })
         .context("Failed to update package entry")?;
 
-        // Check if we actually inserted a new entry
-        if let Some(final_value) = was_inserted {
-            let final_entry = unpack(&final_value)
-                .context("Failed to deserialize final entry")?;
-            Ok(final_entry.commit_sha == entry.commit_sha)
-        } else {
-            Ok(false)
+        // Same decision as the closure above, made on the value it actually replaced
+        let replaced = match previous.as_deref().map(unpack) {
+            None => None,
+            Some(Ok(old_entry)) if entry.timestamp <= old_entry.timestamp => {
+                return Ok(if old_entry.commit_sha == entry.commit_sha {
+                    InsertOutcome::AlreadyStored
+                } else {
+                    InsertOutcome::KeptNewer
+                });
+            }
+            Some(Ok(old_entry)) => Some(old_entry),
+            Some(Err(_)) => None,
+        };
+
+        self.adjust_commit_refs(&entry.commit_sha, entry.timestamp, 1)?;
+        match replaced {
+            Some(old_entry) => {
+                self.adjust_commit_refs(&old_entry.commit_sha, old_entry.timestamp, -1)?;
+                Ok(InsertOutcome::Updated)
+            }
+            None => Ok(InsertOutcome::Inserted),
         }
     }
 
     /// Retrieves a package entry by attribute name and version
+    ///
+    /// With normalized keys, any variant of the version finds the entry,
+    /// whose `version` is the raw string it was indexed with. A version
+    /// indexed only per platform returns its first platform entry (all of
+    /// them come from the same file, so they share the commit).
     pub fn get(&self, attr_name: &str, version: &str) -> Result<Option<PackageEntry>> {
-        let key = format!("{}:{}", attr_name, version);
+        let key = self.key_for(attr_name, version);
         
         match self.packages.get(key.as_bytes())? {
             Some(bytes) => {
@@ -169,8 +942,54 @@ impl ArchiverDb {
                     .context("Failed to deserialize PackageEntry")?;
                 Ok(Some(entry))
             }
-            None => Ok(None),
+            None => Ok(self.get_platforms(attr_name, version)?.into_iter().next()),
+        }
+    }
+
+    /// Per-platform entries of a package version, ordered by platform.
+    /// Empty for platform-independent packages.
+    pub fn get_platforms(&self, attr_name: &str, version: &str) -> Result<Vec<PackageEntry>> {
+        let base = self.key_for(attr_name, version);
+        let prefix = format!("{}:", base);
+        let mut results = Vec::new();
+        for item in self.packages.scan_prefix(prefix.as_bytes()) {
+            let (_, value) = item.context("Failed to read from database")?;
+            let entry = unpack(&value).context("Failed to deserialize PackageEntry")?;
+            // Skip versions that merely start with this one (e.g. "1.0:rc")
+            if entry.platform.is_some() && self.key_for(&entry.attr_name, &entry.version) == base {
+                results.push(entry);
+            }
+        }
+        Ok(results)
+    }
+
+    /// Retrieves a package entry, matching attr_name case-insensitively.
+    ///
+    /// Tries the exact-case key first; only on a miss does it fall back to
+    /// scanning the keys of names starting with the same letter in either
+    /// case. Per-platform entries are found like with [`get`](Self::get).
+    /// e.g. `get_ci("NodeJS", "20.0.0")` finds `nodejs:20.0.0`.
+    pub fn get_ci(&self, attr_name: &str, version: &str) -> Result<Option<PackageEntry>> {
+        if let Some(entry) = self.get(attr_name, version)? {
+            return Ok(Some(entry));
+        }
+
+        let Some(&first) = attr_name.as_bytes().first() else { return Ok(None) };
+        let mut firsts = vec![first.to_ascii_lowercase(), first.to_ascii_uppercase()];
+        firsts.dedup();
+        for first in firsts {
+            for name in distinct_attr_names(self.packages.scan_prefix([first]).keys()) {
+                let name = name?;
+                if name == attr_name.as_bytes() || !name.eq_ignore_ascii_case(attr_name.as_bytes()) {
+                    continue;
+                }
+                if let Some(entry) = self.get(&String::from_utf8_lossy(&name), version)? {
+                    return Ok(Some(entry));
+                }
+            }
         }
+
+        Ok(None)
     }
 
     /// Retrieves all versions of a given package
@@ -186,10 +1005,143 @@ impl ArchiverDb {
         }
 
         // Sort by timestamp (newest first)
-        results.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+        results.sort_by_key(|e| std::cmp::Reverse(e.timestamp));
         Ok(results)
     }
 
+    /// Versions of a package that start with `prefix` at a component
+    /// boundary, newest first: `20` and `20.11` match `20.11.0`, but `20`
+    /// doesn't match `200.1`. Per-platform entries are all included.
+    ///
+    /// A `scan_prefix` over the `attr_name:version` keys, so only the
+    /// matching part of the package's history is read.
+    pub fn get_by_version_prefix(&self, attr_name: &str, prefix: &str) -> Result<Vec<PackageEntry>> {
+        let base = self.key_for(attr_name, prefix);
+        let mut results = Vec::new();
+
+        for item in self.packages.scan_prefix(base.as_bytes()) {
+            let (key, value) = item.context("Failed to read from database")?;
+            if key[base.len()..].first().is_some_and(u8::is_ascii_digit) {
+                continue;
+            }
+            results.push(unpack(&value).context("Failed to deserialize PackageEntry")?);
+        }
+
+        results.sort_by_key(|e| std::cmp::Reverse(e.timestamp));
+        Ok(results)
+    }
+
+    /// Returns the `k` newest versions of a package by natural version order
+    /// ([`archiver_core::version`]), newest first. `k` counts versions: a
+    /// version indexed per platform comes with all its platform entries,
+    /// ordered by platform.
+    ///
+    /// Streams the package's entries through a heap of at most `k` items,
+    /// so packages with thousands of versions (e.g. `linux` over its full
+    /// history) are never materialized in full as with
+    /// [`get_all_versions`](Self::get_all_versions).
+    pub fn top_versions(&self, attr_name: &str, k: usize) -> Result<Vec<PackageEntry>> {
+        if k == 0 {
+            return Ok(Vec::new());
+        }
+        let prefix = format!("{}:", attr_name);
+        let mut heap = BinaryHeap::with_capacity(k + 1);
+        // Further platform entries of the versions in the heap
+        let mut platforms: HashMap<String, Vec<PackageEntry>> = HashMap::new();
+
+        for item in self.packages.scan_prefix(prefix.as_bytes()) {
+            let (_, value) = item.context("Failed to read from database")?;
+            let entry = unpack(&value)
+                .context("Failed to deserialize PackageEntry")?;
+            if let Some(others) = platforms.get_mut(&entry.version) {
+                others.push(entry);
+                continue;
+            }
+            platforms.insert(entry.version.clone(), Vec::new());
+            // Min-heap of the best k so far: the root is the one to evict
+            heap.push(Reverse(RankedEntry { key: parse_version_key(&entry.version), entry }));
+            if heap.len() > k {
+                if let Some(Reverse(evicted)) = heap.pop() {
+                    platforms.remove(&evicted.entry.version);
+                }
+            }
+        }
+
+        // Ascending order of Reverse(..) is newest first
+        let mut newest = Vec::new();
+        for Reverse(ranked) in heap.into_sorted_vec() {
+            let mut group = platforms.remove(&ranked.entry.version).unwrap_or_default();
+            group.push(ranked.entry);
+            group.sort_by(|a, b| a.platform.cmp(&b.platform));
+            newest.extend(group);
+        }
+        Ok(newest)
+    }
+
+    /// Returns the version of a package that was current at `timestamp`:
+    /// the entry whose commit is the newest one at or before that time.
+    ///
+    /// `None` if every indexed commit of the package is later (the package
+    /// didn't exist yet, or history that far back isn't indexed). Streams
+    /// the package's entries, keeping only the best match.
+    pub fn version_at_date(&self, attr_name: &str, timestamp: u64) -> Result<Option<PackageEntry>> {
+        let prefix = format!("{}:", attr_name);
+        let mut best: Option<PackageEntry> = None;
+
+        for item in self.packages.scan_prefix(prefix.as_bytes()) {
+            let (_, value) = item.context("Failed to read from database")?;
+            let entry = unpack(&value)
+                .context("Failed to deserialize PackageEntry")?;
+            if entry.timestamp <= timestamp && best.as_ref().is_none_or(|b| entry.timestamp > b.timestamp) {
+                best = Some(entry);
+            }
+        }
+        Ok(best)
+    }
+
+    /// Counts versions per attr_name for all packages starting with `prefix`.
+    /// A version indexed per platform counts once.
+    ///
+    /// Reads only keys, so it is cheap even when the matching packages have
+    /// long histories. The exception are keys with a second ':', which can
+    /// be an epoch ("1:1.0") or a platform suffix: those entries are
+    /// unpacked to tell. All keys of one attr_name are adjacent, so only
+    /// that package's versions are held at a time.
+    pub fn count_versions_by_prefix(&self, prefix: &str) -> Result<HashMap<String, usize>> {
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        let mut current: Option<(Vec<u8>, HashSet<Vec<u8>>)> = None;
+        let mut finish = |name: Vec<u8>, versions: HashSet<Vec<u8>>| {
+            *counts.entry(String::from_utf8_lossy(&name).into_owned()).or_insert(0) += versions.len();
+        };
+        for item in self.packages.scan_prefix(prefix.as_bytes()) {
+            let (key, value) = item.context("Failed to read from database")?;
+            // key format: "attr_name:version", plus ":platform" for per-platform entries
+            let pos = key.iter().position(|&b| b == b':').unwrap_or(key.len());
+            let attr_name = &key[..pos];
+            let version_key = if key[pos..].iter().skip(1).any(|&b| b == b':') {
+                let entry = unpack(&value).context("Failed to deserialize PackageEntry")?;
+                self.key_for(&entry.attr_name, &entry.version).into_bytes()
+            } else {
+                key.to_vec()
+            };
+            match &mut current {
+                Some((name, versions)) if name.as_slice() == attr_name => {
+                    versions.insert(version_key);
+                }
+                _ => {
+                    let next = (attr_name.to_vec(), HashSet::from([version_key]));
+                    if let Some((name, versions)) = current.replace(next) {
+                        finish(name, versions);
+                    }
+                }
+            }
+        }
+        if let Some((name, versions)) = current {
+            finish(name, versions);
+        }
+        Ok(counts)
+    }
+
     /// Searches packages by prefix across all attr_names.
     /// e.g. query "python" matches python27, python311, python312, python313, ...
     /// Returns a map of attr_name → list of versions (sorted newest first).
@@ -205,7 +1157,7 @@ impl ArchiverDb {
 
         // Sort each group by timestamp (newest first)
         for entries in results.values_mut() {
-            entries.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+            entries.sort_by_key(|e| std::cmp::Reverse(e.timestamp));
         }
 
         Ok(results)
@@ -229,12 +1181,104 @@ impl ArchiverDb {
         }
 
         for entries in results.values_mut() {
-            entries.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+            entries.sort_by_key(|e| std::cmp::Reverse(e.timestamp));
         }
 
         Ok(results)
     }
 
+    /// Iterates over every stored package entry in key order.
+    ///
+    /// Entries are decoded lazily, so callers can stream the whole database
+    /// (e.g. for export) without loading it into memory.
+    pub fn iter_entries(&self) -> impl Iterator<Item = Result<PackageEntry>> + '_ {
+        self.packages.iter().map(|item| {
+            let (_, value) = item.context("Failed to read from database")?;
+            unpack(&value).context("Failed to deserialize PackageEntry")
+        })
+    }
+
+    /// Compares the packages of this database with `other`, calling
+    /// `on_diff` for every key stored in only one of them or mapped to
+    /// different commits, in key order. Returns the number of entries the
+    /// two databases share unchanged.
+    ///
+    /// Walks both key-ordered trees side by side, so memory use does not
+    /// grow with database size. Both databases must use the same key mode
+    /// (see [`enable_normalized_keys`](Self::enable_normalized_keys)).
+    pub fn diff(&self, other: &ArchiverDb, mut on_diff: impl FnMut(EntryDiff)) -> Result<usize> {
+        if self.normalized_keys != other.normalized_keys {
+            anyhow::bail!(
+                "Only one of the databases uses normalized version keys; \
+                 run `index --normalize-versions` on the other before comparing"
+            );
+        }
+
+        let decode = |item: sled::Result<(sled::IVec, sled::IVec)>| -> Result<(sled::IVec, PackageEntry)> {
+            let (key, value) = item.context("Failed to read from database")?;
+            Ok((key, unpack(&value).context("Failed to deserialize PackageEntry")?))
+        };
+        let mut here = self.packages.iter().map(decode).peekable();
+        let mut there = other.packages.iter().map(decode).peekable();
+        let mut unchanged = 0;
+
+        loop {
+            let order = match (here.peek(), there.peek()) {
+                (None, None) => break,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(Err(_)), _) | (_, Some(Err(_))) => Ordering::Equal,
+                (Some(Ok((a, _))), Some(Ok((b, _)))) => a.cmp(b),
+            };
+            match order {
+                Ordering::Less => on_diff(EntryDiff::OnlyHere(here.next().unwrap()?.1)),
+                Ordering::Greater => on_diff(EntryDiff::OnlyThere(there.next().unwrap()?.1)),
+                Ordering::Equal => {
+                    let (_, ours) = here.next().unwrap()?;
+                    let (_, theirs) = there.next().unwrap()?;
+                    if ours.commit_sha == theirs.commit_sha {
+                        unchanged += 1;
+                    } else {
+                        on_diff(EntryDiff::CommitChanged { here: ours, there: theirs });
+                    }
+                }
+            }
+        }
+        Ok(unchanged)
+    }
+
+    /// Returns every entry whose package provides the executable `program`
+    /// (`meta.mainProgram`), sorted by attr_name and newest commit first.
+    ///
+    /// Scans all entries; fine for interactive lookups.
+    pub fn find_by_main_program(&self, program: &str) -> Result<Vec<PackageEntry>> {
+        let mut found = Vec::new();
+        for entry in self.iter_entries() {
+            let entry = entry?;
+            if entry.main_program.as_deref() == Some(program) {
+                found.push(entry);
+            }
+        }
+        found.sort_by(|a, b| a.attr_name.cmp(&b.attr_name).then(b.timestamp.cmp(&a.timestamp)));
+        Ok(found)
+    }
+
+    /// Entries whose description contains `query`, ignoring case; sorted
+    /// by attr name, newest first. A full scan: only entries indexed since
+    /// descriptions are stored have one
+    pub fn search_by_description(&self, query: &str) -> Result<Vec<PackageEntry>> {
+        let query = query.to_lowercase();
+        let mut found = Vec::new();
+        for entry in self.iter_entries() {
+            let entry = entry?;
+            if entry.description.as_ref().is_some_and(|d| d.to_lowercase().contains(&query)) {
+                found.push(entry);
+            }
+        }
+        found.sort_by(|a, b| a.attr_name.cmp(&b.attr_name).then(b.timestamp.cmp(&a.timestamp)));
+        Ok(found)
+    }
+
     /// Marks a commit as processed
     pub fn mark_commit_processed(&self, commit_sha: &str, timestamp: u64) -> Result<()> {
         self.processed_commits
@@ -249,22 +1293,46 @@ impl ArchiverDb {
     }
 
     /// Returns the total number of stored (attr_name, version) entries.
+    ///
+    /// Counts versions, not packages: `nodejs 18.0.0` and `nodejs 20.0.0`
+    /// are two. Use [`unique_package_count`](Self::unique_package_count)
+    /// for the number of distinct packages.
     pub fn version_count(&self) -> usize {
         self.packages.len()
     }
 
     /// Returns the number of distinct package attr_names.
+    ///
+    /// Counts packages regardless of how many versions each has: `nodejs
+    /// 18.0.0` and `nodejs 20.0.0` are one. Use
+    /// [`version_count`](Self::version_count) for the number of entries.
     /// Scans only keys (no value deserialization) for performance.
     pub fn unique_package_count(&self) -> usize {
-        let mut seen = std::collections::HashSet::new();
-        for item in self.packages.iter().keys() {
-            if let Ok(key) = item {
-                // key format: "attr_name:version" — take bytes before first ':'
-                let pos = key.iter().position(|&b| b == b':').unwrap_or(key.len());
-                seen.insert(key[..pos].to_vec());
-            }
-        }
-        seen.len()
+        self.distinct_attr_names().flatten().count()
+    }
+
+    /// Every distinct attr_name, sorted. Reads only keys, like
+    /// [`unique_package_count`](Self::unique_package_count).
+    pub fn package_names(&self) -> Result<Vec<String>> {
+        let mut names = self
+            .distinct_attr_names()
+            .map(|name| Ok(String::from_utf8_lossy(&name?).into_owned()))
+            .collect::<Result<Vec<_>>>()?;
+        names.sort();
+        Ok(names)
+    }
+
+    /// Attr names of the package keys, each once
+    fn distinct_attr_names(&self) -> impl Iterator<Item = Result<Vec<u8>>> + '_ {
+        distinct_attr_names(self.packages.iter().keys())
+    }
+
+    /// Number of indexed versions per attr_name, most versions first (ties
+    /// by name). Reads mostly keys, like [`count_versions_by_prefix`](Self::count_versions_by_prefix).
+    pub fn version_counts(&self) -> Result<Vec<(String, usize)>> {
+        let mut counts: Vec<_> = self.count_versions_by_prefix("")?.into_iter().collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        Ok(counts)
     }
 
     /// Checks if database is empty (no packages indexed yet)
@@ -279,21 +1347,66 @@ impl ArchiverDb {
 
     /// Returns total on-disk size of the database directory in bytes.
     /// Sums sizes of all files inside the sled directory recursively.
+    /// A temporary database has no directory and reports 0.
     pub fn db_size_bytes(&self) -> u64 {
-        fn dir_size(path: &std::path::Path) -> u64 {
-            let Ok(entries) = std::fs::read_dir(path) else { return 0; };
-            entries.flatten().map(|e| {
-                let p = e.path();
-                if p.is_dir() {
-                    dir_size(&p)
-                } else {
-                    e.metadata().map(|m| m.len()).unwrap_or(0)
-                }
-            }).sum()
+        if self.path.as_os_str().is_empty() {
+            return 0;
         }
         dir_size(&self.path)
     }
 
+    /// Rewrites the database into a fresh directory and swaps it into place,
+    /// returning the on-disk size before and after.
+    ///
+    /// sled never shrinks its files by itself, so a database that saw many
+    /// overwrites (re-indexing, `--normalize-versions`) keeps the space of
+    /// old values. Copying every tree into a new database drops it. This
+    /// needs free disk for a second copy while it runs, and closes the
+    /// database: reopen it to keep using it.
+    pub fn compact(self) -> Result<(u64, u64)> {
+        if self.path.as_os_str().is_empty() || self.snapshot.is_some() {
+            anyhow::bail!("Only a database opened for writing from disk can be compacted");
+        }
+        self.flush()?;
+        let before = self.db_size_bytes();
+        let path = self.path.clone();
+        let sibling = |suffix: &str| {
+            let mut name = path.file_name().unwrap_or_default().to_os_string();
+            name.push(suffix);
+            path.with_file_name(name)
+        };
+        let (fresh_path, old_path) = (sibling(".compacting"), sibling(".pre-compact"));
+        for leftover in [&fresh_path, &old_path] {
+            if leftover.exists() {
+                std::fs::remove_dir_all(leftover)
+                    .with_context(|| format!("Failed to remove leftover {:?}", leftover))?;
+            }
+        }
+
+        let fresh = sled::open(&fresh_path)
+            .with_context(|| format!("Failed to create database at {:?}", fresh_path))?;
+        for name in self.db.tree_names() {
+            let from = self.db.open_tree(&name)?;
+            let to = fresh.open_tree(&name)?;
+            for item in from.iter() {
+                let (key, value) = item.context("Failed to read from database")?;
+                to.insert(key, value)?;
+            }
+        }
+        fresh.flush().context("Failed to flush compacted database")?;
+        drop(fresh);
+        // Closes sled and releases the lock before the directories move
+        drop(self);
+
+        std::fs::rename(&path, &old_path)
+            .with_context(|| format!("Failed to move {:?} aside", path))?;
+        std::fs::rename(&fresh_path, &path)
+            .with_context(|| format!("Failed to move the compacted database to {:?}", path))?;
+        std::fs::remove_dir_all(&old_path)
+            .with_context(|| format!("Failed to remove {:?}", old_path))?;
+        Ok((before, dir_size(&path)))
+    }
+
     // -----------------------------------------------------------------------
     // Tarball hash store (per-commit nixpkgs sha256 for use in fetchTarball)
     // -----------------------------------------------------------------------
@@ -324,23 +1437,211 @@ impl ArchiverDb {
         self.tarball_hashes.len()
     }
 
-    /// Returns all unique commit SHAs found in the packages tree.
+    /// Stores the narHash of nixpkgs at a commit as fetched by
+    /// `builtins.fetchGit` (SRI, from `nix flake prefetch`). It hashes the
+    /// git tree checkout and differs from the tarball hash.
+    pub fn store_git_narhash(&self, commit_sha: &str, hash: &str) -> Result<()> {
+        self.git_narhashes
+            .insert(commit_sha.as_bytes(), hash.as_bytes())
+            .context("Failed to store git narHash")?;
+        Ok(())
+    }
+
+    /// Retrieves the stored `fetchGit` narHash for a commit, if any.
+    pub fn get_git_narhash(&self, commit_sha: &str) -> Result<Option<String>> {
+        match self.git_narhashes.get(commit_sha.as_bytes())? {
+            Some(bytes) => {
+                let s = String::from_utf8(bytes.to_vec())
+                    .context("Git narHash contains invalid UTF-8")?;
+                Ok(Some(s))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Records that prefetching the tarball hash (or `fetchGit` narHash with
+    /// `git_narhash`) of a commit failed at `failed_at`, counting repeated
+    /// failures
+    pub fn record_prefetch_failure(&self, commit_sha: &str, git_narhash: bool, failed_at: u64, error: &str) -> Result<()> {
+        let failures = self.prefetch_failure(commit_sha, git_narhash)?.map_or(0, |f| f.failures);
+        let failure = PrefetchFailure { failed_at, failures: failures + 1, error: error.to_string() };
+        let value = serde_json::to_vec(&failure).context("Failed to serialize prefetch failure")?;
+        self.prefetch_failures
+            .insert(prefetch_failure_key(commit_sha, git_narhash), value)
+            .context("Failed to store prefetch failure")?;
+        Ok(())
+    }
+
+    /// The recorded prefetch failure of a commit, if its last attempt failed
+    pub fn prefetch_failure(&self, commit_sha: &str, git_narhash: bool) -> Result<Option<PrefetchFailure>> {
+        match self.prefetch_failures.get(prefetch_failure_key(commit_sha, git_narhash))? {
+            Some(bytes) => Ok(Some(
+                serde_json::from_slice(&bytes).context("Failed to deserialize prefetch failure")?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// Forgets the prefetch failure of a commit (after a successful fetch)
+    pub fn clear_prefetch_failure(&self, commit_sha: &str, git_narhash: bool) -> Result<()> {
+        self.prefetch_failures
+            .remove(prefetch_failure_key(commit_sha, git_narhash))
+            .context("Failed to clear prefetch failure")?;
+        Ok(())
+    }
+
+    /// Returns all unique commit SHAs referenced by package entries, sorted.
     /// Used by `prefetch-hashes` to know which commits to fetch.
+    /// Reads only the keys of the `commits` index.
     pub fn all_unique_commits(&self) -> Result<Vec<String>> {
-        let mut seen = std::collections::HashSet::new();
-        for item in self.packages.iter() {
-            let (_, value) = item.context("Failed to read from database")?;
-            let entry = unpack(&value).context("Failed to deserialize PackageEntry")?;
-            seen.insert(entry.commit_sha);
-        }
-        let mut commits: Vec<String> = seen.into_iter().collect();
-        commits.sort();
+        self.commits
+            .iter()
+            .keys()
+            .map(|key| {
+                let key = key.context("Failed to read from database")?;
+                String::from_utf8(key.to_vec()).context("Commit SHA contains invalid UTF-8")
+            })
+            .collect()
+    }
+
+    /// Returns all unique commit SHAs referenced by package entries together
+    /// with their commit timestamp, sorted oldest first.
+    /// Used by `prefetch-hashes` to prioritise newest or oldest commits.
+    pub fn all_unique_commits_with_timestamps(&self) -> Result<Vec<(String, u64)>> {
+        let mut commits = self.commits
+            .iter()
+            .map(|item| {
+                let (key, value) = item.context("Failed to read from database")?;
+                let sha = String::from_utf8(key.to_vec()).context("Commit SHA contains invalid UTF-8")?;
+                let timestamp = value
+                    .get(..8)
+                    .and_then(|b| b.try_into().ok())
+                    .map(u64::from_be_bytes)
+                    .context("Corrupt commits index record")?;
+                Ok((sha, timestamp))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        commits.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
         Ok(commits)
     }
 
-    /// Flushes all pending operations to disk
+    /// Adds branch/tag names to a commit's record, keeping earlier ones:
+    /// a branch that has since moved on still marks the commits it was at.
+    pub fn add_commit_refs(&self, commit_sha: &str, names: &[String]) -> Result<()> {
+        let mut all = self.commit_refs(commit_sha)?;
+        all.extend(names.iter().cloned());
+        all.sort();
+        all.dedup();
+        let value = serde_json::to_vec(&all).context("Failed to serialize commit refs")?;
+        self.commit_refs
+            .insert(commit_sha.as_bytes(), value)
+            .context("Failed to store commit refs")?;
+        Ok(())
+    }
+
+    /// Branch/tag names recorded for a commit, sorted (empty if none)
+    pub fn commit_refs(&self, commit_sha: &str) -> Result<Vec<String>> {
+        match self.commit_refs.get(commit_sha.as_bytes())? {
+            Some(bytes) => serde_json::from_slice(&bytes).context("Failed to deserialize commit refs"),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Records that `entry`'s version was seen in its commit, alongside the
+    /// deduplicated `packages` tree. Every commit is kept, so a version that
+    /// was reverted and reintroduced shows up at each appearance.
+    pub fn record_version_occurrence(&self, entry: &PackageEntry) -> Result<()> {
+        let mut key = version_history_prefix(&entry.attr_name, &entry.version);
+        key.extend_from_slice(&entry.timestamp.to_be_bytes());
+        key.extend_from_slice(entry.commit_sha.as_bytes());
+        self.version_history
+            .insert(key, &[])
+            .context("Failed to record version occurrence")?;
+        Ok(())
+    }
+
+    /// Commits (with timestamps) in which a version was seen, oldest first;
+    /// empty unless indexed with `--append-history`
+    pub fn version_history(&self, attr_name: &str, version: &str) -> Result<Vec<(String, u64)>> {
+        let prefix = version_history_prefix(attr_name, version);
+        self.version_history
+            .scan_prefix(&prefix)
+            .keys()
+            .map(|key| {
+                let key = key.context("Failed to read from database")?;
+                let rest = &key[prefix.len()..];
+                let timestamp = rest
+                    .get(..8)
+                    .and_then(|b| b.try_into().ok())
+                    .map(u64::from_be_bytes)
+                    .context("Corrupt version history record")?;
+                let sha = String::from_utf8(rest[8..].to_vec()).context("Commit SHA contains invalid UTF-8")?;
+                Ok((sha, timestamp))
+            })
+            .collect()
+    }
+
+    /// Stores the subject line of a commit's message (e.g.
+    /// "nodejs: 18.0.0 -> 20.0.0"), replacing any earlier one
+    pub fn store_commit_subject(&self, commit_sha: &str, subject: &str) -> Result<()> {
+        self.commit_subjects
+            .insert(commit_sha.as_bytes(), subject.as_bytes())
+            .context("Failed to store commit subject")?;
+        Ok(())
+    }
+
+    /// The recorded subject line of a commit, if any
+    pub fn commit_subject(&self, commit_sha: &str) -> Result<Option<String>> {
+        match self.commit_subjects.get(commit_sha.as_bytes())? {
+            Some(bytes) => Ok(Some(
+                String::from_utf8(bytes.to_vec()).context("Commit subject contains invalid UTF-8")?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// Schema version recorded in the database
+    pub fn schema_version(&self) -> Result<u32> {
+        let bytes = self
+            .meta
+            .get(SCHEMA_VERSION_KEY)?
+            .context("Database has no schema version record")?;
+        let bytes: [u8; 4] = bytes.as_ref().try_into().context("Corrupt schema version record")?;
+        Ok(u32::from_be_bytes(bytes))
+    }
+
+    // -----------------------------------------------------------------------
+    // Provenance (how this database was built)
+    // -----------------------------------------------------------------------
+
+    /// Appends a record of an `index` run. Earlier records are never
+    /// modified, so re-runs build up a history.
+    pub fn record_index_run(&self, run: &IndexRun) -> Result<()> {
+        let id = self.db.generate_id().context("Failed to allocate index run id")?;
+        let value = serde_json::to_vec(run).context("Failed to serialize index run")?;
+        self.index_runs
+            .insert(id.to_be_bytes(), value)
+            .context("Failed to store index run")?;
+        Ok(())
+    }
+
+    /// Returns all recorded `index` runs, oldest first.
+    pub fn index_runs(&self) -> Result<Vec<IndexRun>> {
+        self.index_runs
+            .iter()
+            .values()
+            .map(|value| {
+                let value = value.context("Failed to read from database")?;
+                serde_json::from_slice(&value).context("Failed to deserialize index run")
+            })
+            .collect()
+    }
+
+    /// Flushes all pending operations to disk, retrying transient I/O
+    /// errors (see [`retry`](crate::retry)) before giving up
     pub fn flush(&self) -> Result<()> {
-        self.db.flush().context("Failed to flush database")?;
+        retry_on_io_error("Database flush", &FLUSH_RETRY_DELAYS, || self.db.flush())
+            .context("Failed to flush database")?;
         Ok(())
     }
 }
@@ -4,6 +4,7 @@
 //! for each unique package version, only the latest commit is stored.
 
 mod database;
+pub mod retry;
 
-pub use database::ArchiverDb;
+pub use database::{ArchiverDb, EntryDiff, InsertOutcome, PrefetchFailure, SCHEMA_VERSION};
 
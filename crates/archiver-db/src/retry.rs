@@ -0,0 +1,38 @@
+//! Retrying sled operations that fail on transient I/O errors
+//!
+//! A long `index` run flushes many times; a momentary ENOSPC or an NFS blip
+//! during one flush shouldn't throw away hours of work. I/O errors are
+//! retried after each delay in turn; other errors (corruption, unsupported
+//! layouts) would fail the same way again and are returned at once.
+
+use std::time::Duration;
+
+/// Pauses before the retries of a flush: up to four attempts in ~5 s
+pub const FLUSH_RETRY_DELAYS: [Duration; 3] = [
+    Duration::from_millis(250),
+    Duration::from_secs(1),
+    Duration::from_secs(4),
+];
+
+/// Runs `op`, retrying on `sled::Error::Io` after each of `delays`.
+/// `what` names the operation in the warning logged before each retry.
+/// The last error is returned once the delays are used up.
+pub fn retry_on_io_error<T>(
+    what: &str,
+    delays: &[Duration],
+    mut op: impl FnMut() -> sled::Result<T>,
+) -> sled::Result<T> {
+    let mut delays = delays.iter();
+    loop {
+        match op() {
+            Err(sled::Error::Io(e)) => match delays.next() {
+                Some(delay) => {
+                    log::warn!("{} failed ({}); retrying in {:?}", what, e, delay);
+                    std::thread::sleep(*delay);
+                }
+                None => return Err(sled::Error::Io(e)),
+            },
+            result => return result,
+        }
+    }
+}
@@ -1,7 +1,7 @@
 //! Tests for database functionality
 
 use archiver_core::PackageEntry;
-use archiver_db::ArchiverDb;
+use archiver_db::{ArchiverDb, EntryDiff, InsertOutcome, PrefetchFailure};
 use anyhow::Result;
 use tempfile::TempDir;
 
@@ -22,8 +22,7 @@ fn node(ver: &str, sha: &str, ts: u64) -> PackageEntry {
 
 #[test]
 fn test_insert_and_get() -> Result<()> {
-    let tmp = TempDir::new()?;
-    let db = ArchiverDb::open(tmp.path())?;
+    let db = ArchiverDb::open_temporary()?;
 
     let entry = PackageEntry::new(
         "nodejs".to_string(),
@@ -39,8 +38,7 @@ fn test_insert_and_get() -> Result<()> {
 
 #[test]
 fn test_get_nonexistent_returns_none() -> Result<()> {
-    let tmp = TempDir::new()?;
-    let db = ArchiverDb::open(tmp.path())?;
+    let db = ArchiverDb::open_temporary()?;
     assert_eq!(db.get("nonexistent", "0.0.0")?, None);
     Ok(())
 }
@@ -49,8 +47,7 @@ fn test_get_nonexistent_returns_none() -> Result<()> {
 
 #[test]
 fn test_deduplication_newer_wins() -> Result<()> {
-    let tmp = TempDir::new()?;
-    let db = ArchiverDb::open(tmp.path())?;
+    let db = ArchiverDb::open_temporary()?;
 
     db.insert_if_better(&node("14.17.0", SHA_OLD, 1000))?;
     db.insert_if_better(&node("14.17.0", SHA_NEW, 2000))?;
@@ -61,8 +58,7 @@ fn test_deduplication_newer_wins() -> Result<()> {
 
 #[test]
 fn test_deduplication_older_does_not_overwrite() -> Result<()> {
-    let tmp = TempDir::new()?;
-    let db = ArchiverDb::open(tmp.path())?;
+    let db = ArchiverDb::open_temporary()?;
 
     db.insert_if_better(&node("14.17.0", SHA_NEW, 2000))?;
     // Attempt to insert older entry — should be ignored
@@ -72,12 +68,23 @@ fn test_deduplication_older_does_not_overwrite() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_insert_with_outcome_distinguishes_new_updated_and_kept() -> Result<()> {
+    let db = ArchiverDb::open_temporary()?;
+
+    assert_eq!(db.insert_with_outcome(&node("14.17.0", SHA_OLD, 1000))?, InsertOutcome::Inserted);
+    assert_eq!(db.insert_with_outcome(&node("14.17.0", SHA_OLD, 1000))?, InsertOutcome::AlreadyStored);
+    assert_eq!(db.insert_with_outcome(&node("14.17.0", SHA_NEW, 2000))?, InsertOutcome::Updated);
+    assert_eq!(db.insert_with_outcome(&node("14.17.0", SHA_OLD, 1000))?, InsertOutcome::KeptNewer);
+    assert!(!InsertOutcome::KeptNewer.is_stored());
+    Ok(())
+}
+
 // ── get_all_versions ─────────────────────────────────────────────────────────
 
 #[test]
 fn test_get_all_versions_sorted_newest_first() -> Result<()> {
-    let tmp = TempDir::new()?;
-    let db = ArchiverDb::open(tmp.path())?;
+    let db = ArchiverDb::open_temporary()?;
 
     db.insert_if_better(&node("14.17.0", SHA1, 1000))?;
     db.insert_if_better(&node("16.0.0",  SHA2, 2000))?;
@@ -90,12 +97,78 @@ fn test_get_all_versions_sorted_newest_first() -> Result<()> {
     Ok(())
 }
 
+// ── get_by_version_prefix ────────────────────────────────────────────────────
+
+#[test]
+fn test_get_by_version_prefix_matches_whole_components() -> Result<()> {
+    let db = ArchiverDb::open_temporary()?;
+
+    db.insert_if_better(&node("20.11.0", SHA1, 1000))?;
+    db.insert_if_better(&node("20.11.1", SHA2, 2000))?;
+    db.insert_if_better(&node("20.2.0",  SHA_OLD, 500))?;
+    db.insert_if_better(&node("200.1.0", SHA_NEW, 3000))?;
+    db.insert_if_better(&PackageEntry::new("nodejs_20".to_string(), "20.0.0".to_string(), SHA1.to_string(), 1000))?;
+
+    let versions: Vec<String> = db.get_by_version_prefix("nodejs", "20")?.into_iter().map(|e| e.version).collect();
+    assert_eq!(versions, vec!["20.11.1", "20.11.0", "20.2.0"]); // newest first, no 200.x
+
+    let versions: Vec<String> = db.get_by_version_prefix("nodejs", "20.11")?.into_iter().map(|e| e.version).collect();
+    assert_eq!(versions, vec!["20.11.1", "20.11.0"]);
+
+    // An exact version is its own only match
+    let versions: Vec<String> = db.get_by_version_prefix("nodejs", "20.11.0")?.into_iter().map(|e| e.version).collect();
+    assert_eq!(versions, vec!["20.11.0"]);
+    assert!(db.get_by_version_prefix("nodejs", "21")?.is_empty());
+    Ok(())
+}
+
+// ── top_versions (bounded top-K) ─────────────────────────────────────────────
+
+#[test]
+fn test_top_versions_matches_full_sort_on_large_package() -> Result<()> {
+    use archiver_core::version::compare_versions;
+
+    let db = ArchiverDb::open_temporary()?;
+
+    // Kernel-like history: point releases and release candidates, inserted
+    // in an order unrelated to either version or timestamp
+    let mut n = 0u64;
+    for minor in 0..20 {
+        for patch in 0..60 {
+            n += 1;
+            let ts = (n * 7919) % 10007;
+            db.insert_if_better(&PackageEntry::new(
+                "linux".to_string(), format!("6.{}.{}", minor, patch), SHA1.to_string(), ts,
+            ))?;
+        }
+        db.insert_if_better(&PackageEntry::new(
+            "linux".to_string(), format!("6.{}-rc{}", minor + 1, minor % 7), SHA2.to_string(), minor,
+        ))?;
+    }
+    db.insert_if_better(&node("20.0.0", SHA1, 1))?;
+
+    let mut full = db.get_all_versions("linux")?;
+    full.sort_by(|a, b| compare_versions(&b.version, &a.version));
+
+    for k in [0, 1, 10, 50, full.len(), full.len() + 5] {
+        let top = db.top_versions("linux", k)?;
+        let expected: Vec<&str> = full.iter().take(k).map(|e| e.version.as_str()).collect();
+        let got: Vec<&str> = top.iter().map(|e| e.version.as_str()).collect();
+        assert_eq!(got, expected, "k = {}", k);
+    }
+    assert_eq!(db.top_versions("linux", 3)?[0].version, "6.20-rc5");
+
+    let counts = db.count_versions_by_prefix("linux")?;
+    assert_eq!(counts.len(), 1);
+    assert_eq!(counts["linux"], full.len());
+    Ok(())
+}
+
 // ── search_packages (prefix scan) ────────────────────────────────────────────
 
 #[test]
 fn test_search_packages_prefix() -> Result<()> {
-    let tmp = TempDir::new()?;
-    let db = ArchiverDb::open(tmp.path())?;
+    let db = ArchiverDb::open_temporary()?;
 
     let py311 = PackageEntry::new("python311".to_string(), "3.11.14".to_string(), SHA1.to_string(), 1000);
     let py312 = PackageEntry::new("python312".to_string(), "3.12.12".to_string(), SHA2.to_string(), 2000);
@@ -115,8 +188,7 @@ fn test_search_packages_prefix() -> Result<()> {
 
 #[test]
 fn test_search_packages_exact_name() -> Result<()> {
-    let tmp = TempDir::new()?;
-    let db = ArchiverDb::open(tmp.path())?;
+    let db = ArchiverDb::open_temporary()?;
 
     let e = PackageEntry::new("nodejs".to_string(), "20.0.0".to_string(), SHA1.to_string(), 1000);
     db.insert_if_better(&e)?;
@@ -129,8 +201,7 @@ fn test_search_packages_exact_name() -> Result<()> {
 
 #[test]
 fn test_search_packages_contains_substring() -> Result<()> {
-    let tmp = TempDir::new()?;
-    let db = ArchiverDb::open(tmp.path())?;
+    let db = ArchiverDb::open_temporary()?;
 
     let biome = PackageEntry::new(
         "vscode-extensions.biomejs.biome".to_string(), "2025.10.0".to_string(),
@@ -169,8 +240,7 @@ fn test_search_packages_contains_substring() -> Result<()> {
 
 #[test]
 fn test_commit_tracking() -> Result<()> {
-    let tmp = TempDir::new()?;
-    let db = ArchiverDb::open(tmp.path())?;
+    let db = ArchiverDb::open_temporary()?;
 
     assert!(!db.is_commit_processed(SHA1)?);
     db.mark_commit_processed(SHA1, 1234567890)?;
@@ -179,3 +249,679 @@ fn test_commit_tracking() -> Result<()> {
     assert!(!db.is_commit_processed(SHA2)?);
     Ok(())
 }
+
+// ── unique commits ───────────────────────────────────────────────────────────
+
+#[test]
+fn test_all_unique_commits_with_timestamps_sorted_oldest_first() -> Result<()> {
+    let db = ArchiverDb::open_temporary()?;
+
+    db.insert_if_better(&node("18.0.0", SHA_NEW, 3000))?;
+    db.insert_if_better(&node("14.17.0", SHA1, 1000))?;
+    db.insert_if_better(&node("16.0.0", SHA1, 1000))?;
+    db.insert_if_better(&node("17.0.0", SHA2, 2000))?;
+
+    let commits = db.all_unique_commits_with_timestamps()?;
+    assert_eq!(commits, vec![
+        (SHA1.to_string(), 1000),
+        (SHA2.to_string(), 2000),
+        (SHA_NEW.to_string(), 3000),
+    ]);
+    Ok(())
+}
+
+#[test]
+fn test_all_unique_commits_deduplicates_and_sorts() -> Result<()> {
+    let db = ArchiverDb::open_temporary()?;
+    assert!(db.all_unique_commits()?.is_empty());
+
+    db.insert_if_better(&node("17.0.0", SHA2, 2000))?;
+    db.insert_if_better(&node("14.17.0", SHA1, 1000))?;
+    db.insert_if_better(&node("16.0.0", SHA1, 1000))?;
+    db.insert_if_better(&PackageEntry::new("python3".to_string(), "3.11.4".to_string(), SHA2.to_string(), 2000))?;
+
+    assert_eq!(db.all_unique_commits()?, vec![SHA1.to_string(), SHA2.to_string()]);
+    Ok(())
+}
+
+#[test]
+fn test_commit_index_drops_commits_no_longer_referenced() -> Result<()> {
+    let db = ArchiverDb::open_temporary()?;
+
+    db.insert_if_better(&node("14.17.0", SHA_OLD, 1000))?;
+    db.insert_if_better(&node("16.0.0", SHA_OLD, 1000))?;
+    db.insert_if_better(&node("18.0.0", SHA2, 2000))?;
+
+    // 14.17.0 moves to a newer commit; SHA_OLD is still used by 16.0.0
+    assert!(db.insert_if_better(&node("14.17.0", SHA_NEW, 3000))?);
+    // Re-inserting an entry that loses changes nothing
+    assert!(!db.insert_if_better(&node("16.0.0", SHA1, 500))?);
+    assert_eq!(
+        db.all_unique_commits()?,
+        vec![SHA_OLD.to_string(), SHA_NEW.to_string(), SHA2.to_string()]
+    );
+
+    // Last reference to SHA_OLD is replaced: the commit leaves the index
+    db.insert_if_better(&node("16.0.0", SHA_NEW, 3000))?;
+    assert_eq!(db.all_unique_commits()?, vec![SHA_NEW.to_string(), SHA2.to_string()]);
+    assert_eq!(db.all_unique_commits_with_timestamps()?, vec![
+        (SHA2.to_string(), 2000),
+        (SHA_NEW.to_string(), 3000),
+    ]);
+    Ok(())
+}
+
+// ── tarball hashes ───────────────────────────────────────────────────────────
+
+#[test]
+fn test_tarball_hash_store_get_and_count() -> Result<()> {
+    let db = ArchiverDb::open_temporary()?;
+    let hash1 = "0v9sbmrx8gpk5dzrhcwr7iz4vzwc5lc5jhm9bqjb3nxnnzj0fzxc";
+    let hash2 = "1i2nv6l2gzk9iykrlkbr7h1fq1k0gdpkh4wr00vn6w8nnl1xf0mm";
+
+    assert_eq!(db.get_tarball_hash(SHA1)?, None);
+    assert_eq!(db.tarball_hash_count(), 0);
+
+    db.store_tarball_hash(SHA1, hash1)?;
+    db.store_tarball_hash(SHA2, hash2)?;
+    assert_eq!(db.get_tarball_hash(SHA1)?.as_deref(), Some(hash1));
+    assert_eq!(db.get_tarball_hash(SHA2)?.as_deref(), Some(hash2));
+    assert_eq!(db.tarball_hash_count(), 2);
+
+    // Re-prefetching a commit overwrites its hash instead of adding a row
+    db.store_tarball_hash(SHA1, hash2)?;
+    assert_eq!(db.get_tarball_hash(SHA1)?.as_deref(), Some(hash2));
+    assert_eq!(db.tarball_hash_count(), 2);
+
+    // Tarball hashes live in their own tree and don't count as packages
+    assert_eq!(db.version_count(), 0);
+    Ok(())
+}
+
+#[test]
+fn test_git_narhash_is_stored_separately_from_tarball_hash() -> Result<()> {
+    let db = ArchiverDb::open_temporary()?;
+
+    let nar = "sha256-1t4a7U6pIWDlsXQlsAfAa+u9uS999tngBaykwy22Vx8=";
+    assert_eq!(db.get_git_narhash(SHA1)?, None);
+    db.store_git_narhash(SHA1, nar)?;
+    assert_eq!(db.get_git_narhash(SHA1)?.as_deref(), Some(nar));
+    assert_eq!(db.get_tarball_hash(SHA1)?, None);
+    assert_eq!(db.tarball_hash_count(), 0);
+    Ok(())
+}
+
+#[test]
+fn test_prefetch_failures_count_up_per_hash_kind_and_clear() -> Result<()> {
+    let db = ArchiverDb::open_temporary()?;
+    assert_eq!(db.prefetch_failure(SHA1, false)?, None);
+
+    db.record_prefetch_failure(SHA1, false, 1000, "HTTP error 404")?;
+    db.record_prefetch_failure(SHA1, false, 2000, "HTTP error 410")?;
+    assert_eq!(
+        db.prefetch_failure(SHA1, false)?,
+        Some(PrefetchFailure { failed_at: 2000, failures: 2, error: "HTTP error 410".to_string() })
+    );
+    // The fetchGit narHash is tracked on its own
+    assert_eq!(db.prefetch_failure(SHA1, true)?, None);
+
+    db.clear_prefetch_failure(SHA1, false)?;
+    assert_eq!(db.prefetch_failure(SHA1, false)?, None);
+    Ok(())
+}
+
+// ── version history ──────────────────────────────────────────────────────────
+
+#[test]
+fn test_version_history_keeps_every_appearance_oldest_first() -> Result<()> {
+    let db = ArchiverDb::open_temporary()?;
+    // 18.0.0 is seen at SHA_OLD, replaced, then comes back at SHA_NEW
+    db.record_version_occurrence(&node("18.0.0", SHA_NEW, 3000))?;
+    db.record_version_occurrence(&node("18.0.0", SHA_OLD, 1000))?;
+    db.record_version_occurrence(&node("18.0", SHA2, 2000))?;
+    // Seen again in the same commit (e.g. another platform): one record
+    db.record_version_occurrence(&node("18.0.0", SHA_OLD, 1000))?;
+
+    assert_eq!(db.version_history("nodejs", "18.0.0")?, vec![
+        (SHA_OLD.to_string(), 1000),
+        (SHA_NEW.to_string(), 3000),
+    ]);
+    // A version that prefixes another doesn't pick up its history
+    assert_eq!(db.version_history("nodejs", "18.0")?, vec![(SHA2.to_string(), 2000)]);
+    assert_eq!(db.version_history("nodejs", "20.0.0")?, vec![]);
+
+    // The deduplicated entries are untouched
+    assert_eq!(db.version_count(), 0);
+    Ok(())
+}
+
+// ── counts ───────────────────────────────────────────────────────────────────
+
+#[test]
+fn test_version_count_vs_unique_package_count() -> Result<()> {
+    let db = ArchiverDb::open_temporary()?;
+
+    db.insert_if_better(&node("18.0.0", SHA1, 1000))?;
+    db.insert_if_better(&node("20.0.0", SHA2, 2000))?;
+    db.insert_if_better(&PackageEntry::new("python312".to_string(), "3.12.1".to_string(), SHA1.to_string(), 1000))?;
+
+    assert_eq!(db.version_count(), 3, "three (attr_name, version) entries");
+    assert_eq!(db.unique_package_count(), 2, "two distinct packages: nodejs, python312");
+    Ok(())
+}
+
+#[test]
+fn test_package_names_lists_each_name_once_sorted() -> Result<()> {
+    let db = ArchiverDb::open_temporary()?;
+    let pkg = |name: &str, ver: &str| PackageEntry::new(name.to_string(), ver.to_string(), SHA1.to_string(), 1000);
+
+    // "foo-bar" and "foo.baz" sort between keys of "foo" and the next name
+    for (name, ver) in [("foo", "1.0"), ("foo", "2.0"), ("foo-bar", "1.0"), ("foo.baz", "1.0"), ("fooa", "1.0"), ("bar", "1")] {
+        db.insert_if_better(&pkg(name, ver))?;
+    }
+    db.insert_if_better(&pkg("foo", "3.0").with_platform("x86_64-linux".to_string(), None))?;
+
+    assert_eq!(db.package_names()?, vec!["bar", "foo", "foo-bar", "foo.baz", "fooa"]);
+    assert_eq!(db.unique_package_count(), 5);
+    Ok(())
+}
+
+// ── normalized version keys ──────────────────────────────────────────────────
+
+#[test]
+fn test_raw_keys_keep_v_prefixed_variants_apart() -> Result<()> {
+    let db = ArchiverDb::open_temporary()?;
+
+    db.insert_if_better(&node("v1.2.3", SHA1, 1000))?;
+    db.insert_if_better(&node("1.2.3", SHA2, 2000))?;
+
+    assert!(!db.uses_normalized_keys());
+    assert_eq!(db.version_count(), 2);
+    assert_eq!(db.get("nodejs", "v1.2.3")?.unwrap().commit_sha, SHA1);
+    assert_eq!(db.get("nodejs", "1.2.3")?.unwrap().commit_sha, SHA2);
+    Ok(())
+}
+
+#[test]
+fn test_normalized_keys_merge_variants_and_keep_raw_version() -> Result<()> {
+    let mut db = ArchiverDb::open_temporary()?;
+
+    db.insert_if_better(&node("1.2.3", SHA_OLD, 1000))?;
+    db.insert_if_better(&node("v1.2.3", SHA_NEW, 2000))?;
+    db.insert_if_better(&node("2.0.0", SHA_OLD, 1000))?;
+
+    // Re-keying merges the variants, keeping the newer entry's raw version
+    assert_eq!(db.enable_normalized_keys()?, 1);
+    assert!(db.uses_normalized_keys());
+    assert_eq!(db.version_count(), 2);
+    let merged = db.get("nodejs", "1.2.3")?.unwrap();
+    assert_eq!(merged.version, "v1.2.3");
+    assert_eq!(merged.commit_sha, SHA_NEW);
+    assert_eq!(db.get("nodejs", "v1.2.3")?, Some(merged.clone()));
+    assert_eq!(db.get_ci("NodeJS", "V1.2.3")?, Some(merged));
+
+    // New variants land on the same key; an older commit doesn't replace it
+    db.insert_if_better(&node("1.2.3-RELEASE", SHA1, 1500))?;
+    assert_eq!(db.version_count(), 2);
+    assert_eq!(db.get("nodejs", "1.2.3")?.unwrap().version, "v1.2.3");
+    // SHA_OLD is still referenced by 2.0.0
+    assert_eq!(db.all_unique_commits()?, vec![SHA_OLD.to_string(), SHA_NEW.to_string()]);
+    Ok(())
+}
+
+// ── case-insensitive lookup ──────────────────────────────────────────────────
+
+#[test]
+fn test_get_ci_matches_mixed_case_attr_name() -> Result<()> {
+    let db = ArchiverDb::open_temporary()?;
+
+    let entry = node("20.0.0", SHA1, 1000);
+    db.insert_if_better(&entry)?;
+
+    // Exact lookup is case-sensitive
+    assert_eq!(db.get("NodeJS", "20.0.0")?, None);
+    // Fallback finds it
+    assert_eq!(db.get_ci("NodeJS", "20.0.0")?, Some(entry.clone()));
+    assert_eq!(db.get_ci("nodejs", "20.0.0")?, Some(entry));
+    // Version must still match
+    assert_eq!(db.get_ci("NODEJS", "21.0.0")?, None);
+    Ok(())
+}
+
+#[test]
+fn test_get_ci_finds_per_platform_entries() -> Result<()> {
+    let db = ArchiverDb::open_temporary()?;
+
+    let entry = PackageEntry::new("vscode-extensions.ms-python.python".to_string(), "1.0".to_string(), SHA1.to_string(), 1000)
+        .with_platform("x86_64-linux".to_string(), None);
+    db.insert_if_better(&entry)?;
+    db.insert_if_better(&node("1.0", SHA2, 2000))?;
+
+    assert_eq!(db.get_ci("Vscode-Extensions.MS-Python.Python", "1.0")?, Some(entry));
+    assert_eq!(db.get_ci("Vscode-Extensions.ms-python.python", "2.0")?, None);
+    Ok(())
+}
+
+// ── provenance ───────────────────────────────────────────────────────────────
+
+#[test]
+fn test_index_runs_are_appended_in_order() -> Result<()> {
+    use archiver_core::IndexRun;
+
+    let run = |from: &str, head_only: bool| IndexRun {
+        started_at: 1700000000,
+        tool_version: "0.1.0".to_string(),
+        command_line: vec!["nix-archiver".to_string(), "index".to_string()],
+        repo_path: "/src/nixpkgs".to_string(),
+        repo_url: Some("https://github.com/NixOS/nixpkgs".to_string()),
+        from_commit: from.to_string(),
+        to_commit: None,
+        to_date: None,
+        max_commits: None,
+        head_only,
+        version_regex: r#"version\s*=\s*"([^"]+)""#.to_string(),
+        path_filter: "pkgs/**/*.nix".to_string(),
+        attr_map: None,
+        only: Vec::new(),
+        commit_path: None,
+        skipped_versions: Vec::new(),
+        first_parent: false,
+    };
+
+    let db = ArchiverDb::open_temporary()?;
+    assert!(db.index_runs()?.is_empty());
+    db.record_index_run(&run(SHA1, true))?;
+    db.record_index_run(&run(SHA2, false))?;
+
+    // Oldest first
+    assert_eq!(db.index_runs()?, vec![run(SHA1, true), run(SHA2, false)]);
+    Ok(())
+}
+
+// ── NAR hashes ───────────────────────────────────────────────────────────────
+
+#[test]
+fn test_nar_hash_round_trips_and_is_normalized() -> Result<()> {
+    let db = ArchiverDb::open_temporary()?;
+
+    let hash = "sha256-47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=";
+    db.insert_if_better(&node("20.0.0", SHA1, 1000).with_nar_hash(hash.to_string()))?;
+    db.insert_if_better(&node("18.0.0", SHA1, 1000))?;
+
+    assert_eq!(db.get("nodejs", "20.0.0")?.unwrap().nar_hash.as_deref(), Some(hash));
+    assert_eq!(db.get("nodejs", "18.0.0")?.unwrap().nar_hash, None);
+
+    // Nix base32 and hex encodings of the same digest are stored as SRI
+    let nix32 = "sha256:0mdqa9w1p6cmli6976v4wi0sw9r4p5prkj7lzfd1877wk11c9c73";
+    let hex = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+    db.insert_if_better(&node("21.0.0", SHA1, 1000).with_nar_hash(nix32.to_string()))?;
+    db.insert_if_better(&node("22.0.0", SHA1, 1000).with_nar_hash(hex.to_string()))?;
+    assert_eq!(db.get("nodejs", "21.0.0")?.unwrap().nar_hash.as_deref(), Some(hash));
+    assert_eq!(db.get("nodejs", "22.0.0")?.unwrap().nar_hash.as_deref(), Some(hash));
+
+    // A placeholder hash doesn't cost the entry, it is stored without a hash
+    let unknown = node("23.0.0", SHA1, 1000).with_nar_hash("unknown".to_string());
+    assert!(db.insert_if_better(&unknown)?);
+    assert_eq!(db.get("nodejs", "23.0.0")?.unwrap().nar_hash, None);
+    Ok(())
+}
+
+// ── main program ─────────────────────────────────────────────────────────────
+
+#[test]
+fn test_find_by_main_program() -> Result<()> {
+    let db = ArchiverDb::open_temporary()?;
+
+    let rg = |name: &str, ver: &str, ts: u64| {
+        PackageEntry::new(name.to_string(), ver.to_string(), SHA1.to_string(), ts)
+            .with_main_program("rg".to_string())
+    };
+    db.insert_if_better(&rg("ripgrep", "13.0.0", 1000))?;
+    db.insert_if_better(&rg("ripgrep", "14.1.0", 2000))?;
+    db.insert_if_better(&rg("ripgrep-all", "0.10.6", 1500))?;
+    db.insert_if_better(&node("20.0.0", SHA1, 1000).with_main_program("node".to_string()))?;
+    db.insert_if_better(&node("18.0.0", SHA1, 1000))?;
+
+    let found: Vec<_> = db.find_by_main_program("rg")?
+        .into_iter()
+        .map(|e| (e.attr_name, e.version))
+        .collect();
+    assert_eq!(found, vec![
+        ("ripgrep".to_string(), "14.1.0".to_string()),
+        ("ripgrep".to_string(), "13.0.0".to_string()),
+        ("ripgrep-all".to_string(), "0.10.6".to_string()),
+    ]);
+    assert_eq!(db.get("nodejs", "18.0.0")?.unwrap().main_program, None);
+    assert!(db.find_by_main_program("missing")?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_search_by_description_matches_substring_ignoring_case() -> Result<()> {
+    let db = ArchiverDb::open_temporary()?;
+    let pkg = |name: &str, ver: &str, ts: u64, description: &str| {
+        PackageEntry::new(name.to_string(), ver.to_string(), SHA1.to_string(), ts)
+            .with_description(description.to_string())
+    };
+    db.insert_if_better(&pkg("cmark", "0.30.0", 1000, "CommonMark Markdown parser"))?;
+    db.insert_if_better(&pkg("cmark", "0.31.0", 2000, "CommonMark Markdown parser and renderer"))?;
+    db.insert_if_better(&pkg("ripgrep", "14.1.0", 1500, "Fast line-oriented search tool"))?;
+    db.insert_if_better(&node("20.0.0", SHA1, 1000))?;
+
+    let found: Vec<_> = db.search_by_description("markdown PARSER")?
+        .into_iter()
+        .map(|e| (e.attr_name, e.version))
+        .collect();
+    assert_eq!(found, vec![
+        ("cmark".to_string(), "0.31.0".to_string()),
+        ("cmark".to_string(), "0.30.0".to_string()),
+    ]);
+    assert!(db.search_by_description("compiler")?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_source_rev_round_trips() -> Result<()> {
+    let db = ArchiverDb::open_temporary()?;
+    db.insert_if_better(&node("20.0.0", SHA1, 1000).with_source_rev("abc123".to_string()))?;
+    db.insert_if_better(&node("18.0.0", SHA1, 1000))?;
+
+    assert_eq!(db.get("nodejs", "20.0.0")?.unwrap().source_rev.as_deref(), Some("abc123"));
+    assert_eq!(db.get("nodejs", "18.0.0")?.unwrap().source_rev, None);
+    Ok(())
+}
+
+#[test]
+fn test_source_path_round_trips() -> Result<()> {
+    let db = ArchiverDb::open_temporary()?;
+    let entry = node("20.0.0", SHA1, 1000).with_source_path("pkgs/development/web/nodejs/v20.nix".to_string(), true);
+    db.insert_if_better(&entry)?;
+    db.insert_if_better(&node("18.0.0", SHA1, 1000))?;
+
+    assert_eq!(db.get("nodejs", "20.0.0")?, Some(entry));
+    let old = db.get("nodejs", "18.0.0")?.unwrap();
+    assert_eq!((old.source_path, old.attr_from_path), (None, false));
+    Ok(())
+}
+
+#[test]
+fn test_rename_entries_moves_keys_and_merges_newest() -> Result<()> {
+    let db = ArchiverDb::open_temporary()?;
+    let named = |name: &str, ver: &str, sha: &str, ts: u64| {
+        PackageEntry::new(name.to_string(), ver.to_string(), sha.to_string(), ts)
+    };
+    db.insert_if_better(&named("a", "1.0", SHA_OLD, 1000))?;
+    db.insert_if_better(&named("b", "1.0", SHA_NEW, 2000))?;
+    db.insert_if_better(&named("b", "2.0", SHA_NEW, 2000))?;
+    db.insert_if_better(&named("c", "1.0", SHA1, 500))?;
+
+    // a → b and b → c in one pass: a's entry must not be swept along to c
+    let (renamed, merged) = db.rename_entries(|e| match e.attr_name.as_str() {
+        "a" => Some("b".to_string()),
+        "b" => Some("c".to_string()),
+        _ => None,
+    })?;
+    assert_eq!((renamed, merged), (3, 1));
+    assert_eq!(db.get("a", "1.0")?, None);
+    assert_eq!(db.get("b", "1.0")?.unwrap().commit_sha, SHA_OLD);
+    // Merged with the existing c 1.0: the newer commit wins
+    assert_eq!(db.get("c", "1.0")?.unwrap().commit_sha, SHA_NEW);
+    assert!(db.get("c", "2.0")?.is_some());
+    assert_eq!(db.version_count(), 3);
+
+    // SHA1 is no longer referenced by any entry
+    assert_eq!(db.all_unique_commits()?.len(), 2);
+    Ok(())
+}
+
+#[test]
+fn test_version_counts_sorted_by_count_then_name() -> Result<()> {
+    let db = ArchiverDb::open_temporary()?;
+
+    let entry = |name: &str, ver: &str| PackageEntry::new(name.to_string(), ver.to_string(), SHA1.to_string(), 1000);
+    for ver in ["6.1", "6.6", "6.12"] {
+        db.insert_if_better(&entry("linux", ver))?;
+    }
+    db.insert_if_better(&entry("zlib", "1.3"))?;
+    db.insert_if_better(&entry("zlib", "1.2.13"))?;
+    db.insert_if_better(&entry("go", "1.22.0"))?;
+    db.insert_if_better(&entry("go", "1.21.5"))?;
+
+    assert_eq!(db.version_counts()?, vec![
+        ("linux".to_string(), 3),
+        ("go".to_string(), 2),
+        ("zlib".to_string(), 2),
+    ]);
+    Ok(())
+}
+
+#[test]
+fn test_per_platform_versions_count_once_and_limit_by_version() -> Result<()> {
+    let db = ArchiverDb::open_temporary()?;
+
+    const RUFF: &str = "vscode-extensions.charliermarsh.ruff";
+    let systems = ["aarch64-darwin", "aarch64-linux", "x86_64-darwin", "x86_64-linux"];
+    for (ver, ts) in [("2026.30.0", 1000), ("2026.32.0", 2000), ("2026.36.0", 3000)] {
+        for system in systems {
+            db.insert_if_better(
+                &PackageEntry::new(RUFF.to_string(), ver.to_string(), SHA1.to_string(), ts)
+                    .with_platform(system.to_string(), None),
+            )?;
+        }
+    }
+    db.insert_if_better(&PackageEntry::new("zlib".to_string(), "1.3".to_string(), SHA1.to_string(), 1000))?;
+
+    assert_eq!(db.count_versions_by_prefix("vscode-extensions")?.get(RUFF), Some(&3));
+    assert_eq!(db.version_counts()?, vec![(RUFF.to_string(), 3), ("zlib".to_string(), 1)]);
+
+    // The limit counts versions; each comes with all its platforms
+    let top: Vec<_> = db.top_versions(RUFF, 2)?
+        .into_iter()
+        .map(|e| (e.version, e.platform.unwrap()))
+        .collect();
+    let expected: Vec<_> = ["2026.36.0", "2026.32.0"]
+        .iter()
+        .flat_map(|ver| systems.map(|system| (ver.to_string(), system.to_string())))
+        .collect();
+    assert_eq!(top, expected);
+    Ok(())
+}
+
+#[test]
+fn test_version_at_date() -> Result<()> {
+    let db = ArchiverDb::open_temporary()?;
+
+    db.insert_if_better(&node("18.0.0", SHA1, 1000))?;
+    db.insert_if_better(&node("20.0.0", SHA2, 2000))?;
+    db.insert_if_better(&node("22.0.0", SHA1, 3000))?;
+
+    let at = |ts| db.version_at_date("nodejs", ts).map(|e| e.map(|e| e.version));
+    assert_eq!(at(1500)?.as_deref(), Some("18.0.0"));
+    assert_eq!(at(2000)?.as_deref(), Some("20.0.0"), "a commit at exactly the date counts");
+    assert_eq!(at(9999)?.as_deref(), Some("22.0.0"));
+    assert_eq!(at(999)?, None, "before the package existed");
+    assert_eq!(db.version_at_date("missing", 9999)?, None);
+    Ok(())
+}
+
+// ── per-platform entries ─────────────────────────────────────────────────────
+
+#[test]
+fn test_per_platform_entries_are_keyed_and_deduplicated_separately() -> Result<()> {
+    let db = ArchiverDb::open_temporary()?;
+
+    let linux_hash = "sha256-47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=";
+    let darwin_hash = "sha256-1t4a7U6pIWDlsXQlsAfAa+u9uS999tngBaykwy22Vx8=";
+    let ruff = |platform: &str, hash: &str, sha: &str, ts: u64| {
+        PackageEntry::new("vscode-extensions.charliermarsh.ruff".to_string(), "2026.36.0".to_string(), sha.to_string(), ts)
+            .with_platform(platform.to_string(), Some(hash.to_string()))
+    };
+    db.insert_if_better(&ruff("x86_64-linux", linux_hash, SHA1, 1000))?;
+    db.insert_if_better(&ruff("aarch64-darwin", darwin_hash, SHA1, 1000))?;
+    // A newer commit only replaces the same platform
+    db.insert_if_better(&ruff("x86_64-linux", linux_hash, SHA2, 2000))?;
+
+    let platforms: Vec<_> = db.get_platforms("vscode-extensions.charliermarsh.ruff", "2026.36.0")?
+        .into_iter()
+        .map(|e| (e.platform.unwrap(), e.source_hash.unwrap(), e.commit_sha))
+        .collect();
+    assert_eq!(platforms, vec![
+        ("aarch64-darwin".to_string(), darwin_hash.to_string(), SHA1.to_string()),
+        ("x86_64-linux".to_string(), linux_hash.to_string(), SHA2.to_string()),
+    ]);
+
+    // Version lookups still find the package
+    let entry = db.get("vscode-extensions.charliermarsh.ruff", "2026.36.0")?.expect("version should resolve");
+    assert_eq!(entry.platform.as_deref(), Some("aarch64-darwin"));
+    assert_eq!(db.get_all_versions("vscode-extensions.charliermarsh.ruff")?.len(), 2);
+    assert!(db.get_platforms("nodejs", "20.0.0")?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_epoch_versions_round_trip_and_sort_newest_first() -> Result<()> {
+    let db = ArchiverDb::open_temporary()?;
+    db.insert_if_better(&PackageEntry::new("tzdata".to_string(), "0:9.9".to_string(), SHA1.to_string(), 1000))?;
+    db.insert_if_better(&PackageEntry::new("tzdata".to_string(), "1:1.0".to_string(), SHA2.to_string(), 2000))?;
+    // Per-platform entries of version "1" share the "tzdata:1:" key prefix
+    db.insert_if_better(
+        &PackageEntry::new("tzdata".to_string(), "1".to_string(), SHA1.to_string(), 1000)
+            .with_platform("x86_64-linux".to_string(), None),
+    )?;
+
+    let entry = db.get("tzdata", "1:1.0")?.expect("epoch version should round-trip");
+    assert_eq!((entry.version.as_str(), entry.commit_sha.as_str()), ("1:1.0", SHA2));
+    assert_eq!(entry.platform, None);
+
+    let platforms = db.get_platforms("tzdata", "1")?;
+    assert_eq!(platforms.len(), 1, "version 1:1.0 is not a platform of version 1");
+    assert_eq!(platforms[0].platform.as_deref(), Some("x86_64-linux"));
+
+    let newest: Vec<_> = db.top_versions("tzdata", 3)?.into_iter().map(|e| e.version).collect();
+    assert_eq!(newest[0], "1:1.0");
+    assert_eq!(db.count_versions_by_prefix("tzdata")?.get("tzdata"), Some(&3));
+    Ok(())
+}
+
+// ── temporary database ───────────────────────────────────────────────────────
+
+#[test]
+fn test_temporary_databases_are_independent_and_report_no_size() -> Result<()> {
+    let first = ArchiverDb::open_temporary()?;
+    let second = ArchiverDb::open_temporary()?;
+    first.insert_if_better(&node("20.0.0", SHA1, 1000))?;
+    first.flush()?;
+
+    assert!(first.get("nodejs", "20.0.0")?.is_some());
+    assert!(second.is_empty()?);
+    assert_eq!(first.db_size_bytes(), 0);
+    assert_eq!(first.schema_version()?, archiver_db::SCHEMA_VERSION);
+    Ok(())
+}
+
+// ── diff ─────────────────────────────────────────────────────────────────────
+
+#[test]
+fn test_diff_reports_missing_and_changed_entries() -> Result<()> {
+    let before = ArchiverDb::open_temporary()?;
+    let after = ArchiverDb::open_temporary()?;
+    before.insert_if_better(&node("18.0.0", SHA1, 1000))?;
+    before.insert_if_better(&node("20.0.0", SHA1, 1000))?;
+    after.insert_if_better(&node("20.0.0", SHA2, 1000))?;
+    after.insert_if_better(&node("22.0.0", SHA1, 1000))?;
+    for db in [&before, &after] {
+        db.insert_if_better(&PackageEntry::new("git".to_string(), "2.40.0".to_string(), SHA1.to_string(), 1000))?;
+    }
+
+    let mut diffs = Vec::new();
+    let unchanged = before.diff(&after, |diff| diffs.push(diff))?;
+    assert_eq!(unchanged, 1);
+    assert_eq!(diffs, vec![
+        EntryDiff::OnlyHere(node("18.0.0", SHA1, 1000)),
+        EntryDiff::CommitChanged { here: node("20.0.0", SHA1, 1000), there: node("20.0.0", SHA2, 1000) },
+        EntryDiff::OnlyThere(node("22.0.0", SHA1, 1000)),
+    ]);
+
+    // A database never differs from itself
+    assert_eq!(after.diff(&after, |_| panic!("unexpected difference"))?, 3);
+    Ok(())
+}
+
+// ── opening for reading ──────────────────────────────────────────────────────
+
+#[test]
+fn test_open_for_reading_uses_snapshot_while_locked() -> Result<()> {
+    let tmp = TempDir::new()?;
+    let db = ArchiverDb::open(tmp.path())?;
+    db.insert_if_better(&node("20.0.0", SHA1, 1000))?;
+    db.flush()?;
+
+    // A second read-write open fails on sled's lock, and says so...
+    let err = ArchiverDb::open(tmp.path()).err().expect("second open should fail");
+    assert!(err.to_string().contains("already open by another nix-archiver process"), "got: {:#}", err);
+
+    // ...but opening for reading reads a snapshot of the flushed state
+    let reader = ArchiverDb::open_for_reading(tmp.path())?;
+    assert!(reader.is_snapshot());
+    assert_eq!(reader.get("nodejs", "20.0.0")?.unwrap().commit_sha, SHA1);
+
+    // Writes through the snapshot never reach the real database
+    reader.insert_if_better(&node("21.0.0", SHA1, 2000))?;
+    drop(reader);
+    assert!(db.get("nodejs", "21.0.0")?.is_none());
+    Ok(())
+}
+
+#[test]
+fn test_open_for_reading_locks_free_database_without_upgrading_it() -> Result<()> {
+    let tmp = TempDir::new()?;
+    let reader = ArchiverDb::open_for_reading(tmp.path())?;
+    assert!(!reader.is_snapshot());
+    // No schema record is written on the reading path
+    assert!(reader.schema_version().is_err());
+
+    // The reader holds sled's lock like any other handle
+    assert!(ArchiverDb::open(tmp.path()).is_err());
+    Ok(())
+}
+
+// ── compaction ───────────────────────────────────────────────────────────────
+
+#[test]
+fn test_compact_keeps_every_tree_and_releases_the_database() -> Result<()> {
+    let tmp = TempDir::new()?;
+    let path = tmp.path().join("db");
+    let db = ArchiverDb::open(&path)?;
+    for ts in 0..200 {
+        db.insert_if_better(&node("20.0.0", SHA1, 1000 + ts))?;
+    }
+    db.mark_commit_processed(SHA1, 1000)?;
+    db.store_tarball_hash(SHA1, "0abc")?;
+
+    let (before, after) = db.compact()?;
+    assert!(before > 0 && after > 0);
+    assert!(!tmp.path().join("db.compacting").exists());
+    assert!(!tmp.path().join("db.pre-compact").exists());
+
+    let db = ArchiverDb::open(&path)?;
+    assert_eq!(db.get("nodejs", "20.0.0")?.unwrap().timestamp, 1199);
+    assert!(db.is_commit_processed(SHA1)?);
+    assert_eq!(db.get_tarball_hash(SHA1)?.as_deref(), Some("0abc"));
+    assert_eq!(db.schema_version()?, archiver_db::SCHEMA_VERSION);
+    Ok(())
+}
+
+#[test]
+fn test_compact_rejects_temporary_database() -> Result<()> {
+    assert!(ArchiverDb::open_temporary()?.compact().is_err());
+    Ok(())
+}
+
+// ── schema version ───────────────────────────────────────────────────────────
+
+#[test]
+fn test_new_database_records_current_schema_version() -> Result<()> {
+    let db = ArchiverDb::open_temporary()?;
+    assert_eq!(db.schema_version()?, archiver_db::SCHEMA_VERSION);
+    Ok(())
+}
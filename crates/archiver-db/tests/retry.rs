@@ -0,0 +1,43 @@
+use archiver_db::retry::retry_on_io_error;
+use std::cell::Cell;
+use std::io;
+use std::time::Duration;
+
+const NO_WAIT: [Duration; 3] = [Duration::ZERO; 3];
+
+fn io_error() -> sled::Error {
+    sled::Error::Io(io::Error::other("No space left on device"))
+}
+
+#[test]
+fn test_transient_io_error_is_retried_until_success() {
+    let calls = Cell::new(0);
+    let result = retry_on_io_error("flush", &NO_WAIT, || {
+        calls.set(calls.get() + 1);
+        if calls.get() < 3 { Err(io_error()) } else { Ok(42) }
+    });
+    assert_eq!(result.unwrap(), 42);
+    assert_eq!(calls.get(), 3);
+}
+
+#[test]
+fn test_persistent_io_error_fails_after_every_retry() {
+    let calls = Cell::new(0);
+    let result: sled::Result<()> = retry_on_io_error("flush", &NO_WAIT, || {
+        calls.set(calls.get() + 1);
+        Err(io_error())
+    });
+    assert!(matches!(result, Err(sled::Error::Io(_))));
+    assert_eq!(calls.get(), NO_WAIT.len() + 1);
+}
+
+#[test]
+fn test_non_io_error_is_not_retried() {
+    let calls = Cell::new(0);
+    let result: sled::Result<()> = retry_on_io_error("flush", &NO_WAIT, || {
+        calls.set(calls.get() + 1);
+        Err(sled::Error::Unsupported("old format".to_string()))
+    });
+    assert!(matches!(result, Err(sled::Error::Unsupported(_))));
+    assert_eq!(calls.get(), 1);
+}
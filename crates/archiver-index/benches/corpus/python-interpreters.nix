@@ -0,0 +1,82 @@
+{ __splicedPackages, callPackage, config, darwin, db, lib, makeScopeWithSplicing', pythonPackagesExtensions, stdenv }@args:
+
+(
+  let
+    # Common passthru for all Python interpreters.
+    passthruFun = import ./passthrufun.nix args;
+
+    sources = {
+      python311 = {
+        sourceVersion = {
+          major = "3";
+          minor = "11";
+          patch = "14";
+          suffix = "";
+        };
+        hash = "sha256-jT7Y7FyIwclfXlWGEqclRQ0kUoE92tXlj9saU7Egm3g=";
+      };
+    };
+
+  in
+  {
+    python27 = callPackage ./cpython/2.7 {
+      self = __splicedPackages.python27;
+      sourceVersion = {
+        major = "2";
+        minor = "7";
+        patch = "18";
+        suffix = ".8";
+      };
+      hash = "sha256-HUOzu3uJbtd+3GbmGD35KOk/CDlwL4S7hi9jJGRFiqI=";
+      inherit passthruFun;
+    };
+
+    python310 = callPackage ./cpython {
+      self = __splicedPackages.python310;
+      sourceVersion = {
+        major = "3";
+        minor = "10";
+        patch = "19";
+        suffix = "";
+      };
+      hash = "sha256-yG/t3IrvAxo1mPHBp1Vv7cd7Of2ipGnoN3EwTbpBNlE=";
+      inherit passthruFun;
+    };
+
+    python312 = callPackage ./cpython {
+      self = __splicedPackages.python312;
+      sourceVersion = {
+        major = "3";
+        minor = "12";
+        patch = "12";
+        suffix = "";
+      };
+      hash = "sha256-+9YIFFhGqR9xVsL5zljxaPYqFnmwHdrqj0i3NJiXUdE=";
+      inherit passthruFun;
+    };
+
+    python313 = callPackage ./cpython {
+      self = __splicedPackages.python313;
+      sourceVersion = {
+        major = "3";
+        minor = "13";
+        patch = "9";
+        suffix = "";
+      };
+      hash = "sha256-7Bo8UPyIHcWiAKNdXN6i44TFvj7OSlj12P9yDiACmp0=";
+      inherit passthruFun;
+    };
+
+    python314 = callPackage ./cpython {
+      self = __splicedPackages.python314;
+      sourceVersion = {
+        major = "3";
+        minor = "14";
+        patch = "0";
+        suffix = "";
+      };
+      hash = "sha256-Kcn3Y/VY+ajE5lGsKuAgFsjpSJaDphD9Aosf4rJu8qM=";
+      inherit passthruFun;
+    };
+  }
+)
@@ -0,0 +1,36 @@
+{ lib, rustPlatform, fetchFromGitHub, installShellFiles, pkg-config, withPCRE2 ? true, pcre2 }:
+
+rustPlatform.buildRustPackage rec {
+  pname = "ripgrep";
+  version = "14.1.1";
+
+  src = fetchFromGitHub {
+    owner = "BurntSushi";
+    repo = pname;
+    rev = version;
+    hash = "sha256-gyWnahj1A+iXUQlQ1O1H1u7K5euYQOld9qWm99Vjaeg=";
+  };
+
+  cargoHash = "sha256-b+iA8iTYWlczBpNq9eyHrWG8LMU4WPBzaU6pQRht+yE=";
+
+  nativeBuildInputs = [ installShellFiles ] ++ lib.optional withPCRE2 pkg-config;
+  buildInputs = lib.optional withPCRE2 pcre2;
+
+  buildFeatures = lib.optional withPCRE2 "pcre2";
+
+  postInstall = ''
+    installManPage doc/rg.1
+    installShellCompletion --cmd rg complete/rg.{bash,fish} --zsh complete/_rg
+  '';
+
+  doInstallCheck = true;
+
+  meta = {
+    description = "Utility that combines the usability of The Silver Searcher with the raw speed of grep";
+    homepage = "https://github.com/BurntSushi/ripgrep";
+    changelog = "https://github.com/BurntSushi/ripgrep/releases/tag/${version}";
+    license = with lib.licenses; [ unlicense mit ];
+    maintainers = with lib.maintainers; [ globin ma27 zowoq ];
+    mainProgram = "rg";
+  };
+}
@@ -0,0 +1,39 @@
+{ stdenvNoCC, lib, vscode-utils }:
+
+vscode-utils.buildVscodeMarketplaceExtension {
+  mktplcRef =
+    let
+      sources = {
+        "x86_64-linux" = {
+          arch = "linux-x64";
+          hash = "sha256-47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=";
+        };
+        "aarch64-linux" = {
+          arch = "linux-arm64";
+          hash = "sha256-1t4a7U6pIWDlsXQlsAfAa+u9uS999tngBaykwy22Vx8=";
+        };
+        "x86_64-darwin" = {
+          arch = "darwin-x64";
+          hash = "sha256-Ip5Ns7cVkKEiwIyrmyJMBUNjk1EhmTK5hz0O1pFqtE8=";
+        };
+        "aarch64-darwin" = {
+          arch = "darwin-arm64";
+          hash = "sha256-f9Kn0aJEEcfVrzjJLWwVbuLxfSHv8zNYYRaTeVA7d5E=";
+        };
+      };
+    in
+    {
+      name = "ruff";
+      publisher = "charliermarsh";
+      version = "2026.36.0";
+    }
+    // sources.${stdenvNoCC.system};
+
+  meta = {
+    license = lib.licenses.mit;
+    changelog = "https://marketplace.visualstudio.com/items/charliermarsh.ruff/changelog";
+    description = "Visual Studio Code extension with support for the Ruff linter";
+    homepage = "https://github.com/astral-sh/ruff-vscode";
+    platforms = [ "x86_64-linux" "aarch64-linux" "x86_64-darwin" "aarch64-darwin" ];
+  };
+}
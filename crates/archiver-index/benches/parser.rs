@@ -0,0 +1,210 @@
+//! Parser throughput benchmark
+//!
+//! Runs `extract_packages_from_file` over a small corpus of representative
+//! nixpkgs files plus generated large and pathological inputs, and reports
+//! time per file and throughput.
+//!
+//! ```text
+//! cargo bench -p archiver-index --bench parser                       # all cases
+//! cargo bench -p archiver-index --bench parser -- mktplcref          # name filter
+//! cargo bench -p archiver-index --bench parser -- --save-baseline base.txt
+//! cargo bench -p archiver-index --bench parser -- --baseline base.txt
+//! ```
+//!
+//! With `--baseline`, cases more than 25% slower than the saved run are
+//! reported and the benchmark exits non-zero. `NIX_ARCHIVER_BENCH_SECS`
+//! sets the measuring time per case (default 1 second).
+
+use archiver_index::parsers::extract_packages_from_file;
+use regex::Regex;
+use std::collections::HashMap;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+/// Slowdown against the baseline that counts as a regression
+const REGRESSION_THRESHOLD: f64 = 1.25;
+
+/// Time growth for 4x the input above which the scaling check warns
+/// (linear ≈ 4, quadratic ≈ 16)
+const SCALING_LIMIT: f64 = 8.0;
+
+struct Case {
+    name: &'static str,
+    path: &'static str,
+    content: String,
+}
+
+fn corpus() -> Vec<Case> {
+    vec![
+        Case {
+            name: "single-package",
+            path: "pkgs/by-name/ri/ripgrep/package.nix",
+            content: include_str!("corpus/ripgrep.nix").to_string(),
+        },
+        Case {
+            name: "multi-package-python",
+            path: "pkgs/development/interpreters/python/default.nix",
+            content: include_str!("corpus/python-interpreters.nix").to_string(),
+        },
+        Case {
+            name: "mktplcref-per-platform",
+            path: "pkgs/applications/editors/vscode/extensions/charliermarsh.ruff/default.nix",
+            content: include_str!("corpus/ruff-vscode.nix").to_string(),
+        },
+        Case {
+            name: "generated-large",
+            path: "pkgs/top-level/all-packages.nix",
+            content: generated_large(20_000),
+        },
+        Case {
+            name: "pathological-fallback",
+            path: "pkgs/top-level/broken.nix",
+            content: pathological_fallback(2_000),
+        },
+    ]
+}
+
+/// An all-packages.nix-shaped attrset with `n` callPackage bindings
+fn generated_large(n: usize) -> String {
+    let mut content = String::from("{ lib, callPackage, ... }:\n\n{\n");
+    for i in 0..n {
+        content.push_str(&format!(
+            "  package{i} = callPackage ../by-name/pa/package{i} {{\n    version = \"{}.{}.{}\";\n  }};\n\n",
+            i / 100,
+            i % 100,
+            i % 7
+        ));
+    }
+    content.push_str("}\n");
+    content
+}
+
+/// A file rnix rejects (unclosed brace), so the regex fallback runs, full of
+/// callPackage lines without `sourceVersion`: the worst case for the
+/// fallback's look-ahead scan
+fn pathological_fallback(n: usize) -> String {
+    let mut content = String::from("{ callPackage }:\n{\n");
+    for i in 0..n {
+        content.push_str(&format!("  broken{i} = callPackage ./broken{i} {{\n"));
+        for j in 0..19 {
+            content.push_str(&format!("    arg{j} = \"value {j}\";\n"));
+        }
+    }
+    content.push_str("  version = \"1.0\";\n");
+    content
+}
+
+/// Mean time per call of `extract_packages_from_file` on `case`, measured
+/// for at least `budget`; also returns how many packages a call yields
+fn measure(case: &Case, version_regex: &Regex, budget: Duration) -> (Duration, usize) {
+    // Warm-up, and the package count for the report
+    let packages = extract_packages_from_file(case.path, &case.content, version_regex).len();
+
+    let mut iterations: u32 = 0;
+    let start = Instant::now();
+    while start.elapsed() < budget || iterations == 0 {
+        black_box(extract_packages_from_file(
+            black_box(case.path),
+            black_box(&case.content),
+            version_regex,
+        ));
+        iterations += 1;
+    }
+    (start.elapsed() / iterations, packages)
+}
+
+fn main() {
+    let mut filter = None;
+    let mut save_baseline = None;
+    let mut baseline = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            // Passed by `cargo bench` to every bench target
+            "--bench" => {}
+            "--save-baseline" => save_baseline = args.next(),
+            "--baseline" => baseline = args.next(),
+            other if other.starts_with('-') => {
+                eprintln!("unknown option {}", other);
+                std::process::exit(2);
+            }
+            other => filter = Some(other.to_string()),
+        }
+    }
+
+    let budget = std::env::var("NIX_ARCHIVER_BENCH_SECS")
+        .ok()
+        .and_then(|secs| secs.parse::<f64>().ok())
+        .map_or(Duration::from_secs(1), Duration::from_secs_f64);
+    let version_regex = Regex::new(r#"version\s*=\s*"([^"]+)""#).unwrap();
+
+    let previous: HashMap<String, f64> = match &baseline {
+        Some(path) => std::fs::read_to_string(path)
+            .unwrap_or_else(|e| panic!("cannot read baseline {}: {}", path, e))
+            .lines()
+            .filter_map(|line| {
+                let (name, nanos) = line.split_once(' ')?;
+                Some((name.to_string(), nanos.trim().parse().ok()?))
+            })
+            .collect(),
+        None => HashMap::new(),
+    };
+
+    println!("{:<24} {:>10} {:>12} {:>10} {:>9}", "case", "size", "time/file", "MiB/s", "packages");
+    let mut results = Vec::new();
+    let mut regressions = Vec::new();
+    for case in corpus() {
+        if filter.as_deref().is_some_and(|f| !case.name.contains(f)) {
+            continue;
+        }
+        let (per_call, packages) = measure(&case, &version_regex, budget);
+        let mib_per_sec = case.content.len() as f64 / per_call.as_secs_f64() / (1024.0 * 1024.0);
+        let mut line = format!(
+            "{:<24} {:>9}K {:>12.2?} {:>10.1} {:>9}",
+            case.name,
+            case.content.len() / 1024,
+            per_call,
+            mib_per_sec,
+            packages
+        );
+        if let Some(&old) = previous.get(case.name) {
+            let ratio = per_call.as_nanos() as f64 / old;
+            line.push_str(&format!("  {:+.1}% vs baseline", (ratio - 1.0) * 100.0));
+            if ratio > REGRESSION_THRESHOLD {
+                regressions.push(case.name);
+            }
+        }
+        println!("{}", line);
+        results.push((case.name, per_call));
+    }
+
+    // Linear parsers take ~4x as long for 4x the input; quadratic ones ~16x
+    if filter.as_deref().is_none_or(|f| "pathological-fallback".contains(f)) {
+        let small = Case { name: "scaling", path: "pkgs/top-level/broken.nix", content: pathological_fallback(500) };
+        let large = Case { name: "scaling", path: "pkgs/top-level/broken.nix", content: pathological_fallback(2_000) };
+        let growth = measure(&large, &version_regex, budget).0.as_secs_f64()
+            / measure(&small, &version_regex, budget).0.as_secs_f64();
+        println!("\nfallback scaling: 4x input → {:.1}x time", growth);
+        if growth > SCALING_LIMIT {
+            println!("warning: regex fallback looks superlinear (limit {:.0}x)", SCALING_LIMIT);
+        }
+    }
+
+    if let Some(path) = save_baseline {
+        let text: String = results
+            .iter()
+            .map(|(name, per_call)| format!("{} {}\n", name, per_call.as_nanos()))
+            .collect();
+        std::fs::write(&path, text).unwrap_or_else(|e| panic!("cannot write baseline {}: {}", path, e));
+        println!("\nbaseline saved to {}", path);
+    }
+
+    if !regressions.is_empty() {
+        eprintln!(
+            "\nslower than baseline by more than {:.0}%: {}",
+            (REGRESSION_THRESHOLD - 1.0) * 100.0,
+            regressions.join(", ")
+        );
+        std::process::exit(1);
+    }
+}
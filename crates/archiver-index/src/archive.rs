@@ -0,0 +1,93 @@
+//! Content-addressed store of package directory NARs
+//!
+//! With `index --archive-dir DIR` the directory of every indexed package is
+//! also written out as a NAR file, so old sources stay available even if the
+//! upstream repository loses them. Files are keyed by the NAR hash already
+//! stored in the database:
+//!
+//! ```text
+//! DIR/<first 2 hex digits>/<sha256 in hex>.nar
+//! ```
+//!
+//! A tree whose hash is already present is never serialised again, so
+//! unchanged package directories across thousands of commits cost one file.
+//! Writes go to a temporary file in the same directory and are renamed into
+//! place, so an interrupted run never leaves a truncated `.nar` behind.
+
+use anyhow::{Context, Result};
+use archiver_core::hash::parse_sha256;
+use git2::{Repository, Tree};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::nar::write_nar_for_tree;
+
+/// Suffix for temporary files, unique within the process since parallel
+/// commits often share a tree
+static NEXT_TMP: AtomicU64 = AtomicU64::new(0);
+
+/// Directory of `.nar` files keyed by their NAR hash
+#[derive(Debug, Clone)]
+pub struct NarArchive {
+    root: PathBuf,
+}
+
+impl NarArchive {
+    /// Opens (creating if needed) the archive rooted at `root`
+    pub fn open<P: AsRef<Path>>(root: P) -> Result<Self> {
+        let root = root.as_ref().to_path_buf();
+        std::fs::create_dir_all(&root)
+            .with_context(|| format!("Failed to create archive directory {:?}", root))?;
+        Ok(Self { root })
+    }
+
+    /// Directory holding the archive
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Where the NAR with this hash (SRI, Nix base32 or hex) is stored;
+    /// None if the hash can't be decoded
+    pub fn path_for(&self, nar_hash: &str) -> Option<PathBuf> {
+        let hex = data_encoding::HEXLOWER.encode(&parse_sha256(nar_hash)?);
+        Some(self.root.join(&hex[..2]).join(format!("{}.nar", hex)))
+    }
+
+    /// Whether the NAR with this hash is already archived
+    pub fn contains(&self, nar_hash: &str) -> bool {
+        self.path_for(nar_hash).is_some_and(|path| path.exists())
+    }
+
+    /// Writes the NAR of `tree` under `nar_hash` unless it is already
+    /// archived. Returns whether a new file was written.
+    ///
+    /// `nar_hash` must be the hash of `tree` (as computed by
+    /// [`compute_nar_hash_for_tree`](crate::nar::compute_nar_hash_for_tree));
+    /// it is not recomputed here.
+    pub fn store_tree(&self, repo: &Repository, tree: &Tree, nar_hash: &str) -> Result<bool> {
+        let path = self
+            .path_for(nar_hash)
+            .with_context(|| format!("Invalid NAR hash: {}", nar_hash))?;
+        if path.exists() {
+            return Ok(false);
+        }
+        let dir = path.parent().expect("archive paths have a parent");
+        std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {:?}", dir))?;
+
+        let tmp = path.with_extension(format!("nar.tmp-{}-{}", std::process::id(), NEXT_TMP.fetch_add(1, Ordering::Relaxed)));
+        let written = (|| -> Result<()> {
+            let mut out = BufWriter::new(File::create(&tmp)?);
+            write_nar_for_tree(&mut out, repo, tree)?;
+            out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+            std::fs::rename(&tmp, &path)?;
+            Ok(())
+        })();
+        if let Err(e) = written {
+            let _ = std::fs::remove_file(&tmp);
+            return Err(e).with_context(|| format!("Failed to write {:?}", path));
+        }
+        Ok(true)
+    }
+}
@@ -10,7 +10,7 @@ pub(crate) fn format_number(n: usize) -> String {
     let mut result = String::new();
     
     for (i, c) in chars.iter().enumerate() {
-        if i > 0 && (chars.len() - i) % 3 == 0 {
+        if i > 0 && (chars.len() - i).is_multiple_of(3) {
             result.push(',');
         }
         result.push(*c);
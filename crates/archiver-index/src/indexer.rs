@@ -4,9 +4,127 @@ use anyhow::{Context, Result};
 use archiver_db::ArchiverDb;
 use git2::Repository;
 use regex::Regex;
+use std::collections::HashSet;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
+use crate::archive::NarArchive;
+use crate::io_gate::IoGate;
+use crate::parsers::AttrMap;
+use crate::stats::IndexStats;
+
+/// Callback receiving the running totals after each batch
+/// (see [`Indexer::on_progress`])
+pub type ProgressCallback = dyn Fn(&IndexStats) + Send + Sync;
+
+/// Files considered for indexing, as recorded in provenance
+pub const PATH_FILTER: &str = "pkgs/**/*.nix";
+
+/// Pattern the regex fallback parser uses to find a version
+pub const VERSION_PATTERN: &str = r#"version\s*=\s*"([^"]+)""#;
+
+/// Versions skipped by [`Indexer::with_skipped_versions`] unless another
+/// set is given: stand-ins used by templates and in-progress packages
+pub const PLACEHOLDER_VERSIONS: &[&str] = &["0.0.0", "git", "unstable", "dev", "HEAD"];
+
+/// Largest `.nix` file parsed by default (1 MiB); see
+/// [`Indexer::with_max_blob_size`]
+pub const DEFAULT_MAX_BLOB_SIZE: usize = 1024 * 1024;
+
+/// Returns true for files matched by [`PATH_FILTER`]
+pub(crate) fn is_indexed_path(path: &str) -> bool {
+    path.starts_with("pkgs/") && path.ends_with(".nix")
+}
+
+/// Opens the repository at `path`, telling apart the usual setup mistakes
+/// (a wrong path, a file, a plain directory such as an extracted tarball)
+/// instead of passing on libgit2's message.
+fn open_repository(path: &Path) -> Result<Repository> {
+    let err = match Repository::open(path) {
+        Ok(repo) => return Ok(repo),
+        Err(err) => err,
+    };
+    if !path.exists() {
+        anyhow::bail!("Repository path {:?} does not exist", path);
+    }
+    if !path.is_dir() {
+        anyhow::bail!("Repository path {:?} is not a directory; pass the nixpkgs checkout itself", path);
+    }
+    if err.code() == git2::ErrorCode::NotFound {
+        if let Some(root) = Repository::discover(path).ok().and_then(|repo| repo.workdir().map(Path::to_path_buf)) {
+            anyhow::bail!("{:?} is inside the git repository at {:?}; pass the repository root instead", path, root);
+        }
+        anyhow::bail!(
+            "{:?} is a directory but not a git repository (no .git found). \
+             Run `git status` there to check; an extracted tarball has no history to index",
+            path
+        );
+    }
+    Err(err).with_context(|| format!("Failed to open repository at {:?}", path))
+}
+
+/// Options controlling a single indexing run
+#[derive(Debug, Clone)]
+pub struct IndexOptions {
+    /// Maximum number of commits to walk (None = no limit)
+    pub max_commits: Option<usize>,
+
+    /// Number of commits processed in parallel per batch
+    pub batch_size: usize,
+
+    /// Number of batches between database flushes (must be at least 1).
+    /// Lower values bound the work lost on a crash; higher values reduce
+    /// I/O overhead.
+    pub flush_every: usize,
+
+    /// Also flush once this many packages were inserted since the last
+    /// flush (None = batch count only). Bounds unflushed writes when
+    /// batches differ wildly in how many packages they yield.
+    pub flush_entries: Option<usize>,
+
+    /// Only full-scan the starting commit, skipping the history walk
+    pub head_only: bool,
+
+    /// Record the branches and tags pointing at commits (see
+    /// `ArchiverDb::commit_refs`)
+    pub record_refs: bool,
+
+    /// Only process commits that change this file or directory (like
+    /// `git log -- <path>`); other commits are skipped without being
+    /// marked as processed
+    pub commit_path: Option<String>,
+
+    /// Expected number of new commits, used as the progress and ETA
+    /// denominator when `max_commits` is None. Never limits the walk.
+    pub estimated_commits: Option<usize>,
+
+    /// Follow only the first parent of merge commits, like
+    /// `git log --first-parent`: the mainline, not merged side branches
+    pub first_parent: bool,
+
+    /// Leave out this commit and everything reachable from it, like
+    /// `git log <hide>..<from>`: only what is new since an earlier head.
+    /// Unlike a `max_commits` count, this doesn't depend on the walk order.
+    pub hide: Option<String>,
+}
+
+impl Default for IndexOptions {
+    fn default() -> Self {
+        Self {
+            max_commits: None,
+            batch_size: 500,
+            flush_every: 5,
+            flush_entries: None,
+            head_only: false,
+            record_refs: false,
+            commit_path: None,
+            estimated_commits: None,
+            first_parent: false,
+            hide: None,
+        }
+    }
+}
+
 /// Main indexer structure
 pub struct Indexer {
     /// Path to Nixpkgs Git repository
@@ -17,25 +135,235 @@ pub struct Indexer {
     
     /// Regex for extracting versions from Nix files
     pub(crate) version_regex: Arc<Regex>,
+
+    /// User path → attr_name rules, tried before the path heuristic
+    pub(crate) attr_map: Arc<AttrMap>,
+
+    /// Whether the repository is a shallow clone (history is truncated)
+    pub(crate) is_shallow: bool,
+
+    /// Optional bound on concurrent blob reads (None = unbounded)
+    pub(crate) io_gate: Option<Arc<IoGate>>,
+
+    /// Attr names to insert (None = all packages)
+    pub(crate) only: Option<Arc<HashSet<String>>>,
+
+    /// Lowercased versions never inserted (None = insert every version)
+    pub(crate) skipped_versions: Option<Arc<HashSet<String>>>,
+
+    /// Called with the running totals after each batch
+    pub(crate) progress: Option<Arc<ProgressCallback>>,
+
+    /// Where package directories are archived as NAR files (None = off)
+    pub(crate) archive: Option<Arc<NarArchive>>,
+
+    /// Files larger than this many bytes are skipped (None = no limit)
+    pub(crate) max_blob_size: Option<usize>,
+
+    /// Whether package directories are NAR-hashed (and archived)
+    pub(crate) compute_nar: bool,
+
+    /// Whether the subject lines of commits are stored
+    pub(crate) store_subjects: bool,
+
+    /// Whether every (attr, version, commit) occurrence is recorded
+    pub(crate) record_history: bool,
 }
 
 impl Indexer {
     /// Creates a new indexer for the given repository and database
     pub fn new<P: AsRef<Path>>(repo_path: P, db: ArchiverDb) -> Result<Self> {
         // Verify repository exists
-        let repo = Repository::open(repo_path.as_ref())
-            .with_context(|| format!("Failed to open repository at {:?}", repo_path.as_ref()))?;
+        let repo = open_repository(repo_path.as_ref())?;
+        let is_shallow = repo.is_shallow();
         drop(repo); // We'll open it per-thread
         
         // Regex for extracting versions in format: version = "x.y.z"
         // Also supports: pname = "name"; version = "1.2.3";
-        let version_regex = Regex::new(r#"version\s*=\s*"([^"]+)""#)
+        let version_regex = Regex::new(VERSION_PATTERN)
             .context("Failed to compile version regex")?;
 
         Ok(Self {
             repo_path: repo_path.as_ref().to_path_buf(),
             db: Arc::new(db),
             version_regex: Arc::new(version_regex),
+            attr_map: Arc::new(AttrMap::default()),
+            is_shallow,
+            io_gate: None,
+            only: None,
+            skipped_versions: None,
+            progress: None,
+            archive: None,
+            max_blob_size: Some(DEFAULT_MAX_BLOB_SIZE),
+            compute_nar: true,
+            store_subjects: false,
+            record_history: false,
         })
     }
+
+    /// Limits how many threads read blobs from the object database at once.
+    ///
+    /// Useful on network filesystems or with huge packfiles, where many
+    /// parsing threads reading simultaneously thrash the storage. Parsing
+    /// itself still uses the full rayon thread pool.
+    pub fn with_io_concurrency(mut self, limit: usize) -> Self {
+        self.io_gate = Some(Arc::new(IoGate::new(limit)));
+        self
+    }
+
+    /// Applies custom path → attr_name rules (see [`AttrMap`]) to files
+    /// without a `pname`, before the built-in path heuristic.
+    pub fn with_attr_map(mut self, attr_map: AttrMap) -> Self {
+        self.attr_map = Arc::new(attr_map);
+        self
+    }
+
+    /// Inserts only packages whose attr_name is in `attr_names`.
+    ///
+    /// Changed files are still read and parsed; other packages are dropped
+    /// before insertion (and before NAR hashing). A filtered run leaves
+    /// commits unmarked as processed, so a later unfiltered run still
+    /// indexes the packages it skipped.
+    pub fn with_only<I: IntoIterator<Item = String>>(mut self, attr_names: I) -> Self {
+        self.only = Some(Arc::new(attr_names.into_iter().collect()));
+        self
+    }
+
+    /// Never inserts packages whose version is one of `versions` (compared
+    /// case-insensitively), e.g. [`PLACEHOLDER_VERSIONS`].
+    ///
+    /// Such versions name no actual release and would otherwise be archived
+    /// and sorted like real ones. Skipped packages are logged at debug level.
+    pub fn with_skipped_versions<I: IntoIterator<Item = String>>(mut self, versions: I) -> Self {
+        self.skipped_versions = Some(Arc::new(versions.into_iter().map(|v| v.to_lowercase()).collect()));
+        self
+    }
+
+    /// Also writes the directory of every inserted package to `archive` as
+    /// a NAR file keyed by its NAR hash.
+    ///
+    /// Directories already in the archive are not serialised again, but
+    /// every distinct version of every package directory is — expect the
+    /// archive to grow far larger than the database.
+    pub fn with_archive(mut self, archive: NarArchive) -> Self {
+        self.archive = Some(Arc::new(archive));
+        self
+    }
+
+    /// Skips files larger than `max_bytes` before they are decoded, parsed
+    /// or hashed (`None` parses every file). Defaults to
+    /// [`DEFAULT_MAX_BLOB_SIZE`]; package definitions are far smaller, so
+    /// this only bounds the work spent on huge generated files.
+    pub fn with_max_blob_size(mut self, max_bytes: Option<usize>) -> Self {
+        self.max_blob_size = max_bytes;
+        self
+    }
+
+    /// Turns NAR hashing of package directories on or off (on by default).
+    ///
+    /// Hashing reads every blob in the directory of each changed package,
+    /// the heaviest step per file. Without it entries carry no NAR hash,
+    /// which `generate` doesn't need (it pins tarball hashes), and nothing
+    /// is written to an archive set with [`with_archive`](Self::with_archive).
+    pub fn with_nar_hashes(mut self, compute_nar: bool) -> Self {
+        self.compute_nar = compute_nar;
+        self
+    }
+
+    /// Stores the subject line of each commit that inserted or moved an
+    /// entry (off by default), so views can show why a version changed.
+    ///
+    /// Subjects are kept per commit, not per entry, but still add some
+    /// tens of bytes for every commit that touched a package.
+    pub fn with_commit_subjects(mut self, store_subjects: bool) -> Self {
+        self.store_subjects = store_subjects;
+        self
+    }
+
+    /// Also records every commit each version is seen in (off by default),
+    /// next to the deduplicated entries; see
+    /// [`ArchiverDb::version_history`].
+    ///
+    /// Only commits that change a package's file see it, so this is the
+    /// timeline of when a version was written, including reverts that
+    /// bring it back. Expect the history to outgrow the entries many times.
+    pub fn with_version_history(mut self, record_history: bool) -> Self {
+        self.record_history = record_history;
+        self
+    }
+
+    /// Calls `callback` after each batch of the history walk with the
+    /// totals so far (`elapsed_time` is the time since the run started),
+    /// so embedders can show progress without parsing logs.
+    ///
+    /// Runs on the indexing thread between batches; a slow callback delays
+    /// the next batch. Head-only runs have no batches and never call it.
+    pub fn on_progress<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&IndexStats) + Send + Sync + 'static,
+    {
+        self.progress = Some(Arc::new(callback));
+        self
+    }
+
+    /// Database the indexer writes to
+    pub fn db(&self) -> &ArchiverDb {
+        &self.db
+    }
+
+    /// Gives the database back once indexing is done, e.g. to
+    /// [`compact`](ArchiverDb::compact) it; None while it is still shared
+    pub fn into_db(self) -> Option<ArchiverDb> {
+        Arc::into_inner(self.db)
+    }
+
+    /// Pattern used by the regex fallback parser
+    pub fn version_pattern(&self) -> &str {
+        self.version_regex.as_str()
+    }
+
+    /// Returns true if the repository is a shallow clone.
+    ///
+    /// Shallow clones stop at a grafted boundary, so commits older than the
+    /// clone depth are never visited and their versions won't be indexed.
+    pub fn is_shallow(&self) -> bool {
+        self.is_shallow
+    }
+
+    /// Branch HEAD points at (e.g. `master`), or `None` for a detached HEAD.
+    pub fn head_branch(&self) -> Result<Option<String>> {
+        let repo = Repository::open(&self.repo_path)?;
+        if repo.head_detached()? {
+            return Ok(None);
+        }
+        let head = repo.head().context("Repository has no HEAD commit")?;
+        Ok(head.shorthand().map(str::to_string))
+    }
+
+    /// Resolves a user-supplied revision to a full 40-character commit SHA.
+    ///
+    /// Accepts `HEAD`, full or abbreviated SHAs, and anything else
+    /// `git rev-parse` understands (tags like `23.11`, branches like
+    /// `release-24.05`, `origin/master`, relative refs like `HEAD~10`, ...).
+    /// Annotated tags are peeled to the commit they point at.
+    pub fn resolve_commit(&self, rev: &str) -> Result<String> {
+        let rev = rev.trim();
+        if rev.is_empty() {
+            anyhow::bail!("Commit must not be empty");
+        }
+
+        let repo = Repository::open(&self.repo_path)?;
+        let commit = repo
+            .revparse_single(rev)
+            .and_then(|object| object.peel_to_commit())
+            .with_context(|| {
+                if rev.chars().all(|c| c.is_ascii_hexdigit()) && rev.len() < 40 {
+                    format!("Unknown or ambiguous short commit SHA '{}'", rev)
+                } else {
+                    format!("'{}' is not a commit SHA, tag, branch or revision in {:?}", rev, self.repo_path)
+                }
+            })?;
+
+        Ok(commit.id().to_string())
+    }
 }
@@ -0,0 +1,41 @@
+//! Counting semaphore bounding concurrent git object reads
+
+use std::sync::{Condvar, Mutex};
+
+/// Limits how many threads read from the git object database at once,
+/// independently of the rayon thread count used for parsing.
+pub(crate) struct IoGate {
+    available: Mutex<usize>,
+    released: Condvar,
+}
+
+/// Held while reading; returns its permit to the gate on drop.
+pub(crate) struct IoPermit<'a> {
+    gate: &'a IoGate,
+}
+
+impl IoGate {
+    pub(crate) fn new(permits: usize) -> Self {
+        Self {
+            available: Mutex::new(permits.max(1)),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Blocks until a permit is free.
+    pub(crate) fn acquire(&self) -> IoPermit<'_> {
+        let mut available = self.available.lock().unwrap();
+        while *available == 0 {
+            available = self.released.wait(available).unwrap();
+        }
+        *available -= 1;
+        IoPermit { gate: self }
+    }
+}
+
+impl Drop for IoPermit<'_> {
+    fn drop(&mut self) {
+        *self.gate.available.lock().unwrap() += 1;
+        self.gate.released.notify_one();
+    }
+}
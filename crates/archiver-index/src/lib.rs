@@ -5,12 +5,18 @@
 //! - Parsing .nix files for version strings
 //! - Saving results to database with deduplication
 //! - Parallel processing of commits for better performance
+//! - Cloning or updating a remote nixpkgs on demand
+//! - Optionally archiving package directories as NAR files
 
+pub mod archive;
 mod formatting;
 mod indexer;
+mod io_gate;
+pub mod nar;
 pub mod parsers;
 mod processing;
+pub mod remote;
 mod stats;
 
-pub use indexer::Indexer;
-pub use stats::{IndexStats, PackageInfo};
+pub use indexer::{IndexOptions, Indexer, ProgressCallback, DEFAULT_MAX_BLOB_SIZE, PATH_FILTER, PLACEHOLDER_VERSIONS, VERSION_PATTERN};
+pub use stats::{IndexStats, PackageInfo, ParseBreakdown, ReparseStats};
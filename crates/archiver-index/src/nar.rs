@@ -0,0 +1,93 @@
+//! NAR (Nix ARchive) serialisation of git trees
+//!
+//! Produces the same `sha256-<base64>` hash `nix hash path` would report for
+//! a checkout of the directory, without touching the filesystem. The same
+//! serialiser writes the archive itself for `--archive-dir` (see `archive.rs`).
+//!
+//! Format (all strings are length-prefixed, u64 little-endian, zero-padded
+//! to 8 bytes):
+//!   "nix-archive-1" node
+//!   node := "(" "type" ( "regular" ["executable" ""] "contents" <bytes>
+//!                      | "symlink" "target" <target>
+//!                      | "directory" { "entry" "(" "name" <name> "node" node ")" } ) ")"
+
+use anyhow::{Context, Result};
+use git2::{FileMode, ObjectType, Repository, Tree};
+use sha2::{Digest, Sha256};
+use std::io::Write;
+
+/// Computes the SRI NAR hash (`sha256-<base64>`) of a git tree, as if it
+/// were checked out as a directory.
+///
+/// Submodules (gitlinks) have no content in this repository and are left
+/// out, so trees containing them hash differently from a full checkout.
+pub fn compute_nar_hash_for_tree(repo: &Repository, tree: &Tree) -> Result<String> {
+    let mut hasher = Sha256::new();
+    write_nar_for_tree(&mut hasher, repo, tree)?;
+    Ok(format!("sha256-{}", data_encoding::BASE64.encode(&hasher.finalize())))
+}
+
+/// Writes the NAR serialisation of a git tree to `out`; hashing these
+/// bytes gives [`compute_nar_hash_for_tree`].
+pub fn write_nar_for_tree(out: &mut impl Write, repo: &Repository, tree: &Tree) -> Result<()> {
+    write_str(out, b"nix-archive-1")?;
+    write_tree(out, repo, tree)
+}
+
+/// sha256 of raw file bytes in SRI form (`sha256-<base64>`). Unlike the
+/// NAR hash this covers only the file itself, not its directory.
+pub fn content_hash(bytes: &[u8]) -> String {
+    format!("sha256-{}", data_encoding::BASE64.encode(&Sha256::digest(bytes)))
+}
+
+fn write_tree(out: &mut impl Write, repo: &Repository, tree: &Tree) -> Result<()> {
+    // NAR orders entries by raw name bytes; git sorts directories as if
+    // they had a trailing '/', so re-sort.
+    let mut entries: Vec<_> = tree.iter().collect();
+    entries.sort_by(|a, b| a.name_bytes().cmp(b.name_bytes()));
+
+    write_strs(out, &[b"(", b"type", b"directory"])?;
+    for entry in entries {
+        if entry.kind() == Some(ObjectType::Commit) {
+            continue;
+        }
+        write_strs(out, &[b"entry", b"(", b"name", entry.name_bytes(), b"node"])?;
+        match entry.kind() {
+            Some(ObjectType::Tree) => {
+                let subtree = repo.find_tree(entry.id()).context("Failed to read subtree")?;
+                write_tree(out, repo, &subtree)?;
+            }
+            Some(ObjectType::Blob) => {
+                let blob = repo.find_blob(entry.id()).context("Failed to read blob")?;
+                let mode = entry.filemode();
+                if mode == i32::from(FileMode::Link) {
+                    write_strs(out, &[b"(", b"type", b"symlink", b"target", blob.content(), b")"])?;
+                } else {
+                    write_strs(out, &[b"(", b"type", b"regular"])?;
+                    if mode == i32::from(FileMode::BlobExecutable) {
+                        write_strs(out, &[b"executable", b""])?;
+                    }
+                    write_strs(out, &[b"contents", blob.content(), b")"])?;
+                }
+            }
+            _ => anyhow::bail!("Unexpected tree entry kind for {:?}", entry.name()),
+        }
+        write_str(out, b")")?;
+    }
+    write_str(out, b")")?;
+    Ok(())
+}
+
+fn write_strs(out: &mut impl Write, parts: &[&[u8]]) -> std::io::Result<()> {
+    for part in parts {
+        write_str(out, part)?;
+    }
+    Ok(())
+}
+
+fn write_str(out: &mut impl Write, bytes: &[u8]) -> std::io::Result<()> {
+    out.write_all(&(bytes.len() as u64).to_le_bytes())?;
+    out.write_all(bytes)?;
+    let padding = (8 - bytes.len() % 8) % 8;
+    out.write_all(&[0u8; 8][..padding])
+}
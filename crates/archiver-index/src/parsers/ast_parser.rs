@@ -5,6 +5,7 @@ use rowan::ast::AstNode;
 use rnix::ast::{self, AttrpathValue, Expr, Attr, HasEntry, AstToken};
 use rnix::ast::InterpolPart;
 use crate::stats::PackageInfo;
+use super::{AstStrategy, AttrMap, ReadSibling, StrategyReport};
 
 /// Keys that are NOT package names in top-level attribute sets
 const NON_PACKAGE_KEYS: &[&str] = &[
@@ -16,9 +17,10 @@ const NON_PACKAGE_KEYS: &[&str] = &[
 
 // ─── Public entry point ───────────────────────────────────────────────────────
 
-/// Parses a .nix file using rnix AST and returns all packages found.
-/// Returns empty Vec on parse failure (caller should use regex fallback).
-pub fn extract_packages_ast(path: &str, content: &str) -> Vec<PackageInfo> {
+/// Parses a .nix file using rnix AST and returns all packages found, with
+/// the strategy that found them.
+/// Returns None on parse failure or no match (caller should use regex fallback).
+pub fn extract_packages_ast(path: &str, content: &str, attr_map: &AttrMap, read_sibling: ReadSibling) -> Option<(AstStrategy, Vec<PackageInfo>)> {
     let parsed = rnix::Root::parse(content);
 
     if !parsed.errors().is_empty() {
@@ -27,7 +29,7 @@ pub fn extract_packages_ast(path: &str, content: &str) -> Vec<PackageInfo> {
             parsed.errors().len(),
             path
         );
-        return vec![];
+        return None;
     }
 
     let root = parsed.tree();
@@ -37,25 +39,57 @@ pub fn extract_packages_ast(path: &str, content: &str) -> Vec<PackageInfo> {
     let multi = extract_multi_callpackage(root.syntax());
     if !multi.is_empty() {
         log::debug!("[AST] multi-package '{}': {} package(s)", path, multi.len());
-        return multi;
+        return Some((AstStrategy::Multi, multi));
     }
 
-    if let Some(pkg) = extract_mktplcref(root.syntax(), path) {
-        log::debug!("[AST] mktplcRef '{}': {}", path, pkg.attr_name);
-        return vec![pkg];
+    let marketplace = extract_mktplcref(root.syntax(), path, attr_map);
+    if let Some(pkg) = marketplace.first() {
+        log::debug!("[AST] mktplcRef '{}': {} ({} platform(s))", path, pkg.attr_name, marketplace.len());
+        return Some((AstStrategy::Marketplace, marketplace));
     }
 
-    if let Some(pkg) = extract_single_package(root.syntax(), path) {
+    if let Some(pkg) = extract_single_package(root.syntax(), path, attr_map, read_sibling) {
         log::debug!("[AST] single-package '{}': {} v{}", path, pkg.attr_name, pkg.version);
-        return vec![pkg];
+        return Some((AstStrategy::Single, vec![pkg]));
     }
 
-    vec![]
+    None
+}
+
+/// Runs every AST strategy independently, in the order
+/// [`extract_packages_ast`] tries them. Returns the rnix parse errors
+/// instead when the file does not parse cleanly.
+pub fn diagnose_ast(path: &str, content: &str, attr_map: &AttrMap, read_sibling: ReadSibling) -> Result<Vec<StrategyReport>, Vec<String>> {
+    let parsed = rnix::Root::parse(content);
+    if !parsed.errors().is_empty() {
+        return Err(parsed.errors().iter().map(|e| e.to_string()).collect());
+    }
+
+    let root = parsed.tree();
+    let root = root.syntax();
+    Ok(vec![
+        StrategyReport { name: "multi-package (sourceVersion)", packages: extract_sourceversion_packages(root) },
+        StrategyReport { name: "overlay", packages: extract_overlay(root) },
+        StrategyReport { name: "mktplcRef", packages: extract_mktplcref(root, path, attr_map) },
+        StrategyReport {
+            name: "single-package",
+            packages: extract_single_package(root, path, attr_map, read_sibling).into_iter().collect(),
+        },
+    ])
 }
 
 // ─── Strategy 1 – multi-package (callPackage + sourceVersion) ────────────────
 
 fn extract_multi_callpackage(root: &rnix::SyntaxNode) -> Vec<PackageInfo> {
+    let result = extract_sourceversion_packages(root);
+    if result.is_empty() {
+        extract_overlay(root)
+    } else {
+        result
+    }
+}
+
+fn extract_sourceversion_packages(root: &rnix::SyntaxNode) -> Vec<PackageInfo> {
     let mut result = Vec::new();
 
     for node in root.descendants() {
@@ -85,6 +119,69 @@ fn extract_multi_callpackage(root: &rnix::SyntaxNode) -> Vec<PackageInfo> {
             result.push(PackageInfo {
                 attr_name: key,
                 version,
+                main_program: None,
+                description: None,
+                platform: None,
+                source_hash: None,
+                source_rev: None,
+                name_from_path: false,
+            });
+        }
+    }
+
+    result
+}
+
+fn extract_overlay(root: &rnix::SyntaxNode) -> Vec<PackageInfo> {
+    overlay_body(root)
+        .map(|body| extract_overlay_bindings(&body))
+        .unwrap_or_default()
+}
+
+/// Returns the attrset returned by an overlay-shaped file:
+/// `self: super: { … }` / `final: prev: { … }` (optionally via `let … in`).
+fn overlay_body(root: &rnix::SyntaxNode) -> Option<ast::AttrSet> {
+    let expr = ast::Root::cast(root.clone())?.expr()?;
+    let Expr::Lambda(outer) = expr else { return None };
+    if !matches!(outer.param()?, ast::Param::IdentParam(_)) {
+        return None;
+    }
+    let Expr::Lambda(inner) = outer.body()? else { return None };
+    if !matches!(inner.param()?, ast::Param::IdentParam(_)) {
+        return None;
+    }
+    unwrap_to_attrset(inner.body()?)
+}
+
+/// Extracts `name = callPackage … { version = "…"; }` and
+/// `name = prev.name.overrideAttrs (old: { version = "…"; })` bindings
+/// from an overlay body.
+fn extract_overlay_bindings(body: &ast::AttrSet) -> Vec<PackageInfo> {
+    let mut result = Vec::new();
+
+    for kv in body.attrpath_values() {
+        let Some(key) = get_simple_key(&kv) else { continue };
+        if NON_PACKAGE_KEYS.contains(&key.as_str()) || !looks_like_package_name(&key) {
+            continue;
+        }
+
+        let Some(value) = kv.value() else { continue };
+        if !matches!(value, Expr::Apply(_)) {
+            continue;
+        }
+
+        let version = find_sourceversion_in_expr(&value)
+            .or_else(|| find_version_literal_in_expr(&value));
+        if let Some(version) = version {
+            result.push(PackageInfo {
+                attr_name: key,
+                version,
+                main_program: None,
+                description: None,
+                platform: None,
+                source_hash: None,
+                source_rev: None,
+                name_from_path: false,
             });
         }
     }
@@ -92,6 +189,21 @@ fn extract_multi_callpackage(root: &rnix::SyntaxNode) -> Vec<PackageInfo> {
     result
 }
 
+/// Searches within an expression for the first valid `version = "…"` literal.
+fn find_version_literal_in_expr(expr: &Expr) -> Option<String> {
+    for node in expr.syntax().descendants() {
+        let Some(kv) = AttrpathValue::cast(node) else { continue };
+        if get_simple_key(&kv).as_deref() != Some("version") {
+            continue;
+        }
+        let Some(Expr::Str(s)) = kv.value() else { continue };
+        if let Some(v) = get_string_literal(&s).filter(|v| is_valid_version(v)) {
+            return Some(v);
+        }
+    }
+    None
+}
+
 /// Searches within an expression for `sourceVersion = { major=…; minor=…; patch=…; }`
 fn find_sourceversion_in_expr(expr: &Expr) -> Option<String> {
     // Walk the syntax tree of this expression
@@ -132,7 +244,9 @@ fn extract_version_from_attrset_bindings(set: &ast::AttrSet) -> Option<String> {
 
 // ─── Strategy 2 – mktplcRef (VSCode extensions) ──────────────────────────────
 
-fn extract_mktplcref(root: &rnix::SyntaxNode, path: &str) -> Option<PackageInfo> {
+/// One package, or one per system when the extension has per-platform
+/// `sources` (ruff-style), each with that platform's hash
+fn extract_mktplcref(root: &rnix::SyntaxNode, path: &str, attr_map: &AttrMap) -> Vec<PackageInfo> {
     for node in root.descendants() {
         let Some(kv) = AttrpathValue::cast(node) else { continue };
 
@@ -144,6 +258,7 @@ fn extract_mktplcref(root: &rnix::SyntaxNode, path: &str) -> Option<PackageInfo>
         //   { name=…; publisher=…; version=…; } (biome-style)
         //   let sources = {…}; in { name=…; version=…; } // sources.${…} (ruff-style)
         let Some(value) = kv.value() else { continue };
+        let platforms = platform_sources(&value);
         let Some(ref_set) = unwrap_to_attrset(value) else { continue };
 
         // Extract version from the mktplcRef attrset
@@ -157,20 +272,67 @@ fn extract_mktplcref(root: &rnix::SyntaxNode, path: &str) -> Option<PackageInfo>
         // Falls back to path-derived name if publisher/name bindings are absent.
         let publisher = extract_string_binding(&ref_set, "publisher");
         let name = extract_string_binding(&ref_set, "name");
-        let attr_name = match (publisher, name) {
-            (Some(p), Some(n)) => format!("vscode-extensions.{}.{}", p, n),
-            (None, Some(n)) => format!("vscode-extensions.{}", n),
-            _ => path_to_attr_name(path)
-                .or_else(|| find_pname_in_tree(root))?,
+        let (attr_name, name_from_path) = match (publisher, name) {
+            (Some(p), Some(n)) => (format!("vscode-extensions.{}.{}", p, n), false),
+            (None, Some(n)) => (format!("vscode-extensions.{}", n), false),
+            _ => match attr_map.attr_name_for(path) {
+                Some(name) => (name, true),
+                None => match find_pname_in_tree(root) {
+                    Some(name) => (name, false),
+                    None => return Vec::new(),
+                },
+            },
         };
+        let main_program = find_main_program(root, &HashMap::new());
 
-        return Some(PackageInfo {
-            attr_name,
-            version,
-        });
+        if platforms.is_empty() {
+            return vec![PackageInfo { attr_name, version, main_program, description: None, platform: None, source_hash: None, source_rev: None, name_from_path }];
+        }
+        return platforms
+            .into_iter()
+            .map(|(platform, source_hash)| PackageInfo {
+                attr_name: attr_name.clone(),
+                version: version.clone(),
+                main_program: main_program.clone(),
+                description: None,
+                platform: Some(platform),
+                source_hash,
+                source_rev: None,
+                name_from_path,
+            })
+            .collect();
     }
 
-    None
+    Vec::new()
+}
+
+/// Per-system entries of a `sources = { "x86_64-linux" = { hash = …; }; … }`
+/// binding in a `let` around the mktplcRef attrset, in file order
+fn platform_sources(expr: &Expr) -> Vec<(String, Option<String>)> {
+    let Expr::LetIn(let_in) = expr else { return Vec::new() };
+    for kv in let_in.attrpath_values() {
+        if get_simple_key(&kv).as_deref() != Some("sources") {
+            continue;
+        }
+        let Some(Expr::AttrSet(sources)) = kv.value() else { continue };
+        return sources
+            .attrpath_values()
+            .filter_map(|system| {
+                let name = get_simple_key(&system)?;
+                // Only system doubles like x86_64-linux / aarch64-darwin
+                if !name.contains('-') {
+                    return None;
+                }
+                let hash = match system.value() {
+                    Some(Expr::AttrSet(set)) => extract_string_binding(&set, "hash")
+                        .or_else(|| extract_string_binding(&set, "sha256")),
+                    _ => None,
+                };
+                Some((name, hash))
+            })
+            .collect();
+    }
+    Vec::new()
 }
 
 /// Extracts the innermost AttrSet from an expression, handling:
@@ -195,21 +357,29 @@ fn unwrap_to_attrset(expr: Expr) -> Option<ast::AttrSet> {
 
 // ─── Strategy 3 – single package (pname + version) ───────────────────────────
 
-fn extract_single_package(root: &rnix::SyntaxNode, path: &str) -> Option<PackageInfo> {
+fn extract_single_package(root: &rnix::SyntaxNode, path: &str, attr_map: &AttrMap, read_sibling: ReadSibling) -> Option<PackageInfo> {
     // Collect a flat map of all simple string bindings in the file.
     // This gives us major/minor/patch/suffix and similar vars for interpolation.
     let vars = collect_string_vars(root);
 
-    // Determine attr_name: pname binding OR path-based
-    let attr_name = vars.get("pname").cloned()
-        .or_else(|| path_to_attr_name(path))?;
+    // Determine attr_name: pname binding OR path-based (user rules first)
+    let (attr_name, name_from_path) = match vars.get("pname") {
+        Some(pname) => (pname.clone(), false),
+        None => (attr_map.attr_name_for(path)?, true),
+    };
 
     // Determine version
-    let version = resolve_version(root, &vars)?;
+    let version = resolve_version(root, &vars, path, read_sibling)?;
 
     Some(PackageInfo {
         attr_name,
+        main_program: find_main_program(root, &vars),
+        description: find_description(root, &vars),
+        source_rev: find_source_rev(root, &vars, &version),
         version,
+        platform: None,
+        source_hash: None,
+        name_from_path,
     })
 }
 
@@ -231,7 +401,12 @@ fn collect_string_vars(root: &rnix::SyntaxNode) -> HashMap<String, String> {
 }
 
 /// Finds and resolves a `version = …` binding in the file.
-fn resolve_version(root: &rnix::SyntaxNode, vars: &HashMap<String, String>) -> Option<String> {
+fn resolve_version(
+    root: &rnix::SyntaxNode,
+    vars: &HashMap<String, String>,
+    path: &str,
+    read_sibling: ReadSibling,
+) -> Option<String> {
     for node in root.descendants() {
         let Some(kv) = AttrpathValue::cast(node) else { continue };
 
@@ -251,6 +426,7 @@ fn resolve_version(root: &rnix::SyntaxNode, vars: &HashMap<String, String>) -> O
                 }
                 // Might be interpolated: "${major}.${minor}.${patch}"
                 if let Some(v) = resolve_string_interpolation(s, vars) {
+                    let v = strip_pname_prefix(v, vars, path);
                     if is_valid_version(&v) {
                         return Some(v);
                     }
@@ -264,10 +440,38 @@ fn resolve_version(root: &rnix::SyntaxNode, vars: &HashMap<String, String>) -> O
                     }
                 }
             }
+            // version = (lib.importTOML ./Cargo.toml).package.version;
+            // version = (import ./sources.nix).version;
+            Expr::Select(ref select) => {
+                let imported = resolve_imported_toml_version(select, path, read_sibling)
+                    .or_else(|| resolve_imported_nix_version(select, root, path, read_sibling));
+                if let Some(v) = imported {
+                    if is_valid_version(&v) {
+                        return Some(v);
+                    }
+                }
+            }
             _ => {}
         }
     }
 
+    // inherit (import ./sources.nix) version;
+    for node in root.descendants() {
+        let Some(inherit) = ast::Inherit::cast(node) else { continue };
+        let Some(from) = inherit.from().and_then(|from| from.expr()) else { continue };
+        let inherits_version = inherit.attrs().any(|attr| {
+            matches!(attr, Attr::Ident(ident) if ident.ident_token().is_some_and(|t| t.text() == "version"))
+        });
+        if !inherits_version {
+            continue;
+        }
+        if let Some(v) = read_imported_nix_attr(from, &["version".to_string()], root, path, read_sibling) {
+            if is_valid_version(&v) {
+                return Some(v);
+            }
+        }
+    }
+
     // Fallback: assemble version from major/minor/patch vars
     if let (Some(major), Some(minor), Some(patch)) = (
         vars.get("major"), vars.get("minor"), vars.get("patch")
@@ -282,6 +486,19 @@ fn resolve_version(root: &rnix::SyntaxNode, vars: &HashMap<String, String>) -> O
     None
 }
 
+/// Drops a leading `<pname>-` from an interpolated version such as
+/// `"${pname}-2.0"`, which would otherwise be indexed as `foo-2.0`.
+fn strip_pname_prefix(version: String, vars: &HashMap<String, String>, path: &str) -> String {
+    let Some(pname) = vars.get("pname") else { return version };
+    match version.strip_prefix(pname.as_str()).and_then(|rest| rest.strip_prefix('-')) {
+        Some(rest) if is_valid_version(rest) => {
+            log::debug!("[AST] '{}': stripped pname prefix from version '{}'", path, version);
+            rest.to_string()
+        }
+        _ => version,
+    }
+}
+
 /// Resolves `with <ns>; "${var1}.${var2}"` expressions.
 fn resolve_with_expr(with_expr: &ast::With, vars: &HashMap<String, String>) -> Option<String> {
     // Get namespace: if it's an Ident or AttrSet, collect its vars
@@ -306,6 +523,166 @@ fn resolve_with_expr(with_expr: &ast::With, vars: &HashMap<String, String>) -> O
     }
 }
 
+// ─── Imported files ─────────────────────────────────────────────────────────
+
+/// Resolves `package.version` from a sibling TOML file referenced as
+/// `(lib.importTOML ./Cargo.toml).package.version` or
+/// `(builtins.fromTOML (builtins.readFile ./Cargo.toml)).package.version`.
+fn resolve_imported_toml_version(
+    select: &ast::Select,
+    path: &str,
+    read_sibling: ReadSibling,
+) -> Option<String> {
+    let keys = select_keys(select)?;
+    if keys != ["package", "version"] {
+        return None;
+    }
+
+    let Expr::Apply(apply) = strip_parens(select.expr()?) else { return None };
+    let rel_path = match callee_name(&apply.lambda()?)?.as_str() {
+        "importTOML" => rel_path_arg(apply.argument()?)?,
+        "fromTOML" => {
+            let Expr::Apply(read) = strip_parens(apply.argument()?) else { return None };
+            if callee_name(&read.lambda()?)? != "readFile" {
+                return None;
+            }
+            rel_path_arg(read.argument()?)?
+        }
+        _ => return None,
+    };
+
+    let content = read_sibling(&join_relative(path, &rel_path)?)?;
+    let table: toml::Table = content.parse().ok()?;
+    table.get("package")?.get("version")?.as_str().map(str::to_string)
+}
+
+/// Resolves a `version` or `rev` binding from a sibling Nix file that
+/// evaluates to a plain attrset (typically a generated `sources.nix`):
+/// `(import ./sources.nix).version`, `(import ./sources.nix).foo.rev`, or
+/// `sources.version` where the file binds `sources = import ./sources.nix;`.
+fn resolve_imported_nix_version(
+    select: &ast::Select,
+    root: &rnix::SyntaxNode,
+    path: &str,
+    read_sibling: ReadSibling,
+) -> Option<String> {
+    let keys = select_keys(select)?;
+    if !matches!(keys.last()?.as_str(), "version" | "rev") {
+        return None;
+    }
+    read_imported_nix_attr(select.expr()?, &keys, root, path, read_sibling)
+}
+
+/// Follows `keys` through the attrset of the sibling file `expr` imports
+/// (directly, or via a `name = import ./file.nix;` binding in `root`) and
+/// returns the string literal at the end. Files that are functions or
+/// compute the value are out of scope.
+fn read_imported_nix_attr(
+    expr: Expr,
+    keys: &[String],
+    root: &rnix::SyntaxNode,
+    path: &str,
+    read_sibling: ReadSibling,
+) -> Option<String> {
+    let rel_path = match strip_parens(expr) {
+        Expr::Apply(apply) => import_path(&apply)?,
+        Expr::Ident(ident) => {
+            let name = ident.ident_token()?.text().to_string();
+            root.descendants().filter_map(AttrpathValue::cast).find_map(|kv| {
+                if get_simple_key(&kv)? != name {
+                    return None;
+                }
+                let Expr::Apply(apply) = strip_parens(kv.value()?) else { return None };
+                import_path(&apply)
+            })?
+        }
+        _ => return None,
+    };
+
+    // Second parse: the imported blob, read from the same commit tree
+    let content = read_sibling(&join_relative(path, &rel_path)?)?;
+    let parsed = rnix::Root::parse(&content);
+    if !parsed.errors().is_empty() {
+        return None;
+    }
+    let mut set = unwrap_to_attrset(parsed.tree().expr()?)?;
+    let (last, parents) = keys.split_last()?;
+    for key in parents {
+        let kv = set.attrpath_values().find(|kv| get_simple_key(kv).as_deref() == Some(key))?;
+        set = unwrap_to_attrset(kv.value()?)?;
+    }
+    extract_string_binding(&set, last)
+}
+
+/// Relative path of `import ./file.nix`; `None` for other applications
+/// (including `import ./file.nix { … }`, whose callee is itself an apply)
+fn import_path(apply: &ast::Apply) -> Option<String> {
+    if callee_name(&apply.lambda()?)? != "import" {
+        return None;
+    }
+    rel_path_arg(apply.argument()?)
+}
+
+/// Identifier keys of a select's attrpath: `x.package.version` → `[package, version]`
+fn select_keys(select: &ast::Select) -> Option<Vec<String>> {
+    select
+        .attrpath()?
+        .attrs()
+        .map(|attr| match attr {
+            Attr::Ident(ident) => ident.ident_token().map(|t| t.text().to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Unwraps any number of surrounding parentheses.
+fn strip_parens(mut expr: Expr) -> Expr {
+    while let Expr::Paren(paren) = &expr {
+        match paren.expr() {
+            Some(inner) => expr = inner,
+            None => break,
+        }
+    }
+    expr
+}
+
+/// Returns the function name of a callee: `importTOML`, `lib.importTOML`,
+/// `builtins.readFile` → last identifier.
+fn callee_name(expr: &Expr) -> Option<String> {
+    match expr {
+        Expr::Ident(ident) => Some(ident.ident_token()?.text().to_string()),
+        Expr::Select(select) => match select.attrpath()?.attrs().last()? {
+            Attr::Ident(ident) => Some(ident.ident_token()?.text().to_string()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Returns the text of a relative path literal like `./Cargo.toml`.
+fn rel_path_arg(expr: Expr) -> Option<String> {
+    let Expr::PathRel(p) = strip_parens(expr) else { return None };
+    let text = p.syntax().text().to_string();
+    (!text.contains("${")).then_some(text)
+}
+
+/// Joins a relative path onto the directory of a repo-relative file path.
+/// e.g. (`pkgs/by-name/fo/foo/package.nix`, `./Cargo.toml`) → `pkgs/by-name/fo/foo/Cargo.toml`
+pub(crate) fn join_relative(file_path: &str, rel: &str) -> Option<String> {
+    let mut parts: Vec<&str> = file_path.split('/').collect();
+    parts.pop(); // drop file name
+    for component in rel.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                parts.pop()?;
+            }
+            other => parts.push(other),
+        }
+    }
+    Some(parts.join("/"))
+}
+
 // ─── String helpers ─────────────────────────────────────────────────────────
 
 /// Returns the string value if the Str has no interpolations.
@@ -337,6 +714,15 @@ fn resolve_string_interpolation(s: &ast::Str, vars: &HashMap<String, String>) ->
                         let val = vars.get(&name)?;
                         result.push_str(val);
                     }
+                    // ${finalAttrs.pname} / ${self.pname} – same binding as ${pname}
+                    Expr::Select(select) if select.default_expr().is_none() => {
+                        let mut attrs = select.attrpath()?.attrs();
+                        let Some(Attr::Ident(ident)) = attrs.next() else { return None };
+                        if attrs.next().is_some() || ident.ident_token()?.text() != "pname" {
+                            return None;
+                        }
+                        result.push_str(vars.get("pname")?);
+                    }
                     // e.g. ${versions.major} – too complex, skip
                     _ => return None,
                 }
@@ -360,6 +746,99 @@ fn extract_string_binding(set: &ast::AttrSet, key: &str) -> Option<String> {
     None
 }
 
+/// Finds `meta.mainProgram` — either `meta = { mainProgram = "rg"; }` or
+/// `meta.mainProgram = "rg";`. Simple `${pname}` interpolation is resolved.
+fn find_main_program(root: &rnix::SyntaxNode, vars: &HashMap<String, String>) -> Option<String> {
+    for node in root.descendants() {
+        let Some(kv) = AttrpathValue::cast(node) else { continue };
+        let Some(Attr::Ident(last)) = kv.attrpath()?.attrs().last() else { continue };
+        if last.ident_token()?.text() != "mainProgram" {
+            continue;
+        }
+        if let Some(Expr::Str(s)) = kv.value() {
+            let program = get_string_literal(&s)
+                .or_else(|| resolve_string_interpolation(&s, vars))?;
+            return (!program.is_empty()).then_some(program);
+        }
+    }
+    None
+}
+
+/// Finds `meta.description` — `meta = { description = "…"; }` (also under
+/// `with lib;`) or `meta.description = "…";`. Whitespace of multi-line
+/// strings is collapsed; simple `${pname}` interpolation is resolved.
+fn find_description(root: &rnix::SyntaxNode, vars: &HashMap<String, String>) -> Option<String> {
+    for node in root.descendants() {
+        let Some(kv) = AttrpathValue::cast(node) else { continue };
+        let Some(attrpath) = kv.attrpath() else { continue };
+        let names: Vec<String> = attrpath
+            .attrs()
+            .filter_map(|attr| match attr {
+                Attr::Ident(ident) => Some(ident.ident_token()?.text().to_string()),
+                _ => None,
+            })
+            .collect();
+        let in_meta = match names.as_slice() {
+            [meta, key] => meta == "meta" && key == "description",
+            [key] => key == "description" && is_meta_binding(&kv),
+            _ => false,
+        };
+        if !in_meta {
+            continue;
+        }
+        if let Some(Expr::Str(s)) = kv.value() {
+            let text = get_string_literal(&s)
+                .or_else(|| resolve_string_interpolation(&s, vars))?;
+            let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+            return (!text.is_empty()).then_some(text);
+        }
+    }
+    None
+}
+
+/// Whether `kv` sits directly in the attrset bound to `meta`
+fn is_meta_binding(kv: &AttrpathValue) -> bool {
+    let mut node = kv.syntax().parent().filter(|n| ast::AttrSet::can_cast(n.kind()));
+    while let Some(parent) = node.and_then(|n| n.parent()) {
+        if ast::With::can_cast(parent.kind()) || ast::Paren::can_cast(parent.kind()) {
+            node = Some(parent);
+            continue;
+        }
+        return AttrpathValue::cast(parent).is_some_and(|meta| get_simple_key(&meta).as_deref() == Some("meta"));
+    }
+    false
+}
+
+/// Bindings naming the upstream revision a source is fetched from
+const SOURCE_REV_KEYS: &[&str] = &["rev", "srcRev"];
+
+/// Finds the first `rev = "…"` (or `srcRev`) binding, typically inside
+/// `fetchFromGitHub`. Revisions that merely restate the version, such as
+/// `rev = "v${version}"` or `"release-1.2.3"`, carry no extra information
+/// and are skipped.
+fn find_source_rev(root: &rnix::SyntaxNode, vars: &HashMap<String, String>, version: &str) -> Option<String> {
+    for node in root.descendants() {
+        let Some(kv) = AttrpathValue::cast(node) else { continue };
+        let Some(key) = get_simple_key(&kv) else { continue };
+        if !SOURCE_REV_KEYS.contains(&key.as_str()) {
+            continue;
+        }
+        let Some(Expr::Str(s)) = kv.value() else { continue };
+        let Some(rev) = get_string_literal(&s).or_else(|| resolve_string_interpolation(&s, vars)) else { continue };
+        if !rev.is_empty() && !restates_version(&rev, version) {
+            return Some(rev);
+        }
+    }
+    None
+}
+
+/// `1.2.3`, `v1.2.3`, `release-1.2.3`: a tag naming the version itself
+fn restates_version(rev: &str, version: &str) -> bool {
+    rev.strip_suffix(version).is_some_and(|prefix| {
+        prefix.is_empty() || prefix.eq_ignore_ascii_case("v") || prefix.ends_with(['-', '_', '/', '.'])
+    })
+}
+
 /// Walks the root tree to find the first `pname = "…"` binding.
 fn find_pname_in_tree(root: &rnix::SyntaxNode) -> Option<String> {
     for node in root.descendants() {
@@ -0,0 +1,101 @@
+//! User-supplied path → attr_name rules (`--attr-map`).
+//!
+//! [`path_to_attr_name`] guesses the attribute from the parent directory,
+//! which is wrong for layouts it doesn't know about. An attr map lets users
+//! correct systematic misparses without code changes:
+//!
+//! ```toml
+//! [[rule]]
+//! path = '^pkgs/servers/http/(\w+)/server\.nix$'
+//! attr = "$1-server"
+//! ```
+//!
+//! Rules are tried in file order; the first whose `path` regex matches wins.
+//! `attr` may reference capture groups (`$1`, `${name}`). Rules only replace
+//! the path heuristic — an explicit `pname` in the file still takes priority.
+
+use anyhow::{Context, Result};
+use regex::Regex;
+use serde::Deserialize;
+use std::path::Path;
+
+use super::ast_parser::path_to_attr_name;
+
+/// One path-regex → attr-name-template rule
+#[derive(Debug, Clone)]
+pub struct AttrRule {
+    pub path: Regex,
+    pub attr: String,
+}
+
+/// Ordered list of [`AttrRule`]s; empty means "default heuristic only"
+#[derive(Debug, Clone, Default)]
+pub struct AttrMap {
+    rules: Vec<AttrRule>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AttrMapFile {
+    #[serde(default)]
+    rule: Vec<RawRule>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawRule {
+    path: String,
+    attr: String,
+}
+
+impl AttrMap {
+    /// Reads and validates an attr map file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read attr map {:?}", path))?;
+        Self::parse(&text).with_context(|| format!("Invalid attr map {:?}", path))
+    }
+
+    /// Parses attr map rules from TOML text
+    pub fn parse(text: &str) -> Result<Self> {
+        let file: AttrMapFile = toml::from_str(text)?;
+        let rules = file.rule
+            .into_iter()
+            .enumerate()
+            .map(|(i, raw)| {
+                if raw.attr.trim().is_empty() {
+                    anyhow::bail!("rule #{}: attr must not be empty", i + 1);
+                }
+                let path = Regex::new(&raw.path)
+                    .with_context(|| format!("rule #{}: invalid path regex '{}'", i + 1, raw.path))?;
+                Ok(AttrRule { path, attr: raw.attr })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { rules })
+    }
+
+    pub fn rules(&self) -> &[AttrRule] {
+        &self.rules
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Attr name from the first matching rule, with capture groups expanded
+    pub fn apply(&self, path: &str) -> Option<String> {
+        self.rules.iter().find_map(|rule| {
+            let caps = rule.path.captures(path)?;
+            let mut attr = String::new();
+            caps.expand(&rule.attr, &mut attr);
+            (!attr.is_empty()).then_some(attr)
+        })
+    }
+
+    /// Attr name for a file without a usable `pname`: the user's rules
+    /// first, then the built-in [`path_to_attr_name`] heuristic.
+    pub fn attr_name_for(&self, path: &str) -> Option<String> {
+        self.apply(path).or_else(|| path_to_attr_name(path))
+    }
+}
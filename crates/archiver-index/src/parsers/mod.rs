@@ -7,13 +7,135 @@
 //!   2. If AST returns nothing, fall back to regex heuristics
 
 mod ast_parser;
+mod attr_map;
 mod regex_fallback;
 
 use regex::Regex;
+use std::fmt;
 use crate::stats::PackageInfo;
 
 // Re-export for tests / external callers
 pub use ast_parser::{is_valid_version, path_to_attr_name};
+pub use attr_map::{AttrMap, AttrRule};
+pub(crate) use ast_parser::join_relative;
+
+/// Reads another file from the same commit tree by repo-relative path.
+///
+/// Lets the parser follow references such as
+/// `version = (lib.importTOML ./Cargo.toml).package.version`.
+pub type ReadSibling<'a> = &'a dyn Fn(&str) -> Option<String>;
+
+/// Which parser produced the packages for a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseStrategy {
+    /// rnix AST parser
+    Ast,
+    /// Regex heuristics (AST found nothing)
+    Regex,
+    /// Neither parser found a package
+    None,
+}
+
+/// Which AST strategy matched, for files parsed by [`ParseStrategy::Ast`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AstStrategy {
+    /// Several packages in one file (`sourceVersion` bindings, overlays)
+    Multi,
+    /// VSCode extension `mktplcRef`
+    Marketplace,
+    /// One package (`pname`/`version` or a path-derived name)
+    Single,
+}
+
+/// Why neither parser found a package in a file (see [`diagnose_file`])
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MissReason {
+    /// rnix could not parse the file, and the regex heuristics found nothing
+    AstParseError,
+    /// No `pname`, `callPackage` binding or path rule gives an attr name
+    NoAttrName,
+    /// No `version` in any recognised form
+    NoVersionFound,
+    /// A `version = "…"` literal exists but isn't a usable version
+    /// (e.g. `"${lib.version}"`)
+    VersionRejectedAsInvalid,
+}
+
+impl fmt::Display for MissReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            MissReason::AstParseError => "AST parse error",
+            MissReason::NoAttrName => "no attr name",
+            MissReason::NoVersionFound => "no version found",
+            MissReason::VersionRejectedAsInvalid => "version rejected as invalid",
+        })
+    }
+}
+
+/// Packages extracted from one file and the parser that found them
+#[derive(Debug)]
+pub struct ParseOutcome {
+    pub packages: Vec<PackageInfo>,
+    pub strategy: ParseStrategy,
+    /// Set when `strategy` is [`ParseStrategy::Ast`]
+    pub ast_strategy: Option<AstStrategy>,
+}
+
+/// What a single extraction strategy found in a file
+#[derive(Debug)]
+pub struct StrategyReport {
+    pub name: &'static str,
+    pub packages: Vec<PackageInfo>,
+}
+
+/// Per-strategy breakdown of how one file parses, for troubleshooting
+/// packages that are missing from the index.
+#[derive(Debug)]
+pub struct FileDiagnosis {
+    /// rnix parse errors; when non-empty the AST strategies are skipped
+    pub parse_errors: Vec<String>,
+    /// Each AST strategy run independently, in the order they are tried
+    pub ast_strategies: Vec<StrategyReport>,
+    /// What the regex heuristics found on their own
+    pub regex: Option<PackageInfo>,
+    /// What indexing would actually record for this file
+    pub outcome: ParseOutcome,
+    /// Why nothing was found; None when `outcome` has packages
+    pub miss: Option<MissReason>,
+}
+
+/// Runs every parser strategy on a file and reports what each found,
+/// alongside the combined result used during indexing.
+pub fn diagnose_file(
+    path: &str,
+    content: &str,
+    version_regex: &Regex,
+    attr_map: &AttrMap,
+    read_sibling: ReadSibling,
+) -> FileDiagnosis {
+    let (parse_errors, ast_strategies) = match ast_parser::diagnose_ast(path, content, attr_map, read_sibling) {
+        Ok(strategies) => (Vec::new(), strategies),
+        Err(errors) => (errors, Vec::new()),
+    };
+
+    let regex = regex_fallback::try_extract_regex(path, content, version_regex, attr_map);
+    let outcome = extract_packages_from_file_with(path, content, version_regex, attr_map, read_sibling);
+    // The regex heuristics are the last resort, so their reason is the
+    // file's, unless rnix already failed on it
+    let miss = regex
+        .as_ref()
+        .err()
+        .filter(|_| outcome.packages.is_empty())
+        .map(|reason| if parse_errors.is_empty() { *reason } else { MissReason::AstParseError });
+
+    FileDiagnosis {
+        parse_errors,
+        ast_strategies,
+        regex: regex.ok(),
+        outcome,
+        miss,
+    }
+}
 
 /// Extracts all packages from a `.nix` file.
 ///
@@ -24,15 +146,27 @@ pub fn extract_packages_from_file(
     content: &str,
     version_regex: &Regex,
 ) -> Vec<PackageInfo> {
-    let ast_result = ast_parser::extract_packages_ast(path, content);
-    if !ast_result.is_empty() {
-        return ast_result;
+    extract_packages_from_file_with(path, content, version_regex, &AttrMap::default(), &|_| None).packages
+}
+
+/// Like [`extract_packages_from_file`], but applies user [`AttrMap`] rules,
+/// can read sibling files from the same tree (e.g. a vendored `Cargo.toml`)
+/// to resolve the version, and reports which parser produced the result.
+pub fn extract_packages_from_file_with(
+    path: &str,
+    content: &str,
+    version_regex: &Regex,
+    attr_map: &AttrMap,
+    read_sibling: ReadSibling,
+) -> ParseOutcome {
+    if let Some((ast_strategy, packages)) = ast_parser::extract_packages_ast(path, content, attr_map, read_sibling) {
+        return ParseOutcome { packages, strategy: ParseStrategy::Ast, ast_strategy: Some(ast_strategy) };
     }
 
-    if let Some(pkg) = regex_fallback::extract_packages_regex(path, content, version_regex) {
+    if let Some(pkg) = regex_fallback::extract_packages_regex(path, content, version_regex, attr_map) {
         log::debug!("[regex-fallback] {} -> {} v{}", path, pkg.attr_name, pkg.version);
-        return vec![pkg];
+        return ParseOutcome { packages: vec![pkg], strategy: ParseStrategy::Regex, ast_strategy: None };
     }
 
-    vec![]
+    ParseOutcome { packages: vec![], strategy: ParseStrategy::None, ast_strategy: None }
 }
@@ -2,7 +2,8 @@
 
 use regex::Regex;
 use crate::stats::PackageInfo;
-use super::ast_parser::{is_valid_version, path_to_attr_name};
+use super::ast_parser::is_valid_version;
+use super::{AttrMap, MissReason};
 
 /// Extracts package info using regex heuristics.
 /// Used when AST parsing fails or yields no results.
@@ -10,16 +11,28 @@ pub fn extract_packages_regex(
     path: &str,
     content: &str,
     version_regex: &Regex,
+    attr_map: &AttrMap,
 ) -> Option<PackageInfo> {
-    let attr_name = extract_pname(content)
-        .or_else(|| extract_callpackage_attr(content))
-        .or_else(|| path_to_attr_name(path))?;
+    try_extract_regex(path, content, version_regex, attr_map).ok()
+}
+
+/// [`extract_packages_regex`], saying why nothing was found (analysis path)
+pub(super) fn try_extract_regex(
+    path: &str,
+    content: &str,
+    version_regex: &Regex,
+    attr_map: &AttrMap,
+) -> Result<PackageInfo, MissReason> {
+    let (attr_name, name_from_path) = match extract_pname(content).or_else(|| extract_callpackage_attr(content)) {
+        Some(name) => (name, false),
+        None => (attr_map.attr_name_for(path).ok_or(MissReason::NoAttrName)?, true),
+    };
 
     // 1. Simple literal: version = "1.2.3";
-    let version = version_regex.captures(content)
+    let literal = version_regex.captures(content)
         .and_then(|c| c.get(1))
-        .map(|m| m.as_str().to_string())
-        .filter(|v| is_valid_version(v));
+        .map(|m| m.as_str().to_string());
+    let version = literal.clone().filter(|v| is_valid_version(v));
 
     // 2. sourceVersion block
     let version = version.or_else(|| extract_sourceversion(content));
@@ -30,9 +43,13 @@ pub fn extract_packages_regex(
     // 4. major/minor/patch interpolation
     let version = version.or_else(|| extract_interpolation(content));
 
-    let version = version?;
+    let version = version.ok_or(if literal.is_some() {
+        MissReason::VersionRejectedAsInvalid
+    } else {
+        MissReason::NoVersionFound
+    })?;
 
-    Some(PackageInfo { attr_name, version })
+    Ok(PackageInfo { attr_name, version, main_program: extract_main_program(content), description: None, platform: None, source_hash: None, source_rev: None, name_from_path })
 }
 
 fn extract_pname(content: &str) -> Option<String> {
@@ -42,18 +59,25 @@ fn extract_pname(content: &str) -> Option<String> {
         .map(|m| m.as_str().to_string())
 }
 
+fn extract_main_program(content: &str) -> Option<String> {
+    Regex::new(r#"mainProgram\s*=\s*"([^"$]+)""#).ok()?
+        .captures(content)
+        .and_then(|c| c.get(1))
+        .map(|m| m.as_str().to_string())
+}
+
 fn extract_callpackage_attr(content: &str) -> Option<String> {
     let lines: Vec<&str> = content.lines().collect();
     for (i, line) in lines.iter().enumerate() {
         if line.contains('=') && line.contains("callPackage") {
             if let Some(eq) = line.find('=') {
-                let attr = line[..eq].trim().split_whitespace().last()?.to_string();
+                let attr = line[..eq].split_whitespace().last()?.to_string();
                 let end = std::cmp::min(i + 20, lines.len());
-                for j in (i + 1)..end {
-                    if lines[j].contains("sourceVersion") {
+                for next in &lines[(i + 1)..end] {
+                    if next.contains("sourceVersion") {
                         return Some(attr);
                     }
-                    if lines[j].trim().starts_with('}') && !lines[j].contains('{') {
+                    if next.trim().starts_with('}') && !next.contains('{') {
                         break;
                     }
                 }
@@ -1,26 +1,26 @@
 //! Commit processing logic
 
 use anyhow::{Context, Result};
-use git2::{Commit, Oid, Repository, TreeWalkMode, TreeWalkResult};
+use git2::{Commit, ObjectType, Oid, Repository, TreeWalkMode, TreeWalkResult};
 use rayon::prelude::*;
-use regex::Regex;
 use std::sync::{Arc, Mutex};
 
-use crate::indexer::Indexer;
+use crate::indexer::{is_indexed_path, Indexer};
 use crate::stats::{CommitStats, IndexStats};
-use super::file::process_file;
+use super::file::{resolve_file_blob, CommitTree};
 
 impl Indexer {
     /// Processes a batch of commits in parallel
     /// Returns list of (commit_sha, timestamp) pairs to mark as processed after flush
-    pub(super) fn process_batch(&self, oids: &[Oid], stats: &Arc<Mutex<IndexStats>>) -> Result<Vec<(String, u64)>> {
+    /// With `first_parent`, merges are diffed against their first parent, since
+    /// the walk never visits the commits they bring in
+    pub(super) fn process_batch(&self, oids: &[Oid], first_parent: bool, stats: &Arc<Mutex<IndexStats>>) -> Result<Vec<(String, u64)>> {
         let repo_path = &self.repo_path;
-        let version_regex = &self.version_regex;
 
         // OPTIMIZATION: Split batch into chunks - each thread processes multiple commits
         // with ONE repository open, instead of opening repo for EACH commit!
         let num_threads = rayon::current_num_threads();
-        let chunk_size = (oids.len() + num_threads - 1) / num_threads; // Round up
+        let chunk_size = oids.len().div_ceil(num_threads);
         
         let results: Vec<_> = oids.par_chunks(chunk_size.max(1))
             .flat_map(|chunk| {
@@ -36,7 +36,7 @@ impl Indexer {
                 
                     log::debug!("Processing commit: {}", oid);
                     
-                    let commit_stats = self.process_commit_with_repo(&repo, &commit, version_regex)?;
+                    let commit_stats = self.process_commit_with_repo(&repo, &commit, first_parent)?;
                     
                     // Return commit info to mark as processed later (after flush)
                     let timestamp = commit.time().seconds() as u64;
@@ -56,6 +56,10 @@ impl Indexer {
                     stats_lock.processed += 1;
                     stats_lock.packages_found += commit_stats.packages_found;
                     stats_lock.packages_inserted += commit_stats.packages_inserted;
+                    stats_lock.packages_updated += commit_stats.packages_updated;
+                    stats_lock.trees_archived += commit_stats.trees_archived;
+                    stats_lock.ast_files += commit_stats.ast_files;
+                    stats_lock.regex_files += commit_stats.regex_files;
                     commits_to_mark.push((commit_sha, timestamp));
                 }
                 Err(e) => {
@@ -71,63 +75,89 @@ impl Indexer {
     /// Processes a single commit with FULL tree walk (for initial HEAD scan)
     /// This indexes ALL packages in the commit to build complete database
     pub(super) fn process_commit_full_scan(&self, repo: &Repository, commit: &Commit) -> Result<CommitStats> {
+        self.full_scan(repo, commit, false)
+    }
+
+    /// Full tree walk; with `skip_recognized`, files whose packages are all
+    /// already in the database are not inserted again (nor hashed)
+    pub(super) fn full_scan(&self, repo: &Repository, commit: &Commit, skip_recognized: bool) -> Result<CommitStats> {
         let tree = commit.tree().context("Failed to get commit tree")?;
         let timestamp = commit.time().seconds() as u64;
         let commit_sha = commit.id().to_string();
-        let version_regex = &self.version_regex;
+        let source = CommitTree { repo, tree: &tree, sha: &commit_sha, timestamp };
 
         let mut stats = CommitStats::default();
-        let db = &self.db;
 
         // Walk entire tree to index all packages
         tree.walk(TreeWalkMode::PreOrder, |root, entry| {
             let full_path = format!("{}{}", root, entry.name().unwrap_or(""));
+
+            // Submodule content lives in another repository and can't be read here
+            if entry.kind() == Some(ObjectType::Commit) && full_path.starts_with("pkgs/") {
+                log::debug!("Skipping submodule at '{}'", full_path);
+                return TreeWalkResult::Ok;
+            }
             
             // We're only interested in .nix files in pkgs/ directory
-            if !full_path.starts_with("pkgs/") || !full_path.ends_with(".nix") {
+            if !is_indexed_path(&full_path) {
                 return TreeWalkResult::Ok;
             }
 
-            // Get object and check if it's a blob (file)
-            if let Ok(object) = entry.to_object(repo) {
-                if let Some(blob) = object.as_blob() {
-                    let oid = blob.id();
-                    process_file(repo, &full_path, oid, &commit_sha, timestamp, db, version_regex, &mut stats);
-                }
+            // Files and in-tree symlinks; the blob itself is read (and I/O-gated) in process_file
+            if let Some(oid) = resolve_file_blob(repo, &tree, &full_path, entry) {
+                self.process_file(&source, &full_path, oid, skip_recognized, &mut stats);
             }
 
             TreeWalkResult::Ok
         })?;
 
+        self.store_subject(commit, &stats)?;
         Ok(stats)
     }
 
     /// Processes a single commit with DIFF optimization (only changed files)
     /// This is much faster than full tree walk - used after initial HEAD scan
-    pub(super) fn process_commit_with_repo(&self, repo: &Repository, commit: &Commit, version_regex: &Regex) -> Result<CommitStats> {
+    pub(super) fn process_commit_with_repo(&self, repo: &Repository, commit: &Commit, first_parent: bool) -> Result<CommitStats> {
         let tree = commit.tree().context("Failed to get commit tree")?;
         let timestamp = commit.time().seconds() as u64;
         let commit_sha = commit.id().to_string();
+        let source = CommitTree { repo, tree: &tree, sha: &commit_sha, timestamp };
 
         let mut stats = CommitStats::default();
-        let db = &self.db;
 
-        // OPTIMIZATION: Use external git log to get changed files (much faster!)
-        // Git's internal diff machinery is highly optimized with packfile deltas
-        let output = std::process::Command::new("git")
+        // OPTIMIZATION: Use external git diff-tree to get changed files (much faster!)
+        // Git's internal diff machinery is highly optimized with packfile deltas.
+        // Not `git log -1 --diff-filter`: the filter also selects commits, so a
+        // commit without A/M/R changes would be skipped and the next ancestor's
+        // files listed in its place.
+        let mut command = std::process::Command::new("git");
+        command
             .arg("-C")
             .arg(&self.repo_path)
-            .arg("log")
+            .arg("diff-tree")
+            .arg("--no-commit-id")  // Just filenames, even for a root commit
+            .arg("-r")
             .arg("--name-only")
-            .arg("--diff-filter=AM")  // Added or Modified only
-            .arg("--format=")  // No commit message, just filenames
-            .arg("-1")  // Only this commit
-            .arg(&commit_sha)
-            .output()
-            .context("Failed to run git log")?;
+            // Detect renames explicitly and keep them (R), so a moved package
+            // (e.g. during the by-name migration) is listed under its new path.
+            // Without this, git's default rename detection plus an AM filter
+            // reports the old path, which no longer exists in this tree.
+            .arg("-M")
+            .arg("--diff-filter=AMR");  // Added, Modified or Renamed
+        match commit.parent_count() {
+            0 => command.arg("--root").arg(&commit_sha),
+            1 => command.arg(commit.parent_id(0)?.to_string()).arg(&commit_sha),
+            // Following first parents only, the merge is the one commit on the
+            // walk that brings in the side branch's versions
+            _ if first_parent => command.arg(commit.parent_id(0)?.to_string()).arg(&commit_sha),
+            // Otherwise a merge changes nothing of its own: the walk sees the
+            // merged versions in the commits that introduced them
+            _ => return Ok(stats),
+        };
+        let output = command.output().context("Failed to run git diff-tree")?;
 
         if !output.status.success() {
-            anyhow::bail!("git log failed: {}", String::from_utf8_lossy(&output.stderr));
+            anyhow::bail!("git diff-tree failed: {}", String::from_utf8_lossy(&output.stderr));
         }
 
         let changed_files = String::from_utf8_lossy(&output.stdout);
@@ -140,16 +170,31 @@ impl Indexer {
             }
             
             // We're only interested in .nix files in pkgs/ directory
-            if !full_path.starts_with("pkgs/") || !full_path.ends_with(".nix") {
+            if !is_indexed_path(full_path) {
                 continue;
             }
 
             // Get the file's OID from the tree
             if let Ok(entry) = tree.get_path(std::path::Path::new(full_path)) {
-                process_file(repo, full_path, entry.id(), &commit_sha, timestamp, db, version_regex, &mut stats);
+                if let Some(oid) = resolve_file_blob(repo, &tree, full_path, &entry) {
+                    self.process_file(&source, full_path, oid, false, &mut stats);
+                }
             }
         }
 
+        self.store_subject(commit, &stats)?;
         Ok(stats)
     }
+
+    /// Records the commit's subject line if enabled and the commit is now
+    /// referenced by an entry
+    fn store_subject(&self, commit: &Commit, stats: &CommitStats) -> Result<()> {
+        if !self.store_subjects || stats.packages_inserted + stats.packages_updated == 0 {
+            return Ok(());
+        }
+        match commit.summary() {
+            Some(subject) => self.db.store_commit_subject(&commit.id().to_string(), subject),
+            None => Ok(()),
+        }
+    }
 }
@@ -0,0 +1,40 @@
+//! Single-file parse diagnostics
+
+use anyhow::{Context, Result};
+use archiver_core::short_sha;
+use git2::Repository;
+use std::path::Path;
+
+use crate::indexer::Indexer;
+use crate::parsers::{diagnose_file, FileDiagnosis};
+use super::file::resolve_file_blob;
+
+impl Indexer {
+    /// Loads `path` from the given revision and runs every parser strategy on
+    /// it, so you can see why a package is (or isn't) picked up.
+    pub fn diagnose_file(&self, rev: &str, path: &str) -> Result<FileDiagnosis> {
+        let commit_sha = self.resolve_commit(rev)?;
+        let repo = Repository::open(&self.repo_path)?;
+        let commit = repo.find_commit(git2::Oid::from_str(&commit_sha)?)?;
+        let tree = commit.tree()?;
+
+        let path = path.trim_start_matches("./");
+        let entry = tree
+            .get_path(Path::new(path))
+            .with_context(|| format!("'{}' does not exist at commit {}", path, short_sha(&commit_sha, 8)))?;
+        let oid = resolve_file_blob(&repo, &tree, path, &entry)
+            .with_context(|| format!("'{}' is not a file (or a symlink to one within the repository)", path))?;
+        let blob = repo.find_blob(oid)?;
+        let content = std::str::from_utf8(blob.content())
+            .with_context(|| format!("'{}' is not valid UTF-8", path))?;
+
+        // Sibling files (e.g. Cargo.toml) are read from the same commit tree
+        let read_sibling = |rel: &str| -> Option<String> {
+            let entry = tree.get_path(Path::new(rel)).ok()?;
+            let blob = repo.find_blob(entry.id()).ok()?;
+            String::from_utf8(blob.content().to_vec()).ok()
+        };
+
+        Ok(diagnose_file(path, content, &self.version_regex, &self.attr_map, &read_sibling))
+    }
+}
@@ -1,43 +1,197 @@
 //! File processing logic
 
 use archiver_core::PackageEntry;
-use git2::{Oid, Repository};
-use regex::Regex;
+use archiver_db::InsertOutcome;
+use git2::{FileMode, ObjectType, Oid, Repository, Tree, TreeEntry};
+use std::path::Path;
 
-use crate::parsers::extract_packages_from_file;
-use crate::stats::CommitStats;
+use crate::archive::NarArchive;
+use crate::indexer::Indexer;
+use crate::io_gate::IoGate;
+use crate::nar::{compute_nar_hash_for_tree, content_hash};
+use crate::parsers::{extract_packages_from_file_with, join_relative, AstStrategy, ParseStrategy};
+use crate::stats::{CommitStats, PackageInfo};
 
-/// Helper function to process a single file (shared between diff and tree walk)
-pub(super) fn process_file(
-    repo: &Repository,
-    full_path: &str,
-    oid: Oid,
-    commit_sha: &str,
-    timestamp: u64,
-    db: &archiver_db::ArchiverDb,
-    version_regex: &Regex,
-    stats: &mut CommitStats,
-) {
-    if let Ok(object) = repo.find_object(oid, None) {
-        if let Some(blob) = object.as_blob() {
-            if let Ok(content) = std::str::from_utf8(blob.content()) {
-                let packages = extract_packages_from_file(full_path, content, version_regex);
-
-                for package_info in packages {
-                    stats.packages_found += 1;
-
-                    let entry = PackageEntry::new(
-                        package_info.attr_name,
-                        package_info.version,
-                        commit_sha.to_string(),
-                        timestamp,
-                    );
-
-                    match db.insert_if_better(&entry) {
-                        Ok(true) => stats.packages_inserted += 1,
-                        Ok(false) => {},
-                        Err(e) => {
-                            log::warn!("Failed to insert package {}: {:?}", entry.key(), e);
+/// Symlink hops followed before giving up (guards against cycles)
+const MAX_SYMLINK_DEPTH: usize = 8;
+
+/// Returns the blob to parse for a tree entry.
+///
+/// Regular files are returned as-is. Symlinks are followed to their target
+/// within the same tree, so a symlinked `default.nix` is indexed under the
+/// symlink's path. Submodules (gitlinks), directories and symlinks that
+/// dangle or point outside the tree are skipped.
+pub(super) fn resolve_file_blob(repo: &Repository, tree: &Tree, path: &str, entry: &TreeEntry) -> Option<Oid> {
+    let mut path = path.to_string();
+    let mut entry = entry.to_owned();
+
+    for _ in 0..MAX_SYMLINK_DEPTH {
+        match entry.kind() {
+            Some(ObjectType::Blob) if entry.filemode() == i32::from(FileMode::Link) => {
+                let blob = repo.find_blob(entry.id()).ok()?;
+                let target = String::from_utf8_lossy(blob.content()).trim_end().to_string();
+                let resolved = if target.starts_with('/') { None } else { join_relative(&path, &target) };
+                let Some(resolved) = resolved else {
+                    log::debug!("Skipping symlink '{}' → '{}': points outside the repository", path, target);
+                    return None;
+                };
+                entry = match tree.get_path(Path::new(&resolved)) {
+                    Ok(target_entry) => target_entry,
+                    Err(_) => {
+                        log::debug!("Skipping dangling symlink '{}' → '{}'", path, target);
+                        return None;
+                    }
+                };
+                path = resolved;
+            }
+            Some(ObjectType::Blob) => return Some(entry.id()),
+            Some(ObjectType::Commit) => {
+                log::debug!("Skipping submodule at '{}'", path);
+                return None;
+            }
+            _ => return None,
+        }
+    }
+
+    log::debug!("Skipping '{}': more than {} levels of symlinks", path, MAX_SYMLINK_DEPTH);
+    None
+}
+
+/// The commit a file is read from: its tree plus what each entry records
+pub(super) struct CommitTree<'a> {
+    pub(super) repo: &'a Repository,
+    pub(super) tree: &'a Tree<'a>,
+    pub(super) sha: &'a str,
+    pub(super) timestamp: u64,
+}
+
+impl Indexer {
+    /// Helper function to process a single file (shared between diff and tree walk)
+    pub(super) fn process_file(
+        &self,
+        commit: &CommitTree,
+        full_path: &str,
+        oid: Oid,
+        skip_recognized: bool,
+        stats: &mut CommitStats,
+    ) {
+        let CommitTree { repo, tree, sha: commit_sha, timestamp } = *commit;
+        let db = &*self.db;
+        let io_gate = self.io_gate.as_deref();
+
+        // Only the object read is gated; parsing runs unbounded
+        let object = {
+            let _permit = io_gate.map(IoGate::acquire);
+            repo.find_object(oid, None)
+        };
+
+        if let Ok(object) = object {
+            if let Some(blob) = object.as_blob() {
+                if self.max_blob_size.is_some_and(|max| blob.size() > max) {
+                    log::debug!("Skipping {}: {} bytes is over the size limit", full_path, blob.size());
+                    return;
+                }
+                if let Ok(content) = std::str::from_utf8(blob.content()) {
+                    // Sibling files (e.g. Cargo.toml) are read from the same commit tree
+                    let read_sibling = |rel: &str| -> Option<String> {
+                        let entry = tree.get_path(Path::new(rel)).ok()?;
+                        let _permit = io_gate.map(IoGate::acquire);
+                        let blob = repo.find_blob(entry.id()).ok()?;
+                        String::from_utf8(blob.content().to_vec()).ok()
+                    };
+                    let mut outcome = extract_packages_from_file_with(full_path, content, &self.version_regex, &self.attr_map, &read_sibling);
+                    stats.parse.files += 1;
+                    match (outcome.strategy, outcome.ast_strategy) {
+                        (ParseStrategy::Ast, strategy) => {
+                            stats.ast_files += 1;
+                            match strategy {
+                                Some(AstStrategy::Multi) => stats.parse.ast_multi += 1,
+                                Some(AstStrategy::Marketplace) => stats.parse.ast_marketplace += 1,
+                                Some(AstStrategy::Single) | None => stats.parse.ast_single += 1,
+                            }
+                        }
+                        (ParseStrategy::Regex, _) => {
+                            stats.regex_files += 1;
+                            stats.parse.regex += 1;
+                        }
+                        (ParseStrategy::None, _) => stats.parse.none += 1,
+                    }
+
+                    // The file is still read and parsed; only the inserts are limited
+                    if let Some(only) = self.only.as_deref() {
+                        outcome.packages.retain(|p| only.contains(&p.attr_name));
+                    }
+                    if let Some(skipped) = self.skipped_versions.as_deref() {
+                        outcome.packages.retain(|p| {
+                            let placeholder = skipped.contains(&p.version.to_lowercase());
+                            if placeholder {
+                                log::debug!("Skipping {} {} in {}: placeholder version", p.attr_name, p.version, full_path);
+                            }
+                            !placeholder
+                        });
+                    }
+
+                    if skip_recognized && is_recognized(db, &outcome.packages) {
+                        stats.recognized_files += 1;
+                        return;
+                    }
+
+                    // One hash per file, shared by every package it defines
+                    let (nar_hash, content_hash) = if outcome.packages.is_empty() {
+                        (None, None)
+                    } else {
+                        let nar_hash = if self.compute_nar {
+                            package_dir_nar_hash(repo, tree, full_path, io_gate, self.archive.as_deref(), stats)
+                        } else {
+                            None
+                        };
+                        (nar_hash, Some(content_hash(blob.content())))
+                    };
+
+                    for package_info in outcome.packages {
+                        stats.packages_found += 1;
+
+                        let mut entry = PackageEntry::new(
+                            package_info.attr_name,
+                            package_info.version,
+                            commit_sha.to_string(),
+                            timestamp,
+                        )
+                        .with_source_path(full_path.to_string(), package_info.name_from_path);
+                        if let Some(hash) = &nar_hash {
+                            entry = entry.with_nar_hash(hash.clone());
+                        }
+                        if let Some(hash) = &content_hash {
+                            entry = entry.with_content_hash(hash.clone());
+                        }
+                        if let Some(program) = package_info.main_program {
+                            entry = entry.with_main_program(program);
+                        }
+                        if let Some(description) = package_info.description {
+                            entry = entry.with_description(description);
+                        }
+                        if let Some(rev) = package_info.source_rev {
+                            entry = entry.with_source_rev(rev);
+                        }
+                        if let Some(platform) = package_info.platform {
+                            entry = entry.with_platform(platform, package_info.source_hash);
+                        }
+
+                        if self.record_history {
+                            if let Err(e) = db.record_version_occurrence(&entry) {
+                                log::warn!("Failed to record history of {}: {:?}", entry.key(), e);
+                            }
+                        }
+                        match db.insert_with_outcome(&entry) {
+                            Ok(InsertOutcome::Updated) => {
+                                stats.packages_inserted += 1;
+                                stats.packages_updated += 1;
+                            }
+                            Ok(outcome) if outcome.is_stored() => stats.packages_inserted += 1,
+                            Ok(_) => {},
+                            Err(e) => {
+                                log::warn!("Failed to insert package {}: {:?}", entry.key(), e);
+                            }
                         }
                     }
                 }
@@ -45,3 +199,55 @@ pub(super) fn process_file(
         }
     }
 }
+
+/// True if the file yields packages and the database already has all of them
+fn is_recognized(db: &archiver_db::ArchiverDb, packages: &[PackageInfo]) -> bool {
+    !packages.is_empty()
+        && packages
+            .iter()
+            .all(|p| match &p.platform {
+                Some(platform) => db
+                    .get_platforms(&p.attr_name, &p.version)
+                    .is_ok_and(|entries| entries.iter().any(|e| e.platform.as_ref() == Some(platform))),
+                None => matches!(db.get(&p.attr_name, &p.version), Ok(Some(_))),
+            })
+}
+
+/// NAR hash of the directory containing `full_path` (the package definition
+/// plus its patches and helper files). Reads every blob in that directory,
+/// so it is by far the most I/O-heavy step per file.
+///
+/// With an archive, the directory is also written there unless a NAR with
+/// the same hash already is; a failed write is logged and doesn't affect
+/// the returned hash.
+fn package_dir_nar_hash(
+    repo: &Repository,
+    tree: &Tree,
+    full_path: &str,
+    io_gate: Option<&IoGate>,
+    archive: Option<&NarArchive>,
+    stats: &mut CommitStats,
+) -> Option<String> {
+    let (dir, _) = full_path.rsplit_once('/')?;
+    let dir_tree = tree
+        .get_path(Path::new(dir))
+        .ok()
+        .and_then(|entry| repo.find_tree(entry.id()).ok())?;
+
+    let _permit = io_gate.map(IoGate::acquire);
+    let hash = match compute_nar_hash_for_tree(repo, &dir_tree) {
+        Ok(hash) => hash,
+        Err(e) => {
+            log::warn!("Failed to compute NAR hash for {}: {:?}", dir, e);
+            return None;
+        }
+    };
+    if let Some(archive) = archive {
+        match archive.store_tree(repo, &dir_tree, &hash) {
+            Ok(true) => stats.trees_archived += 1,
+            Ok(false) => {}
+            Err(e) => log::warn!("Failed to archive {} ({}): {:?}", dir, hash, e),
+        }
+    }
+    Some(hash)
+}
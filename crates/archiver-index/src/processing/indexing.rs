@@ -1,18 +1,35 @@
 //! Main indexing logic
 
 use anyhow::{Context, Result};
+use archiver_core::short_sha;
 use git2::{Oid, Repository};
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 use std::time::Instant;
 
 use crate::formatting::{format_duration, format_number, format_unix_timestamp};
-use crate::indexer::Indexer;
-use crate::stats::IndexStats;
+use crate::indexer::{IndexOptions, Indexer};
+use crate::stats::{IndexStats, ParseBreakdown};
 
 impl Indexer {
     /// Indexes all commits from the specified commit backwards
     /// Uses parallel processing to utilize multiple CPU cores
-    pub fn index_from_commit(&self, commit_sha: &str, max_commits: Option<usize>, batch_size: usize) -> Result<IndexStats> {
+    ///
+    /// With `options.head_only` set, only the starting commit is fully
+    /// scanned and the history walk is skipped.
+    pub fn index_from_commit(&self, commit_sha: &str, options: &IndexOptions) -> Result<IndexStats> {
+        let max_commits = options.max_commits;
+        // Denominator for progress and ETA; only max_commits stops the walk
+        let progress_total = max_commits.or(options.estimated_commits);
+        let batch_size = options.batch_size;
+        let flush_every = options.flush_every;
+        if flush_every == 0 {
+            anyhow::bail!("flush_every must be at least 1");
+        }
+        if options.flush_entries == Some(0) {
+            anyhow::bail!("flush_entries must be at least 1");
+        }
+
         let start_time = Instant::now();
         let repo = Repository::open(&self.repo_path)
             .context("Failed to open repository")?;
@@ -26,10 +43,26 @@ impl Indexer {
         // Log commit info
         let commit_time = commit.time().seconds();
         let commit_date = format_unix_timestamp(commit_time as u64);
-        log::info!("From commit: {} ({})", &commit_sha[..12], commit_date);
+        log::info!("From commit: {} ({})", short_sha(commit_sha, 12), commit_date);
+
+        if self.is_shallow {
+            log::warn!("⚠️  Repository is a shallow clone - history is truncated");
+            log::warn!("   Commits older than the clone depth will NOT be indexed");
+            log::warn!("   Run `git fetch --unshallow` in the repository for a complete index");
+        }
+
+        if options.record_refs {
+            self.record_commit_refs(&repo)?;
+        }
+
+        if options.head_only {
+            return self.index_head_only(&repo, &commit, start_time);
+        }
 
         // Check if database is empty (first run)
         let db_is_empty = self.db.is_empty()?;
+        let mut head_archived = 0;
+        let mut head_scan = None;
         
         if db_is_empty {
             log::info!("📊 Database is empty - performing full scan of HEAD commit");
@@ -40,28 +73,43 @@ impl Indexer {
             // Do full tree walk on HEAD to get all current packages
             let head_stats = self.process_commit_full_scan(&repo, &commit)?;
             let initial_packages = head_stats.packages_inserted;
+            head_archived = head_stats.trees_archived;
             
             // Mark HEAD as processed
             let timestamp = commit.time().seconds() as u64;
-            self.db.mark_commit_processed(commit_sha, timestamp)?;
+            self.mark_processed(commit_sha, timestamp)?;
             
             log::info!("✅ Full scan complete: {} packages indexed from HEAD", initial_packages);
+            log_parse_breakdown(&head_stats.parse);
+            head_scan = Some(head_stats.parse);
             log::info!("   Now starting incremental indexing of commit history...");
             log::info!("");
         }
 
-        let stats = Arc::new(Mutex::new(IndexStats::default()));
+        let commit_path = options.commit_path.as_deref().map(|p| p.trim_matches('/'));
+        if let Some(path) = commit_path {
+            log::info!("Only processing commits that touch {}", path);
+        }
+
+        let stats = Arc::new(Mutex::new(IndexStats { trees_archived: head_archived, head_scan, ..IndexStats::default() }));
         let mut revwalk = repo.revwalk()?;
         revwalk.push(commit.id())?;
         revwalk.set_sorting(git2::Sort::TIME)?;
+        if options.first_parent {
+            revwalk.simplify_first_parent()?;
+        }
+        if let Some(hide) = &options.hide {
+            let oid = Oid::from_str(hide).context("Invalid commit SHA to hide")?;
+            revwalk.hide(oid).context("Failed to hide already indexed history")?;
+        }
 
         // Collect commits in batches for parallel processing
         // Larger batch size = better CPU utilization
         // Default: 100 commits, configurable via CLI
-        const FLUSH_INTERVAL: usize = 5; // Flush every N batches
         let mut batch = Vec::with_capacity(batch_size);
         let mut total_processed = 0;
-        let mut batches_processed = 0;
+        let mut batches_processed: usize = 0;
+        let mut inserted_at_last_flush: usize = 0;
         
         log::info!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
 
@@ -84,6 +132,22 @@ impl Indexer {
                 continue;
             }
 
+            // Commits outside the path filter count towards the limit too,
+            // so --to-commit/--to-date ranges keep their meaning
+            if let Some(path) = commit_path {
+                if !commit_touches_path(&repo, oid, path)? {
+                    stats.lock().unwrap().skipped += 1;
+                    total_processed += 1;
+                    if let Some(max) = max_commits {
+                        if total_processed >= max {
+                            log::info!("Reached max commit limit: {}", max);
+                            break;
+                        }
+                    }
+                    continue;
+                }
+            }
+
             // Check limit for new commits to process
             if let Some(max) = max_commits {
                 if total_processed >= max {
@@ -97,7 +161,7 @@ impl Indexer {
 
             // Process batch when full or reached end
             if batch.len() >= batch_size {
-                let commits_to_mark = self.process_batch(&batch, &stats)?;
+                let commits_to_mark = self.process_batch(&batch, options.first_parent, &stats)?;
                 batches_processed += 1;
                 
                 let stats_lock = stats.lock().unwrap();
@@ -113,13 +177,13 @@ impl Indexer {
                     0.0
                 };
                 
-                let progress_pct = if let Some(max) = max_commits {
-                    (commits_done as f64 / max as f64 * 100.0) as u32
+                let progress_pct = if let Some(max) = progress_total {
+                    (commits_done as f64 / max as f64 * 100.0).min(100.0) as u32
                 } else {
                     0
                 };
                 
-                let eta_str = if let Some(max) = max_commits {
+                let eta_str = if let Some(max) = progress_total {
                     if speed > 0.0 {
                         let remaining = max.saturating_sub(commits_done);
                         let eta_secs = remaining as f64 / speed;
@@ -132,8 +196,9 @@ impl Indexer {
                 };
                 
                 // Log progress
-                if let Some(max) = max_commits {
+                if let Some(max) = progress_total {
                     log::info!(
+                        batch = batches_processed, commits_done, max_commits = max, packages_inserted, packages_found, commits_per_sec = speed;
                         "⚡ Batch #{} | Commits: {}/{} ({}%) | Packages: {} inserted ({} found) | Speed: {:.1} commits/s | ETA: {}",
                         batches_processed,
                         format_number(commits_done),
@@ -146,6 +211,7 @@ impl Indexer {
                     );
                 } else {
                     log::info!(
+                        batch = batches_processed, commits_done, packages_inserted, packages_found, commits_per_sec = speed;
                         "⚡ Batch #{} | Commits: {} | Packages: {} inserted ({} found) | Speed: {:.1} commits/s | Elapsed: {}",
                         batches_processed,
                         format_number(commits_done),
@@ -156,25 +222,32 @@ impl Indexer {
                     );
                 }
                 
+                if let Some(callback) = &self.progress {
+                    callback(&IndexStats { elapsed_time: elapsed, ..stats_lock.clone() });
+                }
                 drop(stats_lock);
                 
-                // Flush less frequently to reduce I/O overhead
-                if batches_processed % FLUSH_INTERVAL == 0 {
+                // Flush less frequently to reduce I/O overhead, but never let
+                // more than flush_entries inserts pile up unflushed
+                let unflushed = packages_inserted.saturating_sub(inserted_at_last_flush);
+                let entries_due = options.flush_entries.is_some_and(|limit| unflushed >= limit);
+                if batches_processed.is_multiple_of(flush_every) || entries_due {
                     let flush_start = Instant::now();
                     self.db.flush()?;
                     let flush_time = flush_start.elapsed();
-                    log::debug!("Database flushed after {} batches ({:.2}s flush time)", 
-                        batches_processed, flush_time.as_secs_f64());
+                    inserted_at_last_flush = packages_inserted;
+                    log::debug!("Database flushed after {} batches, {} new entries ({:.2}s flush time)", 
+                        batches_processed, unflushed, flush_time.as_secs_f64());
                     
                     // NOW mark commits as processed - only after successful flush
                     for (commit_sha, timestamp) in commits_to_mark.iter() {
-                        self.db.mark_commit_processed(commit_sha, *timestamp)?;
+                        self.mark_processed(commit_sha, *timestamp)?;
                     }
                     log::debug!("Marked {} commits as processed", commits_to_mark.len());
                 } else {
                     // Mark commits immediately if not flushing (will be flushed later)
                     for (commit_sha, timestamp) in commits_to_mark.iter() {
-                        self.db.mark_commit_processed(commit_sha, *timestamp)?;
+                        self.mark_processed(commit_sha, *timestamp)?;
                     }
                 }
                 
@@ -184,14 +257,17 @@ impl Indexer {
 
         // Process remaining commits
         if !batch.is_empty() {
-            let commits_to_mark = self.process_batch(&batch, &stats)?;
+            let commits_to_mark = self.process_batch(&batch, options.first_parent, &stats)?;
+            if let Some(callback) = &self.progress {
+                callback(&IndexStats { elapsed_time: start_time.elapsed(), ..stats.lock().unwrap().clone() });
+            }
             
             // Always flush at the end
             self.db.flush()?;
             
             // Mark remaining commits as processed after final flush
             for (commit_sha, timestamp) in commits_to_mark.iter() {
-                self.db.mark_commit_processed(commit_sha, *timestamp)?;
+                self.mark_processed(commit_sha, *timestamp)?;
             }
             log::debug!("Marked {} final commits as processed", commits_to_mark.len());
         } else {
@@ -210,7 +286,16 @@ impl Indexer {
         
         // Log final statistics
         log::info!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
-        log::info!("✅ Indexing completed!");
+        log::info!(
+            commits_processed = final_stats.processed,
+            commits_skipped = final_stats.skipped,
+            packages_found = final_stats.packages_found,
+            packages_inserted = final_stats.packages_inserted,
+            packages_updated = final_stats.packages_updated,
+            errors = final_stats.errors,
+            elapsed_secs = total_time.as_secs_f64();
+            "✅ Indexing completed!"
+        );
         log::info!("📊 Final Statistics:");
         log::info!("   • Total time:        {}", format_duration(total_time));
         log::info!("   • Commits processed: {} ({} new, {} skipped)",
@@ -223,20 +308,17 @@ impl Indexer {
             format_number(final_stats.packages_inserted),
             format_number(final_stats.packages_found.saturating_sub(final_stats.packages_inserted))
         );
-        
-        let avg_commit_speed = if total_time.as_secs() > 0 {
-            final_stats.processed as f64 / total_time.as_secs_f64()
-        } else {
-            0.0
-        };
-        let avg_package_speed = if total_time.as_secs() > 0 {
-            final_stats.packages_inserted as f64 / total_time.as_secs_f64()
-        } else {
-            0.0
-        };
+        log::info!("   • Versions updated:  {} moved to newer commits",
+            format_number(final_stats.packages_updated)
+        );
+        if let Some(archive) = &self.archive {
+            log::info!("   • Trees archived:    {} new NAR files in {:?}",
+                format_number(final_stats.trees_archived), archive.root()
+            );
+        }
         
         log::info!("   • Average speed:     {:.1} commits/s, {:.1} packages/s",
-            avg_commit_speed, avg_package_speed
+            final_stats.commits_per_sec(), final_stats.packages_per_sec()
         );
         
         if final_stats.errors > 0 {
@@ -250,4 +332,84 @@ impl Indexer {
         
         Ok(final_stats)
     }
+
+    /// Full-scans a single commit and stops, without walking history.
+    /// Gives a complete snapshot of the packages at that commit.
+    fn index_head_only(&self, repo: &Repository, commit: &git2::Commit, start_time: Instant) -> Result<IndexStats> {
+        let commit_sha = commit.id().to_string();
+
+        log::info!("📊 Head-only mode - full scan of a single commit, no history walk");
+        let head_stats = self.process_commit_full_scan(repo, commit)?;
+
+        self.db.flush()?;
+        self.mark_processed(&commit_sha, commit.time().seconds() as u64)?;
+        self.db.flush()?;
+
+        let stats = IndexStats {
+            processed: 1,
+            packages_found: head_stats.packages_found,
+            packages_inserted: head_stats.packages_inserted,
+            packages_updated: head_stats.packages_updated,
+            ast_files: head_stats.ast_files,
+            regex_files: head_stats.regex_files,
+            trees_archived: head_stats.trees_archived,
+            head_scan: Some(head_stats.parse.clone()),
+            elapsed_time: start_time.elapsed(),
+            ..IndexStats::default()
+        };
+
+        log::info!(
+            packages_found = stats.packages_found,
+            packages_inserted = stats.packages_inserted,
+            elapsed_secs = stats.elapsed_time.as_secs_f64();
+            "✅ Head-only scan complete: {} packages found, {} inserted in {}",
+            format_number(stats.packages_found),
+            format_number(stats.packages_inserted),
+            format_duration(stats.elapsed_time)
+        );
+        if let Some(archive) = &self.archive {
+            log::info!("   {} new NAR files archived in {:?}", format_number(stats.trees_archived), archive.root());
+        }
+        log_parse_breakdown(&head_stats.parse);
+
+        Ok(stats)
+    }
+
+    /// Records a commit as processed, unless the run is limited by
+    /// [`Indexer::with_only`]: its other packages were never inserted, so
+    /// a later unfiltered run must not skip it
+    fn mark_processed(&self, commit_sha: &str, timestamp: u64) -> Result<()> {
+        if self.only.is_some() {
+            return Ok(());
+        }
+        self.db.mark_commit_processed(commit_sha, timestamp)
+    }
+}
+
+/// Logs how the files of a full HEAD scan were parsed: a quick check of
+/// parser coverage on the current tree
+fn log_parse_breakdown(parse: &ParseBreakdown) {
+    let percent = |n: usize| if parse.files > 0 { n as f64 / parse.files as f64 * 100.0 } else { 0.0 };
+    log::info!("   Parsed {} .nix files, {} ({:.1}%) with packages:",
+        format_number(parse.files), format_number(parse.with_packages()), percent(parse.with_packages()));
+    log::info!("   • AST multi-package:  {}", format_number(parse.ast_multi));
+    log::info!("   • AST mktplcRef:      {}", format_number(parse.ast_marketplace));
+    log::info!("   • AST single-package: {}", format_number(parse.ast_single));
+    log::info!("   • Regex fallback:     {}", format_number(parse.regex));
+    log::info!("   • No package:         {}", format_number(parse.none));
+}
+
+/// Returns true if `path` (a file or directory) differs between the commit
+/// and its first parent, or exists in a root commit. Compares tree entry ids
+/// only, so it is much cheaper than a diff.
+fn commit_touches_path(repo: &Repository, oid: Oid, path: &str) -> Result<bool> {
+    let commit = repo.find_commit(oid).context("Failed to find commit")?;
+    let entry_id = |tree: &git2::Tree| tree.get_path(Path::new(path)).ok().map(|entry| entry.id());
+
+    let current = entry_id(&commit.tree()?);
+    let parent = match commit.parents().next() {
+        Some(parent) => entry_id(&parent.tree()?),
+        None => None,
+    };
+    Ok(current != parent)
 }
@@ -4,7 +4,13 @@
 //! - Main indexing workflow (indexing.rs)
 //! - Batch commit processing (commit.rs)
 //! - Individual file processing (file.rs)
+//! - Single-file parse diagnostics (diagnose.rs)
+//! - Re-running the parsers over one commit (reparse.rs)
+//! - Branch/tag names of commits (refs.rs)
 
 mod file;
 mod commit;
 mod indexing;
+mod diagnose;
+mod reparse;
+mod refs;
@@ -0,0 +1,49 @@
+//! Branch and tag names of commits (`index --record-refs`)
+
+use anyhow::{Context, Result};
+use git2::Repository;
+use std::collections::HashMap;
+
+use crate::indexer::Indexer;
+
+impl Indexer {
+    /// Records the branches and tags pointing at each commit into the
+    /// database, so pins can be shown with a name like `release-24.05`.
+    ///
+    /// Only commits that a ref points at get a record, so this is a small
+    /// pre-pass over the refs rather than a lookup per walked commit.
+    /// Returns the number of commits with at least one ref.
+    pub(crate) fn record_commit_refs(&self, repo: &Repository) -> Result<usize> {
+        let refs = refs_by_commit(repo)?;
+        for (commit_sha, names) in &refs {
+            self.db.add_commit_refs(commit_sha, names)?;
+        }
+        log::info!("Recorded branch/tag names for {} commit(s)", refs.len());
+        Ok(refs.len())
+    }
+}
+
+/// Maps commit SHAs to the short names of the branches and tags pointing at
+/// them (e.g. `release-24.05`, `origin/nixos-unstable`, `24.05`).
+///
+/// Annotated tags are peeled to their commit; symbolic refs such as
+/// `origin/HEAD` are skipped since they only alias another branch.
+fn refs_by_commit(repo: &Repository) -> Result<HashMap<String, Vec<String>>> {
+    let mut refs: HashMap<String, Vec<String>> = HashMap::new();
+    for reference in repo.references().context("Failed to list references")? {
+        let reference = reference.context("Failed to read reference")?;
+        if reference.kind() == Some(git2::ReferenceType::Symbolic) {
+            continue;
+        }
+        let Some(name) = reference.shorthand() else { continue };
+        if name == "HEAD" || name.ends_with("/HEAD") {
+            continue;
+        }
+        let Ok(commit) = reference.peel_to_commit() else { continue };
+        refs.entry(commit.id().to_string()).or_default().push(name.to_string());
+    }
+    for names in refs.values_mut() {
+        names.sort();
+    }
+    Ok(refs)
+}
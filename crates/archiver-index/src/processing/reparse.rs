@@ -0,0 +1,34 @@
+//! Re-running the parsers over one commit after a parser upgrade
+
+use anyhow::Result;
+use git2::{Oid, Repository};
+use std::time::Instant;
+
+use crate::indexer::Indexer;
+use crate::stats::ReparseStats;
+
+impl Indexer {
+    /// Re-extracts every `.nix` file at `rev` and inserts what the current
+    /// parsers find, so improvements reach an existing database without a
+    /// full reindex. `processed_commits` is left untouched.
+    ///
+    /// With `only_unrecognized`, files whose packages are all already in
+    /// the database are skipped — in practice, only files that previously
+    /// yielded nothing (or something different) are written.
+    pub fn reparse(&self, rev: &str, only_unrecognized: bool) -> Result<ReparseStats> {
+        let start_time = Instant::now();
+        let commit_sha = self.resolve_commit(rev)?;
+        let repo = Repository::open(&self.repo_path)?;
+        let commit = repo.find_commit(Oid::from_str(&commit_sha)?)?;
+
+        let commit_stats = self.full_scan(&repo, &commit, only_unrecognized)?;
+        self.db.flush()?;
+
+        Ok(ReparseStats {
+            packages_found: commit_stats.packages_found,
+            packages_inserted: commit_stats.packages_inserted,
+            recognized_files: commit_stats.recognized_files,
+            elapsed_time: start_time.elapsed(),
+        })
+    }
+}
@@ -0,0 +1,118 @@
+//! Cloning and updating a remote nixpkgs for `index --clone`
+//!
+//! The clone is bare and mirrors `refs/heads/*` directly, so `HEAD` and
+//! branch names resolve the same way they do in the upstream repository.
+//! Updates fetch new objects into the existing clone instead of re-cloning.
+//! Credentials come from the SSH agent or the configured git credential
+//! helpers, so private mirrors work like they do with the `git` CLI.
+
+use anyhow::{Context, Result};
+use git2::build::RepoBuilder;
+use git2::{Cred, CredentialType, FetchOptions, RemoteCallbacks, Repository};
+use std::path::Path;
+
+/// Branches are mirrored as-is (like `git clone --mirror`, without tags)
+const MIRROR_REFSPEC: &str = "+refs/heads/*:refs/heads/*";
+
+/// Clones `url` into `dest` as a bare mirror, or fetches new objects if
+/// `dest` already holds a clone. `depth` limits history (shallow clone).
+pub fn clone_or_fetch(url: &str, dest: &Path, depth: Option<i32>) -> Result<()> {
+    if dest.exists() {
+        let repo = Repository::open_bare(dest)
+            .with_context(|| format!("{:?} exists but is not a bare git repository", dest))?;
+        log::info!("Fetching updates from {} into {:?}", url, dest);
+
+        let mut remote = repo
+            .remote_anonymous(url)
+            .with_context(|| format!("Invalid remote URL '{}'", url))?;
+        remote
+            .fetch(&[MIRROR_REFSPEC], Some(&mut fetch_options(repo.config()?, depth)), None)
+            .with_context(|| format!("Failed to fetch from {}", url))?;
+        return Ok(());
+    }
+
+    log::info!("Cloning {} into {:?} (this can take a long time for nixpkgs)", url, dest);
+    if let Some(parent) = dest.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {:?}", parent))?;
+    }
+
+    // Config used by the credential helpers; the clone doesn't exist yet
+    let config = git2::Config::open_default().context("Failed to read git configuration")?;
+    let result = RepoBuilder::new()
+        .bare(true)
+        .remote_create(|repo, name, url| repo.remote_with_fetch(name, url, MIRROR_REFSPEC))
+        .fetch_options(fetch_options(config, depth))
+        .clone(url, dest);
+
+    if let Err(e) = result {
+        // Don't leave a half-written clone that the next run would try to fetch into
+        let _ = std::fs::remove_dir_all(dest);
+        return Err(e).with_context(|| format!("Failed to clone {}", url));
+    }
+    Ok(())
+}
+
+/// Fetches the named remote of an existing repository with its configured
+/// refspecs. For a clone made by [`clone_or_fetch`] this updates the
+/// mirrored branches; for a regular clone it updates `refs/remotes/*`.
+pub fn fetch_remote(repo_path: &Path, remote: &str) -> Result<()> {
+    let repo = Repository::open(repo_path)
+        .with_context(|| format!("Failed to open repository at {:?}", repo_path))?;
+    let mut remote = repo
+        .find_remote(remote)
+        .with_context(|| format!("Repository at {:?} has no remote '{}'", repo_path, remote))?;
+    let url = remote.url().unwrap_or("<non-UTF-8 URL>").to_string();
+    log::debug!("Fetching updates from {}", url);
+    remote
+        .fetch::<&str>(&[], Some(&mut fetch_options(repo.config()?, None)), None)
+        .with_context(|| format!("Failed to fetch from {}", url))?;
+    Ok(())
+}
+
+/// Fetch options with credential callbacks and coarse progress logging
+fn fetch_options(config: git2::Config, depth: Option<i32>) -> FetchOptions<'static> {
+    let mut callbacks = RemoteCallbacks::new();
+
+    // libgit2 calls back again after a rejected credential; give up
+    // instead of looping forever on a bad helper or agent key
+    let mut attempts = 0;
+    callbacks.credentials(move |url, username, allowed| {
+        attempts += 1;
+        if attempts > 3 {
+            return Err(git2::Error::from_str("authentication failed"));
+        }
+        if allowed.contains(CredentialType::SSH_KEY) {
+            return Cred::ssh_key_from_agent(username.unwrap_or("git"));
+        }
+        if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) {
+            return Cred::credential_helper(&config, url, username);
+        }
+        Cred::default()
+    });
+
+    let mut last_percent = 0;
+    callbacks.transfer_progress(move |progress| {
+        if progress.total_objects() > 0 {
+            let percent = progress.received_objects() * 100 / progress.total_objects();
+            if percent >= last_percent + 10 {
+                last_percent = percent;
+                log::info!(
+                    "Received {}% of objects ({} / {}, {} MiB)",
+                    percent,
+                    progress.received_objects(),
+                    progress.total_objects(),
+                    progress.received_bytes() / (1024 * 1024)
+                );
+            }
+        }
+        true
+    });
+
+    let mut options = FetchOptions::new();
+    options.remote_callbacks(callbacks);
+    if let Some(depth) = depth {
+        options.depth(depth);
+    }
+    options
+}
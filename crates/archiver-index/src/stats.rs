@@ -8,6 +8,19 @@ use crate::formatting::{format_number, format_duration};
 pub struct PackageInfo {
     pub attr_name: String,
     pub version: String,
+    /// `meta.mainProgram`, the executable the package provides
+    pub main_program: Option<String>,
+    /// `meta.description`, whitespace collapsed
+    pub description: Option<String>,
+    /// System (e.g. `x86_64-linux`) of a per-platform source
+    pub platform: Option<String>,
+    /// Hash of that platform's source, as written in the file
+    pub source_hash: Option<String>,
+    /// Upstream revision of the source when it differs from the version
+    pub source_rev: Option<String>,
+    /// `attr_name` came from the file's path (attr map or directory name),
+    /// not from its contents
+    pub name_from_path: bool,
 }
 
 /// Indexing statistics
@@ -18,6 +31,16 @@ pub struct IndexStats {
     pub errors: usize,
     pub packages_found: usize,
     pub packages_inserted: usize,
+    /// Inserted packages that replaced the same version from an older commit
+    pub packages_updated: usize,
+    /// Files whose packages came from the AST parser
+    pub ast_files: usize,
+    /// Files whose packages came from the regex fallback
+    pub regex_files: usize,
+    /// Package directories newly written to the NAR archive
+    pub trees_archived: usize,
+    /// How the files of the full HEAD scan were parsed, if one ran
+    pub head_scan: Option<ParseBreakdown>,
     pub elapsed_time: Duration,
 }
 
@@ -29,28 +52,96 @@ impl Default for IndexStats {
             errors: 0,
             packages_found: 0,
             packages_inserted: 0,
+            packages_updated: 0,
+            ast_files: 0,
+            regex_files: 0,
+            trees_archived: 0,
+            head_scan: None,
             elapsed_time: Duration::from_secs(0),
         }
     }
 }
 
+impl IndexStats {
+    /// Average commits processed per second over the whole run
+    pub fn commits_per_sec(&self) -> f64 {
+        if self.elapsed_time.as_secs_f64() > 0.0 {
+            self.processed as f64 / self.elapsed_time.as_secs_f64()
+        } else {
+            0.0
+        }
+    }
+
+    /// Average packages inserted per second over the whole run
+    pub fn packages_per_sec(&self) -> f64 {
+        if self.elapsed_time.as_secs_f64() > 0.0 {
+            self.packages_inserted as f64 / self.elapsed_time.as_secs_f64()
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Files of a scan by the parser strategy that found their packages
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ParseBreakdown {
+    /// `.nix` files handed to the parsers
+    pub files: usize,
+    /// AST: several packages in one file (`sourceVersion`, overlays)
+    pub ast_multi: usize,
+    /// AST: VSCode extension `mktplcRef`
+    pub ast_marketplace: usize,
+    /// AST: one `pname`/`version` package
+    pub ast_single: usize,
+    /// Regex fallback
+    pub regex: usize,
+    /// Parsed, but no strategy found a package
+    pub none: usize,
+}
+
+impl ParseBreakdown {
+    /// Files that yielded at least one package
+    pub fn with_packages(&self) -> usize {
+        self.ast_multi + self.ast_marketplace + self.ast_single + self.regex
+    }
+}
+
 /// Statistics for processing a single commit
 #[derive(Debug, Default)]
 pub(crate) struct CommitStats {
     pub packages_found: usize,
     pub packages_inserted: usize,
+    pub packages_updated: usize,
+    pub ast_files: usize,
+    pub regex_files: usize,
+    pub trees_archived: usize,
+    /// Files left alone because the database already has their packages
+    pub recognized_files: usize,
+    pub parse: ParseBreakdown,
+}
+
+/// Result of [`Indexer::reparse`](crate::Indexer::reparse)
+#[derive(Debug, Clone, Default)]
+pub struct ReparseStats {
+    pub packages_found: usize,
+    /// Packages that were new to the database (or moved to this commit)
+    pub packages_inserted: usize,
+    /// Files skipped because the database already has their packages
+    pub recognized_files: usize,
+    pub elapsed_time: Duration,
 }
 
 impl std::fmt::Display for IndexStats {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "Commits: {} processed, {} skipped, {} errors | Packages: {} found, {} inserted | Time: {}",
+            "Commits: {} processed, {} skipped, {} errors | Packages: {} found, {} inserted ({} updated) | Time: {}",
             format_number(self.processed), 
             format_number(self.skipped), 
             self.errors,
             format_number(self.packages_found), 
             format_number(self.packages_inserted),
+            format_number(self.packages_updated),
             format_duration(self.elapsed_time)
         )
     }
@@ -0,0 +1,856 @@
+//! Integration tests for the `Indexer` API against a throwaway git repository
+
+use anyhow::Result;
+use archiver_db::ArchiverDb;
+use archiver_index::Indexer;
+use git2::{Oid, Repository, Signature};
+use std::path::Path;
+use tempfile::TempDir;
+
+// ── fixtures ─────────────────────────────────────────────────────────────────
+
+/// Commits `content` as `path` on top of HEAD and returns the new commit id.
+/// Each commit is dated a minute after its parent, so timestamps are distinct.
+fn commit_file(repo: &Repository, path: &str, content: &str, message: &str) -> Result<Oid> {
+    let full = repo.workdir().unwrap().join(path);
+    std::fs::create_dir_all(full.parent().unwrap())?;
+    std::fs::write(&full, content)?;
+
+    let mut index = repo.index()?;
+    index.add_path(Path::new(path))?;
+    index.write()?;
+    let tree = repo.find_tree(index.write_tree()?)?;
+
+    let parents = match repo.head() {
+        Ok(head) => vec![head.peel_to_commit()?],
+        Err(_) => Vec::new(),
+    };
+    let time = parents.first().map_or(1_700_000_000, |p| p.time().seconds() + 60);
+    let sig = Signature::new("test", "test@example.com", &git2::Time::new(time, 0))?;
+    let parent_refs: Vec<_> = parents.iter().collect();
+    Ok(repo.commit(Some("HEAD"), &sig, &sig, message, &tree, &parent_refs)?)
+}
+
+/// Deletes `path` in a commit on top of HEAD, dated a minute after its parent.
+fn commit_removal(repo: &Repository, path: &str, message: &str) -> Result<Oid> {
+    std::fs::remove_file(repo.workdir().unwrap().join(path))?;
+    let mut index = repo.index()?;
+    index.remove_path(Path::new(path))?;
+    index.write()?;
+    let tree = repo.find_tree(index.write_tree()?)?;
+
+    let parent = repo.head()?.peel_to_commit()?;
+    let sig = Signature::new("test", "test@example.com", &git2::Time::new(parent.time().seconds() + 60, 0))?;
+    Ok(repo.commit(Some("HEAD"), &sig, &sig, message, &tree, &[&parent])?)
+}
+
+/// Creates a repository with two commits; returns (tmp, repo, [first, second]).
+fn two_commit_repo() -> Result<(TempDir, Repository, [Oid; 2])> {
+    let tmp = TempDir::new()?;
+    let repo = Repository::init(tmp.path().join("repo"))?;
+    let first = commit_file(&repo, "pkgs/foo/default.nix", r#"{ pname = "foo"; version = "1.0"; }"#, "foo 1.0")?;
+    let second = commit_file(&repo, "pkgs/foo/default.nix", r#"{ pname = "foo"; version = "2.0"; }"#, "foo 2.0")?;
+    Ok((tmp, repo, [first, second]))
+}
+
+fn indexer_for(tmp: &TempDir) -> Result<Indexer> {
+    let db = ArchiverDb::open(tmp.path().join("db"))?;
+    Indexer::new(tmp.path().join("repo"), db)
+}
+
+// ── resolve_commit ───────────────────────────────────────────────────────────
+
+#[test]
+fn test_head_branch_reports_branch_or_detached_head() -> Result<()> {
+    let (tmp, repo, [first, second]) = two_commit_repo()?;
+    repo.branch("release-24.05", &repo.find_commit(first)?, false)?;
+    let indexer = indexer_for(&tmp)?;
+
+    repo.set_head("refs/heads/release-24.05")?;
+    assert_eq!(indexer.head_branch()?.as_deref(), Some("release-24.05"));
+
+    repo.set_head_detached(second)?;
+    assert_eq!(indexer.head_branch()?, None);
+    Ok(())
+}
+
+#[test]
+fn test_resolve_commit_head_and_full_sha() -> Result<()> {
+    let (tmp, _repo, [first, second]) = two_commit_repo()?;
+    let indexer = indexer_for(&tmp)?;
+
+    assert_eq!(indexer.resolve_commit("HEAD")?, second.to_string());
+    assert_eq!(indexer.resolve_commit(&first.to_string())?, first.to_string());
+    Ok(())
+}
+
+#[test]
+fn test_resolve_commit_short_sha() -> Result<()> {
+    let (tmp, _repo, [first, _]) = two_commit_repo()?;
+    let indexer = indexer_for(&tmp)?;
+
+    let short = &first.to_string()[..7];
+    assert_eq!(indexer.resolve_commit(short)?, first.to_string());
+    Ok(())
+}
+
+#[test]
+fn test_resolve_commit_lightweight_and_annotated_tags() -> Result<()> {
+    let (tmp, repo, [first, second]) = two_commit_repo()?;
+    repo.tag_lightweight("23.05", &repo.find_object(first, None)?, false)?;
+    let sig = Signature::now("test", "test@example.com")?;
+    repo.tag("v23.11", &repo.find_object(second, None)?, &sig, "release", false)?;
+    let indexer = indexer_for(&tmp)?;
+
+    assert_eq!(indexer.resolve_commit("23.05")?, first.to_string());
+    // Annotated tags are peeled to the tagged commit
+    assert_eq!(indexer.resolve_commit("v23.11")?, second.to_string());
+    Ok(())
+}
+
+#[test]
+fn test_resolve_commit_branches_and_relative_revisions() -> Result<()> {
+    let (tmp, repo, [first, second]) = two_commit_repo()?;
+    repo.branch("release-24.05", &repo.find_commit(first)?, false)?;
+    let indexer = indexer_for(&tmp)?;
+
+    assert_eq!(indexer.resolve_commit("release-24.05")?, first.to_string());
+    assert_eq!(indexer.resolve_commit("HEAD~1")?, first.to_string());
+    assert_eq!(indexer.resolve_commit("HEAD^")?, first.to_string());
+    assert_eq!(indexer.resolve_commit(&format!("{}~0", &second.to_string()[..7]))?, second.to_string());
+    Ok(())
+}
+
+#[test]
+fn test_new_explains_why_the_repo_path_cannot_be_opened() -> Result<()> {
+    let (tmp, _repo, _) = two_commit_repo()?;
+    let open = |path: std::path::PathBuf| -> String {
+        let db = ArchiverDb::open(tmp.path().join("db")).unwrap();
+        match Indexer::new(&path, db) {
+            Ok(_) => panic!("{:?} should not open", path),
+            Err(e) => e.to_string(),
+        }
+    };
+
+    let err = open(tmp.path().join("missing"));
+    assert!(err.contains("does not exist"), "got: {}", err);
+
+    let file = tmp.path().join("nixpkgs.tar.gz");
+    std::fs::write(&file, b"")?;
+    let err = open(file);
+    assert!(err.contains("is not a directory"), "got: {}", err);
+
+    let plain = tmp.path().join("extracted");
+    std::fs::create_dir(&plain)?;
+    let err = open(plain);
+    assert!(err.contains("not a git repository") && err.contains("git status"), "got: {}", err);
+
+    let nested = tmp.path().join("repo/pkgs");
+    std::fs::create_dir_all(&nested)?;
+    let err = open(nested);
+    assert!(err.contains("is inside the git repository") && err.contains("repository root"), "got: {}", err);
+    Ok(())
+}
+
+#[test]
+fn test_resolve_commit_rejects_unknown_revision() -> Result<()> {
+    let (tmp, _repo, _) = two_commit_repo()?;
+    let indexer = indexer_for(&tmp)?;
+
+    let err = indexer.resolve_commit("deadbee").unwrap_err();
+    assert!(err.to_string().contains("short commit SHA 'deadbee'"), "got: {}", err);
+    let err = indexer.resolve_commit("no-such-tag").unwrap_err();
+    assert!(err.to_string().contains("'no-such-tag' is not a commit"), "got: {}", err);
+    assert!(indexer.resolve_commit("").is_err());
+    Ok(())
+}
+
+#[test]
+fn test_is_shallow_detects_depth_limited_clone() -> Result<()> {
+    let (tmp, _repo, _) = two_commit_repo()?;
+    assert!(!indexer_for(&tmp)?.is_shallow());
+
+    let url = format!("file://{}", tmp.path().join("repo").display());
+    let shallow = tmp.path().join("shallow");
+    let status = std::process::Command::new("git")
+        .args(["clone", "-q", "--depth", "1", &url])
+        .arg(&shallow)
+        .status()?;
+    assert!(status.success());
+
+    let indexer = Indexer::new(&shallow, ArchiverDb::open(tmp.path().join("shallow-db"))?)?;
+    assert!(indexer.is_shallow());
+    Ok(())
+}
+
+// ── index_from_commit ────────────────────────────────────────────────────────
+
+#[test]
+fn test_index_with_io_concurrency_limit_indexes_all_versions() -> Result<()> {
+    use archiver_index::IndexOptions;
+
+    let (tmp, _repo, [_, second]) = two_commit_repo()?;
+    let indexer = indexer_for(&tmp)?.with_io_concurrency(1);
+
+    let stats = indexer.index_from_commit(&second.to_string(), &IndexOptions::default())?;
+    assert_eq!(stats.errors, 0);
+
+    let versions: Vec<_> = indexer.db().get_all_versions("foo")?.into_iter().map(|e| e.version).collect();
+    assert_eq!(versions.len(), 2, "got: {:?}", versions);
+    Ok(())
+}
+
+#[test]
+fn test_head_only_indexes_start_commit_without_walking_history() -> Result<()> {
+    use archiver_index::IndexOptions;
+
+    let (tmp, _repo, [first, second]) = two_commit_repo()?;
+    let indexer = indexer_for(&tmp)?;
+
+    let options = IndexOptions { head_only: true, ..IndexOptions::default() };
+    let stats = indexer.index_from_commit(&second.to_string(), &options)?;
+    assert_eq!(stats.processed, 1);
+    assert_eq!(stats.packages_inserted, 1);
+    assert_eq!(indexer.db().get("foo", "2.0")?.unwrap().commit_sha, second.to_string());
+    assert!(indexer.db().get("foo", "1.0")?.is_none());
+    assert!(indexer.db().is_commit_processed(&second.to_string())?);
+    assert!(!indexer.db().is_commit_processed(&first.to_string())?);
+    Ok(())
+}
+
+#[test]
+fn test_flush_entries_indexes_all_versions_and_rejects_zero() -> Result<()> {
+    use archiver_index::IndexOptions;
+
+    let (tmp, _repo, [first, second]) = two_commit_repo()?;
+    let indexer = indexer_for(&tmp)?;
+
+    let zero = IndexOptions { flush_entries: Some(0), ..IndexOptions::default() };
+    assert!(indexer.index_from_commit(&second.to_string(), &zero).is_err());
+
+    // One commit per batch, flushed after every insert
+    let options = IndexOptions { batch_size: 1, flush_every: 100, flush_entries: Some(1), ..IndexOptions::default() };
+    indexer.index_from_commit(&second.to_string(), &options)?;
+    assert_eq!(indexer.db().get_all_versions("foo")?.len(), 2);
+    assert!(indexer.db().is_commit_processed(&first.to_string())?);
+    Ok(())
+}
+
+#[test]
+fn test_full_scan_follows_symlinked_nix_files() -> Result<()> {
+    use archiver_index::IndexOptions;
+
+    let tmp = TempDir::new()?;
+    let repo = Repository::init(tmp.path().join("repo"))?;
+    let workdir = repo.workdir().unwrap().to_path_buf();
+
+    // Real content lives outside pkgs/, so only the symlink can yield it
+    commit_file(&repo, "shared/bar.nix", r#"{ pname = "bar"; version = "3.1.4"; }"#, "shared bar")?;
+    let link = workdir.join("pkgs/tools/bar/default.nix");
+    std::fs::create_dir_all(link.parent().unwrap())?;
+    std::os::unix::fs::symlink("../../../shared/bar.nix", &link)?;
+    // Dangling and out-of-tree symlinks are skipped without failing the scan
+    std::os::unix::fs::symlink("missing.nix", workdir.join("pkgs/tools/bar/dangling.nix"))?;
+    std::os::unix::fs::symlink("/etc/hostname", workdir.join("pkgs/tools/bar/absolute.nix"))?;
+    let mut index = repo.index()?;
+    for path in ["pkgs/tools/bar/default.nix", "pkgs/tools/bar/dangling.nix", "pkgs/tools/bar/absolute.nix"] {
+        index.add_path(Path::new(path))?;
+    }
+    index.write()?;
+    let tree = repo.find_tree(index.write_tree()?)?;
+    let sig = Signature::now("test", "test@example.com")?;
+    let parent = repo.head()?.peel_to_commit()?;
+    let head = repo.commit(Some("HEAD"), &sig, &sig, "symlinks", &tree, &[&parent])?;
+
+    let indexer = indexer_for(&tmp)?;
+    let options = IndexOptions { head_only: true, ..IndexOptions::default() };
+    let stats = indexer.index_from_commit(&head.to_string(), &options)?;
+    assert_eq!(stats.packages_found, 1);
+    assert_eq!(indexer.db().get("bar", "3.1.4")?.map(|e| e.commit_sha), Some(head.to_string()));
+    Ok(())
+}
+
+#[test]
+fn test_stats_count_versions_moved_to_newer_commits() -> Result<()> {
+    use archiver_index::IndexOptions;
+
+    let tmp = TempDir::new()?;
+    let repo = Repository::init(tmp.path().join("repo"))?;
+    let first = commit_file(&repo, "pkgs/foo/default.nix", r#"{ pname = "foo"; version = "1.0"; }"#, "foo 1.0")?;
+    let indexer = indexer_for(&tmp)?;
+    indexer.index_from_commit(&first.to_string(), &IndexOptions::default())?;
+
+    // Same version, touched again: the entry moves to the newer commit
+    let second = commit_file(&repo, "pkgs/foo/default.nix", "{ pname = \"foo\"; version = \"1.0\"; }\n", "foo: reformat")?;
+    let stats = indexer.index_from_commit(&second.to_string(), &IndexOptions::default())?;
+    assert_eq!(stats.packages_updated, 1);
+    assert_eq!(indexer.db().get("foo", "1.0")?.map(|e| e.commit_sha), Some(second.to_string()));
+    Ok(())
+}
+
+#[test]
+fn test_on_progress_reports_totals_after_each_batch() -> Result<()> {
+    use archiver_index::IndexOptions;
+    use std::sync::{Arc, Mutex};
+
+    let tmp = TempDir::new()?;
+    let repo = Repository::init(tmp.path().join("repo"))?;
+    let mut head = commit_file(&repo, "pkgs/foo/default.nix", r#"{ pname = "foo"; version = "1.0"; }"#, "foo 1.0")?;
+    for version in ["1.1", "1.2", "1.3", "1.4"] {
+        let content = format!(r#"{{ pname = "foo"; version = "{}"; }}"#, version);
+        head = commit_file(&repo, "pkgs/foo/default.nix", &content, version)?;
+    }
+
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let sink = Arc::clone(&seen);
+    let indexer = indexer_for(&tmp)?.on_progress(move |stats| sink.lock().unwrap().push(stats.processed));
+    let options = IndexOptions { batch_size: 2, ..IndexOptions::default() };
+    let stats = indexer.index_from_commit(&head.to_string(), &options)?;
+
+    // HEAD is full-scanned first; the 4 older commits run as batches of 2
+    assert_eq!(*seen.lock().unwrap(), vec![2, 4]);
+    assert_eq!(stats.processed, 4);
+    Ok(())
+}
+
+#[test]
+fn test_estimated_commits_only_affects_progress() -> Result<()> {
+    use archiver_index::IndexOptions;
+
+    let tmp = TempDir::new()?;
+    let repo = Repository::init(tmp.path().join("repo"))?;
+    let mut head = commit_file(&repo, "pkgs/foo/default.nix", r#"{ pname = "foo"; version = "1.0"; }"#, "foo 1.0")?;
+    for version in ["1.1", "1.2", "1.3"] {
+        let content = format!(r#"{{ pname = "foo"; version = "{}"; }}"#, version);
+        head = commit_file(&repo, "pkgs/foo/default.nix", &content, version)?;
+    }
+
+    // An estimate that is too low must not stop the walk early
+    let indexer = indexer_for(&tmp)?;
+    let options = IndexOptions { batch_size: 1, estimated_commits: Some(1), ..IndexOptions::default() };
+    let stats = indexer.index_from_commit(&head.to_string(), &options)?;
+    assert_eq!(stats.processed, 3);
+    assert!(indexer.db().get("foo", "1.0")?.is_some());
+    Ok(())
+}
+
+#[test]
+fn test_files_over_max_blob_size_are_skipped() -> Result<()> {
+    use archiver_index::IndexOptions;
+
+    let tmp = TempDir::new()?;
+    let repo = Repository::init(tmp.path().join("repo"))?;
+    commit_file(&repo, "pkgs/foo/default.nix", r#"{ pname = "foo"; version = "1.0"; }"#, "foo")?;
+    // A generated file padded well past the limit
+    let big = format!("{{ pname = \"big\"; version = \"1.0\"; }}\n# {}\n", "x".repeat(4096));
+    let head = commit_file(&repo, "pkgs/big/default.nix", &big, "big")?;
+    let options = IndexOptions { head_only: true, ..IndexOptions::default() };
+
+    let indexer = indexer_for(&tmp)?.with_max_blob_size(Some(1024));
+    let stats = indexer.index_from_commit(&head.to_string(), &options)?;
+    assert_eq!(stats.packages_found, 1);
+    assert!(indexer.db().get("foo", "1.0")?.is_some());
+    assert!(indexer.db().get("big", "1.0")?.is_none());
+
+    // No limit: the same file is indexed
+    let db = indexer.into_db().unwrap();
+    let indexer = Indexer::new(tmp.path().join("repo"), db)?.with_max_blob_size(None);
+    indexer.reparse(&head.to_string(), false)?;
+    assert!(indexer.db().get("big", "1.0")?.is_some());
+    Ok(())
+}
+
+#[test]
+fn test_archive_dir_stores_each_package_directory_once() -> Result<()> {
+    use archiver_index::archive::NarArchive;
+    use archiver_index::nar::content_hash;
+    use archiver_index::IndexOptions;
+
+    let (tmp, _repo, [first, second]) = two_commit_repo()?;
+    let archive = NarArchive::open(tmp.path().join("archive"))?;
+    let indexer = indexer_for(&tmp)?.with_archive(archive.clone());
+    let options = IndexOptions { head_only: true, ..IndexOptions::default() };
+
+    let stats = indexer.index_from_commit(&first.to_string(), &options)?;
+    assert_eq!(stats.trees_archived, 1);
+    let hash = indexer.db().get("foo", "1.0")?.and_then(|e| e.nar_hash).expect("foo 1.0 has a NAR hash");
+    let path = archive.path_for(&hash).unwrap();
+    // The file is the NAR itself, so its sha256 is the NAR hash
+    assert_eq!(content_hash(&std::fs::read(&path)?), hash);
+
+    // Same tree again: nothing new is written
+    let stats = indexer.reparse(&first.to_string(), false)?;
+    assert_eq!(stats.packages_found, 1);
+    assert_eq!(std::fs::read_dir(tmp.path().join("archive"))?.count(), 1);
+
+    let stats = indexer.index_from_commit(&second.to_string(), &options)?;
+    assert_eq!(stats.trees_archived, 1);
+    assert!(archive.contains(&indexer.db().get("foo", "2.0")?.and_then(|e| e.nar_hash).unwrap()));
+    Ok(())
+}
+
+#[test]
+fn test_first_parent_skips_commits_only_on_merged_branches() -> Result<()> {
+    use archiver_index::IndexOptions;
+
+    // A ── B ── M      M merges S; S only exists on the side branch
+    //  \       /
+    //   ── S ──
+    let tmp = TempDir::new()?;
+    let repo = Repository::init(tmp.path().join("repo"))?;
+    let a = commit_file(&repo, "pkgs/foo/default.nix", r#"{ pname = "foo"; version = "1.0"; }"#, "foo 1.0")?;
+    let s = commit_file(&repo, "pkgs/foo/default.nix", r#"{ pname = "foo"; version = "1.1-side"; }"#, "side")?;
+    let branch = repo.head()?.name().unwrap().to_string();
+    repo.reference(&branch, a, true, "rewind to A")?;
+    let b = commit_file(&repo, "pkgs/foo/default.nix", r#"{ pname = "foo"; version = "2.0"; }"#, "foo 2.0")?;
+    let (b_commit, s_commit) = (repo.find_commit(b)?, repo.find_commit(s)?);
+    let sig = Signature::new("test", "test@example.com", &git2::Time::new(b_commit.time().seconds() + 60, 0))?;
+    let merge = repo.commit(Some("HEAD"), &sig, &sig, "merge side", &b_commit.tree()?, &[&b_commit, &s_commit])?;
+
+    let run = |first_parent: bool| -> Result<(usize, bool)> {
+        let tmp_db = TempDir::new()?;
+        let indexer = Indexer::new(tmp.path().join("repo"), ArchiverDb::open(tmp_db.path().join("db"))?)?;
+        let options = IndexOptions { first_parent, ..IndexOptions::default() };
+        let stats = indexer.index_from_commit(&merge.to_string(), &options)?;
+        Ok((stats.processed, indexer.db().get("foo", "1.1-side")?.is_some()))
+    };
+
+    // The merge itself is full-scanned first; the walk covers the rest
+    assert_eq!(run(false)?, (3, true));
+    assert_eq!(run(true)?, (2, false));
+    Ok(())
+}
+
+#[test]
+fn test_first_parent_indexes_merged_versions_at_the_merge() -> Result<()> {
+    use archiver_index::IndexOptions;
+
+    // A ── B ── M ── H     M takes foo 1.1 from S; H is the indexed head
+    //  \       /
+    //   ── S ──
+    let tmp = TempDir::new()?;
+    let repo = Repository::init(tmp.path().join("repo"))?;
+    let a = commit_file(&repo, "pkgs/foo/default.nix", r#"{ pname = "foo"; version = "1.0"; }"#, "foo 1.0")?;
+    let side = r#"{ pname = "foo"; version = "1.1"; }"#;
+    let s = commit_file(&repo, "pkgs/foo/default.nix", side, "foo 1.1")?;
+    repo.reset(&repo.find_object(a, None)?, git2::ResetType::Hard, None)?;
+    let b = commit_file(&repo, "pkgs/bar/default.nix", r#"{ pname = "bar"; version = "1.0"; }"#, "bar 1.0")?;
+
+    // The merge result: B's tree with S's foo
+    std::fs::write(repo.workdir().unwrap().join("pkgs/foo/default.nix"), side)?;
+    let mut index = repo.index()?;
+    index.add_path(Path::new("pkgs/foo/default.nix"))?;
+    index.write()?;
+    let tree = repo.find_tree(index.write_tree()?)?;
+    let (b_commit, s_commit) = (repo.find_commit(b)?, repo.find_commit(s)?);
+    let sig = Signature::new("test", "test@example.com", &git2::Time::new(b_commit.time().seconds() + 60, 0))?;
+    let merge = repo.commit(Some("HEAD"), &sig, &sig, "merge side", &tree, &[&b_commit, &s_commit])?;
+    let head = commit_file(&repo, "pkgs/foo/default.nix", r#"{ pname = "foo"; version = "2.0"; }"#, "foo 2.0")?;
+
+    let indexer = indexer_for(&tmp)?;
+    let options = IndexOptions { first_parent: true, ..IndexOptions::default() };
+    let stats = indexer.index_from_commit(&head.to_string(), &options)?;
+    assert_eq!(stats.processed, 3, "walks M, B and A but not S");
+    assert_eq!(indexer.db().get("foo", "1.1")?.map(|e| e.commit_sha), Some(merge.to_string()));
+    assert!(indexer.db().get("foo", "1.0")?.is_some());
+    assert!(indexer.db().get("bar", "1.0")?.is_some());
+    Ok(())
+}
+
+// ── branch/tag names ─────────────────────────────────────────────────────────
+
+#[test]
+fn test_record_refs_stores_branches_and_tags_per_commit() -> Result<()> {
+    use archiver_index::IndexOptions;
+
+    let (tmp, repo, [first, second]) = two_commit_repo()?;
+    let first_commit = repo.find_commit(first)?;
+    repo.branch("release-23.11", &first_commit, false)?;
+    let sig = Signature::now("test", "test@example.com")?;
+    repo.tag("23.11", first_commit.as_object(), &sig, "annotated", false)?;
+    repo.tag_lightweight("latest", &repo.find_object(second, None)?, false)?;
+
+    let indexer = indexer_for(&tmp)?;
+    let options = IndexOptions { head_only: true, ..IndexOptions::default() };
+    indexer.index_from_commit(&second.to_string(), &options)?;
+    assert!(indexer.db().commit_refs(&first.to_string())?.is_empty(), "refs are only recorded on request");
+
+    let options = IndexOptions { record_refs: true, ..options };
+    indexer.index_from_commit(&second.to_string(), &options)?;
+    assert_eq!(indexer.db().commit_refs(&first.to_string())?, vec!["23.11", "release-23.11"]);
+    let default_branch = repo.head()?.shorthand().unwrap().to_string();
+    let mut expected = vec![default_branch, "latest".to_string()];
+    expected.sort();
+    assert_eq!(indexer.db().commit_refs(&second.to_string())?, expected);
+    Ok(())
+}
+
+// ── NAR hashes ───────────────────────────────────────────────────────────────
+
+#[test]
+fn test_entries_carry_nar_hash_of_package_directory() -> Result<()> {
+    use archiver_index::IndexOptions;
+    use std::os::unix::fs::PermissionsExt;
+
+    let tmp = TempDir::new()?;
+    let repo = Repository::init(tmp.path().join("repo"))?;
+    let workdir = repo.workdir().unwrap().to_path_buf();
+
+    // Regular file, executable in a subdirectory, and a symlink
+    std::fs::create_dir_all(workdir.join("pkgs/foo/sub"))?;
+    std::fs::write(workdir.join("pkgs/foo/default.nix"), r#"{ pname = "foo"; version = "1.0"; }"#)?;
+    std::fs::write(workdir.join("pkgs/foo/sub/run.sh"), "#!/bin/sh\n")?;
+    std::fs::set_permissions(workdir.join("pkgs/foo/sub/run.sh"), std::fs::Permissions::from_mode(0o755))?;
+    std::os::unix::fs::symlink("default.nix", workdir.join("pkgs/foo/link.nix"))?;
+
+    let mut index = repo.index()?;
+    index.add_all(["pkgs"], git2::IndexAddOption::DEFAULT, None)?;
+    index.write()?;
+    let tree = repo.find_tree(index.write_tree()?)?;
+    let sig = Signature::now("test", "test@example.com")?;
+    let head = repo.commit(Some("HEAD"), &sig, &sig, "foo", &tree, &[])?;
+
+    let indexer = indexer_for(&tmp)?;
+    let options = IndexOptions { head_only: true, ..IndexOptions::default() };
+    indexer.index_from_commit(&head.to_string(), &options)?;
+
+    // Same value `nix hash path` gives for a checkout of pkgs/foo
+    let entry = indexer.db().get("foo", "1.0")?.expect("foo should be indexed");
+    assert_eq!(entry.nar_hash.as_deref(), Some("sha256-1t4a7U6pIWDlsXQlsAfAa+u9uS999tngBaykwy22Vx8="));
+    Ok(())
+}
+
+#[test]
+fn test_without_nar_hashes_entries_have_none_but_are_indexed() -> Result<()> {
+    use archiver_index::IndexOptions;
+
+    let tmp = TempDir::new()?;
+    let repo = Repository::init(tmp.path().join("repo"))?;
+    let head = commit_file(&repo, "pkgs/foo/default.nix", r#"{ pname = "foo"; version = "1.0"; }"#, "foo")?;
+
+    let indexer = indexer_for(&tmp)?.with_nar_hashes(false);
+    let options = IndexOptions { head_only: true, ..IndexOptions::default() };
+    indexer.index_from_commit(&head.to_string(), &options)?;
+
+    let entry = indexer.db().get("foo", "1.0")?.expect("foo should be indexed");
+    assert_eq!(entry.nar_hash, None);
+    // The per-file content hash is cheap and still recorded
+    assert!(entry.content_hash.is_some());
+    Ok(())
+}
+
+#[test]
+fn test_commit_subjects_are_stored_only_when_enabled() -> Result<()> {
+    use archiver_index::IndexOptions;
+
+    let tmp = TempDir::new()?;
+    let repo = Repository::init(tmp.path().join("repo"))?;
+    let first = commit_file(&repo, "pkgs/foo/default.nix", r#"{ pname = "foo"; version = "1.0"; }"#, "foo: init at 1.0")?;
+    let second = commit_file(
+        &repo,
+        "pkgs/foo/default.nix",
+        r#"{ pname = "foo"; version = "2.0"; }"#,
+        "foo: 1.0 -> 2.0\n\nChangelog: https://example.com/foo/2.0",
+    )?;
+
+    let indexer = indexer_for(&tmp)?.with_commit_subjects(true);
+    indexer.index_from_commit(&second.to_string(), &IndexOptions::default())?;
+    let db = indexer.db();
+    assert_eq!(db.commit_subject(&second.to_string())?.as_deref(), Some("foo: 1.0 -> 2.0"));
+    assert_eq!(db.commit_subject(&first.to_string())?.as_deref(), Some("foo: init at 1.0"));
+
+    // Off by default
+    let plain = TempDir::new()?;
+    let repo = Repository::init(plain.path().join("repo"))?;
+    let head = commit_file(&repo, "pkgs/bar/default.nix", r#"{ pname = "bar"; version = "1.0"; }"#, "bar: init at 1.0")?;
+    let indexer = indexer_for(&plain)?;
+    indexer.index_from_commit(&head.to_string(), &IndexOptions { head_only: true, ..IndexOptions::default() })?;
+    assert_eq!(indexer.db().commit_subject(&head.to_string())?, None);
+    Ok(())
+}
+
+#[test]
+fn test_version_history_records_a_version_that_comes_back() -> Result<()> {
+    use archiver_index::IndexOptions;
+
+    let tmp = TempDir::new()?;
+    let repo = Repository::init(tmp.path().join("repo"))?;
+    let first = commit_file(&repo, "pkgs/foo/default.nix", r#"{ pname = "foo"; version = "1.0"; }"#, "foo: init at 1.0")?;
+    let second = commit_file(&repo, "pkgs/foo/default.nix", r#"{ pname = "foo"; version = "2.0"; }"#, "foo: 1.0 -> 2.0")?;
+    let third = commit_file(&repo, "pkgs/foo/default.nix", r#"{ pname = "foo"; version = "1.0"; }"#, "Revert \"foo: 1.0 -> 2.0\"")?;
+    let time = |oid: Oid| repo.find_commit(oid).map(|c| c.time().seconds() as u64);
+
+    let indexer = indexer_for(&tmp)?.with_version_history(true);
+    indexer.index_from_commit(&third.to_string(), &IndexOptions::default())?;
+    let db = indexer.db();
+
+    assert_eq!(db.version_history("foo", "1.0")?, vec![
+        (first.to_string(), time(first)?),
+        (third.to_string(), time(third)?),
+    ]);
+    assert_eq!(db.version_history("foo", "2.0")?, vec![(second.to_string(), time(second)?)]);
+    // Deduplication still pins the newest commit
+    assert_eq!(db.get("foo", "1.0")?.expect("foo 1.0 should be indexed").commit_sha, third.to_string());
+    Ok(())
+}
+
+#[test]
+fn test_head_scan_reports_files_by_parser_strategy() -> Result<()> {
+    use archiver_index::{IndexOptions, ParseBreakdown};
+
+    let tmp = TempDir::new()?;
+    let repo = Repository::init(tmp.path().join("repo"))?;
+    commit_file(&repo, "pkgs/foo/default.nix", r#"{ pname = "foo"; version = "1.0"; }"#, "single")?;
+    commit_file(
+        &repo,
+        "pkgs/development/interpreters/python/default.nix",
+        r#"{
+            python311 = callPackage ./cpython { sourceVersion = { major = "3"; minor = "11"; patch = "14"; }; };
+            python312 = callPackage ./cpython { sourceVersion = { major = "3"; minor = "12"; patch = "12"; }; };
+        }"#,
+        "multi",
+    )?;
+    commit_file(
+        &repo,
+        "pkgs/applications/editors/vscode/extensions/biomejs.biome/default.nix",
+        r#"{ vscode-utils }: vscode-utils.buildVscodeMarketplaceExtension {
+            mktplcRef = { name = "biome"; publisher = "biomejs"; version = "2025.10.241456"; };
+        }"#,
+        "marketplace",
+    )?;
+    commit_file(&repo, "pkgs/broken/default.nix", r#"{ pname = "broken"; version = "2.0"; "#, "syntax error")?;
+    let head = commit_file(&repo, "pkgs/lib/helpers.nix", "{ answer = 42; }", "no package")?;
+
+    let indexer = indexer_for(&tmp)?;
+    let stats = indexer.index_from_commit(&head.to_string(), &IndexOptions { head_only: true, ..IndexOptions::default() })?;
+
+    let parse = stats.head_scan.expect("a head-only run is a full scan");
+    assert_eq!(parse, ParseBreakdown { files: 5, ast_multi: 1, ast_marketplace: 1, ast_single: 1, regex: 1, none: 1 });
+    assert_eq!(parse.with_packages(), 4);
+    Ok(())
+}
+
+#[test]
+fn test_entries_record_source_path_and_name_origin() -> Result<()> {
+    use archiver_index::IndexOptions;
+
+    let tmp = TempDir::new()?;
+    let repo = Repository::init(tmp.path().join("repo"))?;
+    commit_file(&repo, "pkgs/foo/default.nix", r#"{ pname = "foo"; version = "1.0"; }"#, "foo")?;
+    let head = commit_file(&repo, "pkgs/tools/bar/default.nix", r#"{ version = "2.0"; }"#, "bar")?;
+
+    let indexer = indexer_for(&tmp)?;
+    let options = IndexOptions { head_only: true, ..IndexOptions::default() };
+    indexer.index_from_commit(&head.to_string(), &options)?;
+
+    let foo = indexer.db().get("foo", "1.0")?.expect("foo should be indexed");
+    assert_eq!((foo.source_path.as_deref(), foo.attr_from_path), (Some("pkgs/foo/default.nix"), false));
+    let bar = indexer.db().get("bar", "2.0")?.expect("bar should be indexed");
+    assert_eq!((bar.source_path.as_deref(), bar.attr_from_path), (Some("pkgs/tools/bar/default.nix"), true));
+    Ok(())
+}
+
+#[test]
+fn test_only_inserts_listed_packages_and_leaves_commits_unprocessed() -> Result<()> {
+    let tmp = TempDir::new()?;
+    let repo = Repository::init(tmp.path().join("repo"))?;
+    commit_file(&repo, "pkgs/foo/default.nix", r#"{ pname = "foo"; version = "1.0"; }"#, "foo")?;
+    let head = commit_file(&repo, "pkgs/bar/default.nix", r#"{ pname = "bar"; version = "2.0"; }"#, "bar")?;
+
+    let indexer = indexer_for(&tmp)?.with_only(["foo".to_string()]);
+    indexer.index_from_commit(&head.to_string(), &archiver_index::IndexOptions::default())?;
+
+    assert!(indexer.db().get("foo", "1.0")?.is_some());
+    assert!(indexer.db().get("bar", "2.0")?.is_none(), "bar is not in the allow-list");
+    assert!(!indexer.db().is_commit_processed(&head.to_string())?, "a later full run must revisit the commit");
+    Ok(())
+}
+
+#[test]
+fn test_skipped_versions_drop_placeholders_and_keep_real_versions() -> Result<()> {
+    let tmp = TempDir::new()?;
+    let repo = Repository::init(tmp.path().join("repo"))?;
+    commit_file(&repo, "pkgs/foo/default.nix", r#"{ pname = "foo"; version = "1.0"; }"#, "foo")?;
+    commit_file(&repo, "pkgs/tmpl/default.nix", r#"{ pname = "tmpl"; version = "0.0.0"; }"#, "tmpl")?;
+    let head = commit_file(&repo, "pkgs/wip/default.nix", r#"{ pname = "wip"; version = "Unstable"; }"#, "wip")?;
+
+    let placeholders = archiver_index::PLACEHOLDER_VERSIONS.iter().map(|v| v.to_string());
+    let indexer = indexer_for(&tmp)?.with_skipped_versions(placeholders);
+    indexer.index_from_commit(&head.to_string(), &archiver_index::IndexOptions::default())?;
+
+    assert!(indexer.db().get("foo", "1.0")?.is_some(), "real versions pass through");
+    assert!(indexer.db().get_all_versions("tmpl")?.is_empty());
+    assert!(indexer.db().get_all_versions("wip")?.is_empty(), "matched case-insensitively");
+    Ok(())
+}
+
+#[test]
+fn test_path_filter_processes_only_commits_touching_path() -> Result<()> {
+    let tmp = TempDir::new()?;
+    let repo = Repository::init(tmp.path().join("repo"))?;
+    let foo_1 = commit_file(&repo, "pkgs/foo/default.nix", r#"{ pname = "foo"; version = "1.0"; }"#, "foo 1.0")?;
+    let bar_1 = commit_file(&repo, "pkgs/bar/default.nix", r#"{ pname = "bar"; version = "1.0"; }"#, "bar 1.0")?;
+    let foo_2 = commit_file(&repo, "pkgs/foo/default.nix", r#"{ pname = "foo"; version = "2.0"; }"#, "foo 2.0")?;
+    let head = commit_file(&repo, "pkgs/bar/default.nix", r#"{ pname = "bar"; version = "2.0"; }"#, "bar 2.0")?;
+
+    let indexer = indexer_for(&tmp)?;
+    let options = archiver_index::IndexOptions {
+        commit_path: Some("pkgs/foo/".to_string()),
+        ..archiver_index::IndexOptions::default()
+    };
+    indexer.index_from_commit(&head.to_string(), &options)?;
+
+    // foo's whole history is indexed
+    assert_eq!(indexer.db().get("foo", "1.0")?.unwrap().commit_sha, foo_1.to_string());
+    assert!(indexer.db().is_commit_processed(&foo_2.to_string())?);
+    // bar only has what the initial HEAD scan saw; its own commit was skipped
+    assert!(indexer.db().get("bar", "2.0")?.is_some());
+    assert!(indexer.db().get("bar", "1.0")?.is_none());
+    assert!(!indexer.db().is_commit_processed(&bar_1.to_string())?, "a later full run must revisit the commit");
+    Ok(())
+}
+
+#[test]
+fn test_identical_file_content_yields_identical_content_hash() -> Result<()> {
+    let tmp = TempDir::new()?;
+    let repo = Repository::init(tmp.path().join("repo"))?;
+
+    // No pname: attr names come from the directories, the bytes are shared
+    let content = r#"{ version = "1.0"; }"#;
+    commit_file(&repo, "pkgs/tools/alpha/default.nix", content, "alpha")?;
+    commit_file(&repo, "pkgs/tools/beta/default.nix", content, "beta")?;
+    let head = commit_file(&repo, "pkgs/tools/gamma/default.nix", r#"{ version = "1.0";  }"#, "gamma")?;
+
+    let indexer = indexer_for(&tmp)?;
+    indexer.index_from_commit(&head.to_string(), &archiver_index::IndexOptions::default())?;
+
+    let hash = |attr: &str| -> Result<Option<String>> {
+        Ok(indexer.db().get(attr, "1.0")?.expect("package should be indexed").content_hash)
+    };
+    let alpha = hash("alpha")?.expect("content hash should be recorded");
+    assert!(alpha.starts_with("sha256-"));
+    assert_eq!(hash("beta")?.as_deref(), Some(alpha.as_str()));
+    assert_ne!(hash("gamma")?.as_deref(), Some(alpha.as_str()), "a whitespace change is a different definition");
+    assert_eq!(alpha, archiver_index::nar::content_hash(content.as_bytes()));
+    Ok(())
+}
+
+// ── incremental (diff-based) indexing ────────────────────────────────────────
+
+#[test]
+fn test_renamed_package_directory_is_attributed_to_rename_commit() -> Result<()> {
+    let tmp = TempDir::new()?;
+    let repo = Repository::init(tmp.path().join("repo"))?;
+    let workdir = repo.workdir().unwrap().to_path_buf();
+
+    let foo_1 = r#"{ pname = "foo"; version = "1.0"; }"#;
+    let added = commit_file(&repo, "pkgs/foo/default.nix", foo_1, "foo 1.0")?;
+
+    // Move to by-name without changing content
+    std::fs::remove_dir_all(workdir.join("pkgs/foo"))?;
+    let mut index = repo.index()?;
+    index.remove_path(Path::new("pkgs/foo/default.nix"))?;
+    index.write()?;
+    let renamed = commit_file(&repo, "pkgs/by-name/fo/foo/package.nix", foo_1, "foo: move to by-name")?;
+
+    let head = commit_file(&repo, "pkgs/by-name/fo/foo/package.nix", r#"{ pname = "foo"; version = "2.0"; }"#, "foo 2.0")?;
+
+    let indexer = indexer_for(&tmp)?;
+    indexer.index_from_commit(&head.to_string(), &archiver_index::IndexOptions::default())?;
+
+    // 1.0 was last present at the rename commit, seen only through the diff
+    let entry = indexer.db().get("foo", "1.0")?.expect("foo 1.0 should be indexed");
+    assert_eq!(entry.commit_sha, renamed.to_string());
+    assert_ne!(entry.commit_sha, added.to_string());
+    Ok(())
+}
+
+#[test]
+fn test_deletion_only_commit_is_not_credited_with_its_parents_changes() -> Result<()> {
+    let tmp = TempDir::new()?;
+    let repo = Repository::init(tmp.path().join("repo"))?;
+    commit_file(&repo, "pkgs/foo/default.nix", r#"{ pname = "foo"; version = "1.0"; }"#, "foo 1.0")?;
+    commit_file(&repo, "pkgs/bar/default.nix", r#"{ pname = "bar"; version = "1.0"; }"#, "bar 1.0")?;
+    let foo_2 = commit_file(&repo, "pkgs/foo/default.nix", r#"{ pname = "foo"; version = "2.0"; }"#, "foo 2.0")?;
+    // Nothing added, modified or renamed here: git log --diff-filter would
+    // skip this commit and list foo_2's files instead
+    commit_removal(&repo, "pkgs/bar/default.nix", "bar: drop")?;
+    let head = commit_file(&repo, "pkgs/foo/default.nix", r#"{ pname = "foo"; version = "3.0"; }"#, "foo 3.0")?;
+
+    let indexer = indexer_for(&tmp)?;
+    indexer.index_from_commit(&head.to_string(), &archiver_index::IndexOptions::default())?;
+
+    let entry = indexer.db().get("foo", "2.0")?.expect("foo 2.0 should be indexed");
+    assert_eq!(entry.commit_sha, foo_2.to_string());
+    Ok(())
+}
+
+// ── clone_or_fetch ───────────────────────────────────────────────────────────
+
+#[test]
+fn test_clone_then_fetch_updates_mirror() -> Result<()> {
+    use archiver_index::remote::clone_or_fetch;
+
+    let (tmp, repo, [_, second]) = two_commit_repo()?;
+    let url = tmp.path().join("repo").display().to_string();
+    let dest = tmp.path().join("cache").join("nixpkgs.git");
+
+    clone_or_fetch(&url, &dest, None)?;
+    let mirror = Repository::open_bare(&dest)?;
+    assert_eq!(mirror.revparse_single("HEAD")?.id(), second);
+
+    // A second run fetches the new commit into the existing clone
+    let third = commit_file(&repo, "pkgs/foo/default.nix", r#"{ pname = "foo"; version = "3.0"; }"#, "foo 3.0")?;
+    clone_or_fetch(&url, &dest, None)?;
+    assert_eq!(mirror.revparse_single("HEAD")?.id(), third);
+
+    // The mirror is indexable like any local checkout
+    let db = ArchiverDb::open(tmp.path().join("db"))?;
+    let indexer = Indexer::new(&dest, db)?;
+    assert_eq!(indexer.resolve_commit("HEAD")?, third.to_string());
+    Ok(())
+}
+
+#[test]
+fn test_clone_failure_leaves_no_partial_directory() {
+    use archiver_index::remote::clone_or_fetch;
+
+    let tmp = TempDir::new().unwrap();
+    let dest = tmp.path().join("nixpkgs.git");
+    let missing = tmp.path().join("does-not-exist").display().to_string();
+
+    assert!(clone_or_fetch(&missing, &dest, None).is_err());
+    assert!(!dest.exists());
+}
+
+// ── reparse ──────────────────────────────────────────────────────────────────
+
+#[test]
+fn test_reparse_inserts_newly_recognized_packages_only() -> Result<()> {
+    use archiver_index::parsers::AttrMap;
+    use archiver_index::IndexOptions;
+
+    let (tmp, repo, _) = two_commit_repo()?;
+    commit_file(&repo, "pkgs/servers/gitea/server.nix", r#"{ version = "1.21.0"; }"#, "gitea")?;
+
+    let indexer = indexer_for(&tmp)?;
+    let options = IndexOptions { head_only: true, ..IndexOptions::default() };
+    indexer.index_from_commit(&indexer.resolve_commit("HEAD")?, &options)?;
+    assert!(indexer.db().get("gitea", "1.21.0")?.is_some());
+    let processed = indexer.db().processed_commit_count();
+
+    // A better naming rule arrives; only the file it changes is re-inserted
+    let map = AttrMap::parse(r#"
+        [[rule]]
+        path = '^pkgs/servers/(\w+)/server\.nix$'
+        attr = "$1-server"
+    "#)?;
+    let indexer = indexer.with_attr_map(map);
+    let stats = indexer.reparse("HEAD", true)?;
+
+    assert_eq!(stats.recognized_files, 1); // pkgs/foo/default.nix
+    assert_eq!(stats.packages_inserted, 1);
+    assert!(indexer.db().get("gitea-server", "1.21.0")?.is_some());
+    assert_eq!(indexer.db().processed_commit_count(), processed);
+    Ok(())
+}
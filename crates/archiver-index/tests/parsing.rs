@@ -7,7 +7,7 @@
 //!
 //! Also covers version validation and path-to-attr-name helpers.
 
-use archiver_index::parsers::{extract_packages_from_file, is_valid_version};
+use archiver_index::parsers::{extract_packages_from_file, is_valid_version, AttrMap};
 use regex::Regex;
 
 fn ver_regex() -> Regex {
@@ -123,6 +123,41 @@ fn test_ast_mktplcref_ruff_let_style() {
     assert_eq!(info.version, "2026.36.0");
 }
 
+#[test]
+fn test_ast_mktplcref_ruff_per_platform_sources() {
+    let content = r#"
+        { stdenvNoCC, lib, vscode-utils }:
+        vscode-utils.buildVscodeMarketplaceExtension {
+            mktplcRef =
+                let
+                    sources = {
+                        "x86_64-linux" = { arch = "linux-x64"; hash = "sha256-linux="; };
+                        "aarch64-darwin" = { arch = "darwin-arm64"; hash = "sha256-darwin="; };
+                    };
+                in
+                {
+                    name = "ruff";
+                    publisher = "charliermarsh";
+                    version = "2026.36.0";
+                }
+                // sources.${stdenvNoCC.system};
+        }
+    "#;
+    let packages = extract_packages_from_file(
+        "pkgs/applications/editors/vscode/extensions/charliermarsh.ruff/default.nix",
+        content,
+        &ver_regex(),
+    );
+    let platforms: Vec<_> = packages
+        .iter()
+        .map(|p| (p.attr_name.as_str(), p.version.as_str(), p.platform.as_deref(), p.source_hash.as_deref()))
+        .collect();
+    assert_eq!(platforms, vec![
+        ("vscode-extensions.charliermarsh.ruff", "2026.36.0", Some("x86_64-linux"), Some("sha256-linux=")),
+        ("vscode-extensions.charliermarsh.ruff", "2026.36.0", Some("aarch64-darwin"), Some("sha256-darwin=")),
+    ]);
+}
+
 // ── Strategy 1: multi-package callPackage + sourceVersion ────────────────────
 
 #[test]
@@ -151,6 +186,30 @@ fn test_ast_multi_package_sourceversion() {
     assert_eq!(v312.version, "3.12.12");
 }
 
+// ── Strategy 1: overlay-shaped files (final: prev: { … }) ────────────────────
+
+#[test]
+fn test_ast_overlay_two_packages() {
+    let content = r#"
+        final: prev: {
+            mytool = final.callPackage ./mytool {
+                version = "1.4.2";
+            };
+            ripgrep = prev.ripgrep.overrideAttrs (old: {
+                version = "14.0.0";
+                src = old.src;
+            });
+            lib = prev.lib;
+        }
+    "#;
+    let pkgs = extract_packages_from_file("pkgs/top-level/overlay.nix", content, &ver_regex());
+    assert_eq!(pkgs.len(), 2);
+    let mytool = pkgs.iter().find(|p| p.attr_name == "mytool").unwrap();
+    assert_eq!(mytool.version, "1.4.2");
+    let rg = pkgs.iter().find(|p| p.attr_name == "ripgrep").unwrap();
+    assert_eq!(rg.version, "14.0.0");
+}
+
 // ── version validation ────────────────────────────────────────────────────────
 
 #[test]
@@ -192,3 +251,333 @@ fn test_path_to_attr_name() {
     // Too short – no valid parent dir
     assert_eq!(path_to_attr_name("default.nix"), None);
 }
+
+// ── version interpolating pname ───────────────────────────────────────────────
+
+#[test]
+fn test_ast_version_strips_interpolated_pname_prefix() {
+    let content = r#"
+        { stdenv }:
+        stdenv.mkDerivation rec {
+            pname = "foo";
+            version = "${pname}-2.0";
+        }
+    "#;
+    let pkgs = extract_packages_from_file("pkgs/tools/misc/foo/default.nix", content, &ver_regex());
+    assert_eq!(pkgs.len(), 1);
+    assert_eq!(pkgs[0].attr_name, "foo");
+    assert_eq!(pkgs[0].version, "2.0");
+
+    let content = content.replace("${pname}", "${finalAttrs.pname}");
+    let pkgs = extract_packages_from_file("pkgs/tools/misc/foo/default.nix", &content, &ver_regex());
+    assert_eq!(pkgs[0].version, "2.0");
+
+    // A literal version that merely starts with the pname is left alone
+    let content = content.replace("${finalAttrs.pname}-2.0", "foo-2.0");
+    let pkgs = extract_packages_from_file("pkgs/tools/misc/foo/default.nix", &content, &ver_regex());
+    assert_eq!(pkgs[0].version, "foo-2.0");
+}
+
+// ── meta.mainProgram ──────────────────────────────────────────────────────────
+
+#[test]
+fn test_main_program_is_extracted() {
+    let content = r#"
+        { rustPlatform }:
+        rustPlatform.buildRustPackage rec {
+            pname = "ripgrep";
+            version = "14.1.0";
+            meta = { description = "grep, but faster"; mainProgram = "rg"; };
+        }
+    "#;
+    let pkgs = extract_packages_from_file("pkgs/by-name/ri/ripgrep/package.nix", content, &ver_regex());
+    assert_eq!(pkgs[0].main_program.as_deref(), Some("rg"));
+
+    // Dotted form, interpolating pname
+    let content = r#"
+        { stdenv }:
+        stdenv.mkDerivation rec {
+            pname = "hello";
+            version = "2.12.1";
+            meta.mainProgram = "${pname}";
+        }
+    "#;
+    let pkgs = extract_packages_from_file("pkgs/by-name/he/hello/package.nix", content, &ver_regex());
+    assert_eq!(pkgs[0].main_program.as_deref(), Some("hello"));
+
+    let content = r#"{ stdenv }: stdenv.mkDerivation { pname = "lib"; version = "1.0"; }"#;
+    let pkgs = extract_packages_from_file("pkgs/by-name/li/lib/package.nix", content, &ver_regex());
+    assert_eq!(pkgs[0].main_program, None);
+}
+
+// ── meta.description ──────────────────────────────────────────────────────────
+
+#[test]
+fn test_description_is_extracted_from_meta_only() {
+    let content = r#"
+        { lib, stdenv }:
+        stdenv.mkDerivation rec {
+            pname = "cmark";
+            version = "0.31.0";
+            passthru.description = "not this one";
+            meta = with lib; {
+                description = ''
+                  CommonMark parsing and
+                  rendering library'';
+            };
+        }
+    "#;
+    let pkgs = extract_packages_from_file("pkgs/by-name/cm/cmark/package.nix", content, &ver_regex());
+    assert_eq!(pkgs[0].description.as_deref(), Some("CommonMark parsing and rendering library"));
+
+    let content = r#"
+        { stdenv }:
+        stdenv.mkDerivation rec {
+            pname = "hello";
+            version = "2.12.1";
+            meta.description = "Program that produces a familiar, friendly greeting";
+        }
+    "#;
+    let pkgs = extract_packages_from_file("pkgs/by-name/he/hello/package.nix", content, &ver_regex());
+    assert_eq!(pkgs[0].description.as_deref(), Some("Program that produces a familiar, friendly greeting"));
+
+    let content = r#"{ stdenv }: stdenv.mkDerivation { pname = "lib"; version = "1.0"; description = "top level"; }"#;
+    let pkgs = extract_packages_from_file("pkgs/by-name/li/lib/package.nix", content, &ver_regex());
+    assert_eq!(pkgs[0].description, None);
+}
+
+// ── source rev ────────────────────────────────────────────────────────────────
+
+#[test]
+fn test_source_rev_is_captured_when_it_differs_from_version() {
+    let content = r#"
+        { stdenv, fetchFromGitHub }:
+        stdenv.mkDerivation rec {
+            pname = "tool";
+            version = "0-unstable-2024-05-01";
+            src = fetchFromGitHub {
+                owner = "someone";
+                repo = "tool";
+                rev = "3f2a9c1d0b7e6f5a4c3b2a1908f7e6d5c4b3a291";
+                hash = "sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
+            };
+        }
+    "#;
+    let pkgs = extract_packages_from_file("pkgs/by-name/to/tool/package.nix", content, &ver_regex());
+    assert_eq!(pkgs[0].source_rev.as_deref(), Some("3f2a9c1d0b7e6f5a4c3b2a1908f7e6d5c4b3a291"));
+
+    // A tag naming the version adds nothing
+    for rev in [r#""v${version}""#, r#""release-1.2.3""#, r#""1.2.3""#] {
+        let content = format!(
+            r#"{{ stdenv, fetchFromGitHub }}: stdenv.mkDerivation rec {{
+                pname = "tool"; version = "1.2.3";
+                src = fetchFromGitHub {{ owner = "o"; repo = "tool"; rev = {}; }};
+            }}"#,
+            rev
+        );
+        let pkgs = extract_packages_from_file("pkgs/by-name/to/tool/package.nix", &content, &ver_regex());
+        assert_eq!(pkgs[0].source_rev, None, "rev = {}", rev);
+    }
+}
+
+// ── attr map rules ────────────────────────────────────────────────────────────
+
+#[test]
+fn test_attr_map_overrides_path_heuristic() {
+    use archiver_index::parsers::extract_packages_from_file_with;
+
+    let map = AttrMap::parse(r#"
+        [[rule]]
+        path = '^pkgs/servers/(?P<name>\w+)/server\.nix$'
+        attr = "${name}-server"
+
+        [[rule]]
+        path = '^pkgs/servers/'
+        attr = "never-reached"
+    "#).unwrap();
+    assert_eq!(map.rules().len(), 2);
+    assert_eq!(map.apply("pkgs/servers/gitea/server.nix").as_deref(), Some("gitea-server"));
+    assert_eq!(map.apply("pkgs/tools/misc/hello/default.nix"), None);
+
+    // No pname: the first matching rule wins over the parent-directory guess
+    let content = r#"{ stdenv }: stdenv.mkDerivation { version = "1.21.0"; }"#;
+    let pkgs = extract_packages_from_file_with(
+        "pkgs/servers/gitea/server.nix", content, &ver_regex(), &map, &|_| None,
+    ).packages;
+    assert_eq!(pkgs[0].attr_name, "gitea-server");
+
+    // Unmatched paths still use the built-in heuristic
+    let pkgs = extract_packages_from_file_with(
+        "pkgs/tools/misc/hello/default.nix", content, &ver_regex(), &map, &|_| None,
+    ).packages;
+    assert_eq!(pkgs[0].attr_name, "hello");
+    assert!(pkgs[0].name_from_path);
+
+    // An explicit pname takes priority over the rules
+    let content = r#"{ stdenv }: stdenv.mkDerivation { pname = "gitea"; version = "1.21.0"; }"#;
+    let pkgs = extract_packages_from_file_with(
+        "pkgs/servers/gitea/server.nix", content, &ver_regex(), &map, &|_| None,
+    ).packages;
+    assert_eq!(pkgs[0].attr_name, "gitea");
+    assert!(!pkgs[0].name_from_path);
+}
+
+#[test]
+fn test_attr_map_rejects_invalid_rules() {
+    assert!(AttrMap::parse("[[rule]]\npath = '('\nattr = \"x\"").is_err());
+    assert!(AttrMap::parse("[[rule]]\npath = 'a'\nattr = \"\"").is_err());
+    assert!(AttrMap::parse("[[rule]]\npath = 'a'\nname = \"x\"").is_err());
+    assert!(AttrMap::parse("").unwrap().is_empty());
+}
+
+// ── version from sibling Cargo.toml ───────────────────────────────────────────
+
+#[test]
+fn test_ast_version_from_imported_cargo_toml() {
+    use archiver_index::parsers::extract_packages_from_file_with;
+
+    let content = r#"
+        { lib, rustPlatform }:
+        rustPlatform.buildRustPackage {
+            pname = "mytool";
+            version = (lib.importTOML ./Cargo.toml).package.version;
+            cargoLock.lockFile = ./Cargo.lock;
+        }
+    "#;
+    let cargo_toml = r#"
+        [package]
+        name = "mytool"
+        version = "0.7.3"
+
+        [dependencies]
+        serde = { version = "1" }
+    "#;
+    let read_sibling = |path: &str| {
+        (path == "pkgs/by-name/my/mytool/Cargo.toml").then(|| cargo_toml.to_string())
+    };
+
+    let pkgs = extract_packages_from_file_with(
+        "pkgs/by-name/my/mytool/package.nix", content, &ver_regex(), &AttrMap::default(), &read_sibling,
+    ).packages;
+    assert_eq!(pkgs.len(), 1);
+    assert_eq!(pkgs[0].attr_name, "mytool");
+    assert_eq!(pkgs[0].version, "0.7.3");
+
+    // fromTOML + readFile form
+    let content = content.replace(
+        "(lib.importTOML ./Cargo.toml)",
+        "(builtins.fromTOML (builtins.readFile ./Cargo.toml))",
+    );
+    let pkgs = extract_packages_from_file_with(
+        "pkgs/by-name/my/mytool/package.nix", &content, &ver_regex(), &AttrMap::default(), &read_sibling,
+    ).packages;
+    assert_eq!(pkgs[0].version, "0.7.3");
+}
+
+// ── version from sibling sources.nix ──────────────────────────────────────────
+
+#[test]
+fn test_ast_version_from_imported_sources_nix() {
+    use archiver_index::parsers::extract_packages_from_file_with;
+
+    let sources_nix = r#"
+        # Generated by ./update.sh
+        {
+            version = "4.2.1";
+            hash = "sha256-47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=";
+            cli = { rev = "1.9.0"; };
+        }
+    "#;
+    let read_sibling = |path: &str| {
+        (path == "pkgs/by-name/my/mytool/sources.nix").then(|| sources_nix.to_string())
+    };
+    let version_of = |content: &str| {
+        extract_packages_from_file_with(
+            "pkgs/by-name/my/mytool/package.nix", content, &ver_regex(), &AttrMap::default(), &read_sibling,
+        ).packages.into_iter().next().map(|p| p.version)
+    };
+
+    // Direct select on the import
+    let direct = r#"
+        { stdenv }:
+        stdenv.mkDerivation {
+            pname = "mytool";
+            version = (import ./sources.nix).version;
+        }
+    "#;
+    assert_eq!(version_of(direct).as_deref(), Some("4.2.1"));
+
+    // Through a let binding, and a nested `rev`
+    let bound = r#"
+        { stdenv }:
+        let
+            sources = import ./sources.nix;
+        in
+        stdenv.mkDerivation {
+            pname = "mytool";
+            version = sources.cli.rev;
+            passthru.updateScript = ./update.sh;
+        }
+    "#;
+    assert_eq!(version_of(bound).as_deref(), Some("1.9.0"));
+
+    // inherit from the import
+    let inherited = r#"
+        { stdenv }:
+        stdenv.mkDerivation {
+            pname = "mytool";
+            inherit (import ./sources.nix) version;
+        }
+    "#;
+    assert_eq!(version_of(inherited).as_deref(), Some("4.2.1"));
+
+    // A sources file that is a function is out of scope
+    let called = direct.replace("(import ./sources.nix)", "(import ./sources.nix { })");
+    assert_eq!(version_of(&called), None);
+}
+
+#[test]
+fn test_diagnose_reports_parse_errors_and_regex_fallback() {
+    use archiver_index::parsers::{diagnose_file, ParseStrategy};
+    // Unbalanced brace: rnix fails, regex heuristics still find the version
+    let content = r#"{ stdenv }: stdenv.mkDerivation { pname = "broken"; version = "0.4.1"; "#;
+    let diagnosis = diagnose_file("pkgs/tools/broken/default.nix", content, &ver_regex(), &AttrMap::default(), &|_| None);
+
+    assert!(!diagnosis.parse_errors.is_empty());
+    assert!(diagnosis.ast_strategies.is_empty());
+    let regex = diagnosis.regex.expect("regex should find the package");
+    assert_eq!((regex.attr_name.as_str(), regex.version.as_str()), ("broken", "0.4.1"));
+    assert_eq!(diagnosis.outcome.strategy, ParseStrategy::Regex);
+}
+
+#[test]
+fn test_diagnose_explains_why_nothing_was_found() {
+    use archiver_index::parsers::{diagnose_file, MissReason};
+    let miss = |path: &str, content: &str| {
+        diagnose_file(path, content, &ver_regex(), &AttrMap::default(), &|_| None).miss
+    };
+
+    assert_eq!(miss("pkgs/by-name/he/hello/package.nix", r#"{ pname = "hello"; version = "2.12.1"; }"#), None);
+    assert_eq!(miss("lib/default.nix", r#"{ version = "1.0"; }"#), Some(MissReason::NoAttrName));
+    assert_eq!(miss("pkgs/tools/foo/default.nix", r#"{ pname = "foo"; src = ./.; }"#), Some(MissReason::NoVersionFound));
+    assert_eq!(
+        miss("pkgs/tools/foo/default.nix", r#"{ pname = "foo"; version = "${lib.trivial.release}"; }"#),
+        Some(MissReason::VersionRejectedAsInvalid)
+    );
+    assert_eq!(miss("pkgs/tools/foo/default.nix", r#"{ pname = "foo"; src = ./. "#), Some(MissReason::AstParseError));
+}
+
+#[test]
+fn test_diagnose_runs_each_ast_strategy() {
+    use archiver_index::parsers::{diagnose_file, ParseStrategy};
+    let content = r#"{ stdenv }: stdenv.mkDerivation { pname = "hello"; version = "2.12.1"; }"#;
+    let diagnosis = diagnose_file("pkgs/by-name/he/hello/package.nix", content, &ver_regex(), &AttrMap::default(), &|_| None);
+
+    assert!(diagnosis.parse_errors.is_empty());
+    let matched: Vec<_> = diagnosis.ast_strategies.iter()
+        .filter(|r| !r.packages.is_empty())
+        .map(|r| r.name)
+        .collect();
+    assert_eq!(matched, vec!["single-package"]);
+    assert_eq!(diagnosis.outcome.strategy, ParseStrategy::Ast);
+}